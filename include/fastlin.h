@@ -0,0 +1,55 @@
+/* C interface to the fastlin typing engine (see src/ffi.rs) */
+
+#ifndef FASTLIN_H
+#define FASTLIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* return codes */
+#define FASTLIN_OK 0
+#define FASTLIN_ERR_NULL_POINTER 1
+#define FASTLIN_ERR_INVALID_STRING 2
+#define FASTLIN_ERR_KMER_SIZE 3
+#define FASTLIN_ERR_SCHEME 4
+#define FASTLIN_ERR_INPUT 5
+#define FASTLIN_ERR_PANIC 6
+
+/* data types */
+#define FASTLIN_DATA_ASSEMBLY 0
+#define FASTLIN_DATA_SINGLE 1
+#define FASTLIN_DATA_PAIRED 2
+
+typedef struct FastlinScheme FastlinScheme;
+
+typedef struct FastlinResult {
+    int32_t data_type;  /* FASTLIN_DATA_* */
+    uint32_t coverage;  /* kmer coverage */
+    int32_t mixture;    /* 1 if more than one lineage was detected */
+    char *lineages;     /* e.g. "4.1 (14)" */
+    char *log_barcodes; /* barcodes passing the minimum count, grouped by lineage */
+    char *log_errors;   /* errors of the files and warnings (e.g. low quality reads), "; "-separated */
+    double mean_read_length;     /* mean length of the reads (or contigs) */
+    uint64_t median_read_length; /* median length of the reads (or contigs) */
+} FastlinResult;
+
+/* load a barcode file for a kmer size of at least 11, and at most 2 x its flank length + 1 */
+int32_t fastlin_load_scheme(const char *path, uint8_t kmer_size, FastlinScheme **out);
+
+/* type a sample (1 assembly, 1 single-end or 2 paired-end files); max_cov = 0 for no limit */
+int32_t fastlin_scan_files(const FastlinScheme *scheme, const char *const *paths, size_t n_paths,
+                           int32_t min_count, size_t n_barcodes, uint64_t max_cov,
+                           FastlinResult **out);
+
+void fastlin_free_result(FastlinResult *result);
+void fastlin_free_scheme(FastlinScheme *scheme);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FASTLIN_H */
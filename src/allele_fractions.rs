@@ -0,0 +1,188 @@
+use crate::process_barcodes::{barcode_lineage, lineage_name, LineageCall, LineageNames};
+
+// sequencing error rate assumed for the reference allele kmers found at the sites of a lineage
+// fixed in a sample, and p-value of the mixture test below which a sample is mixed
+pub const MIXTURE_ERROR_RATE: f64 = 0.01;
+pub const MIXTURE_SIGNIFICANCE: f64 = 0.001;
+
+// occurrences of the barcode kmers (alternate allele) and of the wild-type kmers (reference
+// allele) at a barcode site of a sample
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlleleCounts {
+    pub barcode: String,
+    pub alt: u32,
+    pub reference: u32,
+}
+
+impl AlleleCounts {
+    // proportion of the reads covering the site that carry the allele of the barcode
+    pub fn alt_fraction(&self) -> f64 {
+        self.alt as f64 / (self.alt + self.reference) as f64
+    }
+}
+
+// allele counts of the barcode sites with wild-type kmers (has_ref, by barcode index) covered
+// by the reads of a sample
+pub fn allele_counts(
+    counts: &[u32],
+    ref_counts: &[u32],
+    ids: &[String],
+    has_ref: &[bool],
+) -> Vec<AlleleCounts> {
+    counts
+        .iter()
+        .zip(ref_counts)
+        .zip(ids)
+        .zip(has_ref)
+        .filter(|(((alt, reference), _), has_ref)| **has_ref && **alt + **reference > 0)
+        .map(|(((alt, reference), id), _)| AlleleCounts {
+            barcode: id.clone(),
+            alt: *alt,
+            reference: *reference,
+        })
+        .collect()
+}
+
+// median alternate allele fraction of the sites of each lineage called: lineage (fraction),
+// lineage2 (fraction), or NA for lineages without covered site. Unlike median depths, the
+// fractions don't depend on the coverage of the sites, and estimate the proportion of the
+// sample carrying the lineage (and its sublineages)
+pub fn format_allele_fractions(
+    calls: &[LineageCall],
+    sites: &[AlleleCounts],
+    names: &LineageNames,
+) -> String {
+    calls
+        .iter()
+        .map(|call| {
+            let mut fractions: Vec<f64> = sites
+                .iter()
+                .filter(|site| barcode_lineage(&site.barcode) == call.lineage)
+                .map(AlleleCounts::alt_fraction)
+                .collect();
+            fractions.sort_by(f64::total_cmp);
+            let name = lineage_name(names, &call.lineage);
+            match fractions.len() {
+                0 => format!("{} (NA)", name),
+                n if n % 2 == 0 => format!(
+                    "{} ({:.2})",
+                    name,
+                    (fractions[n / 2 - 1] + fractions[n / 2]) / 2.0
+                ),
+                n => format!("{} ({:.2})", name, fractions[n / 2]),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// p-value of the test of a minor component: for each lineage called, the reference allele
+// kmers at its sites should only come from sequencing errors if the lineage is fixed in the
+// sample (binomial test of the reference allele counts), the smallest p-value being corrected
+// for the number of lineages tested (None without covered site)
+pub fn mixture_pvalue(calls: &[LineageCall], sites: &[AlleleCounts]) -> Option<f64> {
+    let pvalues: Vec<f64> = calls
+        .iter()
+        .filter_map(|call| {
+            let (alt, reference) = sites
+                .iter()
+                .filter(|site| barcode_lineage(&site.barcode) == call.lineage)
+                .fold((0, 0), |(alt, reference), site| {
+                    (alt + site.alt as u64, reference + site.reference as u64)
+                });
+            (alt + reference > 0)
+                .then(|| binomial_tail(alt + reference, reference, MIXTURE_ERROR_RATE))
+        })
+        .collect();
+    let min_pvalue = pvalues.iter().copied().reduce(f64::min)?;
+    Some((min_pvalue * pvalues.len() as f64).min(1.0))
+}
+
+// column of the output file ('NA' without covered site)
+pub fn format_mixture_pvalue(pvalue: Option<f64>) -> String {
+    match pvalue {
+        Some(pvalue) => format!("{:.2e}", pvalue),
+        None => "NA".to_string(),
+    }
+}
+
+fn binomial_tail(n: u64, k: u64, p: f64) -> f64 {
+    // probability that a binomial variable B(n, p) is at least k (log-space terms, so that
+    // deep sites don't underflow)
+    let ratio = (p / (1.0 - p)).ln();
+    let mut log_term = n as f64 * (1.0 - p).ln();
+    for i in 0..k {
+        log_term += ((n - i) as f64).ln() - ((i + 1) as f64).ln() + ratio;
+    }
+    let mut tail = 0.0;
+    for i in k..=n {
+        tail += log_term.exp();
+        log_term += ((n - i) as f64).ln() - ((i + 1) as f64).ln() + ratio;
+    }
+    tail.min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(lineage: &str) -> LineageCall {
+        LineageCall {
+            lineage: lineage.to_string(),
+            median: 10,
+            depth_cv: 0.0,
+            uneven: false,
+            proportion: 0.0,
+            proportion_ci: (0.0, 0.0),
+        }
+    }
+
+    fn site(barcode: &str, alt: u32, reference: u32) -> AlleleCounts {
+        AlleleCounts {
+            barcode: barcode.to_string(),
+            alt,
+            reference,
+        }
+    }
+
+    #[test]
+    fn sites_without_reference_kmers_or_reads_are_left_out() {
+        let ids = ["2__1", "2__2", "4__3"].map(String::from);
+        let sites = allele_counts(&[5, 0, 3], &[1, 0, 2], &ids, &[true, true, false]);
+        assert_eq!(sites, vec![site("2__1", 5, 1)]);
+    }
+
+    #[test]
+    fn allele_fractions_are_the_median_of_the_sites() {
+        let sites = [site("2__1", 9, 1), site("2__2", 5, 5), site("2__3", 8, 2)];
+        let names = LineageNames::from([("2".to_string(), "Beijing".to_string())]);
+        assert_eq!(
+            format_allele_fractions(&[call("2"), call("4")], &sites, &names),
+            "Beijing (0.80), 4 (NA)"
+        );
+        assert_eq!(
+            format_allele_fractions(&[call("2")], &sites[..2], &names),
+            "Beijing (0.70)"
+        );
+    }
+
+    #[test]
+    fn reference_alleles_beyond_sequencing_errors_are_a_mixture() {
+        // (1% of the 200 kmers expected from errors)
+        let fixed = [site("2__1", 99, 1), site("2__2", 100, 0)];
+        let mixed = [site("2__1", 70, 30), site("2__2", 80, 20)];
+        assert!(mixture_pvalue(&[call("2")], &fixed).unwrap() > MIXTURE_SIGNIFICANCE);
+        assert!(mixture_pvalue(&[call("2")], &mixed).unwrap() < MIXTURE_SIGNIFICANCE);
+        assert_eq!(mixture_pvalue(&[call("4")], &mixed), None);
+        assert_eq!(format_mixture_pvalue(None), "NA");
+    }
+
+    #[test]
+    fn binomial_tails() {
+        assert!((binomial_tail(10, 0, 0.3) - 1.0).abs() < 1e-12);
+        assert!((binomial_tail(2, 1, 0.5) - 0.75).abs() < 1e-12);
+        assert!((binomial_tail(3, 3, 0.5) - 0.125).abs() < 1e-12);
+        // (deep sites don't underflow)
+        assert!(binomial_tail(100_000, 5_000, 0.01) < 1e-100);
+    }
+}
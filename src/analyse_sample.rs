@@ -1,113 +1,1287 @@
 use flate2::read::MultiGzDecoder;
-use seq_io::fastq::{Reader, Record};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use seq_io::fasta::{self, Record as _};
+use seq_io::fastq::{self, Reader, Record};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Cursor};
 use std::str;
+use std::sync::Arc;
 
-pub fn get_reader(path: &PathBuf) -> Box<dyn BufRead + Send> {
-    let filename_str = path.to_str().unwrap();
-    let file = match File::open(path) {
+use crate::barcodes::{BarcodeIndex, EncodedIndex, SpacedSeed};
+use crate::encoding::{encode_bases, kmer_mask, BLOCK_SIZE, INVALID_BASE};
+use crate::error_rate::{estimate_error_rate, neighbour_kmers};
+use crate::minimizers::MinimizerFilter;
+use crate::trimming::{quality_trimmed_length, Adapters};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::archives::{is_compressed_tar, open_member, open_members, split_archive_path};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::input_files::is_fasta_file;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::io_retry::{FailedReader, RetryReader};
+#[cfg(all(feature = "ora", not(target_arch = "wasm32")))]
+use crate::ora::{is_ora, open_ora};
+
+// maximum proportion of bases other than A, C, G, T or N in the reads of a sample
+pub const MAX_NON_ACGTN_FRACTION: f64 = 0.1;
+
+// phred+64 qualities are all above '@' (phred 0), and reach values above 'J' (the highest
+// phred+33 Illumina quality)
+pub const PHRED64_MIN_QUALITY: u8 = b'@';
+pub const PHRED33_MAX_QUALITY: u8 = b'J';
+
+// barcode occurrences (by barcode index), kmer coverage and read lengths (number of reads per
+// length, before trimming) of a sample (error message if a file couldn't be read), and how its
+// kmers are matched
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Analysis {
+    pub counts: Vec<u32>,
+    #[serde(default)]
+    pub ref_counts: Vec<u32>,
+    #[serde(skip)]
+    pub barcode_ids: Arc<Vec<String>>,
+    pub kmer_count: u64,
+    // (number of sequences with at least k bases once trimmed)
+    #[serde(default)]
+    pub kmer_sequences: u64,
+    pub coverage: u32,
+    pub read_lengths: BTreeMap<usize, u64>,
+    pub fastq_checks: FastqChecks,
+    pub files: Vec<FileStats>,
+    pub error: String,
+    pub neighbour_count: u64,
+    pub error_rate: Option<f64>,
+    #[serde(skip)]
+    pub matching: KmerMatching,
+    // (barcode indexes of the barcodes found in the read being scanned, to find those found in
+    // both reads of a pair)
+    #[serde(skip)]
+    pub read_barcodes: Vec<u32>,
+}
+
+// how the kmers of the reads are matched against the barcodes: spaced seed of the barcodes
+// (kmers masked to its care positions), barcodes keyed by their 2-bit encoding (kmers of the
+// reads encoded while scanning them, without a spaced seed), minimizer prefilter (reads sharing no minimizer
+// with the barcodes are skipped), kmers at one mismatch from the barcodes (counted to
+// estimate the error rate), adapters and low quality 3' ends (minimum mean quality of the
+// sliding window) trimmed from the FASTQ reads, whether the barcodes found in both reads
+// of a pair are counted once (overlapping mates of short inserts), the maximum count of a
+// barcode (barcodes in repeated regions), and the wild-type kmers of the barcode sites
+// (counted to report allele fractions)
+#[derive(Clone, Debug, Default)]
+pub struct KmerMatching {
+    pub spaced_seed: Option<SpacedSeed>,
+    pub encoded: Option<Arc<EncodedIndex>>,
+    pub prefilter: Option<Arc<MinimizerFilter>>,
+    pub neighbours: Option<Arc<HashSet<String>>>,
+    pub adapters: Option<Arc<Adapters>>,
+    pub trim_quality: Option<u8>,
+    pub mate_overlap: bool,
+    pub max_count: Option<u32>,
+    pub ref_kmers: Option<Arc<HashMap<String, u32>>>,
+}
+
+impl KmerMatching {
+    pub fn new(
+        barcodes: &BarcodeIndex,
+        k: usize,
+        spaced_seed: Option<SpacedSeed>,
+        prefilter: bool,
+    ) -> Self {
+        KmerMatching::with_error_rate(barcodes, k, spaced_seed, prefilter, false)
+    }
+
+    // same as new, also counting the neighbours of the barcode kmers if error_rate is set
+    // (not with a spaced seed)
+    pub fn with_error_rate(
+        barcodes: &BarcodeIndex,
+        k: usize,
+        spaced_seed: Option<SpacedSeed>,
+        prefilter: bool,
+        error_rate: bool,
+    ) -> Self {
+        let neighbours =
+            (error_rate && spaced_seed.is_none()).then(|| Arc::new(neighbour_kmers(barcodes, k)));
+        // (no prefilter for kmers masked by a spaced seed, and reads containing neighbours
+        // must also be scanned)
+        let kmers = barcodes.kmers().map(|(kmer, _)| kmer).chain(
+            neighbours
+                .iter()
+                .flat_map(|neighbours| neighbours.iter())
+                .map(|kmer| Cow::Borrowed(kmer.as_str())),
+        );
+        let prefilter = match prefilter && spaced_seed.is_none() {
+            true => MinimizerFilter::new(kmers, k).map(Arc::new),
+            false => None,
+        };
+        let encoded = match spaced_seed {
+            None => barcodes.encoded(k).map(Arc::new),
+            Some(_) => None,
+        };
+        KmerMatching {
+            spaced_seed,
+            encoded,
+            prefilter,
+            neighbours,
+            adapters: None,
+            trim_quality: None,
+            mate_overlap: false,
+            max_count: None,
+            ref_kmers: None,
+        }
+    }
+
+    // also count the wild-type kmers of the barcode sites (not with a spaced seed), the
+    // prefilter keeping the reads containing them
+    pub fn with_ref_kmers(
+        mut self,
+        ref_kmers: Arc<HashMap<String, u32>>,
+        barcodes: &BarcodeIndex,
+        k: usize,
+    ) -> Self {
+        if self.spaced_seed.is_some() {
+            return self;
+        }
+        if self.prefilter.is_some() {
+            let kmers = barcodes.kmers().map(|(kmer, _)| kmer).chain(
+                self.neighbours
+                    .iter()
+                    .flat_map(|neighbours| neighbours.iter())
+                    .chain(ref_kmers.keys())
+                    .map(|kmer| Cow::Borrowed(kmer.as_str())),
+            );
+            self.prefilter = MinimizerFilter::new(kmers, k).map(Arc::new);
+        }
+        self.ref_kmers = Some(ref_kmers);
+        self
+    }
+}
+
+// reads and kmers extracted from an input file (incomplete if the kmer limit was reached), and
+// the kmer coverage they contribute to the sample
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FileStats {
+    pub file_name: String,
+    pub nb_reads: u64,
+    pub nb_kmers: u64,
+    pub complete: bool,
+    #[serde(default)]
+    pub coverage: u32,
+}
+
+// base composition and quality range of the FASTQ reads of a sample, to flag broken files
+// (reads with qualities and sequences of different lengths are rejected by the parser)
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FastqChecks {
+    pub nb_bases: u64,
+    pub non_acgtn: u64,
+    pub min_quality: Option<u8>,
+    pub max_quality: Option<u8>,
+}
+
+impl FastqChecks {
+    pub fn add_read(&mut self, seq: &[u8], qual: &[u8]) {
+        self.nb_bases += seq.len() as u64;
+        self.non_acgtn += seq
+            .iter()
+            .filter(|base| !b"ACGTNacgtn".contains(base))
+            .count() as u64;
+        if let (Some(min), Some(max)) = (qual.iter().min(), qual.iter().max()) {
+            self.min_quality = Some(self.min_quality.map_or(*min, |q| q.min(*min)));
+            self.max_quality = Some(self.max_quality.map_or(*max, |q| q.max(*max)));
+        }
+    }
+
+    pub fn merge(&mut self, other: &FastqChecks) {
+        self.nb_bases += other.nb_bases;
+        self.non_acgtn += other.non_acgtn;
+        if let (Some(min), Some(max)) = (other.min_quality, other.max_quality) {
+            self.min_quality = Some(self.min_quality.map_or(min, |q| q.min(min)));
+            self.max_quality = Some(self.max_quality.map_or(max, |q| q.max(max)));
+        }
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.nb_bases > 0 {
+            let fraction = self.non_acgtn as f64 / self.nb_bases as f64;
+            if fraction > MAX_NON_ACGTN_FRACTION {
+                warnings.push(format!(
+                    "{:.1}% of the bases are not A, C, G, T or N",
+                    100.0 * fraction
+                ));
+            }
+        }
+        if self.min_quality.is_some_and(|q| q >= PHRED64_MIN_QUALITY)
+            && self.max_quality.is_some_and(|q| q > PHRED33_MAX_QUALITY)
+        {
+            warnings.push("qualities look phred+64 encoded".to_string());
+        }
+        warnings
+    }
+}
+
+// barcode found in a read, at the given (0-based) kmer start position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BarcodeHit<'a> {
+    pub barcode_id: &'a str,
+    pub position: usize,
+}
+
+// read containing at least one barcode (qualities are missing for FASTA records)
+pub struct ReadHits<'a> {
+    pub read_id: &'a str,
+    pub seq: &'a [u8],
+    pub qual: Option<&'a [u8]>,
+    pub hits: &'a [BarcodeHit<'a>],
+}
+
+// called for each read containing at least one barcode (binning, plotting, debugging ...)
+pub trait ReadVisitor {
+    fn visit(&mut self, read: &ReadHits);
+
+    // called before scanning each input file
+    fn start_file(&mut self, _file_name: &str) {}
+
+    // called for every sequence scanned, with or without barcode (trimmed from a read of
+    // read_length bases)
+    fn visit_sequence(&mut self, _seq: &[u8], _read_length: usize) {}
+}
+
+impl<F: FnMut(&ReadHits)> ReadVisitor for F {
+    fn visit(&mut self, read: &ReadHits) {
+        self(read)
+    }
+}
+
+// several visitors can be combined
+impl ReadVisitor for Vec<&mut dyn ReadVisitor> {
+    fn visit(&mut self, read: &ReadHits) {
+        for visitor in self.iter_mut() {
+            visitor.visit(read);
+        }
+    }
+
+    fn start_file(&mut self, file_name: &str) {
+        for visitor in self.iter_mut() {
+            visitor.start_file(file_name);
+        }
+    }
+
+    fn visit_sequence(&mut self, seq: &[u8], read_length: usize) {
+        for visitor in self.iter_mut() {
+            visitor.visit_sequence(seq, read_length);
+        }
+    }
+}
+
+// kmer sizes scanned in addition to the main one, sharing the decompression and parsing of
+// the reads (one barcode index and one analysis per kmer size)
+pub struct KmerSizeScans<'a> {
+    pub scans: Vec<(usize, &'a BarcodeIndex, Analysis)>,
+}
+
+impl<'a> KmerSizeScans<'a> {
+    pub fn new(indexes: impl IntoIterator<Item = (usize, &'a BarcodeIndex)>) -> Self {
+        let scans = indexes
+            .into_iter()
+            .map(|(k, barcodes)| (k, barcodes, Analysis::new(barcodes)))
+            .collect();
+        KmerSizeScans { scans }
+    }
+
+    // analyses of the sample, one per kmer size
+    pub fn finish(self, genome_size: u64) -> Vec<(usize, Analysis)> {
+        self.scans
+            .into_iter()
+            .map(|(k, _, mut analysis)| {
+                analysis.compute_coverage(genome_size);
+                (k, analysis)
+            })
+            .collect()
+    }
+}
+
+impl ReadVisitor for KmerSizeScans<'_> {
+    fn visit(&mut self, _read: &ReadHits) {}
+
+    fn visit_sequence(&mut self, seq: &[u8], read_length: usize) {
+        for (k, barcodes, analysis) in self.scans.iter_mut() {
+            analysis.process_trimmed_sequence(seq, read_length, barcodes, *k);
+        }
+    }
+}
+
+impl Analysis {
+    // analysis of the barcodes of an index (one count per barcode)
+    pub fn new(barcodes: &BarcodeIndex) -> Self {
+        Analysis {
+            counts: vec![0; barcodes.ids.len()],
+            ref_counts: vec![0; barcodes.ids.len()],
+            barcode_ids: barcodes.ids.clone(),
+            ..Default::default()
+        }
+    }
+
+    // add the counts of an analysis of other reads of the same sample and index: each input
+    // file is scanned into an analysis of its own, merged into the one of the sample (the
+    // coverage has to be computed again)
+    pub fn merge(&mut self, other: Analysis) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+            self.barcode_ids = other.barcode_ids.clone();
+        }
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other_count;
+        }
+        if self.ref_counts.len() < other.ref_counts.len() {
+            self.ref_counts.resize(other.ref_counts.len(), 0);
+        }
+        for (count, other_count) in self.ref_counts.iter_mut().zip(&other.ref_counts) {
+            *count += other_count;
+        }
+        self.kmer_count += other.kmer_count;
+        self.kmer_sequences += other.kmer_sequences;
+        for (length, nb_reads) in other.read_lengths {
+            *self.read_lengths.entry(length).or_insert(0) += nb_reads;
+        }
+        self.neighbour_count += other.neighbour_count;
+        self.fastq_checks.merge(&other.fastq_checks);
+        self.files.extend(other.files);
+        if self.error.is_empty() {
+            self.error = other.error;
+        }
+    }
+
+    // cap the count of each barcode, so that a barcode in a duplicated or repeated region
+    // doesn't inflate the depth of its lineage
+    fn cap_counts(&mut self, max_count: u32) {
+        for count in self.counts.iter_mut().chain(self.ref_counts.iter_mut()) {
+            *count = (*count).min(max_count);
+        }
+    }
+
+    // barcode of a wild-type kmer (if they are counted)
+    fn ref_kmer_index(&self, kmer: &[u8]) -> Option<u32> {
+        let ref_kmers = self.matching.ref_kmers.as_ref()?;
+        ref_kmers.get(str::from_utf8(kmer).ok()?).copied()
+    }
+
+    // count once the barcodes found in both reads of a pair (barcodes of the second mate
+    // counted in this analysis) and return their number
+    fn remove_mate_overlaps(&mut self, mate_1: &[u32], mate_2: &[u32]) -> u64 {
+        let mut unpaired = mate_1.to_vec();
+        let mut nb_overlaps = 0;
+        for index in mate_2 {
+            if let Some(n) = unpaired.iter().position(|other| other == index) {
+                unpaired.swap_remove(n);
+                self.counts[*index as usize] -= 1;
+                nb_overlaps += 1;
+            }
+        }
+        nb_overlaps
+    }
+
+    // occurrences of the barcodes found, by barcode ID
+    pub fn barcode_counts(&self) -> HashMap<String, i32> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| (self.barcode_ids[index].clone(), *count as i32))
+            .collect()
+    }
+
+    // count the barcodes found in a single sequence and return its number of kmers
+    pub fn process_sequence(&mut self, seq: &[u8], barcodes: &BarcodeIndex, k: usize) -> u64 {
+        self.count_barcodes(seq, seq.len(), barcodes, k, None)
+    }
+
+    // same as process_sequence for a read trimmed from read_length bases (the length reported)
+    pub fn process_trimmed_sequence(
+        &mut self,
+        seq: &[u8],
+        read_length: usize,
+        barcodes: &BarcodeIndex,
+        k: usize,
+    ) -> u64 {
+        self.count_barcodes(seq, read_length, barcodes, k, None)
+    }
+
+    // same as process_trimmed_sequence, passing the read and its barcode hits to the visitor
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_read(
+        &mut self,
+        read_id: &str,
+        seq: &[u8],
+        qual: Option<&[u8]>,
+        read_length: usize,
+        barcodes: &BarcodeIndex,
+        k: usize,
+        visitor: &mut dyn ReadVisitor,
+    ) -> u64 {
+        visitor.visit_sequence(seq, read_length);
+        let mut hits = Vec::new();
+        let nb_kmers = self.count_barcodes(seq, read_length, barcodes, k, Some(&mut hits));
+        if !hits.is_empty() {
+            visitor.visit(&ReadHits {
+                read_id,
+                seq,
+                qual,
+                hits: &hits,
+            });
+        }
+        nb_kmers
+    }
+
+    fn count_barcodes<'a>(
+        &mut self,
+        seq: &[u8],
+        read_length: usize,
+        barcodes: &'a BarcodeIndex,
+        k: usize,
+        mut hits: Option<&mut Vec<BarcodeHit<'a>>>,
+    ) -> u64 {
+        *self.read_lengths.entry(read_length).or_insert(0) += 1;
+
+        // only consider sequences long enough to have a kmer
+        if seq.len() < k {
+            return 0;
+        }
+        self.kmer_sequences += 1;
+        // update kmer counter: a sequence of length L has L - k + 1 kmers (and none if it is
+        // shorter than k), so that the kmer coverage is the mean number of times each genome
+        // kmer was sequenced
+        let nb_kmers = (seq.len() - k + 1) as u64;
+        self.kmer_count += nb_kmers;
+
+        // skip the sequences that can't contain any barcode
+        if let Some(prefilter) = &self.matching.prefilter {
+            if !prefilter.might_contain(seq) {
+                return nb_kmers;
+            }
+        }
+        if let Some(encoded_index) = self.matching.encoded.clone() {
+            // kmers encoded on 64 bits while scanning the sequence, block by block (the kmers
+            // with other bases than A, C, G and T can't be barcodes)
+            let mask = kmer_mask(k);
+            let (mut encoded, mut valid_bases): (u64, usize) = (0, 0);
+            let mut codes = [INVALID_BASE; BLOCK_SIZE];
+            for (block, bases) in seq.chunks(BLOCK_SIZE).enumerate() {
+                encode_bases(bases, &mut codes);
+                for (offset, (base, code)) in bases.iter().zip(codes.iter()).enumerate() {
+                    // (lowercase bases are not barcode bases)
+                    if *code == INVALID_BASE || base.is_ascii_lowercase() {
+                        valid_bases = 0;
+                    } else {
+                        encoded = ((encoded << 2) | *code as u64) & mask;
+                        valid_bases += 1;
+                    }
+                    let end = block * BLOCK_SIZE + offset + 1;
+                    if end >= k {
+                        let index = match valid_bases >= k {
+                            true => encoded_index.get(encoded),
+                            false => None,
+                        };
+                        let n = end - k;
+                        self.count_kmer(&seq[n..end], n, index, barcodes, hits.as_deref_mut());
+                    }
+                }
+            }
+        } else {
+            // kmers looked up as slices of the sequence
+            let mut key = Vec::with_capacity(k);
+            for n in 0..(seq.len() - k + 1) {
+                let mut kmer = &seq[n..n + k];
+
+                // spaced seed: only the bases of the care positions are compared
+                if let Some(seed) = &self.matching.spaced_seed {
+                    seed.mask(kmer, &mut key);
+                    kmer = &key;
+                }
+                let index = barcodes.get(kmer);
+                self.count_kmer(kmer, n, index, barcodes, hits.as_deref_mut());
+            }
+        }
+        nb_kmers
+    }
+
+    // count the kmer of a sequence starting at position n, given the index of its barcode if
+    // it is one
+    fn count_kmer<'a>(
+        &mut self,
+        kmer: &[u8],
+        n: usize,
+        index: Option<u32>,
+        barcodes: &'a BarcodeIndex,
+        hits: Option<&mut Vec<BarcodeHit<'a>>>,
+    ) {
+        if let Some(index) = index {
+            if let Some(hits) = hits {
+                hits.push(BarcodeHit {
+                    barcode_id: barcodes.id(index),
+                    position: n,
+                });
+            }
+            self.counts[index as usize] += 1;
+            if self.matching.mate_overlap {
+                self.read_barcodes.push(index);
+            }
+        } else if let Some(index) = self.ref_kmer_index(kmer) {
+            self.ref_counts[index as usize] += 1;
+        } else if let Some(neighbours) = &self.matching.neighbours {
+            if str::from_utf8(kmer).is_ok_and(|kmer| neighbours.contains(kmer)) {
+                self.neighbour_count += 1;
+            }
+        }
+    }
+
+    // count the barcodes found in sequences from any source (BAM records, simulations, ...),
+    // stopping once more than kmer_limit kmers have been extracted from them
+    pub fn process_sequences<'a>(
+        &mut self,
+        sequences: impl IntoIterator<Item = &'a [u8]>,
+        barcodes: &BarcodeIndex,
+        k: usize,
+        kmer_limit: Option<u64>,
+    ) -> u64 {
+        let mut kmer_counter: u64 = 0;
+        for seq in sequences {
+            kmer_counter += self.process_sequence(seq, barcodes, k);
+
+            if let Some(max_kmers) = kmer_limit {
+                // stop process if number of maximum kmer coverage reached
+                if kmer_counter > max_kmers {
+                    break;
+                }
+            }
+        }
+        kmer_counter
+    }
+
+    // kmer coverage as computed by the first versions of fastlin (--legacy-output), which
+    // counted L - k kmers in each sequence of length L >= k
+    pub fn legacy_coverage(&self, genome_size: u64) -> u32 {
+        let nb_kmers = self.kmer_count.saturating_sub(self.kmer_sequences);
+        (nb_kmers as f64 / genome_size as f64).round() as u32
+    }
+
+    // mean and median length of the sequences processed, before trimming (0 if there is none)
+    pub fn read_length_stats(&self) -> (f64, usize) {
+        let nb_reads: u64 = self.read_lengths.values().sum();
+        if nb_reads == 0 {
+            return (0.0, 0);
+        }
+        let total: u64 = self
+            .read_lengths
+            .iter()
+            .map(|(length, count)| *length as u64 * count)
+            .sum();
+
+        // (lower) median from the cumulative number of reads
+        let mut cumulative = 0;
+        let mut median = 0;
+        for (length, count) in &self.read_lengths {
+            cumulative += count;
+            if 2 * cumulative >= nb_reads {
+                median = *length;
+                break;
+            }
+        }
+        (total as f64 / nb_reads as f64, median)
+    }
+
+    // error rate of the reads, if the neighbours of the barcode kmers were counted
+    pub fn compute_error_rate(&mut self, k: usize) {
+        if self.matching.neighbours.is_some() {
+            let nb_barcode_kmers = self.counts.iter().map(|count| *count as u64).sum();
+            self.error_rate = estimate_error_rate(nb_barcode_kmers, self.neighbour_count, k);
+        }
+    }
+
+    pub fn compute_coverage(&mut self, genome_size: u64) {
+        // compute kmer coverage (of the sample, and of each input file)
+        self.coverage = (self.kmer_count as f64 / genome_size as f64).round() as u32;
+        for file in self.files.iter_mut() {
+            file.coverage = (file.nb_kmers as f64 / genome_size as f64).round() as u32;
+        }
+    }
+}
+
+// no filesystem in the browser: wasm32 builds only scan in-memory data
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_reader(path: &Path) -> Box<dyn BufRead + Send> {
+    // member of an archive
+    if let Some((archive, member)) = split_archive_path(path) {
+        return open_member(&archive, &member);
+    }
+    // DRAGEN ORA file, decompressed by orad (the sample failing if orad can't be run)
+    #[cfg(feature = "ora")]
+    if is_ora(path) {
+        return match open_ora(path) {
+            Ok(reader) => reader,
+            Err(error) => Box::new(BufReader::new(FailedReader::new(path, error))),
+        };
+    }
+    let file = match RetryReader::open(path) {
         Ok(file) => file,
-        Err(error) => panic!("Error opening compressed file: {:?}.", error),
+        Err(error) => return Box::new(BufReader::new(FailedReader::new(path, error))),
     };
-    if filename_str.ends_with(".gz") {
-        Box::new(BufReader::new(MultiGzDecoder::new(file)))
-    } else {
-        Box::new(BufReader::new(file))
+    if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gz"))
+    {
+        return Box::new(BufReader::new(MultiGzDecoder::new(file)));
+    }
+    // streams without file extension (e.g. /dev/fd/63 of a process substitution): gzip detected
+    // from the magic bytes, peeked without seeking
+    let mut reader = BufReader::new(file);
+    if is_fasta_file(path).is_none()
+        && matches!(reader.fill_buf(), Ok(buffer) if buffer.starts_with(&[0x1f, 0x8b]))
+    {
+        return Box::new(BufReader::new(MultiGzDecoder::new(reader)));
     }
+    Box::new(reader)
 }
 
 pub fn process_buffer<R: BufRead>(
     k: usize,
     kmer_limit: Option<u64>,
-    barcodes: &HashMap<String, String>,
-    result_barcodes: &mut HashMap<String, i32>,
+    barcodes: &BarcodeIndex,
+    analysis: &mut Analysis,
     mut reader: Reader<R>,
-) -> Result<u64, String> {
+    mut visitor: Option<&mut dyn ReadVisitor>,
+) -> Result<(u64, u64), String> {
+    // numbers of kmers and reads extracted
     let mut kmer_counter: u64 = 0;
+    let mut read_counter: u64 = 0;
 
     while let Some(record) = reader.next() {
         // unwrap record (contains name, sequence and quality)
         let record_ready = match record {
             Ok(record) => record,
             Err(err) => {
-                return Err(format!("Error in file: {}", err));
+                return Err(format!("Error in file: {}", fastq_error_message(err)));
             }
         };
 
-        // get sequences and sequence length
-        let seq = record_ready.seq();
-        //let len_seq = seq.len();
+        // count barcodes and update kmer counter
+        read_counter += 1;
+        kmer_counter +=
+            process_record(k, barcodes, analysis, &record_ready, visitor.as_deref_mut());
 
-        // only consider sequences long enough to have a kmer
-        if seq.len() >= k {
-            // extract kmers (slices from Vect seq)
-            for n in 0..(seq.len() - k + 1) {
-                // get slice of Vect[u8]
-                let kmer = &seq[n..n + k];
+        if let Some(max_kmers) = kmer_limit {
+            // stop process if number of maximum kmer coverage reached
+            if kmer_counter > max_kmers {
+                return Ok((kmer_counter, read_counter));
+            }
+        }
+    }
+    Ok((kmer_counter, read_counter))
+}
 
-                // convert Vect[u8] into String
-                let seq_kmer = unsafe { str::from_utf8_unchecked(kmer) };
+// count the barcodes of a FASTQ read and return its number of kmers
+fn process_record<'v>(
+    k: usize,
+    barcodes: &BarcodeIndex,
+    analysis: &mut Analysis,
+    record: &fastq::RefRecord,
+    visitor: Option<&mut (dyn ReadVisitor + 'v)>,
+) -> u64 {
+    analysis.fastq_checks.add_read(record.seq(), record.qual());
 
-                // check if kmer is known -> add to count if yes or create new count if no
-                if let Some(id) = barcodes.get(seq_kmer) {
-                    match result_barcodes.get(id) {
-                        Some(count) => {
-                            result_barcodes.insert(id.to_string(), count + 1);
-                        }
-                        None => {
-                            result_barcodes.insert(id.to_string(), 1);
-                        }
-                    }
-                }
-            }
-            // update kmer counter
-            let nb_kmers = (seq.len() - k) as u64;
-            kmer_counter += nb_kmers;
+    // (adapters and low quality 3' ends trimmed before the kmers are extracted, the read
+    // length reported being the one of the untrimmed read)
+    let (seq, qual) = (record.seq(), record.qual());
+    let read_length = seq.len();
+    let mut length = match &analysis.matching.adapters {
+        Some(adapters) => adapters.trimmed_length(seq),
+        None => seq.len(),
+    };
+    if let Some(min_quality) = analysis.matching.trim_quality {
+        length = quality_trimmed_length(&qual[..length], min_quality);
+    }
+    let (seq, qual) = (&seq[..length], &qual[..length]);
 
-            if let Some(max_kmers) = kmer_limit {
-                // stop process if number of maximum kmer coverage reached
-                if kmer_counter > max_kmers {
-                    return Ok(kmer_counter);
-                }
+    match visitor {
+        Some(visitor) => analysis.process_read(
+            &String::from_utf8_lossy(record.id_bytes()),
+            seq,
+            Some(qual),
+            read_length,
+            barcodes,
+            k,
+            visitor,
+        ),
+        None => analysis.process_trimmed_sequence(seq, read_length, barcodes, k),
+    }
+}
+
+pub fn process_fasta_buffer<R: BufRead>(
+    k: usize,
+    barcodes: &BarcodeIndex,
+    analysis: &mut Analysis,
+    mut reader: fasta::Reader<R>,
+    mut visitor: Option<&mut dyn ReadVisitor>,
+) -> Result<(u64, u64), String> {
+    // numbers of kmers and sequences extracted
+    let mut kmer_counter: u64 = 0;
+    let mut read_counter: u64 = 0;
+
+    while let Some(record) = reader.next() {
+        let record_ready = match record {
+            Ok(record) => record,
+            Err(fasta::Error::Io(err)) => {
+                return Err(format!("Error in file: {}", io_error_message(err)));
             }
+            Err(err) => {
+                return Err(format!("Error in file: {}", err));
+            }
+        };
+
+        // assembly sequences can be wrapped over several lines
+        read_counter += 1;
+        let seq = record_ready.full_seq();
+        kmer_counter += match visitor.as_deref_mut() {
+            Some(visitor) => analysis.process_read(
+                &String::from_utf8_lossy(record_ready.id_bytes()),
+                &seq,
+                None,
+                seq.len(),
+                barcodes,
+                k,
+                visitor,
+            ),
+            None => analysis.process_sequence(&seq, barcodes, k),
+        };
+    }
+    Ok((kmer_counter, read_counter))
+}
+
+// note of the errors of truncated inputs
+const TRUNCATED_INPUT: &str = "truncated input (";
+
+fn fastq_error_message(err: fastq::Error) -> String {
+    // truncated downloads end with an incomplete gzip stream or an incomplete record
+    match err {
+        fastq::Error::Io(err) => io_error_message(err),
+        fastq::Error::UnexpectedEnd { .. } => format!("{}{})", TRUNCATED_INPUT, err),
+        err => err.to_string(),
+    }
+}
+
+fn io_error_message(err: io::Error) -> String {
+    if err.kind() == io::ErrorKind::UnexpectedEof {
+        format!("{}{})", TRUNCATED_INPUT, err)
+    } else {
+        err.to_string()
+    }
+}
+
+// error of a sample as reported by the first versions of fastlin (--legacy-output), which
+// didn't note the truncated inputs
+pub fn legacy_error(error: &str) -> String {
+    match error.split_once(TRUNCATED_INPUT) {
+        Some((start, rest)) => match rest.strip_suffix(")\"") {
+            Some(message) => format!("{}{}\"", start, message),
+            None => error.to_string(),
+        },
+        None => error.to_string(),
+    }
+}
+
+// input file name (for reporting) and its reader
+pub type NamedReader = (String, Box<dyn BufRead + Send>);
+
+pub fn get_reader_from_bytes(data: Vec<u8>) -> Box<dyn BufRead + Send> {
+    // in-memory data (e.g. POSTed to the server), gzip detected from the magic bytes
+    if data.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(MultiGzDecoder::new(Cursor::new(data))))
+    } else {
+        Box::new(BufReader::new(Cursor::new(data)))
+    }
+}
+
+// readers of the input files of a sample, sorted by file name (the files of a sample stored in
+// a same compressed tar archive being streamed in a single pass over the archive)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_readers(mut vect_files: Vec<PathBuf>) -> Vec<NamedReader> {
+    vect_files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    let names = vect_files
+        .iter()
+        .map(|path| path.to_string_lossy().to_string());
+    if let Some((archive, members)) = shared_compressed_tar(&vect_files) {
+        return names.zip(open_members(&archive, &members)).collect();
+    }
+    names
+        .zip(vect_files.iter().map(|path| get_reader(path)))
+        .collect()
+}
+
+// compressed tar archive storing all the files of a sample (at least two), and their members
+#[cfg(not(target_arch = "wasm32"))]
+fn shared_compressed_tar(files: &[PathBuf]) -> Option<(PathBuf, Vec<PathBuf>)> {
+    if files.len() < 2 {
+        return None;
+    }
+    let mut members = Vec::new();
+    let mut shared_archive = None;
+    for file in files {
+        let (archive, member) = split_archive_path(file)?;
+        if !is_compressed_tar(&archive) || shared_archive.get_or_insert(archive.clone()) != &archive
+        {
+            return None;
         }
+        members.push(member);
     }
-    Ok(kmer_counter)
+    shared_archive.map(|archive| (archive, members))
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub fn scan_reads(
-    mut vect_files: Vec<PathBuf>,
-    barcodes: HashMap<String, String>,
+    vect_files: Vec<PathBuf>,
+    barcodes: &BarcodeIndex,
     k_size: &u8,
     kmer_limit: Option<u64>,
     genome_size: u64,
-) -> (HashMap<String, i32>, u32, String) {
+    matching: Option<&KmerMatching>,
+    visitor: Option<&mut dyn ReadVisitor>,
+) -> Analysis {
+    scan_readers(
+        get_readers(vect_files),
+        barcodes,
+        k_size,
+        kmer_limit,
+        genome_size,
+        matching,
+        visitor,
+    )
+}
+
+// numbers of reads and bases of the FASTQ files of a sample, counted until both minimums are
+// reached (None if the files can't be parsed, the error being then reported by the scan)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn count_reads(vect_files: &[PathBuf], min_reads: u64, min_bases: u64) -> Option<(u64, u64)> {
+    let (mut nb_reads, mut nb_bases) = (0, 0);
+    for path in vect_files {
+        let mut reader = Reader::new(get_reader(path));
+        while let Some(record) = reader.next() {
+            nb_reads += 1;
+            nb_bases += record.ok()?.seq().len() as u64;
+            if nb_reads >= min_reads && nb_bases >= min_bases {
+                return Some((nb_reads, nb_bases));
+            }
+        }
+    }
+    Some((nb_reads, nb_bases))
+}
+
+// number of reads whose names are compared in the two files of paired samples
+pub const NB_MATES_CHECKED: usize = 100;
+
+// first reads of the two files of a paired sample whose names don't correspond (e.g. files of
+// different samples sharing a prefix), among the first reads of the files (None if the names
+// correspond, or if the files can't be parsed, the error being then reported by the scan)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn mismatched_mates(files: &[PathBuf]) -> Option<(String, String)> {
+    let mut files = files.to_vec();
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    let [file_1, file_2] = &files[..] else {
+        return None;
+    };
+    let (mut reader_1, mut reader_2) = (
+        Reader::new(get_reader(file_1)),
+        Reader::new(get_reader(file_2)),
+    );
+    for _ in 0..NB_MATES_CHECKED {
+        let (Some(Ok(read_1)), Some(Ok(read_2))) = (reader_1.next(), reader_2.next()) else {
+            return None;
+        };
+        let (id_1, id_2) = (read_1.id().ok()?, read_2.id().ok()?);
+        if mate_name(id_1) != mate_name(id_2) {
+            return Some((id_1.to_string(), id_2.to_string()));
+        }
+    }
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn mate_name(id: &str) -> &str {
+    // name shared by the mates: read ID without its mate number ('/1', '.1' or '_1')
+    ["/1", "/2", ".1", ".2", "_1", "_2"]
+        .iter()
+        .find_map(|suffix| id.strip_suffix(suffix))
+        .unwrap_or(id)
+}
+
+pub fn scan_readers(
+    readers: Vec<NamedReader>,
+    barcodes: &BarcodeIndex,
+    k_size: &u8,
+    kmer_limit: Option<u64>,
+    genome_size: u64,
+    matching: Option<&KmerMatching>,
+    mut visitor: Option<&mut dyn ReadVisitor>,
+) -> Analysis {
     // initialise kmer size
     let k = *k_size as usize;
 
-    // sort vector of paths
-    vect_files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    // each file is counted separately (the kmer limit applies to each file), and the counts
+    // of the files are merged into the analysis of the sample
+    let mut analysis = Analysis::new(barcodes);
+    let mut readers = readers;
+    if let Some(matching) = matching.filter(|matching| matching.mate_overlap) {
+        if let [(_, reader_1), (_, reader_2)] = &mut readers[..] {
+            if is_fastq(reader_1) && is_fastq(reader_2) {
+                match scan_mates(readers, barcodes, k, kmer_limit, matching, visitor) {
+                    Ok(file_analyses) => {
+                        for file_analysis in file_analyses {
+                            analysis.merge(file_analysis);
+                        }
+                    }
+                    Err(err) => {
+                        return Analysis {
+                            error: format!("{:?}", err),
+                            ..Default::default()
+                        };
+                    }
+                }
+                return finish_scan(analysis, k, genome_size, Some(matching));
+            }
+        }
+    }
+    for (file_name, reader) in readers {
+        let visitor = match visitor.as_mut() {
+            Some(visitor) => {
+                visitor.start_file(&file_name);
+                Some(&mut **visitor as &mut dyn ReadVisitor)
+            }
+            None => None,
+        };
+        match scan_file(
+            file_name, reader, barcodes, k, kmer_limit, matching, visitor,
+        ) {
+            Ok(file_analysis) => analysis.merge(file_analysis),
+            Err(err) => {
+                return Analysis {
+                    error: format!("{:?}", err),
+                    ..Default::default()
+                };
+            }
+        }
+    }
+    finish_scan(analysis, k, genome_size, matching)
+}
 
-    let mut result_barcodes: HashMap<String, i32> = HashMap::new();
-    let mut kmer_counter: u64 = 0;
+// coverage, error rate and capped counts of the analysis of a sample once its files are scanned
+fn finish_scan(
+    mut analysis: Analysis,
+    k: usize,
+    genome_size: u64,
+    matching: Option<&KmerMatching>,
+) -> Analysis {
+    analysis.matching = matching.cloned().unwrap_or_default();
+    analysis.compute_coverage(genome_size);
+    analysis.compute_error_rate(k);
+    if let Some(max_count) = analysis.matching.max_count {
+        analysis.cap_counts(max_count);
+    }
+
+    analysis
+}
+
+// whether a reader starts with a FASTQ record (false for FASTA files and files that can't be
+// read, whose error is reported when they are scanned)
+fn is_fastq(reader: &mut Box<dyn BufRead + Send>) -> bool {
+    matches!(reader.fill_buf(), Ok(buffer) if buffer.first() == Some(&b'@'))
+}
 
-    for filename in vect_files {
-        // set the reader
-        let reader = Reader::new(get_reader(&filename));
-        match process_buffer(k, kmer_limit, &barcodes, &mut result_barcodes, reader) {
-            Ok(kmer_count) => {
-                kmer_counter += kmer_count;
+// barcode counts of the two FASTQ files of a paired sample, read in step so that the barcodes
+// found in both reads of a pair are counted once as the pairs are read (--mate-overlap), the
+// barcodes of each read being dropped once compared with those of its mate
+fn scan_mates(
+    readers: Vec<NamedReader>,
+    barcodes: &BarcodeIndex,
+    k: usize,
+    kmer_limit: Option<u64>,
+    matching: &KmerMatching,
+    mut visitor: Option<&mut dyn ReadVisitor>,
+) -> Result<Vec<Analysis>, String> {
+    let (file_names, readers): (Vec<String>, Vec<_>) = readers.into_iter().unzip();
+    let mut readers: Vec<_> = readers.into_iter().map(Reader::new).collect();
+    let mut analyses: Vec<Analysis> = file_names
+        .iter()
+        .map(|_| {
+            let mut analysis = Analysis::new(barcodes);
+            analysis.matching = matching.clone();
+            analysis
+        })
+        .collect();
+
+    // numbers of kmers and reads extracted from each file, until the file ends or the kmer
+    // limit is reached
+    let mut counters = [(0, 0); 2];
+    let mut scanning = [true; 2];
+    while scanning.contains(&true) {
+        for mate in 0..2 {
+            if !scanning[mate] {
+                continue;
             }
-            Err(err) => {
-                return (HashMap::new(), 0, format!("{:?}", err));
+            let Some(record) = readers[mate].next() else {
+                scanning[mate] = false;
+                continue;
+            };
+            let record =
+                record.map_err(|err| format!("Error in file: {}", fastq_error_message(err)))?;
+            if let Some(visitor) = visitor.as_deref_mut() {
+                visitor.start_file(&file_names[mate]);
             }
+            let (nb_kmers, nb_reads) = &mut counters[mate];
+            *nb_reads += 1;
+            *nb_kmers += process_record(
+                k,
+                barcodes,
+                &mut analyses[mate],
+                &record,
+                visitor.as_deref_mut(),
+            );
+            if kmer_limit.is_some_and(|max_kmers| *nb_kmers > max_kmers) {
+                scanning[mate] = false;
+            }
+        }
+        // (reads without mate once one of the files is complete)
+        let [mate_1, mate_2] = &mut analyses[..] else {
+            unreachable!()
+        };
+        let hits_2 = std::mem::take(&mut mate_2.read_barcodes);
+        mate_2.remove_mate_overlaps(&mate_1.read_barcodes, &hits_2);
+        mate_1.read_barcodes.clear();
+        mate_2.read_barcodes = hits_2;
+        mate_2.read_barcodes.clear();
+    }
+
+    for ((analysis, file_name), (nb_kmers, nb_reads)) in
+        analyses.iter_mut().zip(file_names).zip(counters)
+    {
+        analysis.files.push(FileStats {
+            file_name,
+            nb_reads,
+            nb_kmers,
+            complete: kmer_limit.is_none_or(|max_kmers| nb_kmers <= max_kmers),
+            coverage: 0,
+        });
+    }
+    Ok(analyses)
+}
+
+// barcode counts of a single input file
+fn scan_file(
+    file_name: String,
+    mut reader: Box<dyn BufRead + Send>,
+    barcodes: &BarcodeIndex,
+    k: usize,
+    kmer_limit: Option<u64>,
+    matching: Option<&KmerMatching>,
+    visitor: Option<&mut dyn ReadVisitor>,
+) -> Result<Analysis, String> {
+    let mut analysis = Analysis::new(barcodes);
+    analysis.matching = matching.cloned().unwrap_or_default();
+    // (the mates of paired samples are only compared when both files are scanned in step)
+    analysis.matching.mate_overlap = false;
+
+    // FASTA (assemblies) or FASTQ (reads), depending on the first character (the error of a
+    // file that can't be read is reported here, as the decompression may not repeat it)
+    let is_fasta = match reader.fill_buf() {
+        Ok(buffer) => buffer.first() == Some(&b'>'),
+        Err(err) => return Err(format!("Error in file: {}", io_error_message(err))),
+    };
+    let (nb_kmers, nb_reads) = if is_fasta {
+        let reader = fasta::Reader::new(reader);
+        process_fasta_buffer(k, barcodes, &mut analysis, reader, visitor)?
+    } else {
+        let reader = Reader::new(reader);
+        process_buffer(k, kmer_limit, barcodes, &mut analysis, reader, visitor)?
+    };
+    analysis.files.push(FileStats {
+        file_name,
+        nb_reads,
+        nb_kmers,
+        complete: is_fasta || kmer_limit.is_none_or(|max_kmers| nb_kmers <= max_kmers),
+        coverage: 0,
+    });
+    Ok(analysis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_analysis(counts: Vec<u32>, read_lengths: &[(usize, u64)], error: &str) -> Analysis {
+        let barcode_ids = (0..counts.len()).map(|n| format!("4.1__{}", n)).collect();
+        Analysis {
+            ref_counts: vec![1; counts.len()],
+            counts,
+            barcode_ids: Arc::new(barcode_ids),
+            kmer_count: 100,
+            read_lengths: read_lengths.iter().copied().collect(),
+            files: vec![FileStats {
+                file_name: "reads.fq".to_string(),
+                nb_reads: 10,
+                ..Default::default()
+            }],
+            error: error.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_adds_the_counts_of_the_files() {
+        let mut analysis = Analysis::default();
+        analysis.merge(file_analysis(vec![1, 0, 3], &[(100, 4)], ""));
+        analysis.merge(file_analysis(vec![2, 5, 0], &[(100, 1), (150, 2)], ""));
+        assert_eq!(analysis.counts, vec![3, 5, 3]);
+        assert_eq!(analysis.ref_counts, vec![2, 2, 2]);
+        assert_eq!(analysis.barcode_ids.len(), 3);
+        assert_eq!(analysis.kmer_count, 200);
+        assert_eq!(analysis.read_lengths, BTreeMap::from([(100, 5), (150, 2)]));
+        assert_eq!(analysis.files.len(), 2);
+    }
+
+    #[test]
+    fn merge_keeps_the_first_error() {
+        let mut analysis = Analysis::default();
+        analysis.merge(file_analysis(vec![1], &[], ""));
+        analysis.merge(file_analysis(vec![1], &[], "first"));
+        analysis.merge(file_analysis(vec![1], &[], "second"));
+        assert_eq!(analysis.error, "first");
+    }
+
+    #[test]
+    fn encoded_kmers_are_counted_like_sequence_slices() {
+        let kmers = HashMap::from([
+            ("ACGTACG".to_string(), 0),
+            ("TTTTCCC".to_string(), 1),
+            ("GATTACA".to_string(), 0),
+        ]);
+        let barcodes = BarcodeIndex::new(kmers, vec!["1__a".to_string(), "2__b".to_string()]);
+        let matching = KmerMatching::new(&barcodes, 7, None, false);
+        assert!(matching.encoded.is_some());
+
+        // (kmers across Ns and lowercase bases, and across the blocks of encoded bases)
+        let mut seq = b"ACGTACGNGATTACATTTTCCCacgtacgTTTTCCC".to_vec();
+        seq.extend(b"GATTACA".repeat(200));
+        let mut encoded = Analysis::new(&barcodes);
+        encoded.matching = matching;
+        let mut sliced = Analysis::new(&barcodes);
+        for analysis in [&mut encoded, &mut sliced] {
+            assert_eq!(
+                analysis.process_sequence(&seq, &barcodes, 7),
+                seq.len() as u64 - 6
+            );
         }
+        assert_eq!(encoded.counts, vec![202, 2]);
+        assert_eq!(encoded.counts, sliced.counts);
+    }
+
+    #[test]
+    fn read_lengths_are_the_ones_before_trimming() {
+        let barcodes = BarcodeIndex::new(HashMap::new(), Vec::new());
+        let mut analysis = Analysis::new(&barcodes);
+        assert_eq!(
+            analysis.process_trimmed_sequence(b"ACGTACGTAC", 150, &barcodes, 7),
+            4
+        );
+        assert_eq!(
+            analysis.process_trimmed_sequence(b"ACGT", 150, &barcodes, 7),
+            0
+        );
+        assert_eq!(analysis.process_sequence(b"ACGTACGT", &barcodes, 7), 2);
+        assert_eq!(analysis.read_lengths, BTreeMap::from([(8, 1), (150, 2)]));
+        assert_eq!(analysis.read_length_stats(), (308.0 / 3.0, 150));
+        // (L - k kmers per sequence of at least k bases once trimmed)
+        assert_eq!(analysis.legacy_coverage(1), 4);
     }
-    // compute kmer coverage
-    let coverage = (kmer_counter as f64 / genome_size as f64).round() as u32;
 
-    (result_barcodes, coverage, "".to_string())
+    #[test]
+    fn barcodes_of_overlapping_mates_are_counted_once() {
+        let kmers = HashMap::from([("ACGTACG".to_string(), 0), ("GATTACA".to_string(), 1)]);
+        let barcodes = BarcodeIndex::new(kmers, vec!["1__a".to_string(), "2__b".to_string()]);
+        let mut matching = KmerMatching::new(&barcodes, 7, None, false);
+        matching.mate_overlap = true;
+        // (both barcodes in the two mates of the first pair, the second barcode only in the
+        // first mate of the second pair, and a third read without mate)
+        let fastq = |reads: &[&str]| {
+            let records: String = reads
+                .iter()
+                .enumerate()
+                .map(|(n, seq)| format!("@r{}\n{}\n+\n{}\n", n, seq, "I".repeat(seq.len())))
+                .collect();
+            get_reader_from_bytes(records.into_bytes())
+        };
+        let readers = vec![
+            (
+                "s_1.fq".to_string(),
+                fastq(&["ACGTACGTTGATTACA", "GATTACATT", "ACGTACG"]),
+            ),
+            ("s_2.fq".to_string(), fastq(&["GATTACAACGTACG", "TTTTTTTT"])),
+        ];
+        let analysis = scan_readers(readers, &barcodes, &7, None, 1, Some(&matching), None);
+        assert_eq!(analysis.error, "");
+        assert_eq!(analysis.counts, vec![2, 2]);
+        assert!(analysis.read_barcodes.is_empty());
+        assert_eq!(analysis.files[0].nb_reads, 3);
+        assert_eq!(analysis.files[1].nb_reads, 2);
+
+        // (without --mate-overlap)
+        matching.mate_overlap = false;
+        let readers = vec![
+            ("s_1.fq".to_string(), fastq(&["ACGTACGTTGATTACA"])),
+            ("s_2.fq".to_string(), fastq(&["GATTACAACGTACG"])),
+        ];
+        let analysis = scan_readers(readers, &barcodes, &7, None, 1, Some(&matching), None);
+        assert_eq!(analysis.counts, vec![2, 2]);
+    }
+
+    #[test]
+    fn legacy_errors_drop_the_truncated_input_note() {
+        assert_eq!(
+            legacy_error("\"Error in file: truncated input (incomplete deflate stream)\""),
+            "\"Error in file: incomplete deflate stream\""
+        );
+        assert_eq!(
+            legacy_error("\"Error in file: permission denied\""),
+            "\"Error in file: permission denied\""
+        );
+        assert_eq!(legacy_error(""), "");
+    }
+
+    #[test]
+    fn mates_are_paired_by_their_read_names() {
+        let dir = std::env::temp_dir().join(format!("fastlin_mates_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, ids: &[&str]| -> PathBuf {
+            let path = dir.join(name);
+            let records: String = ids
+                .iter()
+                .map(|id| format!("@{}\nACGT\n+\nIIII\n", id))
+                .collect();
+            std::fs::write(&path, records).unwrap();
+            path
+        };
+        let r1 = write("s_1.fastq", &["r1/1", "r2.1", "r3_1"]);
+        let r2 = write("s_2.fastq", &["r1/2", "r2.2", "r3_2"]);
+        let other = write("t_2.fastq", &["r1/2", "x2.2", "r3_2"]);
+
+        // (whatever the order of the files)
+        assert_eq!(mismatched_mates(&[r2.clone(), r1.clone()]), None);
+        assert_eq!(
+            mismatched_mates(&[r1.clone(), other]),
+            Some(("r2.1".to_string(), "x2.2".to_string()))
+        );
+        assert_eq!(mismatched_mates(&[r1]), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
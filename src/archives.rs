@@ -0,0 +1,491 @@
+use flate2::read::MultiGzDecoder;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+
+use crate::input_files::is_fasta_file;
+
+// extensions of the archives whose members can be used as input files (matched
+// case-insensitively); members are given as virtual paths such as run.tar.gz/sample_1.fq.gz
+pub const TAR_EXTENSIONS: [&str; 3] = [".tar", ".tar.gz", ".tgz"];
+pub const ZIP_EXTENSION: &str = ".zip";
+
+// size of the chunks of data sent by the thread reading an archive
+const CHUNK_SIZE: usize = 1 << 16;
+
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    TAR_EXTENSIONS.iter().any(|ext| name.ends_with(ext)) || is_zip(path)
+}
+
+fn is_zip(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    name.ends_with(ZIP_EXTENSION)
+}
+
+pub fn list_archive(path: &Path) -> io::Result<Vec<PathBuf>> {
+    // virtual paths of the input files (FASTQ or FASTA) stored in the archive
+    let mut members = vec![];
+    if is_zip(path) {
+        let archive = zip::ZipArchive::new(File::open(path)?)?;
+        for name in archive.file_names() {
+            let member = PathBuf::from(name);
+            if !name.ends_with('/') && is_input_file(&member) {
+                members.push(member);
+            }
+        }
+    } else if is_compressed_tar(path) {
+        let mut archive = tar::Archive::new(open_archive(path)?);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let member = entry.path()?.to_path_buf();
+            if entry.header().entry_type().is_file() && is_input_file(&member) {
+                members.push(member);
+            }
+        }
+    } else {
+        // (only the headers are read, the data of the members being skipped)
+        members.extend(
+            tar_members(path)?
+                .into_iter()
+                .filter(|(member, _, _)| is_input_file(member))
+                .map(|(member, _, _)| member),
+        );
+    }
+    members.sort();
+    Ok(members.iter().map(|member| path.join(member)).collect())
+}
+
+fn is_input_file(member: &Path) -> bool {
+    // ORA files can only be decompressed from disk
+    #[cfg(feature = "ora")]
+    if crate::ora::is_ora(member) {
+        return false;
+    }
+    is_fasta_file(member).is_some()
+}
+
+pub fn split_archive_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    // archive and member of a virtual path (None for regular files)
+    if path.is_file() {
+        return None;
+    }
+    let archive = path
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| is_archive(ancestor) && ancestor.is_file())?;
+    let member = path.strip_prefix(archive).ok()?;
+    Some((archive.to_path_buf(), member.to_path_buf()))
+}
+
+pub fn open_member(archive: &Path, member: &Path) -> Box<dyn BufRead + Send> {
+    member_reader(member, member_bytes(archive, member))
+}
+
+// reader of the bytes of a member, decompressed if it is gzipped
+fn member_reader(member: &Path, reader: impl Read + Send + 'static) -> Box<dyn BufRead + Send> {
+    if member
+        .to_string_lossy()
+        .to_ascii_lowercase()
+        .ends_with(".gz")
+    {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(BufReader::new(reader))
+    }
+}
+
+// members of a compressed tar archive read one after another (e.g. the files of a paired
+// sample), streamed in a single pass over the archive: the members met before their turn are
+// spilled to temporary files until they are read
+pub fn open_members(archive: &Path, members: &[PathBuf]) -> Vec<Box<dyn BufRead + Send>> {
+    let (senders, receivers): (Vec<_>, Vec<_>) = members.iter().map(|_| sync_channel(4)).unzip();
+    let (archive, wanted) = (archive.to_path_buf(), members.to_vec());
+    thread::spawn(move || {
+        let mut next = 0;
+        if let Err(err) = stream_members(&archive, &wanted, &senders, &mut next) {
+            // (the members not sent yet fail with the error of the archive)
+            for sender in &senders[next..] {
+                let _ = sender.send(Err(io::Error::new(err.kind(), err.to_string())));
+            }
+        }
+    });
+    members
+        .iter()
+        .zip(receivers)
+        .map(|(member, receiver)| member_reader(member, ChannelReader::new(receiver)))
+        .collect()
+}
+
+fn stream_members(
+    archive: &Path,
+    members: &[PathBuf],
+    senders: &[SyncSender<io::Result<Vec<u8>>>],
+    next: &mut usize,
+) -> io::Result<()> {
+    let mut spilled = SpilledMembers::default();
+    let mut archive = tar::Archive::new(open_archive(archive)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let Some(index) = (*next..members.len())
+            .find(|index| members[*index] == path && !spilled.0.contains_key(index))
+        else {
+            continue;
+        };
+        if index > *next {
+            spilled.add(index, &mut entry)?;
+            continue;
+        }
+        send_chunks(&mut entry, &senders[index])?;
+        *next += 1;
+        // (members spilled while waiting for this one)
+        while spilled.0.contains_key(next) {
+            spilled.send(*next, &senders[*next])?;
+            *next += 1;
+        }
+    }
+    while *next < members.len() {
+        match spilled.0.contains_key(next) {
+            true => spilled.send(*next, &senders[*next])?,
+            false => {
+                let _ = senders[*next].send(Err(member_not_found(&members[*next])));
+            }
+        }
+        *next += 1;
+    }
+    Ok(())
+}
+
+// temporary files of the members met before their turn (removed once sent, or dropped)
+#[derive(Default)]
+struct SpilledMembers(HashMap<usize, PathBuf>);
+
+impl SpilledMembers {
+    fn add(&mut self, index: usize, reader: &mut impl Read) -> io::Result<()> {
+        static NB_SPILLED: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "fastlin_{}_{}.member",
+            std::process::id(),
+            NB_SPILLED.fetch_add(1, Ordering::Relaxed)
+        ));
+        self.0.insert(index, path.clone());
+        io::copy(reader, &mut File::create(&path)?)?;
+        Ok(())
+    }
+
+    fn send(&mut self, index: usize, sender: &SyncSender<io::Result<Vec<u8>>>) -> io::Result<()> {
+        let path = self.0.remove(&index).expect("member not spilled");
+        let result =
+            File::open(&path).and_then(|file| send_chunks(&mut BufReader::new(file), sender));
+        let _ = fs::remove_file(&path);
+        result
+    }
+}
+
+impl Drop for SpilledMembers {
+    fn drop(&mut self) {
+        for path in self.0.values() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// raw bytes of a member (not decompressed)
+pub fn member_bytes(archive: &Path, member: &Path) -> impl Read + Send {
+    // the member is streamed by a separate thread, as archive entries borrow their archive
+    let (sender, receiver) = sync_channel(4);
+    let (archive, member) = (archive.to_path_buf(), member.to_path_buf());
+    thread::spawn(move || {
+        if let Err(err) = stream_member(&archive, &member, &sender) {
+            let _ = sender.send(Err(err));
+        }
+    });
+    ChannelReader::new(receiver)
+}
+
+pub fn is_compressed_tar(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+// decompressed stream of a compressed tar archive
+fn open_archive(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    Ok(Box::new(MultiGzDecoder::new(BufReader::new(file))))
+}
+
+// regular files of an uncompressed tar archive, with the position and size of their data (the
+// headers being read one after another by seeking over the data)
+fn tar_members(path: &Path) -> io::Result<Vec<(PathBuf, u64, u64)>> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut members = vec![];
+    for entry in archive.entries_with_seek()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() {
+            members.push((
+                entry.path()?.to_path_buf(),
+                entry.raw_file_position(),
+                entry.size(),
+            ));
+        }
+    }
+    Ok(members)
+}
+
+fn stream_member(
+    archive: &Path,
+    member: &Path,
+    sender: &SyncSender<io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    if is_zip(archive) {
+        let mut archive = zip::ZipArchive::new(File::open(archive)?)?;
+        let mut entry = archive.by_name(&member.to_string_lossy())?;
+        return send_chunks(&mut entry, sender);
+    }
+    // uncompressed tar archives: the data of the member is read from its position
+    if !is_compressed_tar(archive) {
+        let position = tar_members(archive)?
+            .into_iter()
+            .find(|(path, _, _)| path == member);
+        if let Some((_, offset, size)) = position {
+            let mut file = File::open(archive)?;
+            file.seek(SeekFrom::Start(offset))?;
+            return send_chunks(&mut BufReader::new(file).take(size), sender);
+        }
+        return Err(member_not_found(member));
+    }
+    // (compressed tar archives can't be seeked: they are decompressed up to the member)
+    let mut archive = tar::Archive::new(open_archive(archive)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()? == member {
+            return send_chunks(&mut entry, sender);
+        }
+    }
+    Err(member_not_found(member))
+}
+
+fn member_not_found(member: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found in the archive", member.display()),
+    )
+}
+
+pub(crate) fn send_chunks(
+    reader: &mut impl Read,
+    sender: &SyncSender<io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let nb_bytes = reader.read(&mut chunk)?;
+        if nb_bytes == 0 {
+            return Ok(());
+        }
+        chunk.truncate(nb_bytes);
+        // the receiver is dropped when the scan stops early (e.g. kmer limit reached)
+        if sender.send(Ok(chunk)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+// reads the chunks sent by the thread streaming an archive member (or decompressing a file)
+pub(crate) struct ChannelReader {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    pub(crate) fn new(receiver: Receiver<io::Result<Vec<u8>>>) -> Self {
+        ChannelReader {
+            receiver,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Ok(Err(err)) => return Err(err),
+                // end of the member
+                Err(_) => return Ok(0),
+            }
+        }
+        let nb_bytes = buf.len().min(self.chunk.len() - self.pos);
+        buf[..nb_bytes].copy_from_slice(&self.chunk[self.pos..self.pos + nb_bytes]);
+        self.pos += nb_bytes;
+        Ok(nb_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const MEMBERS: [(&str, &str); 3] = [
+        ("run/s1_2.fastq.gz", "@r2\nACGT\n+\nIIII\n"),
+        ("run/notes.txt.gz", "notes\n"),
+        ("run/s1_1.fastq.gz", "@r1\nTTTT\n+\nIIII\n"),
+    ];
+
+    // member content gzipped like the input files
+    fn gzip(content: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // archive written in a directory of its own (removed by the test)
+    fn write_archive(name: &str) -> (PathBuf, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("fastlin_archives_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let file = File::create(&path).unwrap();
+        let writer: Box<dyn Write> = match name.ends_with(".gz") {
+            true => Box::new(GzEncoder::new(file, Compression::fast())),
+            false => Box::new(file),
+        };
+        let mut builder = tar::Builder::new(writer);
+        for (member, content) in MEMBERS {
+            let content = gzip(content);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, member, content.as_slice())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().flush().unwrap();
+        (dir, path)
+    }
+
+    fn read_all(mut reader: impl Read) -> String {
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn archive_paths() {
+        let (dir, archive) = write_archive("reads.tar");
+        assert!(is_archive(&archive));
+        assert!(is_archive(Path::new("reads.TGZ")));
+        assert!(!is_archive(Path::new("reads.fastq.gz")));
+        let members = list_archive(&archive).unwrap();
+        assert_eq!(
+            members,
+            vec![
+                archive.join("run/s1_1.fastq.gz"),
+                archive.join("run/s1_2.fastq.gz")
+            ]
+        );
+        assert_eq!(
+            split_archive_path(&members[0]),
+            Some((archive.clone(), PathBuf::from("run/s1_1.fastq.gz")))
+        );
+        assert_eq!(split_archive_path(&archive), None);
+        assert_eq!(split_archive_path(&dir.join("missing.fastq.gz")), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn members_are_read_from_the_archives() {
+        for name in ["members.tar", "members.tar.gz"] {
+            let (dir, archive) = write_archive(name);
+            for (member, content) in MEMBERS {
+                assert_eq!(read_all(open_member(&archive, Path::new(member))), content);
+            }
+            let missing = open_member(&archive, Path::new("run/s2_1.fastq.gz"));
+            assert!(missing.bytes().next().unwrap().is_err());
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn members_are_read_from_zip_archives() {
+        let dir = std::env::temp_dir().join(format!("fastlin_archives_{}_zip", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("reads.ZIP");
+        let mut writer = zip::ZipWriter::new(File::create(&archive).unwrap());
+        for (member, content) in MEMBERS {
+            let options = zip::write::SimpleFileOptions::default();
+            writer.start_file(member, options).unwrap();
+            writer.write_all(&gzip(content)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert!(is_archive(&archive));
+        assert_eq!(
+            list_archive(&archive).unwrap(),
+            vec![
+                archive.join("run/s1_1.fastq.gz"),
+                archive.join("run/s1_2.fastq.gz")
+            ]
+        );
+        for (member, content) in MEMBERS {
+            assert_eq!(read_all(open_member(&archive, Path::new(member))), content);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn members_of_uncompressed_archives_are_read_from_their_position() {
+        let (dir, archive) = write_archive("seek.tar");
+        let members = tar_members(&archive).unwrap();
+        assert_eq!(members.len(), 3);
+        for ((member, position, _), (name, _)) in members.iter().zip(MEMBERS) {
+            // (data of the members after their 512-byte headers)
+            assert_eq!(member, Path::new(name));
+            assert_eq!(position % 512, 0);
+        }
+        for (name, content) in MEMBERS {
+            assert_eq!(read_all(open_member(&archive, Path::new(name))), content);
+        }
+        let missing = open_member(&archive, Path::new("run/s2_1.fastq.gz"));
+        assert!(missing.bytes().next().unwrap().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn members_of_compressed_archives_are_read_in_any_order() {
+        let (dir, archive) = write_archive("stream.tar.gz");
+        assert_eq!(list_archive(&archive).unwrap().len(), 2);
+        // (the first member is met after the second one, which is spilled until it is read)
+        let members = [
+            PathBuf::from("run/s1_1.fastq.gz"),
+            PathBuf::from("run/s1_2.fastq.gz"),
+        ];
+        let contents: Vec<String> = open_members(&archive, &members)
+            .into_iter()
+            .map(read_all)
+            .collect();
+        assert_eq!(contents, [MEMBERS[2].1, MEMBERS[0].1]);
+        let members = [
+            PathBuf::from("run/s1_1.fastq.gz"),
+            PathBuf::from("run/missing.fastq.gz"),
+        ];
+        let mut readers = open_members(&archive, &members).into_iter();
+        assert_eq!(read_all(readers.next().unwrap()), MEMBERS[2].1);
+        assert!(readers.next().unwrap().bytes().next().unwrap().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
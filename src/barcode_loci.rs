@@ -0,0 +1,259 @@
+use crate::process_barcodes::barcode_lineage;
+
+// chromosome of the loci of barcode files giving positions without chromosome
+pub const DEFAULT_CHROM: &str = "genome";
+
+// genomic coordinates of the SNP of a barcode (1-based position on a sequence of the
+// reference) with its reference allele (if known) and the allele of the barcode, from the
+// optional position, chrom and ref columns of the barcode file
+#[derive(Clone, Debug, PartialEq)]
+pub struct BarcodeLocus {
+    pub chrom: String,
+    pub position: u64,
+    pub ref_allele: Option<char>,
+    pub allele: char,
+}
+
+// locus of a position field ('761155', or 'NC_000962.3:761155' with its chromosome), of
+// optional chrom and ref fields and of the allele of the barcode (None for an empty position)
+pub fn parse_locus(
+    position: &str,
+    chrom: Option<&str>,
+    ref_allele: Option<&str>,
+    allele: &str,
+) -> Result<Option<BarcodeLocus>, &'static str> {
+    let position = position.trim();
+    if position.is_empty() {
+        return Ok(None);
+    }
+    let (prefix, position) = match position.rsplit_once(':') {
+        Some((prefix, position)) => (Some(prefix), position),
+        None => (None, position),
+    };
+    let chrom = chrom
+        .map(str::trim)
+        .filter(|chrom| !chrom.is_empty())
+        .or(prefix)
+        .unwrap_or(DEFAULT_CHROM);
+    let position = match position.parse::<u64>() {
+        Ok(position) if position > 0 => position,
+        _ => return Err("the position should be a positive number"),
+    };
+    let ref_allele = match ref_allele.map(str::trim).unwrap_or_default() {
+        "" => None,
+        base if base.len() == 1 && "ACGTacgt".contains(base) => {
+            base.chars().next().map(|base| base.to_ascii_uppercase())
+        }
+        _ => return Err("the reference allele should be a single base (A, C, G or T)"),
+    };
+    Ok(Some(BarcodeLocus {
+        chrom: chrom.to_string(),
+        position,
+        ref_allele,
+        allele: allele
+            .trim()
+            .chars()
+            .next()
+            .unwrap_or('N')
+            .to_ascii_uppercase(),
+    }))
+}
+
+// loci of the barcodes found in a sample, with their ID and count, sorted by chromosome and
+// position
+fn found_loci<'a>(
+    counts: &[u32],
+    ids: &'a [String],
+    loci: &'a [Option<BarcodeLocus>],
+) -> Vec<(&'a BarcodeLocus, &'a str, u32)> {
+    let mut found: Vec<(&BarcodeLocus, &str, u32)> = counts
+        .iter()
+        .zip(ids)
+        .zip(loci)
+        .filter(|((count, _), _)| **count > 0)
+        .filter_map(|((count, id), locus)| Some((locus.as_ref()?, id.as_str(), *count)))
+        .collect();
+    found.sort_by(|a, b| (&a.0.chrom, a.0.position).cmp(&(&b.0.chrom, b.0.position)));
+    found
+}
+
+// BED file of the loci of the barcodes found in a sample (with their count in the name, and
+// as the score capped to 1000)
+pub fn format_bed(
+    sample: &str,
+    counts: &[u32],
+    ids: &[String],
+    loci: &[Option<BarcodeLocus>],
+) -> String {
+    let mut bed = format!(
+        "track name=\"{}\" description=\"fastlin barcodes of {}\" useScore=1\n",
+        sample, sample
+    );
+    for (locus, id, count) in found_loci(counts, ids, loci) {
+        bed.push_str(&format!(
+            "{}\t{}\t{}\t{}:{}x\t{}\t.\n",
+            locus.chrom,
+            locus.position - 1,
+            locus.position,
+            id,
+            count,
+            count.min(1000)
+        ));
+    }
+    bed
+}
+
+// VCF of the lineage-defining SNPs of the barcodes found in a sample (the kmers of the
+// barcode found as depth, and barcodes found fewer than min_count times filtered), for the
+// barcodes whose locus has a reference allele
+pub fn format_vcf(
+    sample: &str,
+    counts: &[u32],
+    ids: &[String],
+    loci: &[Option<BarcodeLocus>],
+    min_count: u32,
+) -> String {
+    let found: Vec<(&BarcodeLocus, &str, u32)> = found_loci(counts, ids, loci)
+        .into_iter()
+        .filter(|(locus, _, _)| locus.ref_allele.is_some_and(|base| base != locus.allele))
+        .filter(|(locus, _, _)| "ACGT".contains(locus.allele))
+        .collect();
+    let mut chroms: Vec<&str> = found
+        .iter()
+        .map(|(locus, _, _)| locus.chrom.as_str())
+        .collect();
+    chroms.dedup();
+
+    let mut vcf = format!(
+        "##fileformat=VCFv4.2\n##source=fastlin {}\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    for chrom in chroms {
+        vcf.push_str(&format!("##contig=<ID={}>\n", chrom));
+    }
+    vcf.push_str(&format!(
+        "##FILTER=<ID=LowCount,Description=\"Barcode found fewer than {} times\">\n",
+        min_count
+    ));
+    vcf.push_str(
+        "##INFO=<ID=LIN,Number=1,Type=String,Description=\"Lineage defined by the SNP\">\n",
+    );
+    vcf.push_str("##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n");
+    vcf.push_str(
+        "##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Number of kmers of the barcode found\">\n",
+    );
+    vcf.push_str(&format!(
+        "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}\n",
+        sample
+    ));
+    for (locus, id, count) in found {
+        vcf.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t.\t{}\tLIN={}\tGT:DP\t1:{}\n",
+            locus.chrom,
+            locus.position,
+            id,
+            locus.ref_allele.unwrap_or('N'),
+            locus.allele,
+            if count >= min_count {
+                "PASS"
+            } else {
+                "LowCount"
+            },
+            barcode_lineage(id),
+            count
+        ));
+    }
+    vcf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::barcodes::Barcodes;
+
+    #[test]
+    fn positions_are_parsed_with_their_chromosome() {
+        let locus = |chrom: &str, position, ref_allele| {
+            Some(BarcodeLocus {
+                chrom: chrom.to_string(),
+                position,
+                ref_allele,
+                allele: 'T',
+            })
+        };
+        assert_eq!(
+            parse_locus("761155", None, None, "t"),
+            Ok(locus(DEFAULT_CHROM, 761155, None))
+        );
+        assert_eq!(
+            parse_locus("NC_000962.3:761155", None, Some("g"), "T"),
+            Ok(locus("NC_000962.3", 761155, Some('G')))
+        );
+        // (the chrom column prevails over the prefix of the position)
+        assert_eq!(
+            parse_locus("NC_1:12", Some("chr"), Some(""), "T"),
+            Ok(locus("chr", 12, None))
+        );
+        assert_eq!(parse_locus(" ", Some("chr"), None, "T"), Ok(None));
+        assert!(parse_locus("0", None, None, "T").is_err());
+        assert!(parse_locus("NC_1:", None, None, "T").is_err());
+        assert!(parse_locus("12", None, Some("GA"), "T").is_err());
+    }
+
+    #[test]
+    fn loci_of_the_barcodes_found_are_written_as_bed() {
+        let file = "genome_size\t1000\n\
+                    lineage\tleft_flank\tallele\tright_flank\tposition\n\
+                    2\tAAAAC\tA\tCTTTT\tNC_000962.3:1200\n\
+                    4\tCCACG\tT\tGGATC\t\n\
+                    4\tTTGCA\tC\tAGGTA\tNC_000962.3:300\n\
+                    4\tGGTCA\tA\tTTCGA\tNC_000962.3:800\n";
+        let barcodes = Barcodes::from_string(file, 11).unwrap();
+        assert_eq!(barcodes.loci.len(), 4);
+        assert_eq!(barcodes.loci[1], None);
+
+        // (sorted by position, barcodes without locus or not found being left out)
+        let bed = format_bed("s1", &[5, 3, 2000, 0], &barcodes.index.ids, &barcodes.loci);
+        assert_eq!(
+            bed,
+            "track name=\"s1\" description=\"fastlin barcodes of s1\" useScore=1\n\
+             NC_000962.3\t299\t300\t4__2:2000x\t1000\t.\n\
+             NC_000962.3\t1199\t1200\t2__0:5x\t5\t.\n"
+        );
+
+        let file = "genome_size\t1000\nlineage\tleft_flank\tallele\tright_flank\tposition\n\
+                    2\tAAAAC\tA\tCTTTT\tabc\n";
+        let err = Barcodes::from_string(file, 11).err().unwrap();
+        assert!(
+            err.contains("line 3: the position should be a positive number"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn lineage_snps_of_the_barcodes_found_are_written_as_vcf() {
+        let file = "genome_size\t1000\n\
+                    lineage\tleft_flank\tallele\tright_flank\tposition\tref\n\
+                    2\tAAAAC\tA\tCTTTT\t1200\tG\n\
+                    4\tCCACG\tT\tGGATC\t300\tT\n\
+                    4\tTTGCA\tC\tAGGTA\t800\ta\n\
+                    4\tGGTCA\tA\tTTCGA\t900\t\n";
+        let barcodes = Barcodes::from_string(file, 11).unwrap();
+        let vcf = format_vcf("s1", &[5, 10, 2, 7], &barcodes.index.ids, &barcodes.loci, 3);
+
+        let meta: Vec<&str> = vcf.lines().filter(|line| line.starts_with("##")).collect();
+        assert_eq!(meta[0], "##fileformat=VCFv4.2");
+        assert!(meta.contains(&"##contig=<ID=genome>"));
+        // (barcodes whose allele is the reference one or without reference allele left out)
+        let records: Vec<&str> = vcf.lines().filter(|line| !line.starts_with("##")).collect();
+        assert_eq!(
+            records,
+            [
+                "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1",
+                "genome\t800\t4__2\tA\tC\t.\tLowCount\tLIN=4\tGT:DP\t1:2",
+                "genome\t1200\t2__0\tG\tA\t.\tPASS\tLIN=2\tGT:DP\t1:5"
+            ]
+        );
+    }
+}
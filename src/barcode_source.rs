@@ -0,0 +1,863 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs::read_to_string, path::Path};
+
+use crate::barcode_loci::{parse_locus, BarcodeLocus};
+use crate::barcodes::{
+    BarcodeIndex, Barcodes, DEFAULT_KMER_SIZE, MAX_IUPAC_EXPANSIONS, MIN_KMER_SIZE,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::binary_index::{is_binary_index, load_index};
+use crate::process_barcodes::barcode_lineage;
+use crate::run_log::warning;
+
+// format of a barcode scheme, loaded into barcodes for a kmer size (by default the one
+// declared by the scheme); the genome size given overrides (or supplies) the one of the scheme
+pub trait BarcodeSource {
+    fn load(&self, kmer_size: Option<u8>, genome_size: Option<u64>) -> Result<Barcodes, String>;
+}
+
+// barcode file: lineage, left flank, SNP allele and right flank of each barcode, by position
+// or named by a header row, with genome_size, version, flank_length and kmer_size lines
+pub struct BarcodeFile<'a>(pub &'a str);
+
+impl BarcodeSource for BarcodeFile<'_> {
+    fn load(&self, kmer_size: Option<u8>, genome_size: Option<u64>) -> Result<Barcodes, String> {
+        let kmer_size = match kmer_size {
+            Some(kmer_size) => kmer_size,
+            None => declared_kmer_size(self.0)?.unwrap_or(DEFAULT_KMER_SIZE),
+        };
+        parse_barcode_file(self.0, kmer_size, genome_size)
+    }
+}
+
+// FASTA of full kmers, with the lineage in the header before an optional '|' (e.g.
+// '>lineage4.2|pos123456'), the SNP being the central base of each kmer
+pub struct FastaScheme<'a>(pub &'a str);
+
+impl BarcodeSource for FastaScheme<'_> {
+    fn load(&self, kmer_size: Option<u8>, genome_size: Option<u64>) -> Result<Barcodes, String> {
+        if genome_size.is_none() {
+            return Err(
+                "FASTA barcode schemes don't declare a genome size: give it with --genome-size"
+                    .to_string(),
+            );
+        }
+        let kmers = fasta_kmers(self.0)?;
+        let kmer_size = kmer_size
+            .or(common_length(kmers.iter().map(|(_, kmer)| kmer.as_str())))
+            .unwrap_or(DEFAULT_KMER_SIZE);
+        // barcode file with a header row, the position given in the FASTA header being kept
+        // as metadata
+        let mut converted = String::from("lineage\tleft_flank\tallele\tright_flank\tposition\n");
+        for (header, kmer) in &kmers {
+            let mut fields = header.split('|');
+            let lineage = fields.next().unwrap_or_default().trim();
+            let position = fields
+                .find_map(|field| field.trim().strip_prefix("pos"))
+                .unwrap_or_default();
+            if lineage.is_empty() {
+                return Err(format!("the FASTA barcode '{}' has no lineage", header));
+            }
+            converted.push_str(&format!(
+                "{}\t{}\t{}\n",
+                lineage,
+                split_kmer(kmer)?,
+                position
+            ));
+        }
+        parse_barcode_file(&converted, kmer_size, genome_size)
+    }
+}
+
+// table of full kmers (lineage and kmer, the SNP being the central base of each kmer), with
+// the genome_size, version and kmer_size lines of barcode files
+pub struct KmerTable<'a>(pub &'a str);
+
+impl BarcodeSource for KmerTable<'_> {
+    fn load(&self, kmer_size: Option<u8>, genome_size: Option<u64>) -> Result<Barcodes, String> {
+        let kmer_size = match kmer_size {
+            Some(kmer_size) => kmer_size,
+            None => declared_kmer_size(self.0)?
+                .or(common_length(
+                    self.0.lines().filter_map(kmer_row).map(|(_, kmer)| kmer),
+                ))
+                .unwrap_or(DEFAULT_KMER_SIZE),
+        };
+        // barcode file with the same lines (so that errors give the line of the table)
+        let mut converted = String::with_capacity(2 * self.0.len());
+        for line in self.0.lines() {
+            match kmer_row(line) {
+                Some((lineage, kmer)) => {
+                    converted.push_str(&format!("{}\t{}\n", lineage, split_kmer(kmer)?));
+                }
+                None => {
+                    converted.push_str(line);
+                    converted.push('\n');
+                }
+            }
+        }
+        parse_barcode_file(&converted, kmer_size, genome_size)
+    }
+}
+
+// binary index built by 'fastlin build-index' (memory-mapped)
+#[cfg(not(target_arch = "wasm32"))]
+pub struct BinaryIndex<'a>(pub &'a Path);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BarcodeSource for BinaryIndex<'_> {
+    fn load(&self, kmer_size: Option<u8>, genome_size: Option<u64>) -> Result<Barcodes, String> {
+        let barcodes = load_index(self.0, kmer_size, genome_size)?;
+        eprintln!("\t({} kmers, binary index)", barcodes.index.len());
+        Ok(barcodes)
+    }
+}
+
+// source of a scheme given as text, from its first lines
+pub fn text_source(content: &str) -> Box<dyn BarcodeSource + '_> {
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+    if lines
+        .clone()
+        .next()
+        .is_some_and(|line| line.starts_with('>'))
+    {
+        return Box::new(FastaScheme(content));
+    }
+    let first_row = lines.find(|line| {
+        !line.starts_with('#')
+            && !is_kmer_size_line(line)
+            && !is_header_row(line)
+            && !matches!(
+                line.split('\t').next().map(str::trim),
+                Some("genome_size" | "version" | "flank_length")
+            )
+    });
+    if first_row.is_some_and(|line| kmer_row(line).is_some()) {
+        Box::new(KmerTable(content))
+    } else {
+        Box::new(BarcodeFile(content))
+    }
+}
+
+// barcodes of a scheme file (binary index, or scheme given as text)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_barcodes(
+    path: &Path,
+    kmer_size: Option<u8>,
+    genome_size: Option<u64>,
+) -> Result<Barcodes, String> {
+    eprint!(" . get barcodes and genome size");
+    let barcodes = if is_binary_index(path) {
+        BinaryIndex(path).load(kmer_size, genome_size)
+    } else {
+        match read_to_string(path) {
+            Ok(content) => text_source(&content).load(kmer_size, genome_size),
+            Err(err) => Err(format!("couldn't read {}: {}", path.display(), err)),
+        }
+    };
+    // (ending the line of the step before the error is reported)
+    barcodes.inspect_err(|_| eprintln!())
+}
+
+fn parse_barcode_file(
+    barcode_csv: &str,
+    kmer_size: u8,
+    genome_size_override: Option<u64>,
+) -> Result<Barcodes, String> {
+    // convert kmer_size to usize and calculate the flank sizes around the SNP
+    // (for even kmer sizes, the right flank is one base longer than the left one)
+    let k = kmer_size as usize;
+    if kmer_size < MIN_KMER_SIZE {
+        return Err(format!(
+            "the kmer size should be at least {}",
+            MIN_KMER_SIZE
+        ));
+    }
+    let left_size: usize = (k - 1) / 2;
+    let right_size: usize = k - 1 - left_size;
+
+    // kmer size the barcode file was designed for, if it declares one
+    let declared = declared_kmer_size(barcode_csv)?.filter(|declared| *declared != kmer_size);
+    let mismatch = match declared {
+        Some(declared) => format!(
+            " (the barcode file was designed for a kmer size of {})",
+            declared
+        ),
+        None => String::new(),
+    };
+
+    // initialise Hashmap and genome size
+    let mut barcodes_id: HashMap<String, u32> = HashMap::default();
+    let mut ids: Vec<String> = Vec::new();
+    let mut loci: Vec<Option<BarcodeLocus>> = Vec::new();
+    let mut ref_kmers: HashMap<String, u32> = HashMap::default();
+    let mut genome_size: u64 = 0;
+    let mut version: Option<String> = None;
+    let mut flank_length: Option<usize> = None;
+    let mut ambiguous: HashSet<String> = HashSet::new();
+    let mut collisions: BTreeSet<(usize, usize)> = BTreeSet::new();
+    let mut barcode_lines: Vec<usize> = Vec::new();
+    let mut duplicates: Vec<(usize, usize)> = Vec::new();
+
+    // read barcode file (malformed lines are all reported at the end)
+    let mut counter = 0;
+    let mut malformed: Vec<String> = Vec::new();
+    let mut columns: Option<BarcodeColumns> = None;
+    for (line_number, l) in barcode_csv.lines().enumerate() {
+        // header row naming the columns of the barcodes (v2 format)
+        if columns.is_none() && is_header_row(l) {
+            columns = Some(BarcodeColumns::from_header(l).map_err(|problem| {
+                format!(
+                    "malformed lines in the barcode file:\n   {}",
+                    malformed_line(line_number, l, &problem)
+                )
+            })?);
+            continue;
+        }
+        if is_kmer_size_line(l) || l.trim().is_empty() || l.starts_with('#') {
+            // kmer size declared by the scheme (read beforehand), blank lines and comments
+            continue;
+        }
+        let collection = l.split('\t').map(str::trim).collect::<Vec<&str>>();
+
+        if matches!(collection[0], "genome_size" | "version" | "flank_length")
+            && collection.get(1).is_none_or(|value| value.is_empty())
+        {
+            malformed.push(malformed_line(line_number, l, "missing value"));
+            continue;
+        }
+        if collection[0] == "genome_size" {
+            // convert str to integer
+            let parsed_result = collection[1].parse::<u64>();
+            // check if the conversion was successful
+            match parsed_result {
+                Ok(parsed_number) => genome_size = parsed_number,
+                Err(_) => {
+                    return Err("Failed to read the genome size in barcode file".to_string());
+                }
+            }
+        } else if collection[0] == "version" {
+            // optional version of the barcode scheme
+            version = Some(collection[1].trim().to_string());
+        } else if collection[0] == "flank_length" {
+            // optional flank length declared by the scheme (flanks can otherwise differ)
+            let length = collection[1]
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| "Failed to read the flank length in barcode file".to_string())?;
+            if right_size > length {
+                return Err(format!(
+                    "the kmer size {} is too large for the {} bp flanks of the barcode file (maximum kmer size: {}){}",
+                    k,
+                    length,
+                    2 * length + 1,
+                    mismatch
+                ));
+            }
+            flank_length = Some(length);
+        } else {
+            // optional locus of the barcode (position and chrom columns)
+            let locus = match columns.as_ref().map(|columns| columns.locus(&collection)) {
+                Some(Ok(locus)) => locus,
+                Some(Err(problem)) => {
+                    malformed.push(malformed_line(line_number, l, problem));
+                    continue;
+                }
+                None => None,
+            };
+            // (lineage, left flank, SNP allele and right flank)
+            let collection = match &columns {
+                Some(columns) if collection.len() < columns.nb_columns => {
+                    malformed.push(malformed_line(
+                        line_number,
+                        l,
+                        "fewer fields than the header row",
+                    ));
+                    continue;
+                }
+                Some(columns) => columns.select(&collection),
+                None => collection,
+            };
+            if let Err(problem) = check_barcode_fields(&collection) {
+                malformed.push(malformed_line(line_number, l, problem));
+                continue;
+            }
+            // check the kmer fits within the flanks of the SNP of this line
+            let (left_flank, right_flank) = (collection[1].len(), collection[3].len());
+            if let Some(length) = flank_length {
+                if left_flank < length || right_flank < length {
+                    return Err(format!(
+                        "line {} of the barcode file has flanks shorter than the declared flank length ({} and {} bp instead of {})",
+                        line_number + 1,
+                        left_flank,
+                        right_flank,
+                        length
+                    ));
+                }
+            }
+            if left_size > left_flank || right_size > right_flank {
+                return Err(format!(
+                    "the kmer size {} is too large for the flanks of line {} of the barcode file ({} and {} bp, maximum kmer size: {}){}",
+                    k,
+                    line_number + 1,
+                    left_flank,
+                    right_flank,
+                    2 * left_flank.min(right_flank) + 1,
+                    mismatch
+                ));
+            }
+            // build id
+            let id = format!("{}__{}", &collection[0], counter);
+            // extract both sides
+            let left_side = &collection[1][collection[1].len() - left_size..];
+            let right_side = &collection[3][..right_size];
+            // build barcode
+            let barcode = left_side.to_owned() + collection[2] + right_side;
+            // expand IUPAC ambiguity codes into all concrete kmers
+            let Some(expanded) = expand_iupac(&barcode) else {
+                return Err(format!(
+                    "line {} of the barcode file has too many ambiguous bases (more than {} kmers)",
+                    line_number + 1,
+                    MAX_IUPAC_EXPANSIONS
+                ));
+            };
+            // skip barcodes whose kmers are all already known for this lineage
+            let duplicate = expanded.iter().all(|kmer| match barcodes_id.get(kmer) {
+                Some(previous) => barcode_lineage(&ids[*previous as usize]) == barcode_lineage(&id),
+                None => false,
+            });
+            if duplicate {
+                let index = barcodes_id[&expanded[0]] as usize;
+                duplicates.push((barcode_lines[index], line_number + 1));
+                continue;
+            }
+            for barcode in expanded {
+                // build reverse complement and save both in Hashmap
+                let rev_comp = revcomp(barcode.as_str());
+                for kmer in [barcode, rev_comp] {
+                    let Some(previous) = barcodes_id.insert(kmer.to_owned(), counter) else {
+                        continue;
+                    };
+                    // kmer already found in a barcode of another lineage
+                    let previous = previous as usize;
+                    if barcode_lineage(&ids[previous]) != barcode_lineage(&id) {
+                        collisions.insert((barcode_lines[previous], line_number + 1));
+                        ambiguous.insert(kmer);
+                    }
+                }
+            }
+
+            // wild-type kmers of the barcode site (reference allele of the ref column)
+            if let Some(ref_allele) = locus
+                .as_ref()
+                .and_then(|locus| locus.ref_allele.filter(|base| *base != locus.allele))
+            {
+                let wild_type = format!("{}{}{}", left_side, ref_allele, right_side);
+                for kmer in expand_iupac(&wild_type).unwrap_or_default() {
+                    let rev_comp = revcomp(&kmer);
+                    ref_kmers.insert(kmer, counter);
+                    ref_kmers.insert(rev_comp, counter);
+                }
+            }
+
+            ids.push(id);
+            loci.push(locus);
+            barcode_lines.push(line_number + 1);
+            counter += 1;
+        }
+    }
+    if !malformed.is_empty() {
+        return Err(format!(
+            "malformed lines in the barcode file:\n   {}",
+            malformed.join("\n   ")
+        ));
+    }
+
+    // double-check we have the genome size
+    if let Some(size) = genome_size_override {
+        genome_size = size;
+    }
+    if genome_size == 0 {
+        return Err("The genome size is missing from the barcode file".to_string());
+    }
+
+    //println!("	({} barcodes and genome size {})", counter, genome_size);
+    if duplicates.is_empty() {
+        eprintln!("	({} barcodes)", counter);
+    } else {
+        eprintln!(
+            "	({} barcodes, {} duplicates removed)",
+            counter,
+            duplicates.len()
+        );
+    }
+    if let Some(declared) = declared {
+        warning(&format!(
+            "the barcode file was designed for a kmer size of {} (kmer size used: {})",
+            declared, kmer_size
+        ));
+    }
+    for (previous_line, line) in &duplicates {
+        warning(&format!(
+            "line {} of the barcode file duplicates line {}",
+            line, previous_line
+        ));
+    }
+    for (previous_line, line) in &collisions {
+        warning(&format!(
+            "lines {} and {} of the barcode file share kmers but not their lineage",
+            previous_line, line
+        ));
+    }
+
+    // (wild-type kmers that are also barcode kmers are counted as barcodes)
+    ref_kmers.retain(|kmer, _| !barcodes_id.contains_key(kmer));
+
+    Ok(Barcodes {
+        index: BarcodeIndex::new(barcodes_id, ids),
+        ref_kmers,
+        // (no loci without position column)
+        loci: match loci.iter().any(Option::is_some) {
+            true => loci,
+            false => Vec::new(),
+        },
+        kmer_size,
+        genome_size,
+        version,
+        ambiguous,
+        spaced_seed: None,
+    })
+}
+
+fn declared_kmer_size(barcode_csv: &str) -> Result<Option<u8>, String> {
+    // kmer size declared by the barcode file ('kmer_size' line, or '#kmer_size 25' header)
+    let Some(line) = barcode_csv.lines().find(|line| is_kmer_size_line(line)) else {
+        return Ok(None);
+    };
+    let value = line.trim_start_matches('#')["kmer_size".len()..].trim();
+    match value.parse::<u8>() {
+        Ok(kmer_size) if kmer_size >= MIN_KMER_SIZE => Ok(Some(kmer_size)),
+        _ => Err("Failed to read the kmer size in barcode file".to_string()),
+    }
+}
+
+fn fasta_kmers(content: &str) -> Result<Vec<(&str, String)>, String> {
+    // (header and sequence of each record, sequences possibly spanning several lines)
+    let mut kmers: Vec<(&str, String)> = Vec::new();
+    for (line_number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('>') {
+            kmers.push((header.trim(), String::new()));
+        } else if let Some((_, kmer)) = kmers.last_mut() {
+            kmer.push_str(line);
+        } else if !line.is_empty() {
+            return Err(format!(
+                "line {} of the FASTA barcode file is outside a record",
+                line_number + 1
+            ));
+        }
+    }
+    Ok(kmers)
+}
+
+fn kmer_row(line: &str) -> Option<(&str, &str)> {
+    // lineage and kmer of a row of a kmer table
+    let (lineage, kmer) = line.split_once('\t')?;
+    let kmer = kmer.trim();
+    let is_kmer =
+        !kmer.is_empty() && !kmer.contains('\t') && kmer.chars().all(|c| c.is_ascii_alphabetic());
+    is_kmer.then_some((lineage.trim(), kmer))
+}
+
+fn common_length<'a>(kmers: impl Iterator<Item = &'a str>) -> Option<u8> {
+    // kmer size of full kmers of the same length
+    let lengths: HashSet<usize> = kmers.map(str::len).collect();
+    match lengths.into_iter().collect::<Vec<_>>()[..] {
+        [length] if (MIN_KMER_SIZE as usize..=u8::MAX as usize).contains(&length) => {
+            Some(length as u8)
+        }
+        _ => None,
+    }
+}
+
+fn split_kmer(kmer: &str) -> Result<String, String> {
+    // left flank, SNP allele and right flank (tab-separated) of a full kmer
+    if kmer.len() < MIN_KMER_SIZE as usize || !kmer.is_ascii() {
+        return Err(format!(
+            "the kmer {} should have at least {} bases",
+            kmer, MIN_KMER_SIZE
+        ));
+    }
+    let snp = (kmer.len() - 1) / 2;
+    Ok(format!(
+        "{}\t{}\t{}",
+        &kmer[..snp],
+        &kmer[snp..snp + 1],
+        &kmer[snp + 1..]
+    ))
+}
+
+// positions of the barcode fields in the rows of a barcode file with a header row (v2 format,
+// e.g. 'lineage  left_flank  allele  right_flank  position'), and of the optional position,
+// chrom and ref columns of their loci, other columns being metadata
+#[derive(Debug)]
+struct BarcodeColumns {
+    fields: [usize; 4],
+    nb_columns: usize,
+    position: Option<usize>,
+    chrom: Option<usize>,
+    ref_allele: Option<usize>,
+}
+
+impl BarcodeColumns {
+    fn from_header(header: &str) -> Result<Self, String> {
+        let names: Vec<&str> = header
+            .trim_start_matches('#')
+            .split('\t')
+            .map(str::trim)
+            .collect();
+        let mut fields = [0; 4];
+        for (field, name) in fields.iter_mut().zip(BARCODE_COLUMNS) {
+            *field = names
+                .iter()
+                .position(|column| *column == name)
+                .ok_or(format!("the header row has no '{}' column", name))?;
+        }
+        let column = |name: &str| names.iter().position(|column| *column == name);
+        Ok(BarcodeColumns {
+            fields,
+            nb_columns: fields.iter().max().unwrap() + 1,
+            position: column("position"),
+            chrom: column("chrom"),
+            ref_allele: column("ref"),
+        })
+    }
+
+    fn locus(&self, row: &[&str]) -> Result<Option<BarcodeLocus>, &'static str> {
+        let field = |column: Option<usize>| column.and_then(|n| row.get(n).copied());
+        match field(self.position) {
+            Some(position) => parse_locus(
+                position,
+                field(self.chrom),
+                field(self.ref_allele),
+                field(Some(self.fields[2])).unwrap_or_default(),
+            ),
+            None => Ok(None),
+        }
+    }
+
+    fn select<'a>(&self, row: &[&'a str]) -> Vec<&'a str> {
+        self.fields.iter().map(|n| row[*n]).collect()
+    }
+}
+
+// required columns of the v2 format
+const BARCODE_COLUMNS: [&str; 4] = ["lineage", "left_flank", "allele", "right_flank"];
+
+fn is_header_row(line: &str) -> bool {
+    // (a row naming at least 2 of the required columns)
+    line.trim_start_matches('#')
+        .split('\t')
+        .filter(|column| BARCODE_COLUMNS.contains(&column.trim()))
+        .count()
+        >= 2
+}
+
+fn check_barcode_fields(fields: &[&str]) -> Result<(), &'static str> {
+    // lineage, left flank, SNP allele and right flank (bases or IUPAC codes)
+    let is_sequence = |field: &str| {
+        !field.is_empty()
+            && field
+                .chars()
+                .all(|c| c.is_ascii_alphabetic() && iupac_bases(c) != ['N'])
+    };
+    match fields {
+        ["", ..] => Err("missing lineage"),
+        [_, left_flank, allele, right_flank, ..] => {
+            if allele.chars().count() != 1 || !is_sequence(allele) {
+                Err("the SNP allele should be a single base")
+            } else if !is_sequence(left_flank) || !is_sequence(right_flank) {
+                Err("the flanks should only contain bases or IUPAC codes")
+            } else {
+                Ok(())
+            }
+        }
+        _ => Err("fewer than 4 tab-separated fields"),
+    }
+}
+
+fn malformed_line(line_number: usize, line: &str, problem: &str) -> String {
+    // problem of a line of the barcode file, with the start of the line
+    let excerpt: String = line.chars().take(40).collect();
+    let ellipsis = if excerpt.len() < line.len() {
+        "..."
+    } else {
+        ""
+    };
+    format!(
+        "line {}: {} ('{}{}')",
+        line_number + 1,
+        problem,
+        excerpt,
+        ellipsis
+    )
+}
+
+fn is_kmer_size_line(line: &str) -> bool {
+    line.trim_start_matches('#')
+        .strip_prefix("kmer_size")
+        .is_some_and(|rest| rest.starts_with(['\t', ' ']))
+}
+
+fn expand_iupac(seq: &str) -> Option<Vec<String>> {
+    // all concrete sequences encoded by a sequence with IUPAC codes (None above the cap)
+    let mut expanded = vec![String::with_capacity(seq.len())];
+
+    for c in seq.chars() {
+        let bases = iupac_bases(c);
+        if bases.len() == 1 {
+            for prefix in expanded.iter_mut() {
+                prefix.push(bases[0]);
+            }
+            continue;
+        }
+        if expanded.len() * bases.len() > MAX_IUPAC_EXPANSIONS {
+            return None;
+        }
+        expanded = expanded
+            .iter()
+            .flat_map(|prefix| {
+                bases.iter().map(move |base| {
+                    let mut sequence = prefix.clone();
+                    sequence.push(*base);
+                    sequence
+                })
+            })
+            .collect();
+    }
+    Some(expanded)
+}
+
+fn iupac_bases(c: char) -> &'static [char] {
+    match c.to_ascii_uppercase() {
+        'R' => &['A', 'G'],
+        'Y' => &['C', 'T'],
+        'S' => &['C', 'G'],
+        'W' => &['A', 'T'],
+        'K' => &['G', 'T'],
+        'M' => &['A', 'C'],
+        'B' => &['C', 'G', 'T'],
+        'D' => &['A', 'G', 'T'],
+        'H' => &['A', 'C', 'T'],
+        'V' => &['A', 'C', 'G'],
+        'N' => &['A', 'C', 'G', 'T'],
+        'A' => &['A'],
+        'C' => &['C'],
+        'G' => &['G'],
+        'T' => &['T'],
+        _ => &['N'],
+    }
+}
+
+fn revcomp(seq: &str) -> String {
+    // reverse complement sequence
+    let mut rev_compl: String = String::with_capacity(seq.len());
+
+    // iterate through the input sequence
+    for c in seq.chars().rev() {
+        rev_compl.push(switch_base(c))
+    }
+    rev_compl
+}
+
+fn switch_base(c: char) -> char {
+    match c {
+        'a' => 'T',
+        'c' => 'G',
+        't' => 'A',
+        'g' => 'C',
+        'A' => 'T',
+        'C' => 'G',
+        'T' => 'A',
+        'G' => 'C',
+        _ => 'N',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // barcode file with the given lines (lineage, left flank, SNP, right flank)
+    fn barcode_file(lines: &[(&str, &str, &str, &str)]) -> String {
+        let mut file = String::from("genome_size\t1000\n");
+        for (lineage, left, snp, right) in lines {
+            file.push_str(&format!("{}\t{}\t{}\t{}\n", lineage, left, snp, right));
+        }
+        file
+    }
+
+    // kmers of the barcodes and their barcode ID, in alphabetical order
+    fn kmer_ids(barcodes: &Barcodes) -> Vec<String> {
+        let mut kmers: Vec<String> = barcodes
+            .index
+            .kmers()
+            .map(|(kmer, index)| format!("{} {}", kmer, barcodes.index.id(index)))
+            .collect();
+        kmers.sort();
+        kmers
+    }
+
+    #[test]
+    fn ambiguity_codes_are_expanded_into_every_kmer() {
+        let file = barcode_file(&[("2", "AAAAC", "R", "CTTTT")]);
+        let barcodes = Barcodes::from_string(&file, 11).unwrap();
+        // (and their reverse complements)
+        assert_eq!(
+            kmer_ids(&barcodes),
+            [
+                "AAAACACTTTT 2__0",
+                "AAAACGCTTTT 2__0",
+                "AAAAGCGTTTT 2__0",
+                "AAAAGTGTTTT 2__0"
+            ]
+        );
+
+        let file = barcode_file(&[("2", "NNNNN", "A", "NNNNN")]);
+        let err = Barcodes::from_string(&file, 11).err().unwrap();
+        assert!(err.contains("too many ambiguous bases"), "{}", err);
+    }
+
+    #[test]
+    fn kmers_shared_by_several_lineages_are_ambiguous() {
+        let file = barcode_file(&[
+            ("2", "AAAAC", "A", "CTTTT"),
+            ("4", "AAAAC", "R", "CTTTT"),
+            ("4", "CCCCA", "G", "TGGGG"),
+        ]);
+        let barcodes = Barcodes::from_string(&file, 11).unwrap();
+        let mut ambiguous: Vec<&String> = barcodes.ambiguous.iter().collect();
+        ambiguous.sort();
+        assert_eq!(ambiguous, ["AAAACACTTTT", "AAAAGTGTTTT"]);
+    }
+
+    #[test]
+    fn duplicated_barcodes_of_a_lineage_are_ignored() {
+        let file = barcode_file(&[
+            ("2", "AAAAC", "A", "CTTTT"),
+            ("2", "AAAAC", "A", "CTTTT"),
+            ("2", "CCCCA", "G", "TGGGG"),
+        ]);
+        let barcodes = Barcodes::from_string(&file, 11).unwrap();
+        assert_eq!(
+            kmer_ids(&barcodes),
+            [
+                "AAAACACTTTT 2__0",
+                "AAAAGTGTTTT 2__0",
+                "CCCCACTGGGG 2__1",
+                "CCCCAGTGGGG 2__1"
+            ]
+        );
+        assert!(barcodes.ambiguous.is_empty());
+    }
+
+    #[test]
+    fn blank_lines_comments_and_spaces_are_accepted() {
+        let file = "genome_size\t1000\r\n\r\n# comment\r\n2 \tAAAAC\t A\tCTTTT\r\n";
+        let barcodes = Barcodes::from_string(file, 11).unwrap();
+        assert_eq!(
+            kmer_ids(&barcodes),
+            ["AAAACACTTTT 2__0", "AAAAGTGTTTT 2__0"]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_all_reported() {
+        let file = "genome_size\n\
+                    2\tAAAAC\tA\n\
+                    \tAAAAC\tA\tCTTTT\n\
+                    2\tAAAAC\tAG\tCTTTT\n\
+                    2\tAAA-C\tA\tCTTTT\n";
+        let err = Barcodes::from_string(file, 11).err().unwrap();
+        for problem in [
+            "line 1: missing value ('genome_size')",
+            "line 2: fewer than 4 tab-separated fields",
+            "line 3: missing lineage",
+            "line 4: the SNP allele should be a single base",
+            "line 5: the flanks should only contain bases or IUPAC codes",
+        ] {
+            assert!(err.contains(problem), "{}", err);
+        }
+    }
+
+    #[test]
+    fn named_columns_can_be_in_any_order() {
+        let file = "genome_size\t1000\n\
+                    #position\tallele\tlineage\tright_flank\tleft_flank\tweight\n\
+                    120\tA\t2\tCTTTT\tAAAAC\t0.5\n";
+        let barcodes = Barcodes::from_string(file, 11).unwrap();
+        assert_eq!(
+            kmer_ids(&barcodes),
+            ["AAAACACTTTT 2__0", "AAAAGTGTTTT 2__0"]
+        );
+
+        let file = "genome_size\t1000\nlineage\tleft_flank\tallele\tposition\n2\tAAAAC\tA\t120\n";
+        let err = Barcodes::from_string(file, 11).err().unwrap();
+        assert!(err.contains("no 'right_flank' column"), "{}", err);
+
+        let file = "genome_size\t1000\nlineage\tleft_flank\tallele\tright_flank\n2\tAAAAC\tA\n";
+        let err = Barcodes::from_string(file, 11).err().unwrap();
+        assert!(
+            err.contains("line 3: fewer fields than the header row"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn fasta_kmers_are_split_around_their_central_base() {
+        // (sequences can span several lines)
+        let fasta = ">2|pos120\nAAAAC\nACTTTT\n\n>4.1\nCCACGTGGATC\n";
+        let barcodes = Barcodes::with_genome_size(fasta, 11, Some(1000)).unwrap();
+        assert_eq!(barcodes.genome_size, 1000);
+        assert_eq!(
+            kmer_ids(&barcodes),
+            [
+                "AAAACACTTTT 2__0",
+                "AAAAGTGTTTT 2__0",
+                "CCACGTGGATC 4.1__1",
+                "GATCCACGTGG 4.1__1"
+            ]
+        );
+
+        let err = Barcodes::from_string(fasta, 11).err().unwrap();
+        assert!(err.contains("--genome-size"), "{}", err);
+        let err = Barcodes::with_genome_size(">|pos120\nAAAACACTTTT\n", 11, Some(1000))
+            .err()
+            .unwrap();
+        assert!(err.contains("lineage"), "{}", err);
+    }
+
+    #[test]
+    fn kmer_tables_and_fasta_schemes_default_to_their_kmer_length() {
+        let table = "genome_size\t1000\n2\tAAAACACTTTT\n4.1\tCCACGTGGATC\n";
+        let barcodes = text_source(table).load(None, None).unwrap();
+        assert_eq!(barcodes.kmer_size, 11);
+        assert_eq!(barcodes.index.len(), 4);
+
+        let fasta = ">2|pos120\nAAAACACTTTT\n>4.1\nCCACGTGGATC\n";
+        let barcodes = text_source(fasta).load(None, Some(1000)).unwrap();
+        assert_eq!(barcodes.kmer_size, 11);
+        assert_eq!(barcodes.index.len(), 4);
+
+        // (barcode files don't give full kmers)
+        let file = barcode_file(&[("2", "AAAAC", "A", "CTTTT")]);
+        let err = text_source(&file).load(None, None).err().unwrap();
+        assert!(
+            err.contains(&format!("kmer size {} is too large", DEFAULT_KMER_SIZE)),
+            "{}",
+            err
+        );
+    }
+}
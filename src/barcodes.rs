@@ -0,0 +1,333 @@
+use ahash::AHashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::str;
+use std::sync::Arc;
+
+use crate::barcode_loci::BarcodeLocus;
+use crate::barcode_source::text_source;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::binary_index::MappedTable;
+use crate::encoding::encode_kmer;
+use crate::process_barcodes::barcode_lineage;
+
+// minimum kmer size (the maximum depends on the flank length of the barcode file)
+pub const MIN_KMER_SIZE: u8 = 11;
+
+// kmer size used when neither the command line nor the barcode file gives one
+pub const DEFAULT_KMER_SIZE: u8 = 25;
+
+// maximum number of kmers a barcode with IUPAC ambiguity codes can be expanded into
+pub const MAX_IUPAC_EXPANSIONS: usize = 256;
+
+// barcodes (kmer -> barcode index), kmer size, genome size and optional scheme version
+// (ambiguous kmers are shared by the barcodes of several lineages; with a spaced seed, the
+// kmers are only keyed by the bases of their care positions), loci of the barcodes by
+// barcode index (empty if the scheme has no position column), and wild-type kmers of the
+// barcode sites (reference allele, from the ref column) -> barcode index
+pub struct Barcodes {
+    pub index: BarcodeIndex,
+    pub loci: Vec<Option<BarcodeLocus>>,
+    pub ref_kmers: HashMap<String, u32>,
+    pub kmer_size: u8,
+    pub genome_size: u64,
+    pub version: Option<String>,
+    pub ambiguous: HashSet<String>,
+    pub spaced_seed: Option<SpacedSeed>,
+}
+
+// kmers of the barcodes and their index in the barcode IDs (lineage__index, by index), so
+// that the barcodes found in reads are counted without handling their IDs
+#[derive(Clone, Debug, Default)]
+pub struct BarcodeIndex {
+    kmers: KmerTable,
+    pub ids: Arc<Vec<String>>,
+}
+
+// kmer -> barcode index table, built from the barcode file or memory-mapped from a binary
+// index (shared by the processes using the same index file)
+#[derive(Clone, Debug)]
+enum KmerTable {
+    Map(HashMap<String, u32>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(Arc<MappedTable>),
+}
+
+impl Default for KmerTable {
+    fn default() -> Self {
+        KmerTable::Map(HashMap::new())
+    }
+}
+
+impl BarcodeIndex {
+    pub fn new(kmers: HashMap<String, u32>, ids: Vec<String>) -> Self {
+        BarcodeIndex {
+            kmers: KmerTable::Map(kmers),
+            ids: Arc::new(ids),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn mapped(table: MappedTable, ids: Vec<String>) -> Self {
+        BarcodeIndex {
+            kmers: KmerTable::Mapped(Arc::new(table)),
+            ids: Arc::new(ids),
+        }
+    }
+
+    // index of the barcode of a kmer
+    pub fn get(&self, kmer: &[u8]) -> Option<u32> {
+        match &self.kmers {
+            // barcodes are ASCII so non-UTF-8 kmers can't match any of them
+            KmerTable::Map(kmers) => kmers.get(str::from_utf8(kmer).ok()?).copied(),
+            #[cfg(not(target_arch = "wasm32"))]
+            KmerTable::Mapped(table) => table.get(kmer),
+        }
+    }
+
+    pub fn id(&self, index: u32) -> &str {
+        &self.ids[index as usize]
+    }
+
+    // number of kmers
+    pub fn len(&self) -> usize {
+        match &self.kmers {
+            KmerTable::Map(kmers) => kmers.len(),
+            #[cfg(not(target_arch = "wasm32"))]
+            KmerTable::Mapped(table) => table.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // kmers and the index of their barcode (kmers of a memory-mapped table are decoded)
+    pub fn kmers(&self) -> Box<dyn Iterator<Item = (Cow<'_, str>, u32)> + '_> {
+        match &self.kmers {
+            KmerTable::Map(kmers) => Box::new(
+                kmers
+                    .iter()
+                    .map(|(kmer, index)| (Cow::Borrowed(kmer.as_str()), *index)),
+            ),
+            #[cfg(not(target_arch = "wasm32"))]
+            KmerTable::Mapped(table) => Box::new(
+                table
+                    .entries()
+                    .map(|(kmer, index)| (Cow::Owned(kmer), index)),
+            ),
+        }
+    }
+
+    // table to modify (a memory-mapped table is copied into memory first)
+    fn kmers_mut(&mut self) -> &mut HashMap<String, u32> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let KmerTable::Mapped(table) = &self.kmers {
+            self.kmers = KmerTable::Map(table.entries().collect());
+        }
+        match &mut self.kmers {
+            KmerTable::Map(kmers) => kmers,
+            #[cfg(not(target_arch = "wasm32"))]
+            KmerTable::Mapped(_) => unreachable!(),
+        }
+    }
+
+    // only keep the barcodes whose IDs are accepted by keep and return their number
+    pub fn retain_barcodes(&mut self, keep: impl Fn(&str) -> bool) -> usize {
+        let ids = self.ids.clone();
+        let kmers = self.kmers_mut();
+        kmers.retain(|_, index| keep(&ids[*index as usize]));
+        kmers.values().collect::<HashSet<&u32>>().len()
+    }
+
+    // only keep the barcodes of the lineages accepted by keep and return their number
+    pub fn retain_lineages(&mut self, keep: impl Fn(&str) -> bool) -> usize {
+        self.retain_barcodes(|id| keep(barcode_lineage(id)))
+    }
+
+    // IDs of the barcodes having kmers in the index
+    pub fn barcode_ids(&self) -> HashSet<&String> {
+        self.kmers()
+            .map(|(_, index)| &self.ids[index as usize])
+            .collect()
+    }
+
+    // table of the kmers encoded on 64 bits (None if they are longer than 32 bases, or not all
+    // k-long and made of bases A, C, G and T)
+    pub fn encoded(&self, k: usize) -> Option<EncodedIndex> {
+        if k > 32 {
+            return None;
+        }
+        match &self.kmers {
+            KmerTable::Map(kmers) => kmers
+                .iter()
+                .map(|(kmer, index)| {
+                    let kmer = kmer.as_bytes();
+                    (kmer.len() == k).then(|| Some((encode_kmer(kmer)?, *index)))?
+                })
+                .collect::<Option<AHashMap<u64, u32>>>()
+                .map(EncodedIndex::Map),
+            #[cfg(not(target_arch = "wasm32"))]
+            KmerTable::Mapped(table) => {
+                (table.kmer_size() == k).then(|| EncodedIndex::Mapped(table.clone()))
+            }
+        }
+    }
+}
+
+// encoded kmer -> barcode index table, so that the kmers of the reads are looked up by their
+// 2-bit encoding, computed while scanning the reads, rather than as slices of the reads
+#[derive(Debug)]
+pub enum EncodedIndex {
+    Map(AHashMap<u64, u32>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(Arc<MappedTable>),
+}
+
+impl EncodedIndex {
+    // index of the barcode of an encoded kmer
+    pub fn get(&self, key: u64) -> Option<u32> {
+        match self {
+            EncodedIndex::Map(kmers) => kmers.get(&key).copied(),
+            #[cfg(not(target_arch = "wasm32"))]
+            EncodedIndex::Mapped(table) => table.get_encoded(key),
+        }
+    }
+}
+
+// kmer pattern whose '0' positions are ignored when matching barcodes (e.g. 11110111...),
+// so that isolated SNPs or sequencing errors at these positions don't prevent a match
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpacedSeed {
+    care_positions: Vec<usize>,
+    length: usize,
+}
+
+impl SpacedSeed {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        if pattern.is_empty() || !pattern.chars().all(|c| c == '0' || c == '1') {
+            return Err(format!(
+                "the spaced seed {} should only contain 0 (don't care) and 1",
+                pattern
+            ));
+        }
+        // the barcode SNP is at the left flank size, or the right one on the reverse strand
+        let length = pattern.len();
+        let left_size = (length - 1) / 2;
+        let snp_positions = [left_size, length - 1 - left_size];
+        if let Some(n) = snp_positions
+            .iter()
+            .find(|n| pattern.as_bytes()[**n] != b'1')
+        {
+            return Err(format!(
+                "the spaced seed {} should not ignore the barcode SNP (position {})",
+                pattern,
+                n + 1
+            ));
+        }
+        let care_positions = pattern
+            .bytes()
+            .enumerate()
+            .filter(|(_, c)| *c == b'1')
+            .map(|(n, _)| n)
+            .collect();
+        Ok(SpacedSeed {
+            care_positions,
+            length,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    // bases of the care positions of a kmer, written into key
+    pub fn mask(&self, kmer: &[u8], key: &mut Vec<u8>) {
+        key.clear();
+        key.extend(self.care_positions.iter().map(|n| kmer[*n]));
+    }
+}
+
+impl Barcodes {
+    // barcodes of a scheme given as text (barcode file, FASTA or kmer table)
+    pub fn from_string(barcode_csv: &str, kmer_size: u8) -> Result<Self, String> {
+        Self::with_genome_size(barcode_csv, kmer_size, None)
+    }
+
+    // the genome size given overrides (or supplies) the one of the scheme
+    pub fn with_genome_size(
+        barcode_csv: &str,
+        kmer_size: u8,
+        genome_size: Option<u64>,
+    ) -> Result<Self, String> {
+        text_source(barcode_csv).load(Some(kmer_size), genome_size)
+    }
+
+    // key the barcodes by the care positions of the spaced seed and return the number of
+    // keys shared by several lineages (added to the ambiguous kmers)
+    pub fn apply_spaced_seed(&mut self, seed: SpacedSeed) -> Result<usize, String> {
+        if seed.len() != self.kmer_size as usize {
+            return Err(format!(
+                "the spaced seed should be as long as the kmers ({} instead of {})",
+                seed.len(),
+                self.kmer_size
+            ));
+        }
+        let mut key = Vec::with_capacity(seed.len());
+        let mask = |kmer: &str, key: &mut Vec<u8>| {
+            seed.mask(kmer.as_bytes(), key);
+            String::from_utf8_lossy(key).to_string()
+        };
+
+        let ids = self.index.ids.clone();
+        let mut kmers: HashMap<String, u32> = HashMap::with_capacity(self.index.len());
+        let mut ambiguous: HashSet<String> = HashSet::new();
+        let mut nb_shared = 0;
+
+        // as in the barcode file, shared keys are attributed to the last barcode
+        let mut entries: Vec<(String, u32)> = self.index.kmers_mut().drain().collect();
+        entries.sort_by(|(kmer_1, index_1), (kmer_2, index_2)| {
+            (index_1, kmer_1).cmp(&(index_2, kmer_2))
+        });
+        for (kmer, index) in entries {
+            let masked = mask(&kmer, &mut key);
+            if self.ambiguous.contains(&kmer) {
+                ambiguous.insert(masked.clone());
+            }
+            if let Some(previous) = kmers.insert(masked.clone(), index) {
+                if barcode_lineage(&ids[previous as usize]) != barcode_lineage(&ids[index as usize])
+                    && ambiguous.insert(masked)
+                {
+                    nb_shared += 1;
+                }
+            }
+        }
+        self.index.kmers = KmerTable::Map(kmers);
+        self.ambiguous = ambiguous;
+        self.spaced_seed = Some(seed);
+        Ok(nb_shared)
+    }
+
+    // only keep the barcodes of the lineages accepted by keep and return their number
+    pub fn retain_lineages(&mut self, keep: impl Fn(&str) -> bool) -> usize {
+        self.index.retain_lineages(keep)
+    }
+
+    // remove the barcodes of these IDs and return the number of barcodes kept
+    pub fn drop_barcodes(&mut self, ids: &HashSet<String>) -> usize {
+        self.index.retain_barcodes(|id| !ids.contains(id))
+    }
+
+    // remove the kmers shared by several lineages and return their number
+    pub fn drop_ambiguous(&mut self) -> usize {
+        let kmers = self.index.kmers_mut();
+        for kmer in &self.ambiguous {
+            kmers.remove(kmer);
+        }
+        self.ambiguous.len()
+    }
+}
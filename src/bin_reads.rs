@@ -0,0 +1,140 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::analyse_sample::{ReadHits, ReadVisitor};
+use crate::process_barcodes::barcode_lineage;
+
+// writes the reads containing barcodes into one gzipped FASTQ file per lineage, reads with
+// barcodes from several lineages going to 'unassigned.fastq.gz'
+pub struct ReadBinner {
+    dir: PathBuf,
+    writers: HashMap<String, BufWriter<GzEncoder<File>>>,
+    error: Option<io::Error>,
+}
+
+impl ReadBinner {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        create_dir_all(dir)?;
+        Ok(ReadBinner {
+            dir: dir.to_path_buf(),
+            writers: HashMap::new(),
+            error: None,
+        })
+    }
+
+    fn write_read(&mut self, bin: &str, read: &ReadHits, qual: &[u8]) -> io::Result<()> {
+        if !self.writers.contains_key(bin) {
+            let file = File::create(self.dir.join(format!("{}.fastq.gz", bin)))?;
+            let writer = BufWriter::new(GzEncoder::new(file, Compression::default()));
+            self.writers.insert(bin.to_string(), writer);
+        }
+        let writer = self.writers.get_mut(bin).unwrap();
+        writer.write_all(b"@")?;
+        writer.write_all(read.read_id.as_bytes())?;
+        writer.write_all(b"\n")?;
+        writer.write_all(read.seq)?;
+        writer.write_all(b"\n+\n")?;
+        writer.write_all(qual)?;
+        writer.write_all(b"\n")
+    }
+
+    // flush and close all files, returning the first error encountered
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        for (_, writer) in self.writers {
+            writer
+                .into_inner()
+                .map_err(|err| err.into_error())?
+                .finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl ReadVisitor for ReadBinner {
+    fn visit(&mut self, read: &ReadHits) {
+        // only FASTQ reads are binned (assemblies have no qualities)
+        let Some(qual) = read.qual else {
+            return;
+        };
+        if self.error.is_some() {
+            return;
+        }
+
+        let lineage = barcode_lineage(read.hits[0].barcode_id);
+        let bin = if read
+            .hits
+            .iter()
+            .all(|hit| barcode_lineage(hit.barcode_id) == lineage)
+        {
+            lineage
+        } else {
+            "unassigned"
+        };
+
+        if let Err(err) = self.write_read(bin, read, qual) {
+            self.error = Some(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyse_sample::BarcodeHit;
+    use flate2::read::MultiGzDecoder;
+    use std::fs;
+    use std::io::Read;
+
+    #[test]
+    fn reads_are_binned_by_the_lineage_of_their_barcodes() {
+        let dir = std::env::temp_dir().join(format!("fastlin_bins_{}", std::process::id()));
+        let mut binner = ReadBinner::new(&dir).unwrap();
+        let hit = |barcode_id| BarcodeHit {
+            barcode_id,
+            position: 0,
+        };
+        let reads = [
+            ("r1", vec![hit("2__1"), hit("2__4")]),
+            ("r2", vec![hit("4.1__2")]),
+            ("r3", vec![hit("2__1"), hit("4.1__2")]),
+            ("r4", vec![hit("2__3")]),
+        ];
+        for (read_id, hits) in &reads {
+            binner.visit(&ReadHits {
+                read_id,
+                seq: b"ACGT",
+                qual: Some(b"IIII"),
+                hits,
+            });
+        }
+        // (assemblies have no qualities)
+        binner.visit(&ReadHits {
+            read_id: "contig",
+            seq: b"ACGT",
+            qual: None,
+            hits: &reads[0].1,
+        });
+        binner.finish().unwrap();
+
+        let read_bin = |bin: &str| {
+            let mut content = String::new();
+            let file = fs::File::open(dir.join(format!("{}.fastq.gz", bin))).unwrap();
+            MultiGzDecoder::new(file)
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+        assert_eq!(read_bin("2"), "@r1\nACGT\n+\nIIII\n@r4\nACGT\n+\nIIII\n");
+        assert_eq!(read_bin("4.1"), "@r2\nACGT\n+\nIIII\n");
+        assert_eq!(read_bin("unassigned"), "@r3\nACGT\n+\nIIII\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
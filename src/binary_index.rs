@@ -0,0 +1,337 @@
+use memmap2::Mmap;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::barcodes::{BarcodeIndex, Barcodes};
+use crate::encoding::encode_kmer;
+use crate::minimizers::mix;
+
+// binary barcode index (built by 'fastlin build-index'): the kmers of a barcode file for a
+// given kmer size, stored as an open-addressing hash table of 2-bit encoded kmers. The file is
+// memory-mapped rather than read, so that the processes typing samples on the same node share
+// the pages of a large scheme instead of each building its own table
+pub const INDEX_MAGIC: &[u8; 8] = b"FLINDEX1";
+
+// kmers are encoded on 64 bits
+pub const MAX_INDEX_KMER_SIZE: u8 = 32;
+
+// magic, then kmer size, genome size, number of slots, number of kmers and length of the
+// metadata (little-endian u64), followed by the keys (u64) and values (u32) of the slots and
+// by the metadata (version, barcode IDs and ambiguous kmers, one per line)
+const HEADER_SIZE: usize = 48;
+
+// value of the empty slots
+const EMPTY_SLOT: u32 = u32::MAX;
+
+// bases of the 2-bit codes
+const BASES: [u8; 4] = *b"ACTG";
+
+// kmer -> barcode index table of a memory-mapped index file
+#[derive(Debug)]
+pub struct MappedTable {
+    mmap: Mmap,
+    k: usize,
+    nb_slots: usize,
+    nb_kmers: usize,
+}
+
+impl MappedTable {
+    pub fn get(&self, kmer: &[u8]) -> Option<u32> {
+        if kmer.len() != self.k {
+            return None;
+        }
+        self.get_encoded(encode_kmer(kmer)?)
+    }
+
+    // index of the barcode of a kmer encoded on 64 bits
+    pub fn get_encoded(&self, key: u64) -> Option<u32> {
+        let mask = self.nb_slots - 1;
+        let mut slot = mix(key) as usize & mask;
+        loop {
+            let value = self.value(slot);
+            if value == EMPTY_SLOT {
+                return None;
+            }
+            if self.key(slot) == key {
+                return Some(value);
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    pub fn kmer_size(&self) -> usize {
+        self.k
+    }
+
+    pub fn len(&self) -> usize {
+        self.nb_kmers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nb_kmers == 0
+    }
+
+    // kmers (decoded) and the index of their barcode
+    pub fn entries(&self) -> impl Iterator<Item = (String, u32)> + '_ {
+        (0..self.nb_slots)
+            .filter(|slot| self.value(*slot) != EMPTY_SLOT)
+            .map(|slot| (decode_kmer(self.key(slot), self.k), self.value(slot)))
+    }
+
+    fn key(&self, slot: usize) -> u64 {
+        let offset = HEADER_SIZE + 8 * slot;
+        u64::from_le_bytes(self.mmap[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn value(&self, slot: usize) -> u32 {
+        let offset = HEADER_SIZE + 8 * self.nb_slots + 4 * slot;
+        u32::from_le_bytes(self.mmap[offset..offset + 4].try_into().unwrap())
+    }
+}
+
+pub fn is_binary_index(path: &Path) -> bool {
+    let mut magic = [0; 8];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == INDEX_MAGIC)
+}
+
+// write the barcodes into a binary index and return its number of kmers
+pub fn write_index(barcodes: &Barcodes, path: &Path) -> Result<usize, String> {
+    if barcodes.kmer_size > MAX_INDEX_KMER_SIZE {
+        return Err(format!(
+            "the kmer size of a binary index should be at most {}",
+            MAX_INDEX_KMER_SIZE
+        ));
+    }
+    if barcodes.spaced_seed.is_some() {
+        return Err("a binary index can't be built with a spaced seed".to_string());
+    }
+
+    // hash table of at most 50% occupancy
+    let nb_kmers = barcodes.index.len();
+    let nb_slots = (2 * nb_kmers).next_power_of_two().max(16);
+    let mut keys = vec![0; nb_slots];
+    let mut values = vec![EMPTY_SLOT; nb_slots];
+    for (kmer, index) in barcodes.index.kmers() {
+        let Some(key) = encode_kmer(kmer.as_bytes()) else {
+            return Err(format!(
+                "the kmer {} can't be stored in a binary index (only bases A, C, G and T)",
+                kmer
+            ));
+        };
+        let mut slot = mix(key) as usize & (nb_slots - 1);
+        while values[slot] != EMPTY_SLOT {
+            slot = (slot + 1) & (nb_slots - 1);
+        }
+        keys[slot] = key;
+        values[slot] = index;
+    }
+
+    let mut metadata = String::new();
+    if let Some(version) = &barcodes.version {
+        metadata.push_str(&format!("version\t{}\n", version));
+    }
+    for id in barcodes.index.ids.iter() {
+        metadata.push_str(&format!("id\t{}\n", id));
+    }
+    for kmer in &barcodes.ambiguous {
+        metadata.push_str(&format!("ambiguous\t{}\n", kmer));
+    }
+
+    let file = File::create(path)
+        .map_err(|err| format!("couldn't create the file {}: {}", path.display(), err))?;
+    let mut writer = BufWriter::new(file);
+    let header = [
+        barcodes.kmer_size as u64,
+        barcodes.genome_size,
+        nb_slots as u64,
+        nb_kmers as u64,
+        metadata.len() as u64,
+    ];
+    let mut write = || -> std::io::Result<()> {
+        writer.write_all(INDEX_MAGIC)?;
+        for field in header {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+        for key in &keys {
+            writer.write_all(&key.to_le_bytes())?;
+        }
+        for value in &values {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(metadata.as_bytes())?;
+        writer.flush()
+    };
+    write().map_err(|err| format!("couldn't write the file {}: {}", path.display(), err))?;
+    Ok(nb_kmers)
+}
+
+// barcodes of a binary index, whose kmer table is memory-mapped (the genome size given
+// overrides the one of the index)
+pub fn load_index(
+    path: &Path,
+    kmer_size: Option<u8>,
+    genome_size: Option<u64>,
+) -> Result<Barcodes, String> {
+    let file =
+        File::open(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    // SAFETY: index files are written once by build-index and not modified while in use
+    // (the mapping is read-only)
+    let mmap = unsafe { Mmap::map(&file) }
+        .map_err(|err| format!("couldn't map {}: {}", path.display(), err))?;
+    let corrupted = || format!("the binary index {} is corrupted", path.display());
+
+    if mmap.len() < HEADER_SIZE || &mmap[..8] != INDEX_MAGIC {
+        return Err(corrupted());
+    }
+    let field = |n: usize| u64::from_le_bytes(mmap[8 + 8 * n..16 + 8 * n].try_into().unwrap());
+    let (k, index_genome_size) = (field(0), field(1));
+    let (nb_slots, nb_kmers, metadata_size) = (field(2) as usize, field(3) as usize, field(4));
+    let metadata_start = nb_slots.checked_mul(12).map(|size| HEADER_SIZE + size);
+    let Some(metadata_start) = metadata_start
+        .filter(|start| (*start as u64).checked_add(metadata_size) == Some(mmap.len() as u64))
+    else {
+        return Err(corrupted());
+    };
+    if k > MAX_INDEX_KMER_SIZE as u64 || !nb_slots.is_power_of_two() || nb_kmers >= nb_slots {
+        return Err(corrupted());
+    }
+    let k = k as u8;
+    if let Some(kmer_size) = kmer_size.filter(|kmer_size| *kmer_size != k) {
+        return Err(format!(
+            "the binary index was built for a kmer size of {} (kmer size given: {})",
+            k, kmer_size
+        ));
+    }
+
+    let metadata = std::str::from_utf8(&mmap[metadata_start..]).map_err(|_| corrupted())?;
+    let mut version = None;
+    let mut ids = Vec::new();
+    let mut ambiguous = HashSet::new();
+    for line in metadata.lines() {
+        match line.split_once('\t') {
+            Some(("version", value)) => version = Some(value.to_string()),
+            Some(("id", value)) => ids.push(value.to_string()),
+            Some(("ambiguous", value)) => {
+                ambiguous.insert(value.to_string());
+            }
+            _ => return Err(corrupted()),
+        }
+    }
+
+    let table = MappedTable {
+        mmap,
+        k: k as usize,
+        nb_slots,
+        nb_kmers,
+    };
+    let genome_size = genome_size.unwrap_or(index_genome_size);
+    if genome_size == 0 {
+        return Err("The genome size is missing from the binary index".to_string());
+    }
+    Ok(Barcodes {
+        index: BarcodeIndex::mapped(table, ids),
+        loci: Vec::new(),
+        ref_kmers: HashMap::new(),
+        kmer_size: k,
+        genome_size,
+        version,
+        ambiguous,
+        spaced_seed: None,
+    })
+}
+
+fn decode_kmer(key: u64, k: usize) -> String {
+    (0..k)
+        .rev()
+        .map(|n| BASES[(key >> (2 * n)) as usize & 3] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // kmers of the barcodes and their barcode ID, in alphabetical order
+    fn kmer_ids(barcodes: &Barcodes) -> Vec<String> {
+        let mut kmers: Vec<String> = barcodes
+            .index
+            .kmers()
+            .map(|(kmer, index)| format!("{} {}", kmer, barcodes.index.id(index)))
+            .collect();
+        kmers.sort();
+        kmers
+    }
+
+    #[test]
+    fn kmers_are_encoded_on_two_bits() {
+        let key = encode_kmer(b"GATTACA").unwrap();
+        assert_eq!(decode_kmer(key, 7), "GATTACA");
+        // (leading As are kept by the kmer size)
+        let key = encode_kmer(b"AACGT").unwrap();
+        assert_eq!(decode_kmer(key, 5), "AACGT");
+        assert_eq!(encode_kmer(b"ACNGT"), None);
+        assert_eq!(encode_kmer(b"ACgT"), None);
+    }
+
+    #[test]
+    fn indexes_are_loaded_back_with_their_barcodes() {
+        let file = "genome_size\t1000\n\
+                    version\tv1\n\
+                    2.2\tAAAAC\tA\tCTTTT\n\
+                    4.1\tCCACG\tT\tGGATC\n\
+                    4.1\tTTGCA\tC\tAGGTA\n";
+        let barcodes = Barcodes::from_string(file, 11).unwrap();
+        let path = std::env::temp_dir().join(format!("fastlin_index_{}.bin", std::process::id()));
+        assert_eq!(write_index(&barcodes, &path), Ok(6));
+        assert!(is_binary_index(&path));
+
+        let loaded = load_index(&path, None, None).unwrap();
+        assert_eq!(loaded.kmer_size, 11);
+        assert_eq!(loaded.genome_size, 1000);
+        assert_eq!(loaded.version, barcodes.version);
+        assert_eq!(kmer_ids(&loaded), kmer_ids(&barcodes));
+        let index = loaded.index.get(b"CCACGTGGATC").unwrap();
+        assert!(loaded.index.id(index).starts_with("4.1__"));
+        assert_eq!(loaded.index.get(b"CCACGAGGATC"), None);
+
+        // the genome size given overrides the one of the index, not the kmer size
+        assert_eq!(
+            load_index(&path, Some(11), Some(500)).unwrap().genome_size,
+            500
+        );
+        let err = load_index(&path, Some(13), None).err().unwrap();
+        assert!(err.contains("kmer size of 11"), "{}", err);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_indexes_are_rejected() {
+        let file = "genome_size\t1000\n2.2\tAAAAC\tA\tCTTTT\n";
+        let barcodes = Barcodes::from_string(file, 11).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("fastlin_corrupted_{}.bin", std::process::id()));
+        write_index(&barcodes, &path).unwrap();
+
+        // truncated index
+        let content = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &content[..content.len() - 1]).unwrap();
+        let err = load_index(&path, None, None).err().unwrap();
+        assert!(err.contains("is corrupted"), "{}", err);
+
+        // barcode file rather than an index
+        std::fs::write(&path, file).unwrap();
+        assert!(!is_binary_index(&path));
+        assert!(load_index(&path, None, None).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        // (kmers of 33 bases don't fit on 64 bits)
+        let file = "genome_size\t1000\n2.2\tACGTACGTACGTACGT\tA\tTGCATGCATGCATGCA\n";
+        let barcodes = Barcodes::from_string(file, 33).unwrap();
+        let err = write_index(&barcodes, &path).err().unwrap();
+        assert!(err.contains("at most 32"), "{}", err);
+    }
+}
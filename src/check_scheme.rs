@@ -0,0 +1,136 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::barcodes::BarcodeIndex;
+use crate::error_rate::snp_positions;
+
+// barcode found a number of times in the reference genome other than expected: absent
+// (e.g. barcode designed on another reference) or repeated (its counts are inflated)
+#[derive(Debug)]
+pub struct SchemeIssue {
+    pub barcode: String,
+    pub occurrences: u32,
+}
+
+// occurrences in the reference sequences of the kmers of each barcode, on both strands. The
+// barcode kmers carry the alternate allele of their SNP, so that the kmers are compared with
+// their SNP position masked
+pub fn barcode_occurrences(index: &BarcodeIndex, k: usize, reference: &[Vec<u8>]) -> Vec<u32> {
+    let masked = |kmer: &[u8]| {
+        let mut kmer = kmer.to_ascii_uppercase();
+        for position in snp_positions(k) {
+            kmer[position] = b'N';
+        }
+        kmer
+    };
+
+    // the index holds the kmers of both strands, so that the reference is scanned forward
+    let mut counts: HashMap<Vec<u8>, u32> = index
+        .kmers()
+        .map(|(kmer, _)| (masked(kmer.as_bytes()), 0))
+        .collect();
+    for sequence in reference.iter().filter(|sequence| sequence.len() >= k) {
+        for kmer in sequence.windows(k) {
+            if let Some(count) = counts.get_mut(&masked(kmer)) {
+                *count += 1;
+            }
+        }
+    }
+
+    let mut occurrences = vec![0; index.ids.len()];
+    for (kmer, barcode) in index.kmers() {
+        occurrences[barcode as usize] += counts[&masked(kmer.as_bytes())];
+    }
+    occurrences
+}
+
+// barcodes not found the expected number of times
+pub fn check_scheme(
+    index: &BarcodeIndex,
+    k: usize,
+    reference: &[Vec<u8>],
+    expected: u32,
+) -> Vec<SchemeIssue> {
+    barcode_occurrences(index, k, reference)
+        .into_iter()
+        .enumerate()
+        .filter(|(_, occurrences)| *occurrences != expected)
+        .map(|(barcode, occurrences)| SchemeIssue {
+            barcode: index.id(barcode as u32).to_string(),
+            occurrences,
+        })
+        .collect()
+}
+
+// barcodes found several times in the reference genome, whose counts are inflated by the
+// reads of their other copies
+pub fn repeated_barcodes(index: &BarcodeIndex, k: usize, reference: &[Vec<u8>]) -> HashSet<String> {
+    barcode_occurrences(index, k, reference)
+        .into_iter()
+        .enumerate()
+        .filter(|(_, occurrences)| *occurrences > 1)
+        .map(|(barcode, _)| index.id(barcode as u32).to_string())
+        .collect()
+}
+
+// IDs of the barcodes reported as repeated in the output of check-scheme (barcode, lineage,
+// occurrences and status)
+pub fn parse_scheme_report(content: &str) -> Result<HashSet<String>, String> {
+    let mut repeated = HashSet::new();
+    for (line_number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 4 {
+            return Err(format!(
+                "line {} of the check-scheme report should have 4 tab-separated fields",
+                line_number + 1
+            ));
+        }
+        if fields[3] == "repeated" {
+            repeated.insert(fields[0].to_string());
+        }
+    }
+    Ok(repeated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::barcodes::Barcodes;
+
+    #[test]
+    fn barcodes_repeated_in_the_reference_are_masked() {
+        let file = "genome_size\t1000\n2\tAAAAC\tA\tCTTTT\n4\tCCACG\tT\tGGATC\n";
+        let mut barcodes = Barcodes::from_string(file, 11).unwrap();
+        // (barcode 2__0 twice with other alleles, 4__0 once on the reverse strand)
+        let reference = [
+            b"GGAAAACGCTTTTGGAAAACTCTTTT".to_vec(),
+            b"GATCCTCGTGG".to_vec(),
+        ];
+        let repeated = repeated_barcodes(&barcodes.index, 11, &reference);
+        assert_eq!(repeated, HashSet::from(["2__0".to_string()]));
+        let issues = check_scheme(&barcodes.index, 11, &reference, 1);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            (issues[0].barcode.as_str(), issues[0].occurrences),
+            ("2__0", 2)
+        );
+
+        assert_eq!(barcodes.drop_barcodes(&repeated), 1);
+        assert_eq!(barcodes.index.get(b"AAAACACTTTT"), None);
+        assert!(barcodes.index.get(b"CCACGTGGATC").is_some());
+    }
+
+    #[test]
+    fn repeated_barcodes_are_read_from_check_scheme_reports() {
+        let report = "#barcode\tlineage\toccurrences\tstatus\n\
+                      2__0\t2\t0\tabsent\n\
+                      4__3\t4\t3\trepeated\n";
+        let repeated = parse_scheme_report(report).unwrap();
+        assert_eq!(repeated, HashSet::from(["4__3".to_string()]));
+
+        let err = parse_scheme_report("2__0\t2\t0\n").err().unwrap();
+        assert!(err.contains("line 1"), "{}", err);
+    }
+}
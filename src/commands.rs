@@ -0,0 +1,394 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use seq_io::fasta::Record as _;
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::setup::{check_kmer_size, load_reference, load_scheme};
+use crate::{
+    BenchArgs, BenchIndexArgs, BuildIndexArgs, CheckSchemeArgs, DesignBarcodesArgs,
+    ExportBarcodesArgs, MergeShardsArgs, QcReportArgs, SimulateArgs,
+};
+use fastlin::analyse_sample::{get_reader, Analysis};
+use fastlin::barcode_source::load_barcodes;
+use fastlin::binary_index::write_index;
+use fastlin::check_scheme::check_scheme;
+use fastlin::design::{design_barcodes, format_scheme, parse_lineage_table};
+use fastlin::index_bench::{bench_index, read_sequences};
+use fastlin::process_barcodes::barcode_lineage;
+use fastlin::qc_report::{parse_controls, qc_report, Controls};
+use fastlin::results::{InputType, SampleResult};
+use fastlin::run_log::warning;
+use fastlin::shards::merge_shards;
+use fastlin::simulation::{
+    lineage_genome, parse_lineage_snps, simulate_reads, write_fastq, LineageSnp, Rng,
+};
+
+// output of a command, written to a file or to stdout
+fn write_output(path: &Option<String>, content: String) -> Result<(), String> {
+    match path {
+        Some(path) => std::fs::write(path, content)
+            .map_err(|err| format!("couldn't write the file {}: {}", path, err)),
+        None => {
+            print!("{}", content);
+            Ok(())
+        }
+    }
+}
+
+fn load_simulation_inputs(
+    reference: &str,
+    lineage_snps: &str,
+) -> Result<(Vec<u8>, Vec<LineageSnp>), String> {
+    let snps = std::fs::read_to_string(lineage_snps)
+        .map_err(|err| format!("couldn't read {}: {}", lineage_snps, err))
+        .and_then(|content| parse_lineage_snps(&content))?;
+
+    // reference genome
+    let mut reader = seq_io::fasta::Reader::new(get_reader(&PathBuf::from(reference)));
+    match reader.next() {
+        Some(Ok(record)) => Ok((record.full_seq().to_vec(), snps)),
+        _ => Err(format!("couldn't read a sequence from {}", reference)),
+    }
+}
+
+pub fn simulate(args: &SimulateArgs) -> Result<(), String> {
+    let (reference, snps) = load_simulation_inputs(&args.reference, &args.lineage_snps)?;
+
+    // lineage genomes and their proportions
+    let mut genomes = Vec::new();
+    for lineage in &args.lineages {
+        let (name, ratio) = match lineage.split_once(':') {
+            Some((name, ratio)) => match ratio.parse::<f64>() {
+                Ok(ratio) if ratio > 0.0 => (name, ratio),
+                _ => return Err(format!("invalid proportion for lineage {}", lineage)),
+            },
+            None => (lineage.as_str(), 1.0),
+        };
+        if !snps.iter().any(|(snp_lineage, _, _)| snp_lineage == name) {
+            eprintln!("   Warning: no SNP for lineage {}", name);
+        }
+        genomes.push((lineage_genome(&reference, &snps, name), ratio));
+    }
+
+    eprint!(" . simulate reads");
+    let mut rng = Rng::new(args.seed);
+    let reads = simulate_reads(
+        &genomes,
+        args.coverage,
+        args.read_length,
+        args.error_rate,
+        &mut rng,
+    );
+    eprintln!("\t({} reads)", reads.len());
+
+    let file = File::create(&args.output)
+        .map_err(|err| format!("couldn't create the file {}: {}", args.output, err))?;
+    let mut writer = GzEncoder::new(std::io::BufWriter::new(file), Compression::default());
+    write_fastq(&mut writer, &reads).expect("Failed to write to file");
+    writer.finish().expect("Failed to write to file");
+    eprintln!("   done.");
+    Ok(())
+}
+
+pub fn build_index(args: &BuildIndexArgs) -> Result<(), String> {
+    check_kmer_size(args.kmer_size)?;
+    let scheme = load_barcodes(Path::new(&args.barcodes), args.kmer_size, args.genome_size)?;
+
+    eprint!(" . write binary index");
+    let nb_kmers = write_index(&scheme, Path::new(&args.output))
+        .map_err(|err| err.to_string())
+        .inspect_err(|_| eprintln!())?;
+    eprintln!("\t({} kmers)", nb_kmers);
+    eprintln!("   done.");
+    Ok(())
+}
+
+pub fn write_qc_report(args: &QcReportArgs) -> Result<(), String> {
+    let controls = match &args.controls {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|err| format!("couldn't read {}: {}", path, err))
+            .and_then(|content| parse_controls(&content))?,
+        None => Controls::new(),
+    };
+    let report = std::fs::read_to_string(&args.results)
+        .map_err(|err| format!("couldn't read {}: {}", args.results, err))
+        .and_then(|content| qc_report(&content, &controls))?;
+    match &args.output {
+        Some(output) => std::fs::write(output, report).expect("Failed to write to file"),
+        None => print!("{}", report),
+    }
+    Ok(())
+}
+
+pub fn run_bench_index(args: &BenchIndexArgs) -> Result<(), String> {
+    check_kmer_size(args.kmer_size)?;
+    let scheme = load_barcodes(Path::new(&args.barcodes), args.kmer_size, None)?;
+    let files: Vec<PathBuf> = args.reads.iter().map(PathBuf::from).collect();
+    if let Some(missing) = files.iter().find(|file| !file.is_file()) {
+        return Err(format!("couldn't read {}", missing.display()));
+    }
+    eprintln!(" . load reads");
+    let reads = read_sequences(&files, args.max_reads);
+    eprintln!(" . benchmark the kmer indexes");
+    let index_path = std::env::temp_dir().join(format!("fastlin_bench_{}.idx", process::id()));
+    let results = reads.and_then(|reads| {
+        eprintln!("   ({} reads, {} kmers)", reads.len(), scheme.index.len());
+        bench_index(&scheme, &reads, &index_path)
+    })?;
+    println!("#index\tbuild_ms\tmemory_mb\tlookups\thits\tlookups_per_s");
+    for result in results {
+        println!(
+            "{}\t{:.1}\t{}\t{}\t{}\t{:.3e}",
+            result.backend,
+            result.build_seconds * 1000.0,
+            result.memory.map_or("NA".to_string(), |memory| format!(
+                "{:.1}",
+                memory as f64 / (1 << 20) as f64
+            )),
+            result.nb_lookups,
+            result.nb_hits,
+            result.lookups_per_second()
+        );
+    }
+    Ok(())
+}
+
+pub fn run_merge_shards(args: &MergeShardsArgs) -> Result<(), String> {
+    let shards = args
+        .shards
+        .iter()
+        .map(|path| {
+            std::fs::read_to_string(path)
+                .map(|content| (path.to_string(), content))
+                .map_err(|err| format!("couldn't read {}: {}", path, err))
+        })
+        .collect::<Result<Vec<(String, String)>, String>>()?;
+    let merged = merge_shards(&shards)?;
+    match &args.output {
+        Some(output) => std::fs::write(output, merged).expect("Failed to write to file"),
+        None => print!("{}", merged),
+    }
+    Ok(())
+}
+
+pub fn export_barcodes(args: &ExportBarcodesArgs) -> Result<(), String> {
+    check_kmer_size(args.kmer_size)?;
+    let scheme = load_scheme(&args.barcodes, args.kmer_size, &args.scheme)?;
+
+    // kmers sorted by barcode, with the lineage and index of their barcode
+    let mut kmers: Vec<(u32, String)> = scheme
+        .index
+        .kmers()
+        .map(|(kmer, index)| (index, kmer.into_owned()))
+        .collect();
+    kmers.sort();
+    let mut output = String::new();
+    if args.format == "tsv" {
+        output.push_str("#kmer\tlineage\tbarcode\tindex\n");
+    }
+    for (index, kmer) in kmers {
+        let id = scheme.index.id(index);
+        let lineage = barcode_lineage(id);
+        match args.format.as_str() {
+            "tsv" => output.push_str(&format!("{}\t{}\t{}\t{}\n", kmer, lineage, id, index)),
+            _ => output.push_str(&format!(
+                ">{} lineage={} index={}\n{}\n",
+                id, lineage, index, kmer
+            )),
+        }
+    }
+    write_output(&args.output, output)
+}
+
+pub fn run_check_scheme(args: &CheckSchemeArgs) -> Result<(), String> {
+    check_kmer_size(args.kmer_size)?;
+    let scheme = load_barcodes(Path::new(&args.barcodes), args.kmer_size, None)?;
+
+    let reference = load_reference(&args.reference)?;
+
+    eprint!(" . check barcodes against the reference");
+    let issues = check_scheme(
+        &scheme.index,
+        scheme.kmer_size as usize,
+        &reference,
+        args.expected,
+    );
+    let nb_absent = issues.iter().filter(|issue| issue.occurrences == 0).count();
+    eprintln!(
+        "\t({} absent, {} found more than expected, {} fewer)",
+        nb_absent,
+        issues
+            .iter()
+            .filter(|issue| issue.occurrences > args.expected)
+            .count(),
+        issues
+            .iter()
+            .filter(|issue| issue.occurrences != 0 && issue.occurrences < args.expected)
+            .count()
+    );
+
+    let mut output = String::from("#barcode\tlineage\toccurrences\tstatus\n");
+    for issue in &issues {
+        let status = match issue.occurrences {
+            0 => "absent",
+            n if n > args.expected => "repeated",
+            _ => "missing copies",
+        };
+        output.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            issue.barcode,
+            barcode_lineage(&issue.barcode),
+            issue.occurrences,
+            status
+        ));
+    }
+    write_output(&args.output, output)?;
+    eprintln!("   done.");
+    Ok(())
+}
+
+pub fn design(args: &DesignBarcodesArgs) -> Result<(), String> {
+    let lineages = std::fs::read_to_string(&args.lineages)
+        .map_err(|err| format!("couldn't read {}: {}", args.lineages, err))
+        .and_then(|content| parse_lineage_table(&content))?;
+
+    eprint!(" . read alignment");
+    let mut reader = seq_io::fasta::Reader::new(get_reader(&PathBuf::from(&args.alignment)));
+    let mut alignment = Vec::new();
+    while let Some(record) = reader.next() {
+        let record = record
+            .map_err(|err| format!("couldn't read {}: {}", args.alignment, err))
+            .inspect_err(|_| eprintln!())?;
+        alignment.push((
+            String::from_utf8_lossy(record.id_bytes()).to_string(),
+            record.full_seq().to_vec(),
+        ));
+    }
+    let nb_assigned = alignment
+        .iter()
+        .filter(|(name, _)| lineages.contains_key(name))
+        .count();
+    eprintln!(
+        "\t({} sequences, {} with a lineage)",
+        alignment.len(),
+        nb_assigned
+    );
+
+    eprint!(" . design barcodes");
+    let barcodes =
+        design_barcodes(&alignment, &lineages, args.flank_length).inspect_err(|_| eprintln!())?;
+    eprintln!("\t({} barcodes)", barcodes.len());
+    let mut designed: HashSet<&str> = HashSet::new();
+    designed.extend(barcodes.iter().map(|barcode| barcode.lineage.as_str()));
+    let mut undesigned: Vec<&String> = lineages
+        .values()
+        .filter(|lineage| !designed.contains(lineage.as_str()))
+        .collect();
+    undesigned.sort();
+    undesigned.dedup();
+    for lineage in undesigned {
+        warning(&format!("no barcode specific to lineage {}", lineage));
+    }
+
+    // genome size: mean ungapped length of the aligned genomes
+    let genome_size = alignment
+        .iter()
+        .map(|(_, seq)| seq.iter().filter(|base| !b"-.".contains(base)).count() as u64)
+        .sum::<u64>()
+        / alignment.len().max(1) as u64;
+    let scheme = format_scheme(
+        &barcodes,
+        genome_size,
+        args.flank_length,
+        args.scheme_version.as_deref(),
+    );
+    std::fs::write(&args.output, scheme)
+        .map_err(|err| format!("couldn't write the file {}: {}", args.output, err))?;
+    eprintln!("   done.");
+    Ok(())
+}
+
+pub fn bench(args: &BenchArgs) -> Result<(), String> {
+    check_kmer_size(args.kmer_size)?;
+    if let Some(ratio) = args.ratios.iter().find(|r| !(0.0..=1.0).contains(*r)) {
+        return Err(format!("the proportion {} is not between 0 and 1", ratio));
+    }
+    let scheme = load_barcodes(Path::new(&args.barcodes), args.kmer_size, None)?;
+    let (reference, snps) = load_simulation_inputs(&args.reference, &args.lineage_snps)?;
+    let major = lineage_genome(&reference, &snps, &args.major);
+    let minor = lineage_genome(&reference, &snps, &args.minor);
+
+    // samples are simulated and typed in memory
+    eprintln!(" . type simulated samples");
+    let mut rng = Rng::new(args.seed);
+    println!("#coverage\tminor_ratio\treplicates\tmixtures_called\tdetection_rate");
+    for coverage in &args.coverages {
+        let (mut mixtures, mut detected_mixtures) = (0, 0);
+        let (mut pure, mut detected_pure) = (0, 0);
+        for ratio in &args.ratios {
+            let genomes = [(major.clone(), 1.0 - ratio), (minor.clone(), *ratio)];
+            let mut nb_called = 0;
+            for _ in 0..args.replicates {
+                let reads = simulate_reads(
+                    &genomes,
+                    *coverage,
+                    args.read_length,
+                    args.error_rate,
+                    &mut rng,
+                );
+                let mut analysis = Analysis::new(&scheme.index);
+                analysis.process_sequences(
+                    reads.iter().map(|(_, seq)| seq.as_slice()),
+                    &scheme.index,
+                    scheme.kmer_size as usize,
+                    None,
+                );
+                analysis.compute_coverage(scheme.genome_size);
+                let result = SampleResult::new(
+                    String::new(),
+                    InputType::Single,
+                    analysis,
+                    args.min_count,
+                    args.n_barcodes,
+                );
+                if result.mixture {
+                    nb_called += 1;
+                }
+            }
+            println!(
+                "{}\t{}\t{}\t{}\t{:.2}",
+                coverage,
+                ratio,
+                args.replicates,
+                nb_called,
+                nb_called as f64 / args.replicates as f64
+            );
+            if *ratio > 0.0 {
+                mixtures += args.replicates;
+                detected_mixtures += nb_called;
+            } else {
+                pure += args.replicates;
+                detected_pure += nb_called;
+            }
+        }
+
+        // sensitivity and specificity of mixture detection at this coverage
+        let sensitivity = match mixtures {
+            0 => "NA".to_string(),
+            n => format!("{:.2}", detected_mixtures as f64 / n as f64),
+        };
+        let specificity = match pure {
+            0 => "NA".to_string(),
+            n => format!("{:.2}", (n - detected_pure) as f64 / n as f64),
+        };
+        eprintln!(
+            "   coverage {}: sensitivity {}, specificity {}",
+            coverage, sensitivity, specificity
+        );
+    }
+    eprintln!("   done.");
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::allele_fractions::AlleleCounts;
+use crate::hierarchy::LineageHierarchy;
+use crate::process_barcodes::{barcode_lineage, lineage_name, LineageCall, LineageNames};
+
+// maximum number of lineages (components) of the mixtures deconvolved by default
+pub const DEFAULT_EM_COMPONENTS: usize = 5;
+
+// iterations of the EM algorithm, stopped once the proportions change by less than the
+// tolerance
+const EM_MAX_ITERATIONS: usize = 1000;
+const EM_TOLERANCE: f64 = 1e-6;
+
+// maximum-likelihood proportions of the lineages called (at most max_components, by depth) in
+// a mixture: the count of each barcode scanned (including the barcodes not found) is Poisson
+// distributed, with a mean proportional to the depths of the components carrying it (the
+// lineage of the barcode and its sublineages), and the reference allele count of each site
+// (if allele fractions are available) to the depths of the other components. The depths are
+// estimated by EM (Richardson-Lucy updates), so that barcodes shared by several components
+// are attributed to them in proportion
+pub fn em_proportions<'a>(
+    calls: &[LineageCall],
+    counts: &HashMap<String, i32>,
+    barcode_ids: impl IntoIterator<Item = &'a String>,
+    sites: &[AlleleCounts],
+    hierarchy: &LineageHierarchy,
+    max_components: usize,
+) -> Vec<(String, f64)> {
+    let mut components: Vec<&LineageCall> = calls.iter().collect();
+    components.sort_by_key(|call| Reverse(call.median));
+    components.truncate(max_components);
+    let carries = |barcode: &str| -> Vec<bool> {
+        components
+            .iter()
+            .map(|call| {
+                let lineage = barcode_lineage(barcode);
+                call.lineage == lineage || hierarchy.is_descendant(&call.lineage, lineage)
+            })
+            .collect()
+    };
+
+    // observations: counts of the barcodes and of the reference alleles of the sites, with
+    // the components contributing to them
+    let mut observations: Vec<(f64, Vec<bool>)> = barcode_ids
+        .into_iter()
+        .map(|id| (counts.get(id).copied().unwrap_or(0) as f64, carries(id)))
+        .collect();
+    observations.extend(sites.iter().map(|site| {
+        let carriers = carries(&site.barcode)
+            .iter()
+            .map(|carries| !carries)
+            .collect();
+        (site.reference as f64, carriers)
+    }));
+    observations.retain(|(_, carriers)| carriers.contains(&true));
+
+    // (number of observations each component contributes to)
+    let exposure: Vec<f64> = (0..components.len())
+        .map(|j| {
+            observations
+                .iter()
+                .filter(|(_, carriers)| carriers[j])
+                .count() as f64
+        })
+        .collect();
+    let mut depths: Vec<f64> = components
+        .iter()
+        .map(|call| call.median.max(1) as f64)
+        .collect();
+    for _ in 0..EM_MAX_ITERATIONS {
+        let mut expected = vec![0.0; components.len()];
+        for (count, carriers) in &observations {
+            let mean: f64 = (0..depths.len())
+                .filter(|j| carriers[*j])
+                .map(|j| depths[j])
+                .sum();
+            if mean > 0.0 {
+                for j in (0..depths.len()).filter(|j| carriers[*j]) {
+                    expected[j] += count * depths[j] / mean;
+                }
+            }
+        }
+        let updated: Vec<f64> = expected
+            .iter()
+            .zip(&exposure)
+            .map(|(expected, exposure)| match *exposure > 0.0 {
+                true => expected / exposure,
+                false => 0.0,
+            })
+            .collect();
+        let change = updated
+            .iter()
+            .zip(&depths)
+            .map(|(updated, depth)| (updated - depth).abs())
+            .fold(0.0, f64::max);
+        depths = updated;
+        if change < EM_TOLERANCE * depths.iter().sum::<f64>().max(1.0) {
+            break;
+        }
+    }
+
+    let total: f64 = depths.iter().sum();
+    components
+        .iter()
+        .zip(depths)
+        .map(|(call, depth)| {
+            let proportion = match total > 0.0 {
+                true => depth / total,
+                false => 0.0,
+            };
+            (call.lineage.clone(), proportion)
+        })
+        .collect()
+}
+
+// column of the output file: lineage (proportion), lineage2 (proportion)
+pub fn format_em_proportions(proportions: &[(String, f64)], names: &LineageNames) -> String {
+    proportions
+        .iter()
+        .map(|(lineage, proportion)| {
+            format!("{} ({:.2})", lineage_name(names, lineage), proportion)
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(lineage: &str, median: i32) -> LineageCall {
+        LineageCall {
+            lineage: lineage.to_string(),
+            median,
+            depth_cv: 0.0,
+            uneven: false,
+            proportion: 0.0,
+            proportion_ci: (0.0, 0.0),
+        }
+    }
+
+    // counts of the barcodes (lineage__index) of each lineage
+    fn counts(lineages: &[(&str, i32)]) -> (HashMap<String, i32>, Vec<String>) {
+        let counts: HashMap<String, i32> = lineages
+            .iter()
+            .flat_map(|(lineage, count)| {
+                (0..5).map(move |n| (format!("{}__{}", lineage, n), *count))
+            })
+            .collect();
+        let mut ids: Vec<String> = counts.keys().cloned().collect();
+        ids.sort();
+        (counts, ids)
+    }
+
+    fn assert_proportions(proportions: &[(String, f64)], expected: &[(&str, f64)]) {
+        assert_eq!(proportions.len(), expected.len());
+        for ((lineage, proportion), (expected_lineage, expected_proportion)) in
+            proportions.iter().zip(expected)
+        {
+            assert_eq!(lineage, expected_lineage);
+            assert!(
+                (proportion - expected_proportion).abs() < 1e-3,
+                "{}",
+                proportion
+            );
+        }
+    }
+
+    #[test]
+    fn proportions_of_distinct_lineages_follow_their_depths() {
+        let (counts, ids) = counts(&[("2", 30), ("4", 10)]);
+        let calls = [call("4", 10), call("2", 30)];
+        let hierarchy = LineageHierarchy::default();
+        let proportions = em_proportions(&calls, &counts, &ids, &[], &hierarchy, 5);
+        assert_proportions(&proportions, &[("2", 0.75), ("4", 0.25)]);
+        assert_eq!(
+            format_em_proportions(&proportions, &LineageNames::new()),
+            "2 (0.75), 4 (0.25)"
+        );
+    }
+
+    #[test]
+    fn barcodes_of_an_ancestor_are_shared_with_its_sublineages() {
+        // (the barcodes of 4 are carried by the reads of 4 and of 4.1)
+        let (counts, ids) = counts(&[("4", 40), ("4.1", 10)]);
+        let calls = [call("4", 40), call("4.1", 10)];
+        let hierarchy = LineageHierarchy::default();
+        let proportions = em_proportions(&calls, &counts, &ids, &[], &hierarchy, 5);
+        assert_proportions(&proportions, &[("4", 0.75), ("4.1", 0.25)]);
+    }
+
+    #[test]
+    fn reference_alleles_count_for_the_other_components() {
+        let (counts, ids) = counts(&[("2", 20)]);
+        let sites: Vec<AlleleCounts> = ids
+            .iter()
+            .map(|id| AlleleCounts {
+                barcode: id.clone(),
+                alt: 20,
+                reference: 60,
+            })
+            .collect();
+        let calls = [call("2", 20), call("4", 5)];
+        let hierarchy = LineageHierarchy::default();
+        let proportions = em_proportions(&calls, &counts, &ids, &sites, &hierarchy, 5);
+        assert_proportions(&proportions, &[("2", 0.25), ("4", 0.75)]);
+    }
+
+    #[test]
+    fn only_the_deepest_components_are_deconvolved() {
+        let (counts, ids) = counts(&[("1", 5), ("2", 30), ("4", 10)]);
+        let calls = [call("1", 5), call("2", 30), call("4", 10)];
+        let hierarchy = LineageHierarchy::default();
+        let proportions = em_proportions(&calls, &counts, &ids, &[], &hierarchy, 2);
+        assert_proportions(&proportions, &[("2", 0.75), ("4", 0.25)]);
+    }
+}
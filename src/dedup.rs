@@ -0,0 +1,153 @@
+use ahash::AHasher;
+use seq_io::fastq::{Reader, Record};
+use std::collections::HashSet;
+use std::hash::Hasher;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::analyse_sample::{get_reader, NamedReader};
+
+// number of bases of each read hashed to find duplicates (--dedup-length)
+pub const DEFAULT_DEDUP_LENGTH: usize = 50;
+
+// proportion of duplicated reads above which a sample is flagged (over-amplified library)
+pub const MAX_DUPLICATION_RATE: f64 = 0.3;
+
+// duplicated fragments: reads (pairs of reads for paired samples) whose first bases are
+// identical to those of a previous read (pair), e.g. PCR or optical duplicates. Flags are
+// given by read index, shared by the two files of paired samples
+fn find_duplicates(files: &[PathBuf], length: usize) -> Result<(Vec<bool>, u64), String> {
+    let mut readers: Vec<Reader<Box<dyn BufRead + Send>>> = files
+        .iter()
+        .map(|path| Reader::new(get_reader(path)))
+        .collect();
+    let mut seen: HashSet<u64> = HashSet::new();
+    let mut duplicates = Vec::new();
+    let mut nb_duplicates = 0;
+    'reads: loop {
+        let mut hasher = AHasher::default();
+        for reader in readers.iter_mut() {
+            match reader.next() {
+                Some(Ok(record)) => {
+                    let seq = record.seq();
+                    hasher.write(&seq[..seq.len().min(length)]);
+                    hasher.write_u8(b'\n');
+                }
+                Some(Err(err)) => return Err(err.to_string()),
+                None => break 'reads,
+            }
+        }
+        let duplicate = !seen.insert(hasher.finish());
+        nb_duplicates += u64::from(duplicate);
+        duplicates.push(duplicate);
+    }
+    Ok((duplicates, nb_duplicates))
+}
+
+// readers of the FASTQ files of a sample without their duplicated reads, and number of reads
+// (or pairs of reads) removed. The files are read twice (duplicates are found first), and
+// read as they are if they can't be parsed (the error being then reported by the scan)
+pub fn dedup_readers(mut files: Vec<PathBuf>, length: usize) -> (Vec<NamedReader>, u64) {
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    let (duplicates, nb_duplicates) = match find_duplicates(&files, length) {
+        Ok((duplicates, nb_duplicates)) => (Some(Arc::new(duplicates)), nb_duplicates),
+        Err(_) => (None, 0),
+    };
+    let readers = files
+        .iter()
+        .map(|path| {
+            let reader = get_reader(path);
+            let reader: Box<dyn BufRead + Send> = match &duplicates {
+                Some(duplicates) => Box::new(BufReader::new(DedupReader {
+                    inner: reader,
+                    duplicates: duplicates.clone(),
+                    index: 0,
+                    pending: Vec::new(),
+                    position: 0,
+                })),
+                None => reader,
+            };
+            (path.to_string_lossy().to_string(), reader)
+        })
+        .collect();
+    (readers, nb_duplicates)
+}
+
+// FASTQ records (4 lines) of a reader, without the duplicated ones
+struct DedupReader {
+    inner: Box<dyn BufRead + Send>,
+    duplicates: Arc<Vec<bool>>,
+    index: usize,
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl Read for DedupReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.pending.len() {
+            self.pending.clear();
+            self.position = 0;
+            for _ in 0..4 {
+                if self.inner.read_until(b'\n', &mut self.pending)? == 0 {
+                    break;
+                }
+            }
+            if self.pending.is_empty() {
+                return Ok(0);
+            }
+            // (records beyond the flags, e.g. a truncated mate file, are kept)
+            if self.duplicates.get(self.index) == Some(&true) {
+                self.pending.clear();
+            }
+            self.index += 1;
+        }
+        let size = buf.len().min(self.pending.len() - self.position);
+        buf[..size].copy_from_slice(&self.pending[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicated_pairs_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("fastlin_dedup_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fastq = |seqs: &[&str]| -> String {
+            seqs.iter()
+                .enumerate()
+                .map(|(n, seq)| format!("@r{}\n{}\n+\n{}\n", n, seq, "I".repeat(seq.len())))
+                .collect()
+        };
+        // (the third pair duplicates the first, the second only shares its first mate)
+        let files = [dir.join("s_1.fastq"), dir.join("s_2.fastq")];
+        std::fs::write(
+            &files[0],
+            fastq(&["ACGTACGT", "ACGTACGT", "ACGTACGT", "TTTT"]),
+        )
+        .unwrap();
+        std::fs::write(
+            &files[1],
+            fastq(&["GGGGCCCC", "GGGGAAAA", "GGGGCCCC", "CCCC"]),
+        )
+        .unwrap();
+
+        let (readers, nb_duplicates) = dedup_readers(files.to_vec(), DEFAULT_DEDUP_LENGTH);
+        assert_eq!(nb_duplicates, 1);
+        for (_, mut reader) in readers {
+            let mut content = String::new();
+            reader.read_to_string(&mut content).unwrap();
+            let names: Vec<&str> = content.lines().step_by(4).collect();
+            assert_eq!(names, ["@r0", "@r1", "@r3"]);
+        }
+
+        // (only the first bases are compared)
+        let (_, nb_duplicates) = dedup_readers(files.to_vec(), 4);
+        assert_eq!(nb_duplicates, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
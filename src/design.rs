@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::process_barcodes::is_sublineage;
+
+// barcode derived from an alignment: SNP allele specific to a lineage, with its flanks
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesignedBarcode {
+    pub lineage: String,
+    pub left_flank: String,
+    pub allele: char,
+    pub right_flank: String,
+}
+
+// lineage of each sequence of the alignment, from a tab-separated file (sequence name and
+// lineage). Sequences without lineage are used as outgroups
+pub fn parse_lineage_table(content: &str) -> Result<HashMap<String, String>, String> {
+    let mut lineages = HashMap::new();
+    for (line_number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, lineage)) = line.split_once('\t') else {
+            return Err(format!(
+                "line {} of the lineage table should have 2 tab-separated fields",
+                line_number + 1
+            ));
+        };
+        lineages.insert(name.trim().to_string(), lineage.trim().to_string());
+    }
+    Ok(lineages)
+}
+
+// barcodes of the lineages of aligned genomes: alleles carried by all the sequences of a
+// lineage (and of its sublineages) and by no other sequence, at columns without gaps or
+// ambiguous bases, with flanks identical in all the sequences carrying the allele
+pub fn design_barcodes(
+    alignment: &[(String, Vec<u8>)],
+    lineages: &HashMap<String, String>,
+    flank_length: usize,
+) -> Result<Vec<DesignedBarcode>, String> {
+    let Some((_, first)) = alignment.first() else {
+        return Err("the alignment is empty".to_string());
+    };
+    let length = first.len();
+    if let Some((name, _)) = alignment.iter().find(|(_, seq)| seq.len() != length) {
+        return Err(format!(
+            "the sequence {} doesn't have the length of the alignment",
+            name
+        ));
+    }
+
+    // sequences of each lineage, including its sublineages (lineages sharing the same
+    // sequences are named after the ancestor)
+    let names: Vec<Option<&String>> = alignment
+        .iter()
+        .map(|(name, _)| lineages.get(name))
+        .collect();
+    let mut members: BTreeMap<&String, Vec<usize>> = BTreeMap::new();
+    for lineage in names.iter().flatten() {
+        members.entry(lineage).or_insert_with(|| {
+            (0..names.len())
+                .filter(|n| names[*n].is_some_and(|other| is_sublineage(other, lineage)))
+                .collect()
+        });
+    }
+    let mut lineage_of: HashMap<&Vec<usize>, &String> = HashMap::new();
+    for (lineage, sequences) in &members {
+        lineage_of
+            .entry(sequences)
+            .and_modify(|other| {
+                if is_sublineage(other, lineage) {
+                    *other = lineage;
+                }
+            })
+            .or_insert(lineage);
+    }
+
+    let mut barcodes = Vec::new();
+    for column in 0..length {
+        let bases: Vec<u8> = alignment
+            .iter()
+            .map(|(_, seq)| seq[column].to_ascii_uppercase())
+            .collect();
+        // (SNPs only)
+        if bases.iter().any(|base| !b"ACGT".contains(base))
+            || bases.iter().all(|base| *base == bases[0])
+        {
+            continue;
+        }
+        for allele in b"ACGT" {
+            let carriers: Vec<usize> = (0..bases.len()).filter(|n| bases[*n] == *allele).collect();
+            let Some(lineage) = lineage_of.get(&carriers) else {
+                continue;
+            };
+            let sequences: Vec<&[u8]> = carriers
+                .iter()
+                .map(|n| alignment[*n].1.as_slice())
+                .collect();
+            let left = conserved_flank(&sequences, (0..column).rev(), flank_length);
+            let right = conserved_flank(&sequences, column + 1..length, flank_length);
+            if let (Some(mut left), Some(right)) = (left, right) {
+                left.reverse();
+                barcodes.push(DesignedBarcode {
+                    lineage: lineage.to_string(),
+                    left_flank: String::from_utf8_lossy(&left).to_string(),
+                    allele: *allele as char,
+                    right_flank: String::from_utf8_lossy(&right).to_string(),
+                });
+            }
+        }
+    }
+    Ok(barcodes)
+}
+
+fn conserved_flank(
+    sequences: &[&[u8]],
+    columns: impl Iterator<Item = usize>,
+    flank_length: usize,
+) -> Option<Vec<u8>> {
+    // bases of the flank (in the order of the columns), gaps being shared by all the sequences
+    let mut flank = Vec::with_capacity(flank_length);
+    for column in columns {
+        let base = sequences[0][column].to_ascii_uppercase();
+        if sequences
+            .iter()
+            .any(|seq| seq[column].to_ascii_uppercase() != base)
+        {
+            return None;
+        }
+        match base {
+            b'-' | b'.' => continue,
+            b'A' | b'C' | b'G' | b'T' => flank.push(base),
+            _ => return None,
+        }
+        if flank.len() == flank_length {
+            return Some(flank);
+        }
+    }
+    None
+}
+
+// barcode file of the designed barcodes (genome size: mean ungapped length of the sequences)
+pub fn format_scheme(
+    barcodes: &[DesignedBarcode],
+    genome_size: u64,
+    flank_length: usize,
+    version: Option<&str>,
+) -> String {
+    let mut scheme = format!(
+        "genome_size\t{}\nflank_length\t{}\n",
+        genome_size, flank_length
+    );
+    if let Some(version) = version {
+        scheme.push_str(&format!("version\t{}\n", version));
+    }
+    for barcode in barcodes {
+        scheme.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            barcode.lineage, barcode.left_flank, barcode.allele, barcode.right_flank
+        ));
+    }
+    scheme
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lineage_tables_are_parsed() {
+        let lineages = parse_lineage_table("# name\tlineage\ns1\t4.1\n\ns2\t 2.2 \n").unwrap();
+        assert_eq!(lineages.len(), 2);
+        assert_eq!(lineages["s1"], "4.1");
+        assert_eq!(lineages["s2"], "2.2");
+
+        let err = parse_lineage_table("s1\t4.1\ns2 2.2\n").err().unwrap();
+        assert!(err.contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn barcodes_are_the_alleles_specific_to_a_lineage() {
+        let alignment: Vec<(String, Vec<u8>)> = [
+            ("s1", "ACGTAACC"),
+            ("s2", "ACGTAACC"),
+            ("s3", "ACGCAGCC"),
+            ("s4", "ACGCAACC"),
+        ]
+        .iter()
+        .map(|(name, seq)| (name.to_string(), seq.as_bytes().to_vec()))
+        .collect();
+        // (s4 is an outgroup, so the C of the 4th column isn't specific to lineage 2)
+        let lineages = parse_lineage_table("s1\t1\ns2\t1\ns3\t2\n").unwrap();
+        let barcodes = design_barcodes(&alignment, &lineages, 2).unwrap();
+        let barcode = |lineage: &str, left: &str, allele, right: &str| DesignedBarcode {
+            lineage: lineage.to_string(),
+            left_flank: left.to_string(),
+            allele,
+            right_flank: right.to_string(),
+        };
+        assert_eq!(
+            barcodes,
+            [barcode("1", "CG", 'T', "AA"), barcode("2", "CA", 'G', "CC")]
+        );
+
+        // flanks too short for the SNPs
+        assert_eq!(design_barcodes(&alignment, &lineages, 4), Ok(vec![]));
+
+        let mut truncated = alignment.clone();
+        truncated[2].1.pop();
+        let err = design_barcodes(&truncated, &lineages, 2).err().unwrap();
+        assert!(err.contains("s3"), "{}", err);
+        assert!(design_barcodes(&[], &lineages, 2).is_err());
+    }
+
+    #[test]
+    fn designed_barcodes_are_written_as_a_barcode_file() {
+        let barcodes = [DesignedBarcode {
+            lineage: "4.1".to_string(),
+            left_flank: "ACG".to_string(),
+            allele: 'T',
+            right_flank: "GCA".to_string(),
+        }];
+        assert_eq!(
+            format_scheme(&barcodes, 4400000, 3, Some("v2")),
+            "genome_size\t4400000\nflank_length\t3\nversion\tv2\n4.1\tACG\tT\tGCA\n"
+        );
+        assert_eq!(
+            format_scheme(&[], 1000, 3, None),
+            "genome_size\t1000\nflank_length\t3\n"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+// 2-bit encoding of nucleotides, computed from the ASCII code so that it can be vectorised:
+// A = 0, C = 1, T = 2, G = 3 (lowercase bases included), anything else being INVALID_BASE
+pub const INVALID_BASE: u8 = 4;
+
+// number of bases encoded at once (bytes of a SSE2 register)
+const LANES: usize = 16;
+
+// number of bases encoded at once when scanning a sequence (long sequences are encoded block
+// by block)
+pub const BLOCK_SIZE: usize = 512;
+
+pub fn encode_bases(seq: &[u8], codes: &mut [u8]) {
+    // codes must be at least as long as seq
+    let mut chunks = seq.chunks_exact(LANES);
+    let mut n = 0;
+    for chunk in chunks.by_ref() {
+        encode_chunk(chunk, &mut codes[n..n + LANES]);
+        n += LANES;
+    }
+    for (base, code) in chunks.remainder().iter().zip(codes[n..].iter_mut()) {
+        *code = encode_base(*base);
+    }
+}
+
+pub fn encode_base(base: u8) -> u8 {
+    let lower = base | 0x20;
+    if matches!(lower, b'a' | b'c' | b'g' | b't') {
+        (lower >> 1) & 3
+    } else {
+        INVALID_BASE
+    }
+}
+
+// kmer of at most 32 bases encoded on 64 bits (None if it has other bases than A, C, G and T,
+// lowercase bases not being barcode bases)
+pub fn encode_kmer(kmer: &[u8]) -> Option<u64> {
+    let mut key: u64 = 0;
+    for base in kmer {
+        let code = encode_base(*base);
+        if code == INVALID_BASE || base.is_ascii_lowercase() {
+            return None;
+        }
+        key = (key << 2) | code as u64;
+    }
+    Some(key)
+}
+
+// mask keeping the codes of the last k bases of an encoded sequence
+pub fn kmer_mask(k: usize) -> u64 {
+    if k >= 32 {
+        u64::MAX
+    } else {
+        (1 << (2 * k)) - 1
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn encode_chunk(chunk: &[u8], codes: &mut [u8]) {
+    use std::arch::x86_64::*;
+
+    // SAFETY: SSE2 is available on every x86_64 processor, and both slices hold LANES bytes
+    // (unaligned loads and stores)
+    unsafe {
+        let bases = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+        let lower = _mm_or_si128(bases, _mm_set1_epi8(0x20));
+        let valid = _mm_or_si128(
+            _mm_or_si128(
+                _mm_cmpeq_epi8(lower, _mm_set1_epi8(b'a' as i8)),
+                _mm_cmpeq_epi8(lower, _mm_set1_epi8(b'c' as i8)),
+            ),
+            _mm_or_si128(
+                _mm_cmpeq_epi8(lower, _mm_set1_epi8(b'g' as i8)),
+                _mm_cmpeq_epi8(lower, _mm_set1_epi8(b't' as i8)),
+            ),
+        );
+        // (bits shifted in from the neighbouring byte are masked out)
+        let encoded = _mm_and_si128(_mm_srli_epi16(lower, 1), _mm_set1_epi8(3));
+        let result = _mm_or_si128(
+            _mm_and_si128(valid, encoded),
+            _mm_andnot_si128(valid, _mm_set1_epi8(INVALID_BASE as i8)),
+        );
+        _mm_storeu_si128(codes.as_mut_ptr() as *mut __m128i, result);
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn encode_chunk(chunk: &[u8], codes: &mut [u8]) {
+    // branch-free loop over a fixed number of bases, vectorised by the compiler (e.g. NEON)
+    for (base, code) in chunk.iter().zip(codes.iter_mut()) {
+        let lower = *base | 0x20;
+        let valid = (lower == b'a') | (lower == b'c') | (lower == b'g') | (lower == b't');
+        *code = if valid {
+            (lower >> 1) & 3
+        } else {
+            INVALID_BASE
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bases_are_encoded_on_two_bits() {
+        let codes: Vec<u8> = b"ACTGactgNn-"
+            .iter()
+            .map(|base| encode_base(*base))
+            .collect();
+        assert_eq!(codes, [0, 1, 2, 3, 0, 1, 2, 3, 4, 4, 4]);
+    }
+
+    #[test]
+    fn chunks_and_remainder_are_encoded_alike() {
+        // (every byte value, in chunks of LANES bases and a shorter remainder)
+        let seq: Vec<u8> = (0..=255).chain(b"ACGTNacgtn".iter().copied()).collect();
+        let mut codes = vec![0; seq.len()];
+        encode_bases(&seq, &mut codes);
+        let expected: Vec<u8> = seq.iter().map(|base| encode_base(*base)).collect();
+        assert_eq!(codes, expected);
+    }
+
+    #[test]
+    fn kmers_are_packed_on_two_bits_per_base() {
+        assert_eq!(encode_kmer(b"ACTG"), Some(0b00_01_10_11));
+        assert_eq!(encode_kmer(b"ACNG"), None);
+        assert_eq!(encode_kmer(b"acTG"), None);
+        let kmer = [b'G'; 32];
+        assert_eq!(encode_kmer(&kmer), Some(u64::MAX));
+        assert_eq!(kmer_mask(32), u64::MAX);
+        assert_eq!(kmer_mask(3), 0b111111);
+    }
+}
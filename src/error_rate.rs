@@ -0,0 +1,51 @@
+use std::collections::HashSet;
+
+use crate::barcodes::BarcodeIndex;
+
+// error rate above which the sequencing errors of a sample are reported
+pub const MAX_ERROR_RATE: f64 = 0.01;
+
+// kmers at one mismatch from a barcode kmer: reads covering a barcode give the barcode kmer,
+// or one of these kmers when a base is miscalled. Mismatches at the SNP position (the alleles
+// of the other lineages) and kmers of other barcodes are excluded
+pub fn neighbour_kmers(barcodes: &BarcodeIndex, k: usize) -> HashSet<String> {
+    let snp_positions = snp_positions(k);
+    let mut neighbours = HashSet::new();
+    for (kmer, _) in barcodes.kmers() {
+        let mut neighbour = kmer.as_bytes().to_vec();
+        for n in (0..neighbour.len()).filter(|n| !snp_positions.contains(n)) {
+            let base = neighbour[n];
+            for other in [b'A', b'C', b'G', b'T'].into_iter().filter(|b| *b != base) {
+                neighbour[n] = other;
+                if barcodes.get(&neighbour).is_none() {
+                    neighbours.insert(String::from_utf8_lossy(&neighbour).to_string());
+                }
+            }
+            neighbour[n] = base;
+        }
+    }
+    neighbours
+}
+
+// per-base error rate from the occurrences of barcode kmers and of their neighbours: a kmer
+// is read without error with a probability (1 - e)^k, and with a single error at one of the
+// p positions of the neighbours with a probability p.e.(1 - e)^(k - 1), so that
+// neighbours / barcodes = p.e / (1 - e) (None without barcode occurrences)
+pub fn estimate_error_rate(
+    nb_barcode_kmers: u64,
+    nb_neighbour_kmers: u64,
+    k: usize,
+) -> Option<f64> {
+    if nb_barcode_kmers == 0 {
+        return None;
+    }
+    let nb_positions = (0..k).filter(|n| !snp_positions(k).contains(n)).count();
+    let ratio = nb_neighbour_kmers as f64 / nb_barcode_kmers as f64;
+    Some(ratio / (nb_positions as f64 + ratio))
+}
+
+pub(crate) fn snp_positions(k: usize) -> [usize; 2] {
+    // SNP position on both strands (see Barcodes::from_string)
+    let left_size = (k - 1) / 2;
+    [left_size, k - 1 - left_size]
+}
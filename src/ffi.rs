@@ -0,0 +1,308 @@
+//! C ABI to embed fastlin in non-Rust software (see include/fastlin.h).
+//!
+//! All functions return one of the `FASTLIN_*` codes below. Objects allocated by fastlin
+//! must be released with the matching `fastlin_free_*` function.
+
+use std::ffi::{c_char, CStr, CString};
+use std::fs::read_to_string;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+
+use crate::analyse_sample::scan_reads;
+use crate::barcodes::{BarcodeIndex, Barcodes, MIN_KMER_SIZE};
+use crate::input_files::get_data_type;
+use crate::process_barcodes::{format_data, format_lineages};
+use crate::results::{InputType, SampleResult};
+
+pub const FASTLIN_OK: i32 = 0;
+pub const FASTLIN_ERR_NULL_POINTER: i32 = 1;
+pub const FASTLIN_ERR_INVALID_STRING: i32 = 2;
+pub const FASTLIN_ERR_KMER_SIZE: i32 = 3;
+pub const FASTLIN_ERR_SCHEME: i32 = 4;
+pub const FASTLIN_ERR_INPUT: i32 = 5;
+pub const FASTLIN_ERR_PANIC: i32 = 6;
+
+pub const FASTLIN_DATA_ASSEMBLY: i32 = 0;
+pub const FASTLIN_DATA_SINGLE: i32 = 1;
+pub const FASTLIN_DATA_PAIRED: i32 = 2;
+
+// path of a C string: any bytes on Unix, UTF-8 elsewhere
+unsafe fn c_path(path: *const c_char) -> Option<PathBuf> {
+    let path = CStr::from_ptr(path);
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Some(PathBuf::from(std::ffi::OsStr::from_bytes(path.to_bytes())))
+    }
+    #[cfg(not(unix))]
+    {
+        path.to_str().ok().map(PathBuf::from)
+    }
+}
+
+/// Opaque handle on a loaded barcode scheme.
+pub struct FastlinScheme {
+    barcodes: BarcodeIndex,
+    genome_size: u64,
+    kmer_size: u8,
+}
+
+/// Result of a sample, with the same fields as a row of the output file.
+#[repr(C)]
+pub struct FastlinResult {
+    pub data_type: i32,
+    pub coverage: u32,
+    pub mixture: i32,
+    pub lineages: *mut c_char,
+    pub log_barcodes: *mut c_char,
+    pub log_errors: *mut c_char,
+    pub mean_read_length: f64,
+    pub median_read_length: u64,
+}
+
+/// Loads the barcode scheme `path` for the given kmer size.
+///
+/// # Safety
+///
+/// `path` must be a valid NUL-terminated string and `out` a valid pointer. On success,
+/// `*out` must later be released with `fastlin_free_scheme`.
+#[no_mangle]
+pub unsafe extern "C" fn fastlin_load_scheme(
+    path: *const c_char,
+    kmer_size: u8,
+    out: *mut *mut FastlinScheme,
+) -> i32 {
+    if path.is_null() || out.is_null() {
+        return FASTLIN_ERR_NULL_POINTER;
+    }
+    let path = match c_path(path) {
+        Some(path) => path,
+        None => return FASTLIN_ERR_INVALID_STRING,
+    };
+    if kmer_size < MIN_KMER_SIZE {
+        return FASTLIN_ERR_KMER_SIZE;
+    }
+    let content = match read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return FASTLIN_ERR_SCHEME,
+    };
+
+    // malformed barcode files can panic, which must not unwind into C code
+    match catch_unwind(|| Barcodes::from_string(&content, kmer_size)) {
+        Ok(Ok(barcodes)) => {
+            let scheme = FastlinScheme {
+                barcodes: barcodes.index,
+                genome_size: barcodes.genome_size,
+                kmer_size,
+            };
+            *out = Box::into_raw(Box::new(scheme));
+            FASTLIN_OK
+        }
+        Ok(Err(_)) | Err(_) => FASTLIN_ERR_SCHEME,
+    }
+}
+
+/// Types the sample made of the `n_paths` files in `paths` (one assembly, one single-end
+/// FASTQ or two paired-end FASTQ files). A `max_cov` of 0 means no coverage limit.
+///
+/// # Safety
+///
+/// `scheme` must come from `fastlin_load_scheme`, `paths` must point to `n_paths` valid
+/// NUL-terminated strings and `out` must be a valid pointer. On success, `*out` must later
+/// be released with `fastlin_free_result`.
+#[no_mangle]
+pub unsafe extern "C" fn fastlin_scan_files(
+    scheme: *const FastlinScheme,
+    paths: *const *const c_char,
+    n_paths: usize,
+    min_count: i32,
+    n_barcodes: usize,
+    max_cov: u64,
+    out: *mut *mut FastlinResult,
+) -> i32 {
+    if scheme.is_null() || paths.is_null() || out.is_null() {
+        return FASTLIN_ERR_NULL_POINTER;
+    }
+    let scheme = &*scheme;
+
+    let mut list_files = Vec::with_capacity(n_paths);
+    for i in 0..n_paths {
+        let path = *paths.add(i);
+        if path.is_null() {
+            return FASTLIN_ERR_NULL_POINTER;
+        }
+        match c_path(path) {
+            Some(path) => list_files.push(path),
+            None => return FASTLIN_ERR_INVALID_STRING,
+        }
+    }
+
+    let data_type = match get_data_type(&list_files) {
+        Ok(data_type) => data_type,
+        Err(_) => return FASTLIN_ERR_INPUT,
+    };
+    let kmer_limit = match max_cov {
+        0 => None,
+        // (saturating: limits beyond the u64 range of kmers are no limit)
+        limit => Some(limit.saturating_mul(scheme.genome_size)),
+    };
+    let (kmer_limit, min_count) = match &data_type {
+        InputType::Assembly => (None, 1),
+        InputType::Single | InputType::Paired => (kmer_limit, min_count),
+    };
+
+    // (unreadable files fail the sample, their error being reported in log_errors, but a panic
+    // must still not unwind into C code)
+    let scan = catch_unwind(AssertUnwindSafe(|| {
+        let analysis = scan_reads(
+            list_files,
+            &scheme.barcodes,
+            &scheme.kmer_size,
+            kmer_limit,
+            scheme.genome_size,
+            None,
+            None,
+        );
+        SampleResult::new(String::new(), data_type, analysis, min_count, n_barcodes)
+    }));
+    let sample_result = match scan {
+        Ok(sample_result) => sample_result,
+        Err(_) => return FASTLIN_ERR_PANIC,
+    };
+
+    let result = FastlinResult {
+        data_type: match sample_result.data_type {
+            InputType::Assembly => FASTLIN_DATA_ASSEMBLY,
+            InputType::Single => FASTLIN_DATA_SINGLE,
+            InputType::Paired => FASTLIN_DATA_PAIRED,
+        },
+        coverage: sample_result.k_cov,
+        mixture: sample_result.mixture as i32,
+        lineages: to_c_string(format_lineages(&sample_result.lineages)),
+        log_barcodes: to_c_string(format_data(&sample_result.log_barcodes)),
+        log_errors: to_c_string(sample_result.log_errors),
+        mean_read_length: sample_result.mean_read_length,
+        median_read_length: sample_result.median_read_length as u64,
+    };
+    *out = Box::into_raw(Box::new(result));
+    FASTLIN_OK
+}
+
+/// Releases a result returned by `fastlin_scan_files`.
+///
+/// # Safety
+///
+/// `result` must come from `fastlin_scan_files` (or be NULL) and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn fastlin_free_result(result: *mut FastlinResult) {
+    if result.is_null() {
+        return;
+    }
+    let result = Box::from_raw(result);
+    for string in [result.lineages, result.log_barcodes, result.log_errors] {
+        if !string.is_null() {
+            drop(CString::from_raw(string));
+        }
+    }
+}
+
+/// Releases a scheme returned by `fastlin_load_scheme`.
+///
+/// # Safety
+///
+/// `scheme` must come from `fastlin_load_scheme` (or be NULL) and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn fastlin_free_scheme(scheme: *mut FastlinScheme) {
+    if !scheme.is_null() {
+        drop(Box::from_raw(scheme));
+    }
+}
+
+fn to_c_string(string: String) -> *mut c_char {
+    match CString::new(string) {
+        Ok(string) => string.into_raw(),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // path of a file of the test data, as a C string
+    fn data_path(name: &str) -> CString {
+        let path = format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name);
+        CString::new(path).unwrap()
+    }
+
+    #[test]
+    fn samples_are_typed_through_the_c_functions() {
+        let mut scheme = ptr::null_mut();
+        let path = data_path("barcodes.txt");
+        assert_eq!(
+            unsafe { fastlin_load_scheme(path.as_ptr(), 25, &mut scheme) },
+            FASTLIN_OK
+        );
+
+        let files = [
+            data_path("reads/pure_1.fastq.gz"),
+            data_path("reads/pure_2.fastq.gz"),
+        ];
+        let paths: Vec<*const c_char> = files.iter().map(|file| file.as_ptr()).collect();
+        let mut result = ptr::null_mut();
+        let code = unsafe { fastlin_scan_files(scheme, paths.as_ptr(), 2, 4, 3, 0, &mut result) };
+        assert_eq!(code, FASTLIN_OK);
+        let (data_type, mixture, lineages, log_errors) = unsafe {
+            (
+                (*result).data_type,
+                (*result).mixture,
+                CStr::from_ptr((*result).lineages).to_str().unwrap(),
+                CStr::from_ptr((*result).log_errors).to_str().unwrap(),
+            )
+        };
+        assert_eq!(data_type, FASTLIN_DATA_PAIRED);
+        assert_eq!(mixture, 0);
+        assert_eq!(lineages, "4.1.2 (20)");
+        assert_eq!(log_errors, "");
+        unsafe {
+            fastlin_free_result(result);
+            fastlin_free_scheme(scheme);
+        }
+    }
+
+    #[test]
+    fn invalid_arguments_are_reported_by_their_codes() {
+        let mut scheme = ptr::null_mut();
+        let path = data_path("barcodes.txt");
+        let missing = data_path("missing.txt");
+        unsafe {
+            assert_eq!(
+                fastlin_load_scheme(path.as_ptr(), 9, &mut scheme),
+                FASTLIN_ERR_KMER_SIZE
+            );
+            assert_eq!(
+                fastlin_load_scheme(missing.as_ptr(), 25, &mut scheme),
+                FASTLIN_ERR_SCHEME
+            );
+            assert_eq!(
+                fastlin_load_scheme(ptr::null(), 25, &mut scheme),
+                FASTLIN_ERR_NULL_POINTER
+            );
+        }
+        assert!(scheme.is_null());
+
+        assert_eq!(
+            unsafe { fastlin_load_scheme(path.as_ptr(), 25, &mut scheme) },
+            FASTLIN_OK
+        );
+        // (three files make neither a single nor a paired sample)
+        let file = data_path("reads/single.fastq.gz");
+        let paths = [file.as_ptr(); 3];
+        let mut result = ptr::null_mut();
+        let code = unsafe { fastlin_scan_files(scheme, paths.as_ptr(), 3, 4, 3, 0, &mut result) };
+        assert_eq!(code, FASTLIN_ERR_INPUT);
+        assert!(result.is_null());
+        unsafe { fastlin_free_scheme(scheme) };
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+// parent of each lineage, from a tab-separated file (parent, child); without this file,
+// sublineages are recognised by their names starting with the name of their parent
+// (calls can be collapsed to the lineages of a given level of the tree, 1 being the root)
+#[derive(Clone, Debug, Default)]
+pub struct LineageHierarchy {
+    parents: HashMap<String, String>,
+    level: Option<usize>,
+}
+
+impl LineageHierarchy {
+    pub fn from_string(content: &str) -> Result<Self, String> {
+        let mut parents: HashMap<String, String> = HashMap::new();
+
+        for (line_number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((parent, child)) = line.split_once('\t') else {
+                return Err(format!(
+                    "line {} of the lineage hierarchy should contain a parent and a child lineage (tab-separated)",
+                    line_number + 1
+                ));
+            };
+            let (parent, child) = (parent.trim(), child.trim());
+            if let Some(previous) = parents.insert(child.to_string(), parent.to_string()) {
+                if previous != parent {
+                    return Err(format!(
+                        "line {} of the lineage hierarchy gives a second parent to {} ({} and {})",
+                        line_number + 1,
+                        child,
+                        previous,
+                        parent
+                    ));
+                }
+            }
+        }
+
+        // a lineage can't be its own ancestor
+        for child in parents.keys() {
+            let mut current = child;
+            for _ in 0..=parents.len() {
+                match parents.get(current) {
+                    Some(parent) if parent == child => {
+                        return Err(format!(
+                            "the lineage hierarchy has a cycle through {}",
+                            child
+                        ));
+                    }
+                    Some(parent) => current = parent,
+                    None => break,
+                }
+            }
+        }
+        Ok(LineageHierarchy {
+            parents,
+            level: None,
+        })
+    }
+
+    // lineage without parent (named without dot when there is no hierarchy file)
+    pub fn is_top_level(&self, lineage: &str) -> bool {
+        if self.parents.is_empty() {
+            return !lineage.contains('.');
+        }
+        !self.parents.contains_key(lineage)
+    }
+
+    pub fn set_level(&mut self, level: usize) {
+        self.level = Some(level);
+    }
+
+    // ancestor of the lineage at the chosen level (the lineage itself if it is less deep)
+    pub fn collapse(&self, lineage: &str) -> String {
+        let Some(level) = self.level else {
+            return lineage.to_string();
+        };
+        if self.parents.is_empty() {
+            // levels given by the dot-separated parts of the names (e.g. 4.2.1.1)
+            return lineage
+                .split('.')
+                .take(level)
+                .collect::<Vec<&str>>()
+                .join(".");
+        }
+        let mut ancestors = vec![lineage];
+        while let Some(parent) = self.parents.get(*ancestors.last().unwrap()) {
+            ancestors.push(parent);
+        }
+        // ancestors go from the lineage up to the root
+        let depth = ancestors.len();
+        ancestors[depth - level.min(depth)].to_string()
+    }
+
+    // lineage is a sublineage (at any depth) of ancestor
+    pub fn is_descendant(&self, lineage: &str, ancestor: &str) -> bool {
+        if self.parents.is_empty() {
+            return lineage != ancestor && lineage.starts_with(ancestor);
+        }
+        let mut current = lineage;
+        while let Some(parent) = self.parents.get(current) {
+            if parent == ancestor {
+                return true;
+            }
+            current = parent;
+        }
+        false
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+
+use crate::analyse_sample::{ReadHits, ReadVisitor};
+use crate::barcodes::MIN_KMER_SIZE;
+
+// size of the human genome, whose kmers are sampled by the panel
+pub const HUMAN_GENOME_SIZE: f64 = 3.1e9;
+
+// panel of human-specific kmers (both strands), from a FASTA file or a file with one kmer
+// per line: the proportion of the kmers of a sample found in the panel, scaled by the
+// proportion of the human genome it covers, estimates the fraction of human reads
+pub struct HumanPanel {
+    kmers: HashSet<Vec<u8>>,
+    k: usize,
+    nb_kmers: usize,
+}
+
+impl HumanPanel {
+    pub fn from_string(content: &str) -> Result<Self, String> {
+        let mut kmers = HashSet::new();
+        let mut k = 0;
+        let mut nb_kmers = 0;
+        for (line_number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['>', '#']) {
+                continue;
+            }
+            let kmer = line.to_ascii_uppercase().into_bytes();
+            if !kmer.iter().all(|base| b"ACGT".contains(base)) {
+                return Err(format!(
+                    "line {} of the human kmer panel should only contain bases",
+                    line_number + 1
+                ));
+            }
+            if k == 0 {
+                k = kmer.len();
+            }
+            if kmer.len() != k || k < MIN_KMER_SIZE as usize {
+                return Err(format!(
+                    "line {} of the human kmer panel has a kmer of {} bases (the kmers should all have the same size, at least {})",
+                    line_number + 1,
+                    kmer.len(),
+                    MIN_KMER_SIZE
+                ));
+            }
+            let rev_comp = reverse_complement(&kmer);
+            if kmers.insert(kmer) {
+                nb_kmers += 1;
+                kmers.insert(rev_comp);
+            }
+        }
+        if nb_kmers == 0 {
+            return Err("the human kmer panel is empty".to_string());
+        }
+        Ok(HumanPanel { kmers, k, nb_kmers })
+    }
+
+    pub fn len(&self) -> usize {
+        self.nb_kmers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nb_kmers == 0
+    }
+}
+
+// kmers of the reads of a sample found in the human panel
+pub struct HumanScan<'a> {
+    panel: &'a HumanPanel,
+    hits: u64,
+    kmers: u64,
+}
+
+impl<'a> HumanScan<'a> {
+    pub fn new(panel: &'a HumanPanel) -> Self {
+        HumanScan {
+            panel,
+            hits: 0,
+            kmers: 0,
+        }
+    }
+
+    // estimated fraction of human reads (None without reads)
+    pub fn fraction(&self) -> Option<f64> {
+        if self.kmers == 0 {
+            return None;
+        }
+        let density = self.panel.nb_kmers as f64 / HUMAN_GENOME_SIZE;
+        Some((self.hits as f64 / (self.kmers as f64 * density)).min(1.0))
+    }
+
+    // column of the output file ('NA' without reads)
+    pub fn fraction_column(&self) -> String {
+        match self.fraction() {
+            Some(fraction) => format!("{:.2}%", 100.0 * fraction),
+            None => "NA".to_string(),
+        }
+    }
+}
+
+impl ReadVisitor for HumanScan<'_> {
+    fn visit(&mut self, _read: &ReadHits) {}
+
+    fn visit_sequence(&mut self, seq: &[u8], _read_length: usize) {
+        if seq.len() < self.panel.k {
+            return;
+        }
+        for kmer in seq.windows(self.panel.k) {
+            self.kmers += 1;
+            if self.panel.kmers.contains(kmer) {
+                self.hits += 1;
+            }
+        }
+    }
+}
+
+fn reverse_complement(kmer: &[u8]) -> Vec<u8> {
+    kmer.iter()
+        .rev()
+        .map(|base| match base {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            _ => b'A',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn human_panels_are_read_from_fasta_or_kmer_lists() {
+        let panel =
+            HumanPanel::from_string(">kmer1\nacgtacgtacg\n# comment\n\nTTTTTCCCCCG\n").unwrap();
+        assert_eq!(panel.len(), 2);
+        // (kmers given on both strands are only counted once)
+        let panel = HumanPanel::from_string("ACGTACGTACG\nCGTACGTACGT\n").unwrap();
+        assert_eq!(panel.len(), 1);
+
+        let err = HumanPanel::from_string("ACGTNCGTACG\n").err().unwrap();
+        assert!(err.contains("line 1"), "{}", err);
+        let err = HumanPanel::from_string("ACGTACGTACG\nACGTACGTACGT\n")
+            .err()
+            .unwrap();
+        assert!(
+            err.contains("line 2 of the human kmer panel has a kmer of 12 bases"),
+            "{}",
+            err
+        );
+        assert!(HumanPanel::from_string(">empty\n").is_err());
+    }
+
+    #[test]
+    fn human_kmers_are_found_on_both_strands() {
+        let panel = HumanPanel::from_string("AAAAACCCCCG\n").unwrap();
+        let mut scan = HumanScan::new(&panel);
+        assert_eq!(scan.fraction(), None);
+        assert_eq!(scan.fraction_column(), "NA");
+
+        // (sequences shorter than the kmers are ignored)
+        scan.visit_sequence(b"GGGGGGGGGGGG", 12);
+        scan.visit_sequence(b"ACGT", 4);
+        assert_eq!(scan.fraction_column(), "0.00%");
+        // (a single kmer of a panel this small is beyond the fraction of a human genome)
+        scan.visit_sequence(b"CGGGGGTTTTT", 11);
+        assert_eq!(scan.fraction(), Some(1.0));
+        assert_eq!(scan.fraction_column(), "100.00%");
+    }
+}
@@ -0,0 +1,251 @@
+use ahash::RandomState;
+use boomphf::Mphf;
+use seq_io::fasta;
+use seq_io::fastq::{self, Record};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::analyse_sample::get_reader;
+use crate::barcodes::{BarcodeIndex, Barcodes};
+use crate::binary_index::{load_index, write_index};
+use crate::minimizers::MinimizerFilter;
+
+// implementations of the kmer index compared by bench-index: the table of fastlin (std
+// HashMap), an aHash HashMap, a minimal perfect hash function, the memory-mapped binary index
+// (build-index) and the table behind the minimizer prefilter of the reads
+pub const INDEX_BACKENDS: [&str; 5] = ["std", "ahash", "mphf", "mapped", "prefilter"];
+
+// load factor of the minimal perfect hash function (boomphf's recommended value)
+const MPHF_GAMMA: f64 = 1.7;
+
+// build time, memory and lookup throughput of an index implementation on the kmers of reads
+#[derive(Debug)]
+pub struct IndexBench {
+    pub backend: &'static str,
+    pub build_seconds: f64,
+    pub memory: Option<u64>,
+    pub nb_lookups: u64,
+    pub nb_hits: u64,
+    pub lookup_seconds: f64,
+}
+
+impl IndexBench {
+    pub fn lookups_per_second(&self) -> f64 {
+        self.nb_lookups as f64 / self.lookup_seconds.max(f64::EPSILON)
+    }
+}
+
+trait KmerLookup {
+    fn get(&self, kmer: &[u8]) -> Option<u32>;
+
+    // false if the read can't contain any kmer of the index (no lookup needed)
+    fn might_contain(&self, _seq: &[u8]) -> bool {
+        true
+    }
+}
+
+impl KmerLookup for BarcodeIndex {
+    fn get(&self, kmer: &[u8]) -> Option<u32> {
+        BarcodeIndex::get(self, kmer)
+    }
+}
+
+struct AHashTable(HashMap<Vec<u8>, u32, RandomState>);
+
+impl KmerLookup for AHashTable {
+    fn get(&self, kmer: &[u8]) -> Option<u32> {
+        self.0.get(kmer).copied()
+    }
+}
+
+// the perfect hash function maps any kmer to a slot, whose kmer is compared to it
+struct MphfTable {
+    mphf: Mphf<Vec<u8>>,
+    slots: Vec<(Vec<u8>, u32)>,
+}
+
+impl KmerLookup for MphfTable {
+    fn get(&self, kmer: &[u8]) -> Option<u32> {
+        let (slot_kmer, index) = &self.slots[self.mphf.try_hash(kmer)? as usize];
+        (slot_kmer.as_slice() == kmer).then_some(*index)
+    }
+}
+
+struct PrefilteredTable {
+    filter: MinimizerFilter,
+    index: BarcodeIndex,
+}
+
+impl KmerLookup for PrefilteredTable {
+    fn get(&self, kmer: &[u8]) -> Option<u32> {
+        self.index.get(kmer)
+    }
+
+    fn might_contain(&self, seq: &[u8]) -> bool {
+        self.filter.might_contain(seq)
+    }
+}
+
+// sequences of the first reads (or contigs) of FASTQ or FASTA files, held in memory so that
+// the decompression and parsing of the files are not timed
+pub fn read_sequences(files: &[PathBuf], max_reads: usize) -> Result<Vec<Vec<u8>>, String> {
+    let mut sequences = Vec::new();
+    for path in files {
+        let mut reader = get_reader(path);
+        let is_fasta = matches!(reader.fill_buf(), Ok(buffer) if buffer.first() == Some(&b'>'));
+        let error = |err: String| format!("couldn't read {}: {}", path.display(), err);
+        if is_fasta {
+            let mut reader = fasta::Reader::new(reader);
+            while let Some(record) = reader.next().filter(|_| sequences.len() < max_reads) {
+                let record = record.map_err(|err| error(err.to_string()))?;
+                sequences.push(record.owned_seq());
+            }
+        } else {
+            let mut reader = fastq::Reader::new(reader);
+            while let Some(record) = reader.next().filter(|_| sequences.len() < max_reads) {
+                let record = record.map_err(|err| error(err.to_string()))?;
+                sequences.push(record.seq().to_vec());
+            }
+        }
+    }
+    Ok(sequences)
+}
+
+// each index implementation built from the kmers of a scheme and used to look up every kmer of
+// the reads (the binary index is written to index_path, and removed afterwards)
+pub fn bench_index(
+    barcodes: &Barcodes,
+    reads: &[Vec<u8>],
+    index_path: &Path,
+) -> Result<Vec<IndexBench>, String> {
+    let k = barcodes.kmer_size as usize;
+    let kmers: Vec<(String, u32)> = barcodes
+        .index
+        .kmers()
+        .map(|(kmer, index)| (kmer.into_owned(), index))
+        .collect();
+    let ids = barcodes.index.ids.to_vec();
+
+    let mut results = Vec::new();
+    // (the indexes are kept until the end, so that the memory of each one is measured apart)
+    let mut tables: Vec<Box<dyn KmerLookup>> = Vec::new();
+    for backend in INDEX_BACKENDS {
+        let memory_before = resident_memory();
+        let start = Instant::now();
+        let table: Box<dyn KmerLookup> = match backend {
+            "ahash" => Box::new(AHashTable(
+                kmers
+                    .iter()
+                    .map(|(kmer, index)| (kmer.as_bytes().to_vec(), *index))
+                    .collect(),
+            )),
+            "mphf" => {
+                let keys: Vec<Vec<u8>> = kmers
+                    .iter()
+                    .map(|(kmer, _)| kmer.as_bytes().to_vec())
+                    .collect();
+                let mphf = Mphf::new(MPHF_GAMMA, &keys);
+                let mut slots = vec![(Vec::new(), 0); keys.len()];
+                for (key, (_, index)) in keys.into_iter().zip(&kmers) {
+                    let slot = mphf.hash(&key) as usize;
+                    slots[slot] = (key, *index);
+                }
+                Box::new(MphfTable { mphf, slots })
+            }
+            "mapped" => {
+                write_index(barcodes, index_path)?;
+                let mapped = load_index(index_path, Some(barcodes.kmer_size), None);
+                let _ = std::fs::remove_file(index_path);
+                Box::new(mapped?.index)
+            }
+            "prefilter" => Box::new(PrefilteredTable {
+                filter: MinimizerFilter::new(kmers.iter().map(|(kmer, _)| kmer), k)
+                    .ok_or("the kmers of the scheme can't be prefiltered (spaced seed)")?,
+                index: BarcodeIndex::new(kmers.iter().cloned().collect(), ids.clone()),
+            }),
+            _ => Box::new(BarcodeIndex::new(
+                kmers.iter().cloned().collect(),
+                ids.clone(),
+            )),
+        };
+        let build_seconds = start.elapsed().as_secs_f64();
+        let memory = resident_memory()
+            .zip(memory_before)
+            .map(|(after, before)| after.saturating_sub(before));
+
+        let (mut nb_lookups, mut nb_hits) = (0, 0);
+        let start = Instant::now();
+        for read in reads.iter().filter(|read| read.len() >= k) {
+            nb_lookups += (read.len() - k + 1) as u64;
+            if !table.might_contain(read) {
+                continue;
+            }
+            nb_hits += read
+                .windows(k)
+                .filter(|kmer| table.get(kmer).is_some())
+                .count() as u64;
+        }
+        results.push(IndexBench {
+            backend,
+            build_seconds,
+            memory,
+            nb_lookups,
+            nb_hits,
+            lookup_seconds: start.elapsed().as_secs_f64(),
+        });
+        tables.push(table);
+    }
+    Ok(results)
+}
+
+// resident memory of the process in bytes (Linux only)
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_index_finds_the_same_kmers() {
+        let file = "genome_size\t1000\n2\tAAAAC\tA\tCTTTT\n4\tCCACG\tT\tGGATC\n";
+        let barcodes = Barcodes::from_string(file, 11).unwrap();
+        // (a kmer of each barcode, one on the reverse strand, and a read shorter than the kmers)
+        let reads = [
+            b"GGAAAACACTTTTGG".to_vec(),
+            b"CCCCC".to_vec(),
+            b"GATCCACGTGGAA".to_vec(),
+        ];
+        let path = std::env::temp_dir().join(format!("fastlin_bench_{}.bin", std::process::id()));
+        let results = bench_index(&barcodes, &reads, &path).unwrap();
+        assert!(!path.exists());
+
+        let backends: Vec<&str> = results.iter().map(|result| result.backend).collect();
+        assert_eq!(backends, INDEX_BACKENDS);
+        for result in &results {
+            assert_eq!(
+                (result.nb_lookups, result.nb_hits),
+                (8, 2),
+                "{}",
+                result.backend
+            );
+        }
+    }
+
+    #[test]
+    fn the_first_reads_of_the_files_are_read() {
+        let reads = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/reads");
+        let files = [reads.join("pure_1.fastq.gz"), reads.join("pure_2.fastq.gz")];
+        let sequences = read_sequences(&files, 10).unwrap();
+        assert_eq!(sequences.len(), 10);
+        assert!(sequences.iter().all(|seq| seq.len() == 100));
+        // (up to the number of reads given)
+        assert_eq!(read_sequences(&files[..1], 1).unwrap().len(), 1);
+    }
+}
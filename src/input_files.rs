@@ -1,78 +1,394 @@
+use regex::Regex;
 use std::collections::HashMap;
-use std::str;
-use std::{ffi::OsStr, fs, path::PathBuf};
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
 
-pub fn get_input_files(name_dir: &str) -> HashMap<String, Vec<PathBuf>> {
-    // get list of files from the input directory
+use crate::archives::{is_archive, list_archive, member_bytes, split_archive_path};
+#[cfg(feature = "ora")]
+use crate::ora::{is_ora, ORA_EXTENSIONS};
+use crate::results::InputType;
+use crate::run_log::log;
+
+// extensions of the input files (matched case-insensitively)
+pub const FASTQ_EXTENSIONS: [&str; 2] = [".fastq.gz", ".fq.gz"];
+pub const FASTA_EXTENSIONS: [&str; 4] = [".fasta.gz", ".fas.gz", ".fna.gz", ".fa.gz"];
+
+// policies for the samples with unexpected file counts (--on-ambiguous-sample)
+pub const AMBIGUOUS_SAMPLE_POLICIES: [&str; 3] = ["error", "skip", "merge"];
+
+pub fn split_extension(filename: &str) -> Option<(&str, bool)> {
+    // file name without its extension and whether it is a FASTA file (None if not an input file)
+    let lowercase = filename.to_ascii_lowercase();
+    for (extensions, is_fasta) in [
+        (&FASTQ_EXTENSIONS[..], false),
+        (&FASTA_EXTENSIONS[..], true),
+        #[cfg(feature = "ora")]
+        (&ORA_EXTENSIONS[..], false),
+    ] {
+        if let Some(extension) = extensions.iter().find(|ext| lowercase.ends_with(*ext)) {
+            return Some((&filename[..filename.len() - extension.len()], is_fasta));
+        }
+    }
+    None
+}
+
+pub fn is_fasta_file(path: &Path) -> Option<bool> {
+    // whether an input file is a FASTA file (None if not an input file), from its file name
+    // (not necessarily UTF-8)
+    let filename = path.file_name()?.to_string_lossy();
+    split_extension(&filename).map(|(_, is_fasta)| is_fasta)
+}
+
+pub fn is_stream(path: &Path) -> bool {
+    // named pipes, process substitutions (/dev/fd/63) and other inputs that can only be read once
+    fs::metadata(path).is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+}
+
+pub fn stream_data_type(vec_files: &[PathBuf]) -> Option<InputType> {
+    // data type of input streams named without file extension (e.g. /dev/fd/63): reads, paired
+    // if there are two streams (FASTA and gzip being detected from their content)
+
+    if vec_files
+        .iter()
+        .any(|file_path| is_fasta_file(file_path).is_some())
+    {
+        return None;
+    }
+    match vec_files.len() {
+        1 => Some(InputType::Single),
+        2 => Some(InputType::Paired),
+        _ => None,
+    }
+}
+
+pub fn get_data_type(vec_files: &[PathBuf]) -> Result<InputType, (i32, i32)> {
+    // depending on the number of files, returns 'assembly', 'single', 'paired' or the file counts
+
+    let mut count_fasta = 0;
+    let mut count_fastq = 0;
+
+    for file_path in vec_files {
+        match is_fasta_file(file_path) {
+            Some(true) => count_fasta += 1,
+            Some(false) => count_fastq += 1,
+            None => {}
+        }
+    }
+
+    if count_fasta == 1 && count_fastq == 0 {
+        Ok(InputType::Assembly)
+    } else if count_fasta == 0 && count_fastq == 1 {
+        Ok(InputType::Single)
+    } else if count_fasta == 0 && count_fastq == 2 {
+        Ok(InputType::Paired)
+    } else {
+        Err((count_fasta, count_fastq))
+    }
+}
+
+pub fn merge_files(vec_files: &[PathBuf]) -> (InputType, Vec<PathBuf>) {
+    // data type and files of a sample with unexpected file counts, merged: its fastq files as
+    // reads (single-end reads beyond a pair), else its fasta files as an assembly
+
+    let mut fasta = vec![];
+    let mut fastq = vec![];
+
+    for file_path in vec_files {
+        match is_fasta_file(file_path) {
+            Some(true) => fasta.push(file_path.to_owned()),
+            Some(false) => fastq.push(file_path.to_owned()),
+            None => {}
+        }
+    }
+
+    match fastq.len() {
+        0 => (InputType::Assembly, fasta),
+        2 => (InputType::Paired, fastq),
+        _ => (InputType::Single, fastq),
+    }
+}
+
+pub fn get_run_dirs(runs_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    // get the sub-directories (one per run) of a directory of runs
+    let mut result = vec![];
+
+    for path in fs::read_dir(runs_dir)? {
+        let path = path?.path();
+        if path.is_dir() {
+            result.push(path);
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+pub fn get_input_files(
+    name_dir: &Path,
+    sample_regex: Option<&Regex>,
+) -> HashMap<String, Vec<PathBuf>> {
+    // get list of files from the input directory (or archive)
     let l_files = list_files(name_dir).unwrap();
 
     // combine files into samples
-    combine_files(l_files)
+    combine_files(l_files, sample_regex)
 }
 
-fn list_files(dir: &str) -> std::io::Result<Vec<PathBuf>> {
-    print!(" . get files from input dir");
+fn list_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    eprint!(" . get files from input dir");
 
     let mut result = vec![];
 
+    // the input files can be stored in archives
+    if is_archive(dir) && dir.is_file() {
+        result = list_archive(dir)?;
+        eprintln!("	({} files)", result.len());
+        return Ok(result);
+    }
+
     for path in fs::read_dir(dir)? {
         let path = path?.path();
-        if let Some("gz") = path.extension().and_then(OsStr::to_str) {
+        if is_archive(&path) {
+            result.extend(list_archive(&path)?);
+            continue;
+        }
+        let extension = path.extension().and_then(OsStr::to_str);
+        if extension.is_some_and(|ext| ext.eq_ignore_ascii_case("gz")) {
+            result.push(path.to_owned());
+            continue;
+        }
+        #[cfg(feature = "ora")]
+        if is_ora(&path) {
             result.push(path.to_owned());
+            continue;
         }
+        log(
+            "INFO",
+            &format!("skipped {} (not a gzipped file)", path.display()),
+        );
     }
-    println!("	({} files)", result.len());
+    eprintln!("	({} files)", result.len());
     Ok(result)
 }
 
-fn combine_files(vect_files: Vec<PathBuf>) -> HashMap<String, Vec<PathBuf>> {
-    print!(" . combine files into samples");
+pub fn combine_files(
+    vect_files: Vec<PathBuf>,
+    sample_regex: Option<&Regex>,
+) -> HashMap<String, Vec<PathBuf>> {
+    eprint!(" . combine files into samples");
 
     let mut results: HashMap<String, Vec<PathBuf>> = HashMap::new();
 
     for file in vect_files {
-        let filename = file.file_name().unwrap().to_str().unwrap();
+        // (sample names of non-UTF-8 file names with replacement characters)
+        let filename = file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
 
         // check extension
-        if filename.ends_with(".fastq.gz") || filename.ends_with(".fq.gz") {
-            let mut sample = filename.replace(".fastq.gz", "").replace(".fq.gz", "");
+        let Some((name, is_fasta)) = split_extension(&filename) else {
+            log(
+                "INFO",
+                &format!("skipped {} (not a FASTA or FASTQ file)", file.display()),
+            );
+            continue;
+        };
+        let mut sample = name.to_string();
 
+        // sample name given by the first capture group of the regex, if it matches
+        let captured = sample_regex
+            .and_then(|regex| regex.captures(&filename))
+            .and_then(|captures| captures.get(1));
+        if let Some(capture) = captured {
+            sample = capture.as_str().to_string();
+        } else if !is_fasta {
+            // paired-end reads
             if sample.ends_with("_1") {
                 sample = sample.trim_end_matches("_1").to_string();
             }
             if sample.ends_with("_2") {
                 sample = sample.trim_end_matches("_2").to_string();
             }
-
-            match results.get(&sample) {
-                Some(_vect_files) => {
-                    results.get_mut(&sample).unwrap().push(file);
-                }
-                None => {
-                    results.insert(sample.to_owned(), Vec::new());
-                    results.get_mut(&sample).unwrap().push(file);
-                }
-            }
-        } else if filename.ends_with(".fas.gz")
-            || filename.ends_with(".fasta.gz")
-            || filename.ends_with(".fna.gz")
-        {
-            let sample = filename
-                .replace(".fas.gz", "")
-                .replace(".fasta.gz", "")
-                .replace(".fna.gz", "");
-
-            match results.get(&sample) {
-                Some(_vect_files) => {
-                    results.get_mut(&sample).unwrap().push(file);
-                }
-                None => {
-                    results.insert(sample.to_owned(), Vec::new());
-                    results.get_mut(&sample).unwrap().push(file);
-                }
-            }
         }
+
+        results.entry(sample).or_default().push(file);
     }
-    println!("	({} samples)", results.len());
+    eprintln!("	({} samples)", results.len());
     results
 }
+
+// samples whose files reduce to the same sample name without making up a single sample (e.g.
+// 'S1.fq.gz' with 'S1_1.fq.gz' and 'S1_2.fq.gz', or 'S1_1.fq.gz' with 'S1_1.fastq.gz'): files
+// of the same kind (assembly, single-end reads, first or second mates), or of different kinds
+// other than the two mates of a pair
+pub fn name_collisions(samples: &HashMap<String, Vec<PathBuf>>) -> Vec<String> {
+    let mut collisions: Vec<String> = samples
+        .iter()
+        .filter(|(_, files)| {
+            let mut kinds: Vec<&str> = files.iter().filter_map(|file| file_kind(file)).collect();
+            kinds.sort();
+            let nb_kinds = kinds.len();
+            kinds.dedup();
+            nb_kinds > kinds.len() || (kinds.len() > 1 && kinds != ["mate_1", "mate_2"])
+        })
+        .map(|(sample, _)| sample.to_string())
+        .collect();
+    collisions.sort();
+    collisions
+}
+
+fn file_kind(path: &Path) -> Option<&'static str> {
+    let filename = path.file_name()?.to_string_lossy();
+    match split_extension(&filename)? {
+        (_, true) => Some("assembly"),
+        (name, false) if name.ends_with("_1") => Some("mate_1"),
+        (name, false) if name.ends_with("_2") => Some("mate_2"),
+        _ => Some("single"),
+    }
+}
+
+// MD5 checksum of an input file as stored (e.g. gzipped), to tie the results to the exact
+// input bytes (and compare them with the checksums of the sequencing archives)
+pub fn file_md5(path: &Path) -> io::Result<String> {
+    let mut reader: Box<dyn Read> = match split_archive_path(path) {
+        Some((archive, member)) => Box::new(member_bytes(&archive, &member)),
+        None => Box::new(fs::File::open(path)?),
+    };
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        match reader.read(&mut buffer)? {
+            0 => break,
+            n => context.consume(&buffer[..n]),
+        }
+    }
+    Ok(format!("{:x}", context.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_are_matched_whatever_their_case() {
+        assert_eq!(split_extension("s1_1.FASTQ.GZ"), Some(("s1_1", false)));
+        assert_eq!(split_extension("s1.fq.gz"), Some(("s1", false)));
+        assert_eq!(split_extension("genome.Fa.gz"), Some(("genome", true)));
+        assert_eq!(split_extension("genome.fna.gz"), Some(("genome", true)));
+        assert_eq!(split_extension("notes.txt.gz"), None);
+        assert_eq!(split_extension("s1.fastq"), None);
+    }
+
+    #[test]
+    fn paired_files_are_combined_into_samples() {
+        let files = [
+            "s1_1.fq.gz",
+            "s1_2.FQ.GZ",
+            "s2.fastq.gz",
+            "g1_1.fa.gz",
+            "notes.txt.gz",
+        ];
+        let samples = combine_files(files.map(PathBuf::from).to_vec(), None);
+        let mut names: Vec<&String> = samples.keys().collect();
+        names.sort();
+        // (assemblies keep their suffix)
+        assert_eq!(names, ["g1_1", "s1", "s2"]);
+        assert_eq!(get_data_type(&samples["s1"]), Ok(InputType::Paired));
+        assert_eq!(get_data_type(&samples["s2"]), Ok(InputType::Single));
+        assert_eq!(get_data_type(&samples["g1_1"]), Ok(InputType::Assembly));
+    }
+
+    #[test]
+    fn sample_names_are_captured_by_the_regex() {
+        let regex = Regex::new(r"^(.+)_S\d+_L\d+_R[12]_001").unwrap();
+        let files = [
+            "ERR1_S3_L001_R1_001.fastq.gz",
+            "ERR1_S3_L001_R2_001.fastq.gz",
+            "other_1.fq.gz",
+            "other_2.fq.gz",
+        ];
+        let samples = combine_files(files.map(PathBuf::from).to_vec(), Some(&regex));
+        let mut names: Vec<&String> = samples.keys().collect();
+        names.sort();
+        // (file names not matching the regex are combined as usual)
+        assert_eq!(names, ["ERR1", "other"]);
+        assert_eq!(samples["ERR1"].len(), 2);
+    }
+
+    #[test]
+    fn ambiguous_samples_are_merged_as_reads() {
+        let files = |names: &[&str]| -> Vec<PathBuf> { names.iter().map(PathBuf::from).collect() };
+        // (lanes of a sample, and reads next to an assembly)
+        let lanes = files(&["s_L1_1.fq.gz", "s_L1_2.fq.gz", "s_L2_1.fq.gz"]);
+        assert_eq!(get_data_type(&lanes), Err((0, 3)));
+        assert_eq!(merge_files(&lanes), (InputType::Single, lanes.clone()));
+
+        let (data_type, merged) = merge_files(&files(&["s.fa.gz", "s_1.fq.gz", "s_2.fq.gz"]));
+        assert_eq!(data_type, InputType::Paired);
+        assert_eq!(merged, files(&["s_1.fq.gz", "s_2.fq.gz"]));
+
+        let assemblies = files(&["s.fa.gz", "s.fasta.gz"]);
+        assert_eq!(
+            merge_files(&assemblies),
+            (InputType::Assembly, assemblies.clone())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_file_names_are_combined_into_samples() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = |name: &[u8]| PathBuf::from(OsStr::from_bytes(name));
+        assert_eq!(is_fasta_file(&path(b"g\xe9.fa.gz")), Some(true));
+        assert_eq!(is_fasta_file(&path(b"s\xe9_1.fq.gz")), Some(false));
+        assert_eq!(is_fasta_file(&path(b"s\xe9.txt")), None);
+
+        // (sample names with replacement characters)
+        let samples = combine_files(vec![path(b"s\xe9_1.fq.gz"), path(b"s\xe9_2.fq.gz")], None);
+        assert_eq!(samples.len(), 1);
+        assert_eq!(get_data_type(&samples["s\u{fffd}"]), Ok(InputType::Paired));
+    }
+
+    #[test]
+    fn file_sets_reducing_to_the_same_name_are_collisions() {
+        let files = [
+            "pair_1.fq.gz",
+            "pair_2.fq.gz",
+            "single.fq.gz",
+            "s1.fq.gz",
+            "s1_1.fq.gz",
+            "s2_1.fq.gz",
+            "s2_1.fastq.gz",
+            "g1.fa.gz",
+            "g1.fasta.gz",
+        ];
+        let samples = combine_files(files.map(PathBuf::from).to_vec(), None);
+        assert_eq!(name_collisions(&samples), ["g1", "s1", "s2"]);
+    }
+
+    #[test]
+    fn input_streams_are_typed_as_reads() {
+        let files = |names: &[&str]| -> Vec<PathBuf> { names.iter().map(PathBuf::from).collect() };
+        assert_eq!(
+            stream_data_type(&files(&["/dev/fd/63"])),
+            Some(InputType::Single)
+        );
+        assert_eq!(
+            stream_data_type(&files(&["/dev/fd/63", "/dev/fd/64"])),
+            Some(InputType::Paired)
+        );
+        assert_eq!(stream_data_type(&files(&["/dev/fd/63", "s.fa.gz"])), None);
+        assert_eq!(stream_data_type(&files(&["a", "b", "c"])), None);
+
+        // (regular files and directories aren't streams)
+        assert!(!is_stream(Path::new(env!("CARGO_MANIFEST_DIR"))));
+        assert!(!is_stream(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml")
+        ));
+    }
+}
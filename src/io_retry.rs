@@ -0,0 +1,173 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::run_log::warning;
+
+// retries of the failed opens and reads of the input files (--io-retries), e.g. hiccups of
+// network filesystems, before the sample is reported as failed
+pub const DEFAULT_IO_RETRIES: u32 = 3;
+
+// delay before the first retry, doubled at each following retry
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+static IO_RETRIES: AtomicU32 = AtomicU32::new(DEFAULT_IO_RETRIES);
+
+pub fn set_io_retries(retries: u32) {
+    IO_RETRIES.store(retries, Ordering::Relaxed);
+}
+
+// errors that can't be fixed by retrying (missing files, permissions or corrupted data)
+fn is_transient(err: &io::Error) -> bool {
+    !matches!(
+        err.kind(),
+        io::ErrorKind::NotFound
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::Unsupported
+    )
+}
+
+// waits before a retry if the error is transient and retries are left (with a warning)
+fn should_retry(path: &Path, err: &io::Error, attempt: u32) -> bool {
+    let retries = IO_RETRIES.load(Ordering::Relaxed);
+    if attempt >= retries || !is_transient(err) {
+        return false;
+    }
+    warning(&format!(
+        "couldn't read {}: {} (retry {} of {})",
+        path.display(),
+        err,
+        attempt + 1,
+        retries
+    ));
+    thread::sleep(RETRY_DELAY * 2u32.pow(attempt));
+    true
+}
+
+pub fn open_with_retry(path: &Path) -> io::Result<File> {
+    let mut attempt = 0;
+    loop {
+        match File::open(path) {
+            Ok(file) => return Ok(file),
+            Err(err) if should_retry(path, &err, attempt) => attempt += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// file whose failed reads are retried: the file is opened again (e.g. stale handles of network
+// filesystems) and read from the position reached. The reads of named pipes and other streams
+// are not retried, as they can't be opened again
+pub struct RetryReader {
+    file: File,
+    path: PathBuf,
+    position: u64,
+    regular: bool,
+}
+
+impl RetryReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = open_with_retry(path)?;
+        let regular = file.metadata().is_ok_and(|metadata| metadata.is_file());
+        Ok(RetryReader {
+            file,
+            path: path.to_path_buf(),
+            position: 0,
+            regular,
+        })
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.position))?;
+        self.file = file;
+        Ok(())
+    }
+}
+
+impl Read for RetryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut attempt = 0;
+        loop {
+            match self.file.read(buf) {
+                Ok(nb_bytes) => {
+                    self.position += nb_bytes as u64;
+                    return Ok(nb_bytes);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                // (the previous handle is read again if the file can't be opened again)
+                Err(err) if self.regular && should_retry(&self.path, &err, attempt) => {
+                    attempt += 1;
+                    let _ = self.reopen();
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+// reader of a file that couldn't be opened, failing with its error (at every read) so that
+// the sample is reported as failed
+pub struct FailedReader {
+    kind: io::ErrorKind,
+    message: String,
+}
+
+impl FailedReader {
+    pub fn new(path: &Path, err: io::Error) -> Self {
+        FailedReader {
+            kind: err.kind(),
+            message: format!("couldn't open {}: {}", path.display(), err),
+        }
+    }
+}
+
+impl Read for FailedReader {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(self.kind, self.message.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files_fail_without_retry() {
+        let path = std::env::temp_dir().join(format!("fastlin_missing_{}.fq", std::process::id()));
+        // (immediately, missing files not being transient errors)
+        let err = open_with_retry(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!is_transient(&err));
+        assert!(is_transient(&io::Error::from(io::ErrorKind::TimedOut)));
+
+        let mut reader = FailedReader::new(&path, err);
+        let err = reader.read(&mut [0; 16]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().starts_with("couldn't open"), "{}", err);
+        // (at every read)
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn files_are_read_through_the_retry_reader() {
+        let path = std::env::temp_dir().join(format!("fastlin_retry_{}.fq", std::process::id()));
+        std::fs::write(&path, "@r1\nACGT\n+\nIIII\n").unwrap();
+        let mut reader = RetryReader::open(&path).unwrap();
+        let mut start = [0; 4];
+        reader.read_exact(&mut start).unwrap();
+        assert_eq!(reader.position, 4);
+        // (a reopened file is read from the position reached)
+        reader.reopen().unwrap();
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "ACGT\n+\nIIII\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use crate::analyse_sample::{ReadHits, ReadVisitor};
+use crate::barcodes::{BarcodeIndex, SpacedSeed};
+
+// occurrences of every kmer of the barcodes in the reads of a sample, including the kmers not
+// found (kmers keyed by the care positions of the spaced seed, if any)
+pub struct KmerSpectrum {
+    counts: HashMap<Vec<u8>, u32>,
+    k: usize,
+    spaced_seed: Option<SpacedSeed>,
+    key: Vec<u8>,
+}
+
+impl KmerSpectrum {
+    pub fn new(barcodes: &BarcodeIndex, k: usize, spaced_seed: Option<SpacedSeed>) -> Self {
+        KmerSpectrum {
+            counts: barcodes
+                .kmers()
+                .map(|(kmer, _)| (kmer.as_bytes().to_vec(), 0))
+                .collect(),
+            k,
+            spaced_seed,
+            key: Vec::with_capacity(k),
+        }
+    }
+
+    // counts of the next sample
+    pub fn reset(&mut self) {
+        self.counts.values_mut().for_each(|count| *count = 0);
+    }
+
+    // one 'kmer<TAB>count' line per kmer, sorted by kmer (as jellyfish dump -c -t)
+    pub fn format(&self) -> String {
+        let mut kmers: Vec<(&Vec<u8>, &u32)> = self.counts.iter().collect();
+        kmers.sort_unstable();
+        let mut content = String::with_capacity(kmers.len() * (self.k + 8));
+        for (kmer, count) in kmers {
+            content.push_str(&String::from_utf8_lossy(kmer));
+            content.push('\t');
+            content.push_str(&count.to_string());
+            content.push('\n');
+        }
+        content
+    }
+}
+
+impl ReadVisitor for KmerSpectrum {
+    fn visit(&mut self, read: &ReadHits) {
+        for hit in read.hits {
+            let Some(mut kmer) = read.seq.get(hit.position..hit.position + self.k) else {
+                continue;
+            };
+            if let Some(seed) = &self.spaced_seed {
+                seed.mask(kmer, &mut self.key);
+                kmer = &self.key;
+            }
+            if let Some(count) = self.counts.get_mut(kmer) {
+                *count += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,48 @@
+pub mod allele_fractions;
+pub mod analyse_sample;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod archives;
+pub mod barcode_loci;
+pub mod barcode_source;
+pub mod barcodes;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bin_reads;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod binary_index;
+pub mod check_scheme;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod deconvolution;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dedup;
+pub mod design;
+pub mod encoding;
+pub mod error_rate;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ffi;
+pub mod hierarchy;
+pub mod human_fraction;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod index_bench;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod input_files;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod io_retry;
+pub mod kmer_spectrum;
+pub mod minimizers;
+#[cfg(all(feature = "ora", not(target_arch = "wasm32")))]
+pub mod ora;
+pub mod position_stats;
+pub mod process_barcodes;
+pub mod qc_report;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod read_table;
+pub mod results;
+pub mod run_log;
+pub mod sample_sheet;
+pub mod shards;
+pub mod simulation;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod threads;
+pub mod trimming;
+#[cfg(feature = "wasm")]
+pub mod wasm;
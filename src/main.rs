@@ -1,41 +1,203 @@
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::fmt;
-use std::fs::File;
-use std::io::Write;
-use std::str;
-use std::{path::PathBuf, process};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use fastlin::deconvolution::DEFAULT_EM_COMPONENTS;
+use fastlin::dedup::DEFAULT_DEDUP_LENGTH;
+use fastlin::input_files::AMBIGUOUS_SAMPLE_POLICIES;
+use fastlin::io_retry::DEFAULT_IO_RETRIES;
+use fastlin::process_barcodes::CALLERS;
+use fastlin::shards::parse_shard;
+
+mod commands;
+mod output;
+mod serve;
+mod setup;
+mod typing;
+use commands::{
+    bench, build_index, design, export_barcodes, run_bench_index, run_check_scheme,
+    run_merge_shards, simulate, write_qc_report,
+};
+use serve::serve;
+use setup::{check_kmer_size, load_hierarchy, load_lineage_names, load_scheme};
+use typing::type_samples;
+
+// options adding columns to the output file, or changing the lineages called and the mixture
+// status, which the output of the original fastlin can't have (--legacy-output)
+const LEGACY_CONFLICTS: [&str; 14] = [
+    "runs",
+    "sample_sheet",
+    "caller",
+    "min_fraction",
+    "lineage_hierarchy",
+    "level",
+    "metagenomic",
+    "amplicon",
+    "error_rate",
+    "depth_cv",
+    "file_coverage",
+    "human_kmers",
+    "allele_fractions",
+    "em_proportions",
+];
+
+// exit code of the runs stopped by --max-runtime (the remaining samples being reported as SKIPPED)
+const PARTIAL_RUN_EXIT_CODE: u8 = 3;
 
-mod get_barcodes;
-use get_barcodes::get_barcodes;
+#[derive(Parser, Debug)]
+#[command(author = None, version, about = None, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-mod input_files;
-use input_files::get_input_files;
+    #[command(flatten)]
+    args: Args,
+}
 
-mod analyse_sample;
-use analyse_sample::scan_reads;
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// load the barcodes once and type samples POSTed over HTTP
+    Serve(ServeArgs),
 
-mod process_barcodes;
-use process_barcodes::process_barcodes;
+    /// simulate the reads of a sample made of one or several lineages
+    Simulate(SimulateArgs),
 
-#[derive(Parser, Debug)]
-#[command(author = None, version, about = None, long_about = None)]
+    /// report the detection of simulated mixtures at a grid of proportions and coverages
+    Bench(BenchArgs),
+
+    /// build a binary index of the barcodes, memory-mapped when given as barcode file
+    BuildIndex(BuildIndexArgs),
+
+    /// compare the build time, memory and lookup throughput of the kmer index implementations on reads
+    BenchIndex(BenchIndexArgs),
+
+    /// QC report of a fastlin output file (coverage, failures, controls and contaminations)
+    QcReport(QcReportArgs),
+
+    /// merge the output files of the shards of a run (--shard) into one output file
+    MergeShards(MergeShardsArgs),
+
+    /// export the kmers matched by fastlin (after slicing, spaced seed and lineage filters)
+    ExportBarcodes(ExportBarcodesArgs),
+
+    /// report the barcodes absent from a reference genome or repeated in it
+    CheckScheme(CheckSchemeArgs),
+
+    /// design a barcode file from an alignment of genomes of known lineages
+    DesignBarcodes(DesignBarcodesArgs),
+}
+
+#[derive(clap::Args, Debug, Serialize)]
 struct Args {
-    /// directory containing the data files
-    #[arg(short, long)]
-    dir: String,
+    /// directory containing the data files (can be repeated)
+    #[arg(short, long, required_unless_present_any = ["runs", "input"])]
+    dir: Vec<String>,
+
+    /// directory containing one sub-directory of data files per run
+    #[arg(long, conflicts_with = "dir")]
+    runs: Option<String>,
+
+    /// data files of a single sample (result row printed to stdout)
+    #[arg(short, long, num_args = 1..=2, conflicts_with_all = ["dir", "runs"])]
+    input: Vec<String>,
+
+    /// regex whose first capture group gives the sample name of a file name
+    #[arg(long)]
+    sample_regex: Option<String>,
+
+    /// type only the i-th of N disjoint subsets of the sorted samples (e.g. 2/10 for a task of a job array), the output files being merged with merge-shards
+    #[arg(long, value_name = "i/N", value_parser = parse_shard, conflicts_with = "input")]
+    shard: Option<(usize, usize)>,
+
+    /// tab-separated file of sample metadata (header, sample name first) copied to the output
+    #[arg(long)]
+    sample_sheet: Option<String>,
+
+    /// sample name in single-sample mode [derived from the file names]
+    #[arg(long, requires = "input")]
+    sample_name: Option<String>,
+
+    /// tool and scheme versions file in single-sample mode (YAML, or JSON if *.json)
+    #[arg(long, default_value_t = String::from("versions.yml"), requires = "input")]
+    versions: String,
 
     /// file containing the reference barcodes
-    #[arg(short = 'b', long)]
-    barcodes: String,
+    #[arg(short = 'b', long, required = true)]
+    barcodes: Option<String>,
 
     /// output file [out_fastlin.txt]
     #[arg(short = 'o', long, default_value_t = String::from("out_fastlin.txt"))]
     output: String,
 
-    /// kmer size
-    #[arg(short, long, default_value_t = 25)]
-    kmer_size: u8,
+    /// directory of the results (summary.tsv, samples/<sample>.json, logs/, manifest.json, and the per-barcode tables given as relative paths in barcodes/)
+    #[arg(long, value_name = "DIR", conflicts_with = "output")]
+    outdir: Option<String>,
+
+    /// output file in the format of the original fastlin (no read length columns nor summary lines, kmer coverage computed as then, and only the errors in the log_errors column)
+    #[arg(long, conflicts_with_all = LEGACY_CONFLICTS)]
+    legacy_output: bool,
+
+    /// directory in which to write the result rows of each sample to a file of its own (with the header)
+    #[arg(long, value_name = "DIR")]
+    per_sample_output: Option<String>,
+
+    /// format of the per-sample result files (tsv, or json: one object per row, keyed by column)
+    #[arg(long, default_value = "tsv", value_parser = ["tsv", "json"], requires = "per_sample_output")]
+    per_sample_format: String,
+
+    /// don't write the combined output file (per-sample result files only)
+    #[arg(long, requires = "per_sample_output", conflicts_with = "append")]
+    no_combined_output: bool,
+
+    /// overwrite the output file (or results directory) if it exists
+    #[arg(long)]
+    force: bool,
+
+    /// append to the output file if it exists (its samples can't be typed again)
+    #[arg(long)]
+    append: bool,
+
+    /// replace the rows of the samples already in the output file (with --append)
+    #[arg(long, requires = "append")]
+    overwrite_samples: bool,
+
+    /// log file of the warnings, skipped files and errors of the samples, with timestamps
+    #[arg(long)]
+    log: Option<String>,
+
+    /// JSON record of the run (parameters, status and timing of each sample, warnings)
+    #[arg(long)]
+    run_json: Option<String>,
+
+    /// record the MD5 checksums of the input files in the log and JSON record of the run
+    #[arg(long)]
+    checksums: bool,
+
+    /// JSON manifest to reproduce the run (version, parameters, scheme, input files and host)
+    #[arg(long)]
+    manifest_out: Option<String>,
+
+    /// number of threads, shared out between the stages of the run [the CPUs available, within the cgroup CPU limits]
+    #[arg(short = 't', long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    threads: Option<usize>,
+
+    /// retries of the failed opens and reads of the input files (e.g. network filesystem hiccups), with a doubling delay from 0.5 s, before the sample is reported as failed
+    #[arg(long, default_value_t = DEFAULT_IO_RETRIES)]
+    io_retries: u32,
+
+    /// read the input files once, in order, so that named pipes and process substitutions (e.g. <(samtools fastq x.bam)) can be typed; the progress is shown in samples rather than bytes
+    #[arg(long, conflicts_with_all = ["checksums", "dedup", "min_reads", "min_bases", "strict_mates", "rescan_mixtures"])]
+    fifo: bool,
+
+    /// maximum run time (seconds, or e.g. 90m or 11h30m), after which the remaining samples are reported as SKIPPED and the run ends with the exit code 3 (e.g. within the wall-clock limit of a scheduler)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    max_runtime: Option<Duration>,
+
+    /// kmer size, or comma-separated kmer sizes to compare [kmer_size of the barcode file, or 25]
+    #[arg(short, long, value_delimiter = ',')]
+    kmer_size: Vec<u8>,
 
     /// minimum number of kmer occurences
     #[arg(short = 'c', long, default_value_t = 4)]
@@ -45,137 +207,557 @@ struct Args {
     #[arg(short = 'n', long, default_value_t = 3)]
     n_barcodes: usize,
 
+    /// minimum depth of a lineage relative to the major lineage (e.g. 2%), else reported as a trace
+    #[arg(long, default_value = "0", value_parser = parse_rate)]
+    min_fraction: f64,
+
+    /// strategy calling the lineages from the barcode counts (threshold, or poisson: depth unlikely from sequencing errors)
+    #[arg(long, default_value = "threshold", value_parser = CALLERS)]
+    caller: String,
+
     /// maximum kmer coverage
     #[arg(short = 'x', long)]
     max_cov: Option<u64>,
+
+    /// minimum number of reads of a sample, else reported as NO_DATA without lineage calls
+    #[arg(long)]
+    min_reads: Option<u64>,
+
+    /// minimum number of bases of a sample, else reported as NO_DATA without lineage calls
+    #[arg(long)]
+    min_bases: Option<u64>,
+
+    /// samples with unexpected file counts (3+ fastq files, or fasta and fastq files): stop the run (error), skip them, or merge their fastq files as reads (single-end beyond a pair)
+    #[arg(long, default_value = "error", value_parser = AMBIGUOUS_SAMPLE_POLICIES)]
+    on_ambiguous_sample: String,
+
+    /// fail the paired samples whose read names don't correspond in the two files (warning by default)
+    #[arg(long)]
+    strict_mates: bool,
+
+    /// trim adapters from the 3' end of the reads before extracting their kmers
+    #[arg(long)]
+    trim_adapters: bool,
+
+    /// adapters to trim (comma-separated) [default: Illumina TruSeq, Nextera and small RNA adapters]
+    #[arg(long, value_delimiter = ',', requires = "trim_adapters")]
+    adapters: Vec<String>,
+
+    /// trim the 3' end of the reads while the mean quality of a 4-base sliding window is below this value
+    #[arg(long, value_name = "QUALITY", value_parser = clap::value_parser!(u8).range(..=93))]
+    trim_qual: Option<u8>,
+
+    /// ignore the duplicated reads (pairs of reads with the same first bases, e.g. PCR duplicates)
+    #[arg(long)]
+    dedup: bool,
+
+    /// number of bases of each read compared to find duplicates
+    #[arg(long, default_value_t = DEFAULT_DEDUP_LENGTH, requires = "dedup")]
+    dedup_length: usize,
+
+    /// count once the barcodes found in both reads of a pair (overlapping mates of short inserts)
+    #[arg(long)]
+    mate_overlap: bool,
+
+    /// maximum count of a barcode (e.g. barcodes in repeated regions), higher counts being capped to it
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_barcode_count: Option<u32>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    scheme: SchemeArgs,
+
+    /// only scan the barcodes of top-level lineages (fast triage)
+    #[arg(long)]
+    major_only: bool,
+
+    /// rescan the samples found mixed in major-only mode with all the barcodes
+    #[arg(long, requires = "major_only")]
+    rescan_mixtures: bool,
+
+    /// directory in which to write the reads containing barcodes, binned by lineage
+    #[arg(long)]
+    bin_reads: Option<String>,
+
+    /// file listing every barcode hit (sample, file, read, lineage, barcode, position)
+    #[arg(long)]
+    read_table: Option<String>,
+
+    /// file reporting the histogram of barcode kmer counts per sample and lineage
+    #[arg(long)]
+    depth_histogram: Option<String>,
+
+    /// file reporting the positions of barcode hits within reads, per sample and barcode
+    #[arg(long)]
+    position_stats: Option<String>,
+
+    /// directory in which to write a BED file of the loci of the barcodes found, per sample
+    #[arg(long, value_name = "DIR")]
+    barcode_bed: Option<String>,
+
+    /// directory in which to write a VCF file of the lineage-defining SNPs of the barcodes found, per sample
+    #[arg(long, value_name = "DIR")]
+    barcode_vcf: Option<String>,
+
+    /// directory in which to write the counts of all the barcode kmers (including the kmers not found), per sample, as 'kmer<TAB>count' lines (like jellyfish dump -c -t)
+    #[arg(long, value_name = "DIR")]
+    kmer_spectrum: Option<String>,
+
+    /// file reporting the counts of both alleles of the barcode sites with a reference allele, per sample and barcode (adds allele_fractions and mixture_pvalue columns)
+    #[arg(long, value_name = "FILE")]
+    allele_fractions: Option<String>,
+
+    /// report the maximum-likelihood proportions of the lineages called, estimated by EM from the barcode counts (and allele counts)
+    #[arg(long)]
+    em_proportions: bool,
+
+    /// maximum number of lineages (components) of the mixtures deconvolved by EM
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_EM_COMPONENTS, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), requires = "em_proportions")]
+    em_components: usize,
+
+    /// report the sequencing error rate of each sample (from the barcode kmers with one mismatch)
+    #[arg(long)]
+    error_rate: bool,
+
+    /// report the coefficient of variation of the barcode depths of each lineage called
+    #[arg(long)]
+    depth_cv: bool,
+
+    /// report the kmer coverage contributed by each input file (e.g. R1 and R2)
+    #[arg(long)]
+    file_coverage: bool,
+
+    /// panel of human-specific kmers (FASTA or one kmer per line) to report the estimated fraction of human reads
+    #[arg(long, value_name = "FILE")]
+    human_kmers: Option<String>,
+
+    /// metagenomic (e.g. direct-from-sputum) sequencing: min count of 1 with the Poisson caller, abundance of the target genome and NO_CALL below the detection limit
+    #[arg(long, conflicts_with = "major_only")]
+    metagenomic: bool,
+
+    /// estimated depth of the target genome below which metagenomic samples are reported as NO_CALL
+    #[arg(long, default_value_t = 1.0, requires = "metagenomic")]
+    detection_limit: f64,
+
+    /// targeted (amplicon) sequencing: depth of the targets instead of the genome coverage, barcode depth thresholds following it, and targets covered
+    #[arg(long)]
+    amplicon: bool,
 }
 
-#[derive(PartialEq)]
-enum InputType {
-    Assembly,
-    Single,
-    Paired,
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// file containing the reference barcodes
+    #[arg(short = 'b', long)]
+    barcodes: String,
+
+    /// address to listen on (e.g. 0.0.0.0 for all the network interfaces)
+    #[arg(long, default_value = "127.0.0.1")]
+    bind: String,
+
+    /// port to listen on
+    #[arg(short, long, default_value_t = 8080)]
+    port: u16,
+
+    /// directory of the files that can be typed through /files (disabled if not given)
+    #[arg(long, value_name = "DIR")]
+    data_dir: Option<String>,
+
+    /// maximum size of the request bodies, in MB (larger ones are refused)
+    #[arg(long, default_value_t = 1024)]
+    max_body_size: u64,
+
+    /// kmer size [kmer_size of the barcode file, or 25]
+    #[arg(short, long)]
+    kmer_size: Option<u8>,
+
+    /// minimum number of kmer occurences
+    #[arg(short = 'c', long, default_value_t = 4)]
+    min_count: i32,
+
+    /// minimum number of barcodes
+    #[arg(short = 'n', long, default_value_t = 3)]
+    n_barcodes: usize,
+
+    /// minimum depth of a lineage relative to the major lineage (e.g. 2%), else reported as a trace
+    #[arg(long, default_value = "0", value_parser = parse_rate)]
+    min_fraction: f64,
+
+    /// strategy calling the lineages from the barcode counts (threshold, or poisson: depth unlikely from sequencing errors)
+    #[arg(long, default_value = "threshold", value_parser = CALLERS)]
+    caller: String,
+
+    /// maximum kmer coverage
+    #[arg(short = 'x', long)]
+    max_cov: Option<u64>,
+
+    #[command(flatten)]
+    scheme: SchemeArgs,
 }
 
-impl fmt::Display for InputType {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            InputType::Assembly => write!(f, "assembly"),
-            InputType::Single => write!(f, "single"),
-            InputType::Paired => write!(f, "paired"),
-        }
-    }
+#[derive(clap::Args, Debug)]
+struct SimulateArgs {
+    /// reference genome (FASTA, first record only)
+    #[arg(short, long = "ref")]
+    reference: String,
+
+    /// tab-separated file of lineage SNPs (lineage, 1-based position, alternate base)
+    #[arg(short, long)]
+    lineage_snps: String,
+
+    /// lineage to simulate, with its proportion in mixtures (e.g. 4.1:0.8, can be repeated)
+    #[arg(long = "lineage", required = true)]
+    lineages: Vec<String>,
+
+    /// sequencing depth of the sample
+    #[arg(short, long, default_value_t = 30.0)]
+    coverage: f64,
+
+    /// rate of sequencing errors (e.g. 0.005 or 0.5%)
+    #[arg(short, long, default_value = "0", value_parser = parse_rate)]
+    error_rate: f64,
+
+    /// length of the reads
+    #[arg(long, default_value_t = 150)]
+    read_length: usize,
+
+    /// seed of the random number generator
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+
+    /// output file (gzipped FASTQ)
+    #[arg(short, long, default_value_t = String::from("simulated.fastq.gz"))]
+    output: String,
 }
 
-fn get_data_type(name_sample: String, vec_files: Vec<PathBuf>) -> InputType {
-    // depending on the number of files, returns 'single', 'paired' or exit with error message
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// reference genome (FASTA, first record only)
+    #[arg(short, long = "ref")]
+    reference: String,
 
-    let mut count_fasta = 0;
-    let mut count_fastq = 0;
+    /// tab-separated file of lineage SNPs (lineage, 1-based position, alternate base)
+    #[arg(short, long)]
+    lineage_snps: String,
 
-    for file_path in vec_files {
-        if let Some(file_str) = file_path.to_str() {
-            if file_str.ends_with(".fna.gz") || file_str.ends_with(".fas.gz") {
-                count_fasta += 1;
-            } else if file_str.ends_with(".fq.gz") || file_str.ends_with(".fastq.gz") {
-                count_fastq += 1;
-            }
-        }
+    /// file containing the reference barcodes
+    #[arg(short = 'b', long)]
+    barcodes: String,
+
+    /// main lineage of the simulated samples
+    #[arg(long)]
+    major: String,
+
+    /// minor lineage of the simulated mixtures
+    #[arg(long)]
+    minor: String,
+
+    /// proportions of the minor lineage (0 for pure samples)
+    #[arg(long, value_delimiter = ',', default_value = "0,0.02,0.05,0.1,0.2")]
+    ratios: Vec<f64>,
+
+    /// sequencing depths of the simulated samples
+    #[arg(long, value_delimiter = ',', default_value = "10,30,80")]
+    coverages: Vec<f64>,
+
+    /// number of samples simulated per proportion and coverage
+    #[arg(long, default_value_t = 10)]
+    replicates: usize,
+
+    /// rate of sequencing errors (e.g. 0.005 or 0.5%)
+    #[arg(short, long, default_value = "0", value_parser = parse_rate)]
+    error_rate: f64,
+
+    /// length of the reads
+    #[arg(long, default_value_t = 150)]
+    read_length: usize,
+
+    /// seed of the random number generator
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+
+    /// kmer size [kmer_size of the barcode file, or 25]
+    #[arg(short, long)]
+    kmer_size: Option<u8>,
+
+    /// minimum number of kmer occurences
+    #[arg(short = 'c', long, default_value_t = 4)]
+    min_count: i32,
+
+    /// minimum number of barcodes
+    #[arg(short = 'n', long, default_value_t = 3)]
+    n_barcodes: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct BuildIndexArgs {
+    /// file containing the reference barcodes
+    #[arg(short = 'b', long)]
+    barcodes: String,
+
+    /// kmer size [kmer_size of the barcode file, or 25]
+    #[arg(short, long)]
+    kmer_size: Option<u8>,
+
+    /// genome size used to compute the kmer coverage [genome_size of the barcode file]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    genome_size: Option<u64>,
+
+    /// output file (binary index)
+    #[arg(short, long)]
+    output: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct QcReportArgs {
+    /// fastlin output file
+    results: String,
+
+    /// tab-separated file of the control samples and their expected lineage ('none' for negative controls)
+    #[arg(long)]
+    controls: Option<String>,
+
+    /// output file (markdown) [stdout]
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchIndexArgs {
+    /// file containing the reference barcodes
+    #[arg(short = 'b', long)]
+    barcodes: String,
+
+    /// kmer size [kmer_size of the barcode file, or 25]
+    #[arg(short, long)]
+    kmer_size: Option<u8>,
+
+    /// reads whose kmers are looked up (FASTQ or FASTA files)
+    #[arg(required = true)]
+    reads: Vec<String>,
+
+    /// maximum number of reads loaded (in memory) from the files
+    #[arg(long, default_value_t = 100000)]
+    max_reads: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct MergeShardsArgs {
+    /// output files of the shards
+    #[arg(required = true)]
+    shards: Vec<String>,
+
+    /// merged output file [stdout]
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportBarcodesArgs {
+    /// file containing the reference barcodes
+    #[arg(short = 'b', long)]
+    barcodes: String,
+
+    /// kmer size [kmer_size of the barcode file, or 25]
+    #[arg(short, long)]
+    kmer_size: Option<u8>,
+
+    #[command(flatten)]
+    scheme: SchemeArgs,
+
+    /// output format ('fasta' or 'tsv')
+    #[arg(long, default_value = "fasta", value_parser = ["fasta", "tsv"])]
+    format: String,
+
+    /// output file [stdout]
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct CheckSchemeArgs {
+    /// file containing the reference barcodes
+    #[arg(short = 'b', long)]
+    barcodes: String,
+
+    /// reference genome (FASTA, can be gzipped)
+    #[arg(short, long)]
+    reference: String,
+
+    /// kmer size [kmer_size of the barcode file, or 25]
+    #[arg(short, long)]
+    kmer_size: Option<u8>,
+
+    /// expected number of occurrences of each barcode in the reference genome
+    #[arg(long, default_value_t = 1)]
+    expected: u32,
+
+    /// output file of the barcodes not found the expected number of times [stdout]
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct DesignBarcodesArgs {
+    /// aligned genomes (FASTA, can be gzipped)
+    #[arg(short, long)]
+    alignment: String,
+
+    /// tab-separated file of the lineage of each aligned genome (others are outgroups)
+    #[arg(short, long)]
+    lineages: String,
+
+    /// length of the flanks of the SNPs (maximum kmer size: 2 x flank length + 1)
+    #[arg(long, default_value_t = 50)]
+    flank_length: usize,
+
+    /// version of the barcode scheme
+    #[arg(long)]
+    scheme_version: Option<String>,
+
+    /// output barcode file
+    #[arg(short, long)]
+    output: String,
+}
+
+#[derive(clap::Args, Debug, Serialize)]
+struct SchemeArgs {
+    /// genome size used to compute the kmer coverage [genome_size of the barcode file]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    genome_size: Option<u64>,
+
+    /// kmer pattern whose 0 positions are ignored when matching barcodes (e.g. 1111011111...)
+    #[arg(long)]
+    spaced_seed: Option<String>,
+
+    /// scan every kmer of every read (no minimizer prefilter of the reads)
+    #[arg(long)]
+    no_prefilter: bool,
+
+    /// drop the kmers shared by the barcodes of several lineages
+    #[arg(long)]
+    drop_ambiguous_kmers: bool,
+
+    /// drop the barcodes repeated in this reference genome (FASTA) or check-scheme report
+    #[arg(long, value_name = "FILE")]
+    mask_repeats: Option<String>,
+
+    /// only keep the barcodes of these lineages and their sublineages (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    include_lineages: Vec<String>,
+
+    /// drop the barcodes of these lineages and their sublineages (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    exclude_lineages: Vec<String>,
+
+    /// tab-separated file mapping the lineages of the barcode file to the names to report
+    #[arg(long)]
+    rename_lineages: Option<String>,
+
+    /// tab-separated file of parent and child lineages [sublineages named after their parent]
+    #[arg(long)]
+    lineage_hierarchy: Option<String>,
+
+    /// collapse the calls to the lineages of this level of the hierarchy (1 = top level)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    level: Option<u8>,
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    // fraction, or percentage if followed by '%'
+    let (number, scale) = match value.strip_suffix('%') {
+        Some(number) => (number, 100.0),
+        None => (value, 1.0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(rate) if (0.0..=scale).contains(&rate) => Ok(rate / scale),
+        _ => Err(format!("{} is not a valid rate", value)),
     }
+}
 
-    if count_fasta == 1 && count_fastq == 0 {
-        InputType::Assembly
-    } else if count_fasta == 0 && count_fastq == 1 {
-        InputType::Single
-    } else if count_fasta == 0 && count_fastq == 2 {
-        InputType::Paired
-    } else {
-        eprintln!(
-            "error: the sample {} has {} fasta and {} fastq files",
-            name_sample, count_fasta, count_fastq
-        );
-        process::abort();
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    // seconds, or a number of days, hours, minutes and seconds (e.g. 1d12h, 90m or 11h30m)
+    let error = || format!("{} is not a valid duration", value);
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    let (mut seconds, mut number) = (0, String::new());
+    for c in value.trim().chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(error()),
+        };
+        seconds += number.parse::<u64>().map_err(|_| error())? * unit;
+        number.clear();
+    }
+    match number.is_empty() && seconds > 0 {
+        true => Ok(Duration::from_secs(seconds)),
+        false => Err(error()),
     }
 }
 
-fn main() {
-    println!("\n      fastlin     \n");
+fn main() -> ExitCode {
+    eprintln!("\n      fastlin     \n");
 
     // get command line arguments
-    let args = Args::parse();
-
-    // check chosen kmer size
-    if args.kmer_size < 11 || args.kmer_size > 99 || args.kmer_size % 2 == 0 {
-        // warning message
-        eprintln!(" Error: the kmer size should be an odd number between 11 and 99.\n");
-        // exit fastlin
-        std::process::exit(0);
+    let cli = Cli::parse();
+
+    // (the errors stopping fastlin are reported here, whatever the command)
+    match run(cli) {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!(" Error: {}\n", err);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    // get reference barcodes
-    let (barcodes, genome_size) = get_barcodes((&args.barcodes).into(), &args.kmer_size);
-
-    // calculate maximum number of kmers to extract
-    let kmer_limit = args.max_cov.map(|limit| limit * genome_size);
-
-    // get samples and input files
-    let all_samples = get_input_files(&args.dir);
-
-    // sort samples
-    let mut sorted_samples: Vec<_> = all_samples.iter().collect();
-    sorted_samples.sort_by_key(|k| k.0);
-
-    // create output file
-    let mut output_file =
-        File::create(args.output).expect("\n   Warning: couldn't not create output file.\n");
-    output_file
-        .write_all("#sample	data_type	k_cov	mixture	lineages	log_barcodes	log_errors\n".as_bytes())
-        .expect("write failed!");
-
-    // initialise progress bar
-    let pb = ProgressBar::new(sorted_samples.len().try_into().unwrap());
-    let sty = ProgressStyle::with_template("   {bar:60.cyan/blue} {pos:>7}/{len:7} {msg}")
-        .unwrap()
-        .progress_chars("##-");
-    pb.set_style(sty);
-
-    // process samples 1 by 1
-    println!(" . analyse all samples");
-    for (sample, list_files) in &sorted_samples {
-        // progress bar
-        pb.inc(1);
-
-        // get sequencing type ('single' or 'paired' reads)
-        let data_type = get_data_type(sample.to_string(), list_files.to_vec());
-
-        let (kmer_limit, min_count) = match &data_type {
-            InputType::Assembly => (None, 1),
-            InputType::Single | InputType::Paired => (kmer_limit, args.min_count),
-        };
-
-        // scan input files
-        let (barcode_found, coverage, error_message) = scan_reads(
-            list_files.to_vec(),
-            barcodes.to_owned(),
-            &args.kmer_size,
-            kmer_limit,
-            genome_size,
-        );
-
-        // Note: coverage used to be fixed to 1 for assemblies
-
-        // process barcodes
-        let (lineages, mixture, string_occurences) =
-            process_barcodes(barcode_found, min_count, args.n_barcodes);
-
-        // write sample info into output file
-        writeln!(
-            output_file,
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
-            sample, data_type, coverage, mixture, lineages, string_occurences, error_message
-        )
-        .expect("Failed to write to file");
+fn run(cli: Cli) -> Result<ExitCode, String> {
+    let start_time = Instant::now();
+    match cli.command {
+        Some(Command::Simulate(simulate_args)) => simulate(&simulate_args)?,
+        Some(Command::Bench(bench_args)) => bench(&bench_args)?,
+        Some(Command::BuildIndex(build_index_args)) => build_index(&build_index_args)?,
+        Some(Command::DesignBarcodes(design_args)) => design(&design_args)?,
+        Some(Command::CheckScheme(check_args)) => run_check_scheme(&check_args)?,
+        Some(Command::ExportBarcodes(export_args)) => export_barcodes(&export_args)?,
+        Some(Command::QcReport(qc_report_args)) => write_qc_report(&qc_report_args)?,
+        Some(Command::BenchIndex(bench_index_args)) => run_bench_index(&bench_index_args)?,
+        Some(Command::MergeShards(merge_args)) => run_merge_shards(&merge_args)?,
+        Some(Command::Serve(serve_args)) => {
+            check_kmer_size(serve_args.kmer_size)?;
+            let scheme = load_scheme(
+                &serve_args.barcodes,
+                serve_args.kmer_size,
+                &serve_args.scheme,
+            )?;
+            let names = load_lineage_names(&serve_args.scheme)?;
+            let hierarchy = load_hierarchy(&serve_args.scheme)?;
+            serve(&serve_args, &scheme, &names, &hierarchy)?;
+        }
+        None => return type_samples(cli.args, start_time),
     }
+    Ok(ExitCode::SUCCESS)
+}
 
-    println!("   done.");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_parsed_in_seconds_or_with_units() {
+        assert_eq!(parse_duration("3600"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("90m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("11h30m"), Ok(Duration::from_secs(41400)));
+        assert_eq!(parse_duration("1d12h"), Ok(Duration::from_secs(129600)));
+        for value in ["", "h", "12x", "1h30", "0m", "-5"] {
+            assert!(parse_duration(value).is_err(), "{}", value);
+        }
+    }
 }
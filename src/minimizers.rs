@@ -0,0 +1,185 @@
+use ahash::AHashSet;
+
+use crate::encoding::{encode_base, encode_bases, kmer_mask, BLOCK_SIZE, INVALID_BASE};
+
+// length of the minimizers (the kmer size if it is smaller)
+pub const MINIMIZER_SIZE: usize = 15;
+
+// maximum number of m-mers in a kmer (kmer sizes are below 256)
+const MAX_WINDOW: usize = 256;
+
+// minimizers of the barcode kmers: a read containing a barcode kmer has the minimizer of this
+// kmer among the minimizers of its own k-long windows, so that the reads sharing no minimizer
+// with the barcodes can be skipped without looking up each of their kmers
+#[derive(Debug)]
+pub struct MinimizerFilter {
+    k: usize,
+    m: usize,
+    minimizers: AHashSet<u64>,
+}
+
+impl MinimizerFilter {
+    // None if a kmer is not made of k bases A, C, G or T (e.g. kmers masked by a spaced seed)
+    pub fn new(kmers: impl IntoIterator<Item = impl AsRef<str>>, k: usize) -> Option<Self> {
+        let m = MINIMIZER_SIZE.min(k);
+        let mut minimizers = AHashSet::new();
+        for kmer in kmers {
+            let kmer = kmer.as_ref().as_bytes();
+            if kmer.len() != k {
+                return None;
+            }
+            minimizers.insert(min_hash(kmer, m)?);
+        }
+        Some(MinimizerFilter { k, m, minimizers })
+    }
+
+    // false if the sequence can't contain any barcode kmer
+    pub fn might_contain(&self, seq: &[u8]) -> bool {
+        let (k, m) = (self.k, self.m);
+        // number of m-mers in a window of k bases
+        let w = k - m + 1;
+        let mask = kmer_mask(m);
+        let mut encoded: u64 = 0;
+        // number of consecutive bases A, C, G or T
+        let mut valid_bases = 0;
+        // hashes of the last w m-mers (ring buffer, next slot to fill) and their minimum
+        let mut hashes = [u64::MAX; MAX_WINDOW];
+        let mut slot = 0;
+        let (mut min_hash, mut min_start) = (u64::MAX, 0);
+        let mut checked = false;
+
+        // the bases are encoded block by block before being scanned
+        let mut codes = [INVALID_BASE; BLOCK_SIZE];
+        for (block, bases) in seq.chunks(BLOCK_SIZE).enumerate() {
+            encode_bases(bases, &mut codes);
+            for (offset, code) in codes[..bases.len()].iter().enumerate() {
+                if *code == INVALID_BASE {
+                    valid_bases = 0;
+                    min_hash = u64::MAX;
+                    continue;
+                }
+                encoded = ((encoded << 2) | *code as u64) & mask;
+                valid_bases += 1;
+                if valid_bases < m {
+                    continue;
+                }
+                let start = block * BLOCK_SIZE + offset + 1 - m;
+                let hash = mix(encoded);
+                hashes[slot] = hash;
+                slot = if slot + 1 == w { 0 } else { slot + 1 };
+                if hash < min_hash {
+                    (min_hash, min_start, checked) = (hash, start, false);
+                } else if min_start + w <= start {
+                    // the minimizer left the window: find the new one among the last w m-mers
+                    // (slots not filled since the last invalid base hold older hashes)
+                    let nb_mmers = w.min(valid_bases + 1 - m);
+                    (min_hash, min_start) = (u64::MAX, start);
+                    for back in 0..nb_mmers {
+                        let index = (slot + w - 1 - back) % w;
+                        if hashes[index] < min_hash {
+                            (min_hash, min_start) = (hashes[index], start - back);
+                        }
+                    }
+                    checked = false;
+                }
+                // minimizer of the k-long window ending at this base
+                if valid_bases >= k && !checked {
+                    checked = true;
+                    if self.minimizers.contains(&min_hash) {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+fn min_hash(kmer: &[u8], m: usize) -> Option<u64> {
+    let mut encoded: u64 = 0;
+    let mut min = u64::MAX;
+    for (n, base) in kmer.iter().enumerate() {
+        let code = encode_base(*base);
+        if code == INVALID_BASE {
+            return None;
+        }
+        encoded = ((encoded << 2) | code as u64) & kmer_mask(m);
+        if n + 1 >= m {
+            min = min.min(mix(encoded));
+        }
+    }
+    Some(min)
+}
+
+// m-mers are ordered by a hash of their encoding rather than lexicographically, to avoid
+// choosing poly-A minimizers (bijective, so that distinct m-mers have distinct hashes); also
+// used to place the kmers of binary indexes, so changing it invalidates existing index files
+pub(crate) fn mix(encoded: u64) -> u64 {
+    let mut z = encoded.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Rng;
+
+    fn random_seq(rng: &mut Rng, length: usize) -> Vec<u8> {
+        (0..length).map(|_| b"ACGT"[rng.below(4)]).collect()
+    }
+
+    #[test]
+    fn kmers_must_be_made_of_acgt() {
+        assert!(MinimizerFilter::new(&["ACGTACGTAC".to_string()], 10).is_some());
+        assert!(MinimizerFilter::new(&["ACGTNCGTAC".to_string()], 10).is_none());
+        assert!(MinimizerFilter::new(&["ACGTACGTA".to_string()], 10).is_none());
+    }
+
+    #[test]
+    fn reads_containing_a_kmer_pass_the_filter() {
+        let mut rng = Rng::new(1);
+        let k = 25;
+        let kmers: Vec<Vec<u8>> = (0..20).map(|_| random_seq(&mut rng, k)).collect();
+        let sequences: Vec<String> = kmers
+            .iter()
+            .map(|kmer| String::from_utf8(kmer.clone()).unwrap())
+            .collect();
+        let filter = MinimizerFilter::new(&sequences, k).unwrap();
+        for (n, kmer) in kmers.iter().enumerate() {
+            // (kmers inside long reads spanning several blocks, after invalid bases, and
+            // in lowercase)
+            let mut read = random_seq(&mut rng, 100 * n);
+            read.extend(b"NNN");
+            read.extend(kmer);
+            read.extend(random_seq(&mut rng, 50));
+            assert!(filter.might_contain(&read));
+            assert!(filter.might_contain(&kmer.to_ascii_lowercase()));
+        }
+        assert!(!filter.might_contain(&kmers[0][1..]));
+        assert!(!filter.might_contain(b""));
+    }
+
+    #[test]
+    fn most_reads_without_kmers_are_skipped() {
+        let mut rng = Rng::new(2);
+        let k = 31;
+        let kmers: Vec<String> = (0..10)
+            .map(|_| String::from_utf8(random_seq(&mut rng, k)).unwrap())
+            .collect();
+        let filter = MinimizerFilter::new(&kmers, k).unwrap();
+        let nb_passed = (0..1000)
+            .filter(|_| filter.might_contain(&random_seq(&mut rng, 150)))
+            .count();
+        assert!(nb_passed < 100, "{}", nb_passed);
+    }
+
+    #[test]
+    fn short_kmers_are_their_own_minimizers() {
+        let filter = MinimizerFilter::new(&["ACGTACGTACG".to_string()], 11).unwrap();
+        assert_eq!(filter.m, 11);
+        assert!(filter.might_contain(b"TTTTACGTACGTACGTTTT"));
+        assert!(!filter.might_contain(b"TTTTACGTACGAACGTTTT"));
+    }
+}
@@ -0,0 +1,68 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+// extensions of the DRAGEN ORA compressed FASTQ files (matched case-insensitively)
+pub const ORA_EXTENSIONS: [&str; 2] = [".fastq.ora", ".fq.ora"];
+
+// program decompressing ORA files (Illumina's orad, looked up in the PATH)
+pub const ORAD_PROGRAM: &str = "orad";
+
+pub fn is_ora(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    ORA_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+pub fn open_ora(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    // the decompressed reads are written by orad to its standard output
+    let mut child = Command::new(ORAD_PROGRAM)
+        .arg("--stdout")
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "couldn't run {} to decompress {}: {}",
+                    ORAD_PROGRAM,
+                    path.display(),
+                    err
+                ),
+            )
+        })?;
+    let stdout = child.stdout.take().unwrap();
+    Ok(Box::new(BufReader::new(OraReader { child, stdout })))
+}
+
+// reads the output of orad, and reports its failure once the output is consumed
+struct OraReader {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for OraReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let nb_bytes = self.stdout.read(buf)?;
+        if nb_bytes == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "{} failed ({})",
+                    ORAD_PROGRAM, status
+                )));
+            }
+        }
+        Ok(nb_bytes)
+    }
+}
+
+impl Drop for OraReader {
+    // the scan can stop before the end of the file (e.g. kmer limit reached)
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,479 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::Args;
+use fastlin::allele_fractions::format_mixture_pvalue;
+use fastlin::position_stats::PositionStats;
+use fastlin::results::SampleResult;
+use fastlin::run_log::{log, timestamp, RunRecord};
+
+// file being written (renamed once complete, so that an interrupted run doesn't leave a
+// truncated file looking complete)
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    PathBuf::from(partial)
+}
+
+fn create_error(path: &str, err: std::io::Error) -> String {
+    format!("couldn't create the file {}: {}", path, err)
+}
+
+// file of a sample in an output directory (in a sub-directory per run in batch mode)
+pub fn write_sample_file(
+    dir: &str,
+    run_name: Option<&str>,
+    file_name: &str,
+    content: &str,
+) -> Result<(), String> {
+    let mut path = PathBuf::from(dir);
+    if let Some(run_name) = run_name {
+        path.push(run_name);
+    }
+    path.push(file_name);
+    let partial = partial_path(&path);
+    path.parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&partial, content))
+        .and_then(|_| std::fs::rename(&partial, &path))
+        .map_err(|err| format!("couldn't write {}: {}", path.display(), err))
+}
+
+// results of a previous run, not overwritten without --force (nor appended to without --append)
+pub fn check_overwrite(args: &Args) -> Result<(), String> {
+    if args.force || args.append || !args.input.is_empty() {
+        return Ok(());
+    }
+    let existing = match &args.outdir {
+        Some(outdir) => std::fs::read_dir(outdir)
+            .is_ok_and(|mut entries| entries.next().is_some())
+            .then_some(outdir),
+        None => {
+            (!args.no_combined_output && Path::new(&args.output).exists()).then_some(&args.output)
+        }
+    };
+    match existing {
+        Some(path) => Err(format!(
+            "{} already exists (use --force to overwrite it, or --append)",
+            path
+        )),
+        None => Ok(()),
+    }
+}
+
+// output files of the results directory (--outdir), the result files given explicitly being
+// kept and the per-barcode tables given as relative paths moved to its barcodes sub-directory
+pub fn set_outdir(args: &mut Args, outdir: &Path) -> Result<(), String> {
+    let path = |name: &str| outdir.join(name).to_string_lossy().to_string();
+    args.output = path("summary.tsv");
+    if args.per_sample_output.is_none() {
+        args.per_sample_output = Some(path("samples"));
+        args.per_sample_format = "json".to_string();
+    }
+    args.log.get_or_insert_with(|| path("logs/fastlin.log"));
+    args.run_json.get_or_insert_with(|| path("logs/run.json"));
+    args.manifest_out
+        .get_or_insert_with(|| path("manifest.json"));
+    let mut sub_dirs = vec![outdir.join("logs")];
+    for file in [
+        &mut args.allele_fractions,
+        &mut args.position_stats,
+        &mut args.barcode_bed,
+        &mut args.barcode_vcf,
+        &mut args.kmer_spectrum,
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|file| Path::new(file.as_str()).is_relative())
+    {
+        *file = path(&format!("barcodes/{}", file));
+        sub_dirs.extend(Path::new(file.as_str()).parent().map(Path::to_path_buf));
+    }
+    for dir in sub_dirs {
+        std::fs::create_dir_all(&dir)
+            .map_err(|err| format!("couldn't create the directory {}: {}", dir.display(), err))?;
+    }
+    Ok(())
+}
+
+// result rows of a sample as a JSON array of objects keyed by the columns of the header
+fn format_json_rows(header: &str, rows: &[String]) -> String {
+    let columns: Vec<&str> = header
+        .trim_start_matches('#')
+        .trim_end()
+        .split('\t')
+        .collect();
+    let rows: Vec<serde_json::Map<String, serde_json::Value>> = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .zip(row.trim_end_matches('\n').split('\t'))
+                .map(|(column, value)| (column.to_string(), value.into()))
+                .collect()
+        })
+        .collect();
+    serde_json::to_string_pretty(&rows).expect("Failed to write to file")
+}
+
+pub fn write_manifest(
+    path: &str,
+    parameters: serde_json::Value,
+    scheme: serde_json::Value,
+    samples: Vec<serde_json::Value>,
+) -> Result<(), String> {
+    // everything needed to reproduce a run, for audits
+    let hostname = std::fs::read_to_string("/etc/hostname")
+        .map(|name| name.trim().to_string())
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok();
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let manifest = serde_json::json!({
+        "fastlin": env!("CARGO_PKG_VERSION"),
+        "date": timestamp(),
+        "command": std::env::args().collect::<Vec<String>>(),
+        "parameters": parameters,
+        "scheme": scheme,
+        "samples": samples,
+        "host": {
+            "hostname": hostname,
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+            "cpus": cpus,
+        },
+    });
+    let content = serde_json::to_string_pretty(&manifest).expect("Failed to write to file");
+    std::fs::write(path, content)
+        .map_err(|err| format!("couldn't write the file {}: {}", path, err))
+}
+
+pub fn write_run_record(path: &str, record: &RunRecord) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(record).expect("Failed to write to file");
+    std::fs::write(path, content)
+        .map_err(|err| format!("couldn't write the file {}: {}", path, err))
+}
+
+pub fn write_versions(file_name: &str, scheme_version: &str) -> Result<(), String> {
+    // tool and scheme versions, following the nf-core versions.yml convention
+    let version = env!("CARGO_PKG_VERSION");
+    let content = if file_name.ends_with(".json") {
+        let versions = serde_json::json!({
+            "fastlin": { "fastlin": version, "barcodes": scheme_version }
+        });
+        format!("{}\n", versions)
+    } else {
+        format!(
+            "fastlin:\n  fastlin: {}\n  barcodes: {}\n",
+            version, scheme_version
+        )
+    };
+    std::fs::write(file_name, content)
+        .map_err(|err| format!("couldn't write the versions file {}: {}", file_name, err))
+}
+
+// header of the output file: run and kmer size (in batch mode, and with several kmer sizes),
+// columns of the results, of the options and of the sample sheet
+pub fn header(
+    args: &Args,
+    batch_mode: bool,
+    multi_k: bool,
+    human_fraction: bool,
+    metadata_columns: &[String],
+) -> String {
+    let mut header = String::from("#");
+    if batch_mode {
+        header.push_str("run\t");
+    }
+    if multi_k {
+        header.push_str("kmer_size\t");
+    }
+    header.push_str(match args.legacy_output {
+        true => "sample	data_type	k_cov	mixture	lineages	log_barcodes	log_errors",
+        false => "sample	data_type	k_cov	mixture	lineages	log_barcodes	log_errors	mean_read_length	median_read_length",
+    });
+    if args.error_rate {
+        header.push_str("\terror_rate");
+    }
+    if args.depth_cv {
+        header.push_str("\tdepth_cv");
+    }
+    if args.file_coverage {
+        header.push_str("\tfile_coverage");
+    }
+    if human_fraction {
+        header.push_str("\thuman_fraction");
+    }
+    if args.metagenomic {
+        header.push_str("\tabundance");
+    }
+    if args.amplicon {
+        header.push_str("\ttargets");
+    }
+    if args.allele_fractions.is_some() {
+        header.push_str("\tallele_fractions\tmixture_pvalue");
+    }
+    if args.em_proportions {
+        header.push_str("\tem_proportions");
+    }
+    for column in metadata_columns {
+        header.push_str(&format!("\t{}", column));
+    }
+    header.push('\n');
+    header
+}
+
+// columns of a sample added by the options of the run (the same for each kmer size)
+#[derive(Default)]
+pub struct ExtraColumns {
+    pub human_fraction: Option<String>,
+    pub abundance: String,
+    pub targets: String,
+    pub allele_fractions: String,
+    pub mixture_pvalue: Option<f64>,
+    pub em_proportions: String,
+    // (columns of the sample sheet, each preceded by a tab)
+    pub metadata: String,
+}
+
+// row of the output file, with the columns of the header
+pub fn sample_row(
+    args: &Args,
+    run_name: Option<&str>,
+    kmer_size: Option<usize>,
+    result: &SampleResult,
+    legacy: (u32, &str),
+    columns: &ExtraColumns,
+) -> String {
+    let mut row = String::new();
+    if let Some(run_name) = run_name {
+        row.push_str(&format!("{}\t", run_name));
+    }
+    if let Some(kmer_size) = kmer_size {
+        row.push_str(&format!("{}\t", kmer_size));
+    }
+    match args.legacy_output {
+        true => row.push_str(&result.to_legacy_row(legacy.0, legacy.1)),
+        false => row.push_str(&result.to_row()),
+    }
+    if args.error_rate {
+        row.push_str(&format!("\t{}", result.error_rate_column()));
+    }
+    if args.depth_cv {
+        row.push_str(&format!("\t{}", result.depth_cv_column()));
+    }
+    if args.file_coverage {
+        row.push_str(&format!("\t{}", result.file_coverage_column()));
+    }
+    if let Some(human_fraction) = &columns.human_fraction {
+        row.push_str(&format!("\t{}", human_fraction));
+    }
+    if args.metagenomic {
+        row.push_str(&format!("\t{}", columns.abundance));
+    }
+    if args.amplicon {
+        row.push_str(&format!("\t{}", columns.targets));
+    }
+    if args.allele_fractions.is_some() {
+        row.push_str(&format!(
+            "\t{}\t{}",
+            columns.allele_fractions,
+            format_mixture_pvalue(columns.mixture_pvalue)
+        ));
+    }
+    if args.em_proportions {
+        row.push_str(&format!("\t{}", columns.em_proportions));
+    }
+    row.push_str(&format!("{}\n", columns.metadata));
+    row
+}
+
+// outputs of a typed sample, written in the order of the samples
+#[derive(Default)]
+pub struct SampleOutputs {
+    // (rows of the output file, one per kmer size)
+    pub rows: Vec<String>,
+    pub histogram: String,
+    pub alleles: String,
+    pub positions: Vec<u8>,
+    // (files of the sample in output directories: directory, file name and content)
+    pub files: Vec<(String, String, String)>,
+}
+
+// files of the run, written as the samples are typed
+pub struct OutputFiles {
+    header: String,
+    // (stdout in single-sample mode, the output file being written as a partial file renamed
+    // once the run is complete otherwise)
+    output: Box<dyn Write>,
+    single_sample: bool,
+    partial_output: Option<(PathBuf, String)>,
+    histogram: Option<File>,
+    alleles: Option<File>,
+    positions: Option<File>,
+    // (directory and format of the result files of the samples)
+    per_sample: Option<(String, String)>,
+}
+
+impl OutputFiles {
+    // output files with their headers (and the rows kept from the existing output file)
+    pub fn create(
+        args: &Args,
+        header: String,
+        previous: &str,
+        single_sample: bool,
+    ) -> Result<Self, String> {
+        let mut partial_output = None;
+        let mut output: Box<dyn Write> = if single_sample {
+            Box::new(std::io::stdout())
+        } else if args.no_combined_output {
+            Box::new(std::io::sink())
+        } else {
+            let partial = partial_path(Path::new(&args.output));
+            let file = File::create(&partial)
+                .map_err(|err| create_error(&partial.to_string_lossy(), err))?;
+            partial_output = Some((partial, args.output.clone()));
+            Box::new(std::io::BufWriter::new(file))
+        };
+        output.write_all(header.as_bytes()).expect("write failed!");
+        output
+            .write_all(previous.as_bytes())
+            .expect("Failed to write to file");
+
+        let create = |path: &String, header: &[u8]| -> Result<File, String> {
+            let mut file = File::create(path).map_err(|err| create_error(path, err))?;
+            file.write_all(header).expect("write failed!");
+            Ok(file)
+        };
+        let histogram = args
+            .depth_histogram
+            .as_ref()
+            .map(|path| create(path, b"#sample\tk_cov\tlineage\tdepth\tnb_barcodes\n"))
+            .transpose()?;
+        // (barcode sites of the barcodes scanned with wild-type kmers)
+        let alleles = args
+            .allele_fractions
+            .as_ref()
+            .map(|path| {
+                create(
+                    path,
+                    b"#sample\tbarcode\tlineage\talt_count\tref_count\talt_fraction\n",
+                )
+            })
+            .transpose()?;
+        let positions = args
+            .position_stats
+            .as_ref()
+            .map(|path| {
+                let mut file = create(path, b"")?;
+                PositionStats::write_header(&mut file).expect("write failed!");
+                Ok::<File, String>(file)
+            })
+            .transpose()?;
+
+        Ok(OutputFiles {
+            header,
+            output,
+            single_sample,
+            partial_output,
+            histogram,
+            alleles,
+            positions,
+            per_sample: args
+                .per_sample_output
+                .clone()
+                .map(|dir| (dir, args.per_sample_format.clone())),
+        })
+    }
+
+    pub fn write_sample(
+        &mut self,
+        run_name: Option<&str>,
+        sample: &str,
+        outputs: &SampleOutputs,
+    ) -> Result<(), String> {
+        for row in &outputs.rows {
+            self.output
+                .write_all(row.as_bytes())
+                .expect("Failed to write to file");
+        }
+        // (rows written as each sample completes, so the output file can be followed)
+        self.output.flush().expect("Failed to write to file");
+
+        if let Some(file) = self.histogram.as_mut() {
+            file.write_all(outputs.histogram.as_bytes())
+                .expect("Failed to write to file");
+        }
+        if let Some(file) = self.alleles.as_mut() {
+            file.write_all(outputs.alleles.as_bytes())
+                .expect("Failed to write to file");
+        }
+        if let Some(file) = self.positions.as_mut() {
+            file.write_all(&outputs.positions)
+                .expect("Failed to write to file");
+        }
+        for (dir, file_name, content) in &outputs.files {
+            write_sample_file(dir, run_name, file_name, content)?;
+        }
+
+        // result file of the sample (its rows, one per kmer size)
+        if let Some((sample_dir, format)) = &self.per_sample {
+            let (extension, content) = match format.as_str() {
+                "json" => ("json", format_json_rows(&self.header, &outputs.rows)),
+                _ => ("tsv", format!("{}{}", self.header, outputs.rows.concat())),
+            };
+            write_sample_file(
+                sample_dir,
+                run_name,
+                &format!("{}.{}", sample, extension),
+                &content,
+            )?;
+        }
+        Ok(())
+    }
+
+    // run summary (commented lines at the end of the output file, except with --legacy-output
+    // and on stdout, which only carries the result row), and output file renamed once complete
+    pub fn finish(mut self, summary: &[String], legacy_output: bool) -> Result<(), String> {
+        for line in summary {
+            if !legacy_output && !self.single_sample {
+                writeln!(self.output, "# {}", line).expect("Failed to write to file");
+            }
+            eprintln!("   {}", line);
+            log("INFO", line);
+        }
+        self.output.flush().expect("Failed to write to file");
+        drop(self.output);
+        if let Some((partial, output)) = &self.partial_output {
+            std::fs::rename(partial, output).map_err(|err| {
+                format!(
+                    "couldn't rename {} to {}: {}",
+                    partial.display(),
+                    output,
+                    err
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_files_are_renamed_once_written() {
+        assert_eq!(
+            partial_path(Path::new("results/out.txt")),
+            PathBuf::from("results/out.txt.partial")
+        );
+        let dir = std::env::temp_dir().join(format!("fastlin_sample_file_{}", std::process::id()));
+        let dir_name = dir.to_string_lossy().to_string();
+        write_sample_file(&dir_name, Some("run1"), "s1.tsv", "#sample\ns1\n").unwrap();
+        let path = dir.join("run1/s1.tsv");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "#sample\ns1\n");
+        assert!(!partial_path(&path).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::analyse_sample::{ReadHits, ReadVisitor};
+use crate::process_barcodes::{barcode_index, barcode_lineage, lineage_name, LineageNames};
+
+// barcode SNPs found within this distance of the 3' end of reads are flagged
+pub const TAIL_LENGTH: usize = 10;
+
+#[derive(Default)]
+struct BarcodePositions {
+    hits: u64,
+    sum_position: u64,
+    sum_distance_3p: u64,
+    tail_hits: u64,
+}
+
+// positions of the barcode SNPs within reads, to detect barcodes only matching near the 3'
+// end of reads (a classic adapter/low quality artifact)
+pub struct PositionStats {
+    half_k: usize,
+    barcodes: BTreeMap<String, BarcodePositions>,
+    names: LineageNames,
+}
+
+impl PositionStats {
+    pub fn new(kmer_size: usize) -> Self {
+        PositionStats {
+            half_k: (kmer_size - 1) / 2,
+            barcodes: BTreeMap::new(),
+            names: LineageNames::new(),
+        }
+    }
+
+    pub fn set_lineage_names(&mut self, names: LineageNames) {
+        self.names = names;
+    }
+
+    pub fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+        writer.write_all(
+            b"#sample\tlineage\tbarcode\thits\tmean_position\tmean_distance_3p\ttail_hits\ttail_only\n",
+        )
+    }
+
+    // write the statistics of each barcode found in the sample and reset them
+    pub fn write_sample<W: Write>(&mut self, writer: &mut W, sample: &str) -> io::Result<()> {
+        for (barcode_id, stats) in &self.barcodes {
+            let hits = stats.hits as f64;
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{:.1}\t{:.1}\t{}\t{}",
+                sample,
+                lineage_name(&self.names, barcode_lineage(barcode_id)),
+                barcode_index(barcode_id),
+                stats.hits,
+                stats.sum_position as f64 / hits,
+                stats.sum_distance_3p as f64 / hits,
+                stats.tail_hits,
+                if stats.tail_hits == stats.hits {
+                    "yes"
+                } else {
+                    "no"
+                }
+            )?;
+        }
+        self.barcodes.clear();
+        Ok(())
+    }
+}
+
+impl ReadVisitor for PositionStats {
+    fn visit(&mut self, read: &ReadHits) {
+        for hit in read.hits {
+            // position of the barcode SNP (centre of the kmer) in the read
+            let position = hit.position + self.half_k;
+            let distance_3p = read.seq.len() - 1 - position;
+
+            let stats = self.barcodes.entry(hit.barcode_id.to_string()).or_default();
+            stats.hits += 1;
+            stats.sum_position += position as u64;
+            stats.sum_distance_3p += distance_3p as u64;
+            if distance_3p < TAIL_LENGTH {
+                stats.tail_hits += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyse_sample::BarcodeHit;
+
+    #[test]
+    fn barcodes_only_found_near_the_3p_end_are_flagged() {
+        let mut stats = PositionStats::new(11);
+        let seq = [b'A'; 100];
+        // (SNPs at the centre of the kmers, 5 bases after their start)
+        for (barcode_id, position) in [("2__1", 10), ("2__1", 30), ("4__2", 88), ("4__2", 90)] {
+            stats.visit(&ReadHits {
+                read_id: "r1",
+                seq: &seq,
+                qual: None,
+                hits: &[BarcodeHit {
+                    barcode_id,
+                    position,
+                }],
+            });
+        }
+        let mut lines = Vec::new();
+        PositionStats::write_header(&mut lines).unwrap();
+        stats.write_sample(&mut lines, "s1").unwrap();
+        assert_eq!(
+            String::from_utf8(lines).unwrap(),
+            "#sample\tlineage\tbarcode\thits\tmean_position\tmean_distance_3p\ttail_hits\ttail_only\n\
+             s1\t2\t1\t2\t25.0\t74.0\t0\tno\n\
+             s1\t4\t2\t2\t94.0\t5.0\t2\tyes\n"
+        );
+        // (statistics reset for the next sample)
+        let mut lines = Vec::new();
+        stats.write_sample(&mut lines, "s2").unwrap();
+        assert!(lines.is_empty());
+    }
+}
@@ -1,74 +1,449 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::str;
 
+use crate::hierarchy::LineageHierarchy;
+use crate::simulation::Rng;
+
+// coefficient of variation of the barcode depths of a lineage above which (after removing
+// the variation expected from the sampling of the reads) its depth profile is flagged
+pub const MAX_DEPTH_CV: f64 = 0.5;
+
+// bootstrap replicates of the barcode depths used for the confidence intervals of the
+// mixture proportions (with a fixed seed, so that results are reproducible)
+pub const BOOTSTRAP_REPLICATES: usize = 1000;
+const BOOTSTRAP_SEED: u64 = 1;
+
+// minimum depth of a barcode in amplicon mode, relative to the depth of the targets (median
+// depth of the barcodes found), so that the threshold follows the depth of the panel
+pub const AMPLICON_MIN_FRACTION: f64 = 0.02;
+
+// lineage detected in a sample, with the median number of occurrences of its barcodes,
+// their coefficient of variation (uneven depths if it is well above the expected variation)
+// and the proportion of the lineage in the sample (median / sum of the medians of the
+// lineages called) with its 95% bootstrap confidence interval (only computed for the JSON
+// results, see proportion_intervals)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineageCall {
+    pub lineage: String,
+    pub median: i32,
+    #[serde(default)]
+    pub depth_cv: f64,
+    #[serde(default)]
+    pub uneven: bool,
+    #[serde(default)]
+    pub proportion: f64,
+    #[serde(default)]
+    pub proportion_ci: (f64, f64),
+}
+
+// probability below which the depth of a lineage is unlikely to come from sequencing errors
+// (Poisson caller), and sequencing error rate assumed for the background depth
+pub const POISSON_SIGNIFICANCE: f64 = 0.001;
+const POISSON_ERROR_RATE: f64 = 0.01;
+
+// strategy calling the lineages present in a sample from the counts of their barcodes (with
+// at least the minimum count), so that strategies can be compared on the same counts
+pub trait Caller: Sync {
+    // lineages called and their depth (median count of their barcodes)
+    fn call(
+        &self,
+        lineages: &HashMap<String, Vec<i32>>,
+        min_barcodes: usize,
+        hierarchy: &LineageHierarchy,
+    ) -> Vec<(String, i32)>;
+}
+
+// lineages with at least min_barcodes barcodes (default)
+pub struct ThresholdCaller;
+
+impl Caller for ThresholdCaller {
+    fn call(
+        &self,
+        lineages: &HashMap<String, Vec<i32>>,
+        min_barcodes: usize,
+        hierarchy: &LineageHierarchy,
+    ) -> Vec<(String, i32)> {
+        // filter lineages using input parameters
+        let filtered_lineages = filter_lineages(lineages.clone(), min_barcodes);
+
+        // get non-inclusive lineages sorted by nb occurrences
+        non_inclusive_lineages(filtered_lineages, hierarchy)
+    }
+}
+
+// lineages with at least min_barcodes barcodes whose depth is unlikely under a Poisson model
+// of the depth of erroneous kmers (reads of the major lineage with an error converting a SNP
+// to the allele of the barcode) for all their barcodes, so that the depth required scales
+// with the coverage
+pub struct PoissonCaller;
+
+impl Caller for PoissonCaller {
+    fn call(
+        &self,
+        lineages: &HashMap<String, Vec<i32>>,
+        min_barcodes: usize,
+        hierarchy: &LineageHierarchy,
+    ) -> Vec<(String, i32)> {
+        let filtered_lineages = filter_lineages(lineages.clone(), min_barcodes);
+        let major = filtered_lineages.values().max().copied().unwrap_or(0);
+        let background = major as f64 * POISSON_ERROR_RATE / 3.0;
+        let significant: HashMap<String, i32> = filtered_lineages
+            .into_iter()
+            .filter(|(lineage, median)| {
+                let nb_barcodes = lineages[lineage].len() as i32;
+                poisson_tail(background, *median).powi(nb_barcodes) < POISSON_SIGNIFICANCE
+            })
+            .collect();
+        non_inclusive_lineages(significant, hierarchy)
+    }
+}
+
+// names of the callers (--caller)
+pub const CALLERS: [&str; 2] = ["threshold", "poisson"];
+
+pub fn caller_by_name(name: &str) -> Option<&'static dyn Caller> {
+    match name {
+        "threshold" => Some(&ThresholdCaller),
+        "poisson" => Some(&PoissonCaller),
+        _ => None,
+    }
+}
+
+fn poisson_tail(mean: f64, count: i32) -> f64 {
+    // probability that a Poisson variable of the given mean is at least count (log-space terms,
+    // as exp(-mean) underflows for the background depths of deep samples)
+    let ln_mean = mean.ln();
+    let mut ln_term = -mean;
+    let mut below = 0.0;
+    for i in 0..count.max(0) {
+        below += ln_term.exp();
+        ln_term += ln_mean - ((i + 1) as f64).ln();
+    }
+    (1.0 - below).max(0.0)
+}
+
 pub fn process_barcodes(
     bar_found: HashMap<String, i32>,
     min_count: i32,
     min_barcodes: usize,
-) -> (String, String, String) {
+    hierarchy: &LineageHierarchy,
+    caller: &dyn Caller,
+) -> (Vec<LineageCall>, BTreeMap<String, Vec<i32>>) {
     // merge barcode IDs to lineages
-    let lineages = merge_barcodes(bar_found, min_count);
+    let lineages = merge_barcodes(bar_found, min_count, hierarchy);
+
+    // lineages called, sorted by nb occurrences
+    let vect_lineages = caller.call(&lineages, min_barcodes, hierarchy);
+
+    let mut calls: Vec<LineageCall> = vect_lineages
+        .into_iter()
+        .map(|(lineage, median)| {
+            let (depth_cv, uneven) = depth_variation(&lineages[&lineage]);
+            LineageCall {
+                lineage,
+                median,
+                depth_cv,
+                uneven,
+                proportion: 0.0,
+                proportion_ci: (0.0, 0.0),
+            }
+        })
+        .collect();
+
+    // keep all barcode info, sorted by lineage
+    let lineages: BTreeMap<String, Vec<i32>> = lineages.into_iter().collect();
+    mixture_proportions(&mut calls);
+    (calls, lineages)
+}
+
+fn depth_variation(depths: &[i32]) -> (f64, bool) {
+    // coefficient of variation (sample standard deviation / mean) of the barcode depths, and
+    // whether it exceeds MAX_DEPTH_CV once the Poisson variation of the depths is removed
+    // (variance = mean + (cv.mean)^2 for depths sampled from uneven coverages)
+    if depths.len() < 2 {
+        return (0.0, false);
+    }
+    let n = depths.len() as f64;
+    let mean = depths.iter().map(|depth| *depth as f64).sum::<f64>() / n;
+    if mean == 0.0 {
+        return (0.0, false);
+    }
+    let variance = depths
+        .iter()
+        .map(|depth| (*depth as f64 - mean).powi(2))
+        .sum::<f64>()
+        / (n - 1.0);
+    let cv = variance.sqrt() / mean;
+    let excess_cv = (cv * cv - 1.0 / mean).max(0.0).sqrt();
+    (cv, excess_cv > MAX_DEPTH_CV)
+}
+
+pub fn mixture_proportions(calls: &mut [LineageCall]) {
+    // proportions of the lineages called (their confidence intervals being reset)
+    let total: i32 = calls.iter().map(|call| call.median).sum();
+    for call in calls.iter_mut() {
+        call.proportion = match total {
+            ..=0 => 0.0,
+            _ => call.median as f64 / total as f64,
+        };
+        call.proportion_ci = (0.0, 0.0);
+    }
+}
+
+pub fn proportion_intervals(calls: &mut [LineageCall], lineages: &BTreeMap<String, Vec<i32>>) {
+    // confidence intervals of the proportions of the lineages called, from the proportions
+    // obtained by resampling the barcode depths of each lineage (with replacement)
+    let total: i32 = calls.iter().map(|call| call.median).sum();
+    if total <= 0 {
+        return;
+    }
+    let mut rng = Rng::new(BOOTSTRAP_SEED);
+    let mut replicates: Vec<Vec<f64>> = vec![Vec::with_capacity(BOOTSTRAP_REPLICATES); calls.len()];
+    let mut resampled = Vec::new();
+    for _ in 0..BOOTSTRAP_REPLICATES {
+        let medians: Vec<i32> = calls
+            .iter()
+            .map(|call| {
+                let depths = &lineages[&call.lineage];
+                resampled.clear();
+                resampled.extend((0..depths.len()).map(|_| depths[rng.below(depths.len())]));
+                median(&resampled)
+            })
+            .collect();
+        let replicate_total: i32 = medians.iter().sum();
+        for (proportions, median) in replicates.iter_mut().zip(medians) {
+            proportions.push(match replicate_total {
+                0 => 0.0,
+                _ => median as f64 / replicate_total as f64,
+            });
+        }
+    }
+
+    for (call, mut proportions) in calls.iter_mut().zip(replicates) {
+        proportions.sort_by(f64::total_cmp);
+        let percentile =
+            |p: f64| proportions[(p * (proportions.len() - 1) as f64).round() as usize];
+        call.proportion_ci = (percentile(0.025), percentile(0.975));
+    }
+}
+
+pub fn trace_lineages_note(calls: &[LineageCall]) -> String {
+    match calls {
+        [] => String::new(),
+        _ => format!("trace lineages: {}", format_lineages(calls)),
+    }
+}
+
+pub fn format_depth_cv(calls: &[LineageCall]) -> String {
+    // coefficient of variation of the called lineages: lineage (cv), lineage2 (cv)
+    calls
+        .iter()
+        .map(|call| format!("{} ({:.2})", call.lineage, call.depth_cv))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+pub fn uneven_depth_warning(call: &LineageCall) -> String {
+    format!(
+        "uneven barcode depths for lineage {} (CV {:.2})",
+        call.lineage, call.depth_cv
+    )
+}
+
+pub fn format_lineages(calls: &[LineageCall]) -> String {
+    // convert calls into a string of the following format: lineage (median), lineage2 (median)
+    calls
+        .iter()
+        .map(|call| format!("{} ({})", call.lineage, call.median))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// preferred names of the lineages of the barcode file (lineages not listed keep their name)
+pub type LineageNames = HashMap<String, String>;
+
+pub fn parse_lineage_names(content: &str) -> Result<LineageNames, String> {
+    // tab-separated lines: lineage of the barcode file, preferred name
+    let mut names = LineageNames::new();
+    for (line_number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('\t') {
+            Some((lineage, name)) if !name.trim().is_empty() => {
+                names.insert(lineage.trim().to_string(), name.trim().to_string());
+            }
+            _ => {
+                return Err(format!(
+                    "line {} of the lineage names file should contain a lineage and its name (tab-separated)",
+                    line_number + 1
+                ));
+            }
+        }
+    }
+    Ok(names)
+}
 
-    // save all barcode info into String
-    let log_barcodes = format_data(lineages.clone());
+pub fn lineage_name<'a>(names: &'a LineageNames, lineage: &'a str) -> &'a str {
+    names.get(lineage).map_or(lineage, String::as_str)
+}
 
-    // filter lineages using input parameters
-    let filtered_lineages = filter_lineages(lineages.clone(), min_barcodes);
+pub fn barcode_lineage(barcode_id: &str) -> &str {
+    // barcode IDs are in the form lineage__index
+    barcode_id.split('_').next().unwrap_or(barcode_id)
+}
+
+pub fn is_sublineage(lineage: &str, parent: &str) -> bool {
+    // lineage is the parent itself or one of its sublineages (e.g. 4.1.2 for 4)
+    lineage == parent
+        || lineage
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
 
-    // get non-inclusive lineages sorted by nb occurrences
-    let vect_lineages = non_inclusive_lineages(filtered_lineages);
+pub fn barcode_index(barcode_id: &str) -> &str {
+    // index of the barcode in the barcode file
+    barcode_id.rsplit('_').next().unwrap_or(barcode_id)
+}
 
-    // check if mixture of lineages
-    let mixture = if vect_lineages.len() > 1 { "yes" } else { "no" };
+pub fn depth_histogram<'a>(
+    counts: &HashMap<String, i32>,
+    barcode_ids: impl IntoIterator<Item = &'a String>,
+) -> BTreeMap<String, BTreeMap<i32, usize>> {
+    // number of barcodes per lineage and kmer count (including barcodes not found)
+    let mut histogram: BTreeMap<String, BTreeMap<i32, usize>> = BTreeMap::new();
+
+    for barcode_id in barcode_ids {
+        let depth = counts.get(barcode_id).copied().unwrap_or(0);
+        *histogram
+            .entry(barcode_lineage(barcode_id).to_string())
+            .or_default()
+            .entry(depth)
+            .or_insert(0) += 1;
+    }
+    histogram
+}
+
+// depth of the targets of an amplicon panel (median depth of the barcodes found)
+pub fn target_depth(counts: &HashMap<String, i32>) -> i32 {
+    let depths: Vec<i32> = counts.values().copied().collect();
+    match depths.is_empty() {
+        true => 0,
+        false => median(&depths),
+    }
+}
+
+pub fn amplicon_min_count(min_count: i32, depth: i32) -> i32 {
+    min_count.max((AMPLICON_MIN_FRACTION * depth as f64).ceil() as i32)
+}
 
-    // convert to String
-    let formatted_lineages: Vec<String> = vect_lineages
+pub fn covered_targets<'a>(
+    counts: &HashMap<String, i32>,
+    barcode_ids: impl IntoIterator<Item = &'a String>,
+    min_count: i32,
+    names: &LineageNames,
+) -> String {
+    // number of barcodes covered (at least min_count occurrences) out of the barcodes of the
+    // scheme, in total and for the lineages with covered barcodes: '3/40 (4.1: 3/15)'
+    let mut targets: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for barcode_id in barcode_ids {
+        let target = targets.entry(barcode_lineage(barcode_id)).or_default();
+        target.1 += 1;
+        if counts
+            .get(barcode_id)
+            .is_some_and(|count| *count >= min_count)
+        {
+            target.0 += 1;
+        }
+    }
+    let (covered, total) = targets.values().fold((0, 0), |(covered, total), target| {
+        (covered + target.0, total + target.1)
+    });
+    let lineages: Vec<String> = targets
         .iter()
-        .map(|(lineage_name, med_value)| format!("{} ({})", lineage_name, med_value))
+        .filter(|(_, (covered, _))| *covered > 0)
+        .map(|(lineage, (covered, total))| {
+            format!("{}: {}/{}", lineage_name(names, lineage), covered, total)
+        })
         .collect();
+    match lineages.is_empty() {
+        true => format!("{}/{}", covered, total),
+        false => format!("{}/{} ({})", covered, total, lineages.join(", ")),
+    }
+}
 
-    let result = formatted_lineages.join(", ");
+// estimated depth of the target genome (metagenomic mode): sum over the lineages called of
+// the mean occurrences of their barcodes, including the barcodes not found, so that it isn't
+// biased upwards at depths where most barcodes are missed
+pub fn target_genome_depth<'a>(
+    calls: &[LineageCall],
+    counts: &HashMap<String, i32>,
+    barcode_ids: impl IntoIterator<Item = &'a String> + Clone,
+) -> f64 {
+    calls
+        .iter()
+        .map(|call| {
+            let depths: Vec<i32> = barcode_ids
+                .clone()
+                .into_iter()
+                .filter(|barcode_id| barcode_lineage(barcode_id) == call.lineage)
+                .map(|barcode_id| counts.get(barcode_id).copied().unwrap_or(0))
+                .collect();
+            match depths.is_empty() {
+                true => 0.0,
+                false => depths.iter().sum::<i32>() as f64 / depths.len() as f64,
+            }
+        })
+        .sum()
+}
 
-    (result, mixture.to_string(), log_barcodes)
+pub fn format_abundance(depth: f64, genome_size: u64, kmer_count: u64) -> String {
+    // depth of the target genome and proportion of the kmers of the sample it accounts for
+    // (the rest being background, e.g. host and commensal reads)
+    let proportion = match kmer_count {
+        0 => 0.0,
+        _ => (depth * genome_size as f64 / kmer_count as f64).min(1.0),
+    };
+    format!("{:.2}x ({:.3}% of the kmers)", depth, 100.0 * proportion)
 }
 
-fn merge_barcodes(b_found: HashMap<String, i32>, min_occurences: i32) -> HashMap<String, Vec<i32>> {
+fn merge_barcodes(
+    b_found: HashMap<String, i32>,
+    min_occurences: i32,
+    hierarchy: &LineageHierarchy,
+) -> HashMap<String, Vec<i32>> {
     let mut merged_lineages: HashMap<String, Vec<i32>> = HashMap::new();
 
-    for (barcode_id, nb_occurences) in &b_found {
+    // barcodes taken in the order of the barcode file, so that the output is reproducible
+    let mut b_found: Vec<(String, i32)> = b_found.into_iter().collect();
+    b_found.sort_by_cached_key(|(barcode_id, _)| {
+        (
+            barcode_index(barcode_id)
+                .parse::<usize>()
+                .unwrap_or(usize::MAX),
+            barcode_id.to_string(),
+        )
+    });
+
+    for (barcode_id, nb_occurences) in b_found {
         // only consider barcode IDs with abundances >= minimum count
-        if nb_occurences >= &min_occurences {
-            let parts: Vec<&str> = barcode_id.split('_').collect();
-            let lineage = parts[0].to_string();
-            match merged_lineages.get(&lineage) {
-                Some(_vect_nb) => {
-                    merged_lineages
-                        .get_mut(&lineage)
-                        .unwrap()
-                        .push(nb_occurences.to_owned());
-                }
-                None => {
-                    merged_lineages.insert(lineage.clone(), Vec::new());
-                    merged_lineages
-                        .get_mut(&lineage)
-                        .unwrap()
-                        .push(nb_occurences.to_owned());
-                }
-            }
+        if nb_occurences >= min_occurences {
+            // (barcodes of sublineages count for their ancestor when calls are collapsed)
+            let lineage = hierarchy.collapse(barcode_lineage(&barcode_id));
+            merged_lineages
+                .entry(lineage)
+                .or_default()
+                .push(nb_occurences);
         }
     }
     merged_lineages
 }
 
-fn format_data(data: HashMap<String, Vec<i32>>) -> String {
-    // convert hashmap into a string of the following format: key (nb,nb,nb), key2 (nb,nb,nb), ...
-    let mut sorted_keys: Vec<&String> = data.keys().collect();
-    sorted_keys.sort();
-
-    sorted_keys
-        .iter()
-        .map(|&key| {
-            let values = data.get(key).unwrap();
+pub fn format_data(data: &BTreeMap<String, Vec<i32>>) -> String {
+    // convert map into a string of the following format: key (nb,nb,nb), key2 (nb,nb,nb), ...
+    data.iter()
+        .map(|(key, values)| {
             let values_string = values
                 .iter()
                 .map(ToString::to_string)
@@ -100,21 +475,24 @@ fn median(values: &[i32]) -> i32 {
     let mut sorted_values = values.to_owned();
     sorted_values.sort();
     let len = sorted_values.len();
-    if len % 2 == 0 {
+    if len.is_multiple_of(2) {
         (sorted_values[len / 2 - 1] + sorted_values[len / 2]) / 2
     } else {
         sorted_values[len / 2]
     }
 }
 
-fn non_inclusive_lineages(lineages: HashMap<String, i32>) -> Vec<(String, i32)> {
+fn non_inclusive_lineages(
+    lineages: HashMap<String, i32>,
+    hierarchy: &LineageHierarchy,
+) -> Vec<(String, i32)> {
     let all_keys: Vec<String> = lineages.keys().cloned().collect();
     let mut final_vect = vec![];
 
     for (lin, med_value) in lineages {
         let mut not_included = true;
         for key in all_keys.clone() {
-            if key.starts_with(lin.as_str()) && lin != key {
+            if hierarchy.is_descendant(&key, &lin) {
                 not_included = false;
                 break;
             }
@@ -124,5 +502,236 @@ fn non_inclusive_lineages(lineages: HashMap<String, i32>) -> Vec<(String, i32)>
             final_vect.push((lin, med_value));
         }
     }
+    // highest medians first, ties broken by lineage name
+    final_vect.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
     final_vect
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // barcodes (lineage__index) found with the given counts
+    fn barcodes(lineages: &[(&str, &[i32])]) -> HashMap<String, i32> {
+        let mut index = 0;
+        let mut found = HashMap::new();
+        for (lineage, counts) in lineages {
+            for count in *counts {
+                index += 1;
+                found.insert(format!("{}__{}", lineage, index), *count);
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn medians() {
+        assert_eq!(median(&[5, 1, 3]), 3);
+        assert_eq!(median(&[4, 1, 3, 10]), 3);
+        assert_eq!(median(&[7]), 7);
+    }
+
+    #[test]
+    fn sublineages_are_called_instead_of_their_ancestors() {
+        let found = barcodes(&[
+            ("4", &[20, 22, 21]),
+            ("4.1", &[20, 19, 18]),
+            ("2", &[5, 6, 7]),
+            ("3", &[9, 9]),
+            ("1", &[1, 2, 3]),
+        ]);
+        let hierarchy = LineageHierarchy::default();
+        let (calls, lineages) = process_barcodes(found, 4, 3, &hierarchy, &ThresholdCaller);
+        assert_eq!(format_lineages(&calls), "4.1 (19), 2 (6)");
+        // (barcodes below the minimum count dropped, in the order of the barcode file)
+        assert_eq!(
+            format_data(&lineages),
+            "2 (5, 6, 7), 3 (9, 9), 4 (20, 22, 21), 4.1 (20, 19, 18)"
+        );
+    }
+
+    #[test]
+    fn poisson_caller_drops_the_depths_of_sequencing_errors() {
+        let lineages = HashMap::from([
+            ("2".to_string(), vec![1000, 1000, 1000]),
+            ("4".to_string(), vec![4, 5, 4]),
+            ("1".to_string(), vec![40, 50, 45]),
+        ]);
+        let hierarchy = LineageHierarchy::default();
+        let threshold = ThresholdCaller.call(&lineages, 3, &hierarchy);
+        let poisson = PoissonCaller.call(&lineages, 3, &hierarchy);
+        assert_eq!(threshold.len(), 3);
+        assert_eq!(
+            poisson,
+            vec![("2".to_string(), 1000), ("1".to_string(), 45)]
+        );
+        assert!(caller_by_name("poisson").is_some());
+        assert!(caller_by_name("other").is_none());
+    }
+
+    #[test]
+    fn poisson_tails_of_deep_samples() {
+        assert_eq!(poisson_tail(0.0, 1), 0.0);
+        assert_eq!(poisson_tail(5.0, 0), 1.0);
+        assert!((poisson_tail(2.0, 1) - (1.0 - (-2.0f64).exp())).abs() < 1e-12);
+        // (3.16 standard deviations above the mean, with exp(-mean) underflowing)
+        let tail = poisson_tail(1000.0, 1100);
+        assert!(tail > 5e-4 && tail < 1.2e-3, "{}", tail);
+        assert!(poisson_tail(5000.0, 4000) > 0.999);
+    }
+
+    #[test]
+    fn poisson_caller_calls_the_lineages_of_deep_samples() {
+        // (background depth of 1000 for a major lineage at 300,000)
+        let lineages = HashMap::from([
+            ("2".to_string(), vec![300_000, 300_000, 300_000]),
+            ("4".to_string(), vec![900, 1000, 1100]),
+            ("1".to_string(), vec![20_000, 21_000, 19_000]),
+        ]);
+        let calls = PoissonCaller.call(&lineages, 3, &LineageHierarchy::default());
+        assert_eq!(
+            calls,
+            vec![("2".to_string(), 300_000), ("1".to_string(), 20_000)]
+        );
+    }
+
+    #[test]
+    fn uneven_depths_beyond_the_sampling_variation() {
+        assert_eq!(depth_variation(&[10]), (0.0, false));
+        let (cv, uneven) = depth_variation(&[100, 100, 100, 100]);
+        assert_eq!((cv, uneven), (0.0, false));
+        // (Poisson-like depths of a shallow lineage)
+        assert!(!depth_variation(&[3, 5, 4, 6, 2]).1);
+        assert!(depth_variation(&[10, 200, 15, 300, 20]).1);
+    }
+
+    #[test]
+    fn mixture_proportions_with_bootstrap_intervals() {
+        let lineages = BTreeMap::from([
+            ("2".to_string(), vec![30, 30, 30, 30]),
+            ("4".to_string(), vec![5, 15, 10, 10]),
+        ]);
+        let mut calls: Vec<LineageCall> = [("2", 30), ("4", 10)]
+            .iter()
+            .map(|(lineage, median)| LineageCall {
+                lineage: lineage.to_string(),
+                median: *median,
+                depth_cv: 0.0,
+                uneven: false,
+                proportion: 0.0,
+                proportion_ci: (0.0, 0.0),
+            })
+            .collect();
+        mixture_proportions(&mut calls);
+        assert_eq!(calls[0].proportion, 0.75);
+        assert_eq!(calls[1].proportion, 0.25);
+        assert_eq!(calls[0].proportion_ci, (0.0, 0.0));
+        proportion_intervals(&mut calls, &lineages);
+        for call in &calls {
+            let (low, high) = call.proportion_ci;
+            assert!(low < call.proportion && call.proportion < high);
+        }
+        // (reproducible with the fixed seed)
+        let first = calls.clone();
+        proportion_intervals(&mut calls, &lineages);
+        assert_eq!(calls, first);
+    }
+
+    #[test]
+    fn lineages_and_indexes_of_the_barcode_ids() {
+        assert_eq!(barcode_lineage("4.1__12"), "4.1");
+        assert_eq!(barcode_lineage("2__1"), "2");
+        assert_eq!(barcode_index("4.1__12"), "12");
+    }
+
+    #[test]
+    fn sublineages() {
+        assert!(is_sublineage("4.1.2", "4"));
+        assert!(is_sublineage("4", "4"));
+        assert!(!is_sublineage("4.10", "4.1"));
+    }
+
+    #[test]
+    fn depth_histogram_of_the_barcodes() {
+        let counts = HashMap::from([
+            ("2__1".to_string(), 5),
+            ("2__2".to_string(), 5),
+            ("4__3".to_string(), 12),
+        ]);
+        let ids = ["2__1", "2__2", "4__3", "4__4"].map(String::from);
+        let histogram = depth_histogram(&counts, &ids);
+        assert_eq!(
+            histogram,
+            BTreeMap::from([
+                ("2".to_string(), BTreeMap::from([(5, 2)])),
+                ("4".to_string(), BTreeMap::from([(0, 1), (12, 1)])),
+            ])
+        );
+    }
+
+    #[test]
+    fn lineage_names() {
+        let names = parse_lineage_names("# lineage\tname\n2\tBeijing\n\n4.1 \t Haarlem\n").unwrap();
+        assert_eq!(lineage_name(&names, "4.1"), "Haarlem");
+        assert_eq!(lineage_name(&names, "3"), "3");
+        assert!(parse_lineage_names("2\n").is_err());
+    }
+
+    #[test]
+    fn amplicon_thresholds_and_targets_follow_the_depth_of_the_panel() {
+        // (barcodes 4__1, 4__2 and 4.1__3)
+        let counts = barcodes(&[("4", &[1000, 800]), ("4.1", &[900])]);
+        assert_eq!(target_depth(&counts), 900);
+        assert_eq!(target_depth(&HashMap::new()), 0);
+        assert_eq!(amplicon_min_count(5, 900), 18);
+        assert_eq!(amplicon_min_count(5, 100), 5);
+
+        let ids: Vec<String> = ["4__1", "4__2", "4__4", "4.1__3", "2__5"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        let names = parse_lineage_names("4.1\tHaarlem\n").unwrap();
+        assert_eq!(
+            covered_targets(&counts, &ids, 850, &names),
+            "2/5 (4: 1/3, Haarlem: 1/1)"
+        );
+        assert_eq!(covered_targets(&counts, &ids, 2000, &names), "0/5");
+    }
+
+    #[test]
+    fn abundance_of_the_target_genome() {
+        // (barcodes 2__1, 2__2 and 4__3)
+        let counts = barcodes(&[("2", &[10, 20]), ("4", &[6])]);
+        let ids: Vec<String> = ["2__1", "2__2", "2__6", "4__3", "4__4"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        let calls: Vec<LineageCall> = ["2", "4"]
+            .iter()
+            .map(|lineage| LineageCall {
+                lineage: lineage.to_string(),
+                median: 0,
+                depth_cv: 0.0,
+                uneven: false,
+                proportion: 0.0,
+                proportion_ci: (0.0, 0.0),
+            })
+            .collect();
+        // (barcodes not found counted as 0: 30 / 3 + 6 / 2)
+        assert_eq!(target_genome_depth(&calls, &counts, &ids), 13.0);
+        assert_eq!(target_genome_depth(&[], &counts, &ids), 0.0);
+
+        assert_eq!(
+            format_abundance(13.0, 1000, 26000),
+            "13.00x (50.000% of the kmers)"
+        );
+        assert_eq!(
+            format_abundance(2.0, 1000, 1000),
+            "2.00x (100.000% of the kmers)"
+        );
+        assert_eq!(
+            format_abundance(0.5, 1000, 0),
+            "0.50x (0.000% of the kmers)"
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+
+// upper bounds of the coverage bins of the report
+const COVERAGE_BINS: [u32; 4] = [10, 20, 50, 100];
+
+// expected lineages of the control samples (None for negative controls), from a tab-separated
+// file with the control sample and its expected lineage ('none' for negative controls)
+pub type Controls = HashMap<String, Option<String>>;
+
+pub fn parse_controls(content: &str) -> Result<Controls, String> {
+    let mut controls = Controls::new();
+    for (line_number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((sample, lineage)) = line.split_once('\t') else {
+            return Err(format!(
+                "line {} of the controls file should have 2 tab-separated fields",
+                line_number + 1
+            ));
+        };
+        let lineage = match lineage.trim() {
+            "none" | "" => None,
+            lineage => Some(lineage.to_string()),
+        };
+        controls.insert(sample.trim().to_string(), lineage);
+    }
+    Ok(controls)
+}
+
+// sample of a fastlin output file
+pub(crate) struct SampleRow {
+    pub(crate) sample: String,
+    data_type: String,
+    k_cov: u32,
+    pub(crate) mixture: bool,
+    lineages: String,
+    log_errors: String,
+}
+
+impl SampleRow {
+    pub(crate) fn failed(&self) -> bool {
+        // files that couldn't be read, or too few reads (coverage reset to 0)
+        self.k_cov == 0 && self.lineages.is_empty() && !self.log_errors.is_empty()
+    }
+
+    pub(crate) fn called_lineages(&self) -> Vec<&str> {
+        // lineages without their median depth
+        self.lineages
+            .split(", ")
+            .filter(|call| !call.is_empty())
+            .map(|call| call.split(" (").next().unwrap_or(call))
+            .collect()
+    }
+}
+
+pub(crate) fn parse_results(content: &str) -> Result<Vec<SampleRow>, String> {
+    // columns located from the header (runs and kmer sizes add columns), the first row of each
+    // sample being kept when several kmer sizes were compared
+    let mut lines = content.lines();
+    let header: Vec<&str> = match lines.next() {
+        Some(header) if header.starts_with('#') => {
+            header.trim_start_matches('#').split('\t').collect()
+        }
+        _ => return Err("the results file should start with a fastlin header".to_string()),
+    };
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| *column == name)
+            .ok_or(format!("the results file has no '{}' column", name))
+    };
+    let run_column = header.iter().position(|column| *column == "run");
+    let columns = [
+        column("sample")?,
+        column("data_type")?,
+        column("k_cov")?,
+        column("mixture")?,
+        column("lineages")?,
+        column("log_errors")?,
+    ];
+
+    let mut rows = Vec::new();
+    let mut seen = HashSet::new();
+    for (line_number, line) in lines.enumerate() {
+        // (summary lines)
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [sample, data_type, k_cov, mixture, lineages, log_errors] =
+            columns.map(|n| fields.get(n).copied());
+        let (Some(sample), Some(data_type), Some(k_cov), Some(mixture), Some(lineages)) =
+            (sample, data_type, k_cov, mixture, lineages)
+        else {
+            return Err(format!(
+                "line {} of the results file has missing fields",
+                line_number + 2
+            ));
+        };
+        let sample = match run_column.and_then(|n| fields.get(n)) {
+            Some(run) => format!("{}/{}", run, sample),
+            None => sample.to_string(),
+        };
+        if !seen.insert(sample.clone()) {
+            continue;
+        }
+        rows.push(SampleRow {
+            sample,
+            data_type: data_type.to_string(),
+            k_cov: k_cov.parse().map_err(|_| {
+                format!(
+                    "line {} of the results file has an invalid k_cov",
+                    line_number + 2
+                )
+            })?,
+            mixture: mixture == "yes",
+            lineages: lineages.to_string(),
+            log_errors: log_errors.unwrap_or_default().to_string(),
+        });
+    }
+    Ok(rows)
+}
+
+// QC report (markdown) of a fastlin output file: coverage distribution of the read samples,
+// failed samples, status of the controls and samples flagged for contamination
+pub fn qc_report(content: &str, controls: &Controls) -> Result<String, String> {
+    let rows = parse_results(content)?;
+    let mut report = String::from("# fastlin QC report\n\n");
+    let nb_failed = rows.iter().filter(|row| row.failed()).count();
+    report.push_str(&format!(
+        "{} samples, {} failed, {} mixtures\n\n",
+        rows.len(),
+        nb_failed,
+        rows.iter().filter(|row| row.mixture).count()
+    ));
+
+    // coverage distribution
+    report.push_str("## Coverage\n\n");
+    let mut coverages: Vec<u32> = rows
+        .iter()
+        .filter(|row| !row.failed() && row.data_type != "assembly")
+        .map(|row| row.k_cov)
+        .collect();
+    coverages.sort();
+    if coverages.is_empty() {
+        report.push_str("No read samples.\n\n");
+    } else {
+        let quantile = |q: f64| coverages[(q * (coverages.len() - 1) as f64).round() as usize];
+        report.push_str(&format!(
+            "kmer coverage of the {} read samples: min {}, quartiles {} / {} / {}, max {}\n\n",
+            coverages.len(),
+            coverages[0],
+            quantile(0.25),
+            quantile(0.5),
+            quantile(0.75),
+            coverages[coverages.len() - 1]
+        ));
+        report.push_str("| k_cov | samples |\n|---|---|\n");
+        let mut lower = 0;
+        for upper in COVERAGE_BINS {
+            let count = coverages
+                .iter()
+                .filter(|cov| (lower..upper).contains(*cov))
+                .count();
+            report.push_str(&format!("| {}-{} | {} |\n", lower, upper - 1, count));
+            lower = upper;
+        }
+        let count = coverages.iter().filter(|cov| **cov >= lower).count();
+        report.push_str(&format!("| {}+ | {} |\n\n", lower, count));
+    }
+
+    // failures
+    report.push_str("## Failures\n\n");
+    if nb_failed == 0 {
+        report.push_str("None.\n\n");
+    } else {
+        for row in rows.iter().filter(|row| row.failed()) {
+            report.push_str(&format!("- {}: {}\n", row.sample, row.log_errors));
+        }
+        report.push('\n');
+    }
+
+    // controls
+    if !controls.is_empty() {
+        report.push_str(
+            "## Controls\n\n| sample | expected | called | status |\n|---|---|---|---|\n",
+        );
+        let mut names: Vec<&String> = controls.keys().collect();
+        names.sort();
+        for name in names {
+            let expected = &controls[name];
+            let row = rows.iter().find(|row| {
+                row.sample == *name || row.sample.rsplit('/').next() == Some(name.as_str())
+            });
+            let (called, status) = match row {
+                None => (String::new(), "MISSING"),
+                // (negative controls often have too few reads)
+                Some(row) if row.failed() => match expected {
+                    None => (String::new(), "PASS"),
+                    Some(_) => (String::new(), "FAIL"),
+                },
+                Some(row) => {
+                    let called = row.called_lineages();
+                    let pass = match expected {
+                        None => called.is_empty(),
+                        Some(lineage) => called == [lineage.as_str()],
+                    };
+                    (row.lineages.clone(), if pass { "PASS" } else { "FAIL" })
+                }
+            };
+            report.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                name,
+                expected.as_deref().unwrap_or("none"),
+                called,
+                status
+            ));
+        }
+        report.push('\n');
+    }
+
+    // contamination flags (mixtures and warnings of the typed samples)
+    report.push_str("## Contamination flags\n\n");
+    let flagged: Vec<&SampleRow> = rows
+        .iter()
+        .filter(|row| !row.failed() && (row.mixture || !row.log_errors.is_empty()))
+        .collect();
+    if flagged.is_empty() {
+        report.push_str("None.\n");
+    } else {
+        for row in flagged {
+            let mut flags = Vec::new();
+            if row.mixture {
+                flags.push(format!("mixture ({})", row.lineages));
+            }
+            if !row.log_errors.is_empty() {
+                flags.push(row.log_errors.clone());
+            }
+            report.push_str(&format!("- {}: {}\n", row.sample, flags.join("; ")));
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::analyse_sample::{ReadHits, ReadVisitor};
+use crate::process_barcodes::{barcode_index, barcode_lineage, lineage_name, LineageNames};
+
+// tab-separated table with one line per barcode hit: sample, file, read, lineage, barcode
+// index and position of the kmer in the read
+pub struct ReadTable {
+    writer: BufWriter<File>,
+    sample: String,
+    file_name: String,
+    names: LineageNames,
+    error: Option<io::Error>,
+}
+
+impl ReadTable {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"#sample\tfile\tread\tlineage\tbarcode\tposition\n")?;
+        Ok(ReadTable {
+            writer,
+            sample: String::new(),
+            file_name: String::new(),
+            names: LineageNames::new(),
+            error: None,
+        })
+    }
+
+    pub fn set_sample(&mut self, sample: &str) {
+        self.sample = sample.to_string();
+    }
+
+    pub fn set_lineage_names(&mut self, names: LineageNames) {
+        self.names = names;
+    }
+
+    // flush the table, returning the first error encountered
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.flush()
+    }
+
+    fn write_hits(&mut self, read: &ReadHits) -> io::Result<()> {
+        for hit in read.hits {
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                self.sample,
+                self.file_name,
+                read.read_id,
+                lineage_name(&self.names, barcode_lineage(hit.barcode_id)),
+                barcode_index(hit.barcode_id),
+                hit.position
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl ReadVisitor for ReadTable {
+    fn visit(&mut self, read: &ReadHits) {
+        if self.error.is_none() {
+            if let Err(err) = self.write_hits(read) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    fn start_file(&mut self, file_name: &str) {
+        self.file_name = file_name.to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyse_sample::BarcodeHit;
+
+    #[test]
+    fn one_line_per_barcode_hit() {
+        let path =
+            std::env::temp_dir().join(format!("fastlin_read_table_{}.tsv", std::process::id()));
+        let mut table = ReadTable::new(&path).unwrap();
+        let hits = [
+            BarcodeHit {
+                barcode_id: "2__7",
+                position: 12,
+            },
+            BarcodeHit {
+                barcode_id: "4.1__30",
+                position: 80,
+            },
+        ];
+        table.set_sample("s1");
+        table.start_file("s1_1.fastq.gz");
+        table.visit(&ReadHits {
+            read_id: "r1",
+            seq: b"ACGT",
+            qual: None,
+            hits: &hits,
+        });
+        table.start_file("s1_2.fastq.gz");
+        table.visit(&ReadHits {
+            read_id: "r2",
+            seq: b"ACGT",
+            qual: None,
+            hits: &hits[1..],
+        });
+        table.finish().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            content,
+            "#sample\tfile\tread\tlineage\tbarcode\tposition\n\
+             s1\ts1_1.fastq.gz\tr1\t2\t7\t12\n\
+             s1\ts1_1.fastq.gz\tr1\t4.1\t30\t80\n\
+             s1\ts1_2.fastq.gz\tr2\t4.1\t30\t80\n"
+        );
+    }
+}
@@ -0,0 +1,518 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::analyse_sample::Analysis;
+use crate::error_rate::MAX_ERROR_RATE;
+use crate::hierarchy::LineageHierarchy;
+use crate::process_barcodes::{
+    format_data, format_depth_cv, format_lineages, lineage_name, mixture_proportions,
+    process_barcodes, proportion_intervals, trace_lineages_note, uneven_depth_warning, Caller,
+    LineageCall, LineageNames, ThresholdCaller,
+};
+
+// ratio of the kmer coverages of the two files of a paired sample above which the mates are
+// flagged as imbalanced (e.g. a mate file truncated or of poor quality)
+pub const MAX_MATE_IMBALANCE: f64 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputType {
+    Assembly,
+    Single,
+    Paired,
+}
+
+impl fmt::Display for InputType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InputType::Assembly => write!(f, "assembly"),
+            InputType::Single => write!(f, "single"),
+            InputType::Paired => write!(f, "paired"),
+        }
+    }
+}
+
+// typed version of a row of the output file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SampleResult {
+    pub sample: String,
+    pub data_type: InputType,
+    pub k_cov: u32,
+    pub mean_read_length: f64,
+    pub median_read_length: usize,
+    pub mixture: bool,
+    pub lineages: Vec<LineageCall>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace_lineages: Vec<LineageCall>,
+    pub log_barcodes: BTreeMap<String, Vec<i32>>,
+    pub log_errors: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_rate: Option<f64>,
+    // kmer coverage contributed by each input file (file name without directory)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub file_coverage: Vec<(String, u32)>,
+}
+
+impl SampleResult {
+    pub fn new(
+        sample: String,
+        data_type: InputType,
+        analysis: Analysis,
+        min_count: i32,
+        min_barcodes: usize,
+    ) -> Self {
+        let hierarchy = LineageHierarchy::default();
+        SampleResult::with_hierarchy(
+            sample,
+            data_type,
+            analysis,
+            min_count,
+            min_barcodes,
+            &hierarchy,
+        )
+    }
+
+    // same as new, with sublineages defined by a lineage hierarchy
+    pub fn with_hierarchy(
+        sample: String,
+        data_type: InputType,
+        analysis: Analysis,
+        min_count: i32,
+        min_barcodes: usize,
+        hierarchy: &LineageHierarchy,
+    ) -> Self {
+        SampleResult::with_caller(
+            sample,
+            data_type,
+            analysis,
+            min_count,
+            min_barcodes,
+            hierarchy,
+            &ThresholdCaller,
+        )
+    }
+
+    // same as with_hierarchy, with the lineages called by another strategy
+    pub fn with_caller(
+        sample: String,
+        data_type: InputType,
+        analysis: Analysis,
+        min_count: i32,
+        min_barcodes: usize,
+        hierarchy: &LineageHierarchy,
+        caller: &dyn Caller,
+    ) -> Self {
+        let (mean_read_length, median_read_length) = analysis.read_length_stats();
+        let (lineages, log_barcodes) = process_barcodes(
+            analysis.barcode_counts(),
+            min_count,
+            min_barcodes,
+            hierarchy,
+            caller,
+        );
+        // errors and warnings about the input files
+        let paired_warning = paired_reads_warning(&data_type, &analysis);
+        let imbalance_warning = mate_imbalance_warning(&data_type, &analysis);
+        let error_rate_warning = match analysis.error_rate {
+            Some(rate) if rate > MAX_ERROR_RATE => {
+                format!("high sequencing error rate ({:.2}%)", 100.0 * rate)
+            }
+            _ => String::new(),
+        };
+        // (lineages whose barcode depths are uneven, e.g. contamination or amplification bias)
+        let uneven_warnings = lineages
+            .iter()
+            .filter(|call| call.uneven)
+            .map(uneven_depth_warning);
+        let file_coverage = analysis
+            .files
+            .iter()
+            .map(|file| {
+                let name = Path::new(&file.file_name)
+                    .file_name()
+                    .map_or(file.file_name.clone(), |name| {
+                        name.to_string_lossy().to_string()
+                    });
+                (name, file.coverage)
+            })
+            .collect();
+        let log_errors = [
+            analysis.error,
+            paired_warning,
+            imbalance_warning,
+            error_rate_warning,
+        ]
+        .into_iter()
+        .chain(analysis.fastq_checks.warnings())
+        .chain(uneven_warnings)
+        .filter(|message| !message.is_empty())
+        .collect::<Vec<String>>()
+        .join("; ");
+
+        SampleResult {
+            sample,
+            data_type,
+            k_cov: analysis.coverage,
+            mean_read_length,
+            median_read_length,
+            mixture: lineages.len() > 1,
+            lineages,
+            trace_lineages: Vec::new(),
+            log_barcodes,
+            log_errors,
+            error_rate: analysis.error_rate,
+            file_coverage,
+        }
+    }
+
+    // report the lineages whose median depth is below a fraction of the one of the major lineage
+    // as traces rather than as a mixture (proportions of the other lineages recomputed)
+    pub fn apply_min_fraction(&mut self, min_fraction: f64) {
+        let Some(major) = self.lineages.iter().map(|call| call.median).max() else {
+            return;
+        };
+        let (lineages, traces): (Vec<LineageCall>, Vec<LineageCall>) =
+            std::mem::take(&mut self.lineages)
+                .into_iter()
+                .partition(|call| call.median as f64 >= min_fraction * major as f64);
+        self.lineages = lineages;
+        if traces.is_empty() {
+            return;
+        }
+        mixture_proportions(&mut self.lineages);
+        self.mixture = self.lineages.len() > 1;
+        self.trace_lineages = traces;
+        if !self.log_errors.is_empty() {
+            self.log_errors.push_str("; ");
+        }
+        self.log_errors
+            .push_str(&trace_lineages_note(&self.trace_lineages));
+    }
+
+    // bootstrap confidence intervals of the proportions, only computed for the JSON results
+    // (server, module and run record) as they resample the barcode depths of each lineage
+    // BOOTSTRAP_REPLICATES times; those of the trace lineages are relative to all the lineages,
+    // like their proportions
+    pub fn compute_proportion_intervals(&mut self) {
+        if !self.trace_lineages.is_empty() {
+            let mut calls: Vec<LineageCall> = self
+                .lineages
+                .iter()
+                .chain(&self.trace_lineages)
+                .cloned()
+                .collect();
+            proportion_intervals(&mut calls, &self.log_barcodes);
+            for (trace, call) in self
+                .trace_lineages
+                .iter_mut()
+                .zip(&calls[self.lineages.len()..])
+            {
+                trace.proportion_ci = call.proportion_ci;
+            }
+        }
+        proportion_intervals(&mut self.lineages, &self.log_barcodes);
+    }
+
+    // no lineage reported (e.g. target genome below the detection limit), the barcode counts
+    // being kept in the log
+    pub fn no_call(&mut self, reason: &str) {
+        self.lineages.clear();
+        self.trace_lineages.clear();
+        self.mixture = false;
+        self.add_warning(&format!("NO_CALL ({})", reason));
+    }
+
+    pub fn add_warning(&mut self, warning: &str) {
+        if !self.log_errors.is_empty() {
+            self.log_errors.push_str("; ");
+        }
+        self.log_errors.push_str(warning);
+    }
+
+    // report the lineages under their preferred names (after calling them with the scheme names)
+    pub fn rename_lineages(&mut self, names: &LineageNames) {
+        for call in self.lineages.iter_mut() {
+            let warning = uneven_depth_warning(call);
+            call.lineage = lineage_name(names, &call.lineage).to_string();
+            if call.uneven {
+                self.log_errors = self
+                    .log_errors
+                    .replace(&warning, &uneven_depth_warning(call));
+            }
+        }
+        let note = trace_lineages_note(&self.trace_lineages);
+        for call in self.trace_lineages.iter_mut() {
+            call.lineage = lineage_name(names, &call.lineage).to_string();
+        }
+        if !note.is_empty() {
+            self.log_errors = self
+                .log_errors
+                .replace(&note, &trace_lineages_note(&self.trace_lineages));
+        }
+        self.log_barcodes = std::mem::take(&mut self.log_barcodes)
+            .into_iter()
+            .map(|(lineage, counts)| (lineage_name(names, &lineage).to_string(), counts))
+            .collect();
+    }
+
+    // coefficient of variation of the barcode depths of the called lineages
+    pub fn depth_cv_column(&self) -> String {
+        format_depth_cv(&self.lineages)
+    }
+
+    // error rate column ('NA' if it couldn't be estimated)
+    pub fn error_rate_column(&self) -> String {
+        match self.error_rate {
+            Some(rate) => format!("{:.3}%", 100.0 * rate),
+            None => "NA".to_string(),
+        }
+    }
+
+    // kmer coverage of each input file: file (coverage), file2 (coverage), or NA if the files
+    // couldn't be read
+    pub fn file_coverage_column(&self) -> String {
+        if self.file_coverage.is_empty() {
+            return "NA".to_string();
+        }
+        self.file_coverage
+            .iter()
+            .map(|(file, coverage)| format!("{} ({})", file, coverage))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    // row of the output file of the first versions of fastlin (--legacy-output): without the
+    // read length columns, with their kmer coverage, their mixture status (several lineages
+    // called) and only the errors of the files (no warnings) in the last column
+    pub fn to_legacy_row(&self, k_cov: u32, error: &str) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.sample,
+            self.data_type,
+            k_cov,
+            if self.lineages.len() > 1 { "yes" } else { "no" },
+            format_lineages(&self.lineages),
+            format_data(&self.log_barcodes),
+            error
+        )
+    }
+
+    pub fn to_row(&self) -> String {
+        // tab-separated columns of the output file (without trailing newline)
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.1}\t{}",
+            self.sample,
+            self.data_type,
+            self.k_cov,
+            if self.mixture { "yes" } else { "no" },
+            format_lineages(&self.lineages),
+            format_data(&self.log_barcodes),
+            self.log_errors,
+            self.mean_read_length,
+            self.median_read_length
+        )
+    }
+}
+
+// tally of the samples of a run (summary lines at the end of the output file)
+#[derive(Debug, Default, Serialize)]
+pub struct RunSummary {
+    pub nb_samples: usize,
+    pub nb_failed: usize,
+    pub nb_mixtures: usize,
+    pub lineages: BTreeMap<String, usize>,
+}
+
+impl RunSummary {
+    // add a sample (failed if its files couldn't be read or it had too few reads)
+    pub fn add(&mut self, result: &SampleResult, failed: bool) {
+        self.nb_samples += 1;
+        if failed {
+            self.nb_failed += 1;
+        }
+        if result.mixture {
+            self.nb_mixtures += 1;
+        }
+        for call in &result.lineages {
+            *self.lineages.entry(call.lineage.clone()).or_default() += 1;
+        }
+    }
+
+    pub fn lines(&self, wall_time: Duration) -> Vec<String> {
+        // lineages sorted by number of samples: lineage (nb samples), lineage2 (nb samples)
+        let mut tally: Vec<(&String, &usize)> = self.lineages.iter().collect();
+        tally.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let tally = tally
+            .into_iter()
+            .map(|(lineage, count)| format!("{} ({})", lineage, count))
+            .collect::<Vec<String>>()
+            .join(", ");
+        vec![
+            format!("samples: {}", self.nb_samples),
+            format!("failures: {}", self.nb_failed),
+            format!("mixtures: {}", self.nb_mixtures),
+            format!("lineages: {}", tally),
+            format!("wall time: {:.1} s", wall_time.as_secs_f64()),
+        ]
+    }
+}
+
+// rows of a previous output file to keep when appending to it: its header should be the same
+// as the new one, and its samples (run and sample name in batch mode) should not be typed again
+// unless they are replaced (summary lines are dropped, a new summary being written)
+pub fn previous_rows(
+    content: &str,
+    header: &str,
+    samples: &HashSet<String>,
+    overwrite: bool,
+) -> Result<String, String> {
+    let mut lines = content.lines();
+    if lines.next() != Some(header.trim_end()) {
+        return Err("the columns of the output file differ from the ones of this run".to_string());
+    }
+    let columns: Vec<&str> = header.trim_start_matches('#').split('\t').collect();
+    let sample_column = columns.iter().position(|column| *column == "sample");
+    let run_column = columns.iter().position(|column| *column == "run");
+
+    let mut rows = String::new();
+    for line in lines.filter(|line| !line.starts_with('#') && !line.is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let sample = sample_column.and_then(|n| fields.get(n)).unwrap_or(&"");
+        let sample = match run_column.and_then(|n| fields.get(n)) {
+            Some(run) => format!("{}\t{}", run, sample),
+            None => sample.to_string(),
+        };
+        if samples.contains(&sample) {
+            if overwrite {
+                continue;
+            }
+            return Err(format!(
+                "the sample {} is already in the output file",
+                sample.replace('\t', "/")
+            ));
+        }
+        rows.push_str(line);
+        rows.push('\n');
+    }
+    Ok(rows)
+}
+
+fn paired_reads_warning(data_type: &InputType, analysis: &Analysis) -> String {
+    // paired-end files should have the same number of reads (unless they were only partly
+    // scanned because of the kmer limit)
+    match (data_type, &analysis.files[..]) {
+        (InputType::Paired, [file_1, file_2])
+            if file_1.complete && file_2.complete && file_1.nb_reads != file_2.nb_reads =>
+        {
+            format!(
+                "paired files with different numbers of reads ({} and {}), possibly truncated",
+                file_1.nb_reads, file_2.nb_reads
+            )
+        }
+        _ => String::new(),
+    }
+}
+
+fn mate_imbalance_warning(data_type: &InputType, analysis: &Analysis) -> String {
+    // the two files of a paired sample should contribute similar kmer coverages (compared when
+    // both files were entirely scanned, files with different numbers of reads being reported
+    // as truncated)
+    match (data_type, &analysis.files[..]) {
+        (InputType::Paired, [file_1, file_2])
+            if file_1.complete && file_2.complete && file_1.nb_reads == file_2.nb_reads =>
+        {
+            let (low, high) = match file_1.nb_kmers <= file_2.nb_kmers {
+                true => (file_1, file_2),
+                false => (file_2, file_1),
+            };
+            if high.coverage > 0 && high.nb_kmers as f64 > MAX_MATE_IMBALANCE * low.nb_kmers as f64
+            {
+                format!(
+                    "imbalanced paired files (kmer coverage {} and {})",
+                    file_1.coverage, file_2.coverage
+                )
+            } else {
+                String::new()
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    // sample with 5 barcodes of each lineage found at the given depth
+    fn result(depths: &[(&str, u32)], error: &str) -> SampleResult {
+        let (ids, counts): (Vec<String>, Vec<u32>) = depths
+            .iter()
+            .flat_map(|(lineage, depth)| {
+                (0..5).map(move |n| (format!("{}__{}", lineage, n), *depth))
+            })
+            .unzip();
+        let analysis = Analysis {
+            counts,
+            barcode_ids: Arc::new(ids),
+            coverage: 40,
+            read_lengths: BTreeMap::from([(100, 3), (150, 1)]),
+            error: error.to_string(),
+            ..Default::default()
+        };
+        SampleResult::new("s1".to_string(), InputType::Single, analysis, 4, 3)
+    }
+
+    #[test]
+    fn legacy_rows_have_the_original_columns() {
+        let pure = result(&[("2", 30)], "");
+        assert_eq!(
+            pure.to_legacy_row(25, ""),
+            "s1\tsingle\t25\tno\t2 (30)\t2 (30, 30, 30, 30, 30)\t"
+        );
+        assert_eq!(
+            pure.to_row(),
+            "s1\tsingle\t40\tno\t2 (30)\t2 (30, 30, 30, 30, 30)\t\t112.5\t100"
+        );
+    }
+
+    #[test]
+    fn legacy_mixtures_are_several_lineages_called() {
+        let mixed = result(&[("2", 30), ("4", 10)], "");
+        assert!(mixed
+            .to_legacy_row(25, "")
+            .contains("\tyes\t2 (30), 4 (10)\t"));
+        let mut traces = mixed.clone();
+        traces.apply_min_fraction(0.5);
+        assert!(traces.to_legacy_row(25, "").contains("\tno\t2 (30)\t"));
+    }
+
+    #[test]
+    fn legacy_rows_only_report_the_errors_given() {
+        let failed = result(&[], "\"Error in file: truncated input (unexpected end)\"");
+        assert!(failed
+            .to_legacy_row(0, "\"Error in file: unexpected end\"")
+            .ends_with("\t0\tno\t\t\t\"Error in file: unexpected end\""));
+    }
+
+    #[test]
+    fn proportion_intervals_are_only_computed_on_demand() {
+        let mut mixed = result(&[("2", 30), ("4", 10), ("3", 5)], "");
+        mixed.apply_min_fraction(0.2);
+        assert_eq!(mixed.lineages[0].proportion, 0.75);
+        assert_eq!(mixed.trace_lineages[0].proportion, 5.0 / 45.0);
+        assert!(mixed
+            .lineages
+            .iter()
+            .all(|call| call.proportion_ci == (0.0, 0.0)));
+
+        mixed.compute_proportion_intervals();
+        for call in mixed.lineages.iter().chain(&mixed.trace_lineages) {
+            let (low, high) = call.proportion_ci;
+            assert!(low <= call.proportion && call.proportion <= high && high > 0.0);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::process_barcodes::LineageCall;
+use crate::results::RunSummary;
+
+// log file of the run (--log), with the warnings, skipped input files and errors of the
+// samples (nothing is logged until it is opened)
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+// warnings of the run (for the JSON run record)
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// structured record of a run (--run-json), for LIMS and workflow systems
+#[derive(Debug, Serialize)]
+pub struct RunRecord {
+    pub version: String,
+    pub command: Vec<String>,
+    pub parameters: serde_json::Value,
+    pub samples: Vec<SampleRecord>,
+    pub summary: RunSummary,
+    pub wall_time: f64,
+    pub warnings: Vec<String>,
+}
+
+// status ('ok', 'warning' or 'failed') and timing of a sample, with its calls (and the MD5
+// checksums of its files with --checksums)
+#[derive(Debug, Serialize)]
+pub struct SampleRecord {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+    pub sample: String,
+    pub status: String,
+    pub seconds: f64,
+    pub k_cov: u32,
+    pub mixture: bool,
+    pub lineages: Vec<LineageCall>,
+    pub log_errors: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+}
+
+pub fn open_log(path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+    *LOG_FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+// timestamped line of the log file: time, level (INFO, WARNING or ERROR) and message
+pub fn log(level: &str, message: &str) {
+    if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+        // (a log that can't be written doesn't stop the run)
+        let _ = writeln!(file, "{}\t{}\t{}", timestamp(), level, message);
+    }
+}
+
+// warning printed to stderr and logged
+pub fn warning(message: &str) {
+    eprintln!("   Warning: {}", message);
+    log("WARNING", message);
+    WARNINGS.lock().unwrap().push(message.to_string());
+}
+
+pub fn warnings() -> Vec<String> {
+    WARNINGS.lock().unwrap().clone()
+}
+
+// status of a sample (failed if its files couldn't be read or it had too few reads)
+pub fn sample_status(failed: bool, log_errors: &str) -> &'static str {
+    match (failed, log_errors.is_empty()) {
+        (true, _) => "failed",
+        (false, false) => "warning",
+        (false, true) => "ok",
+    }
+}
+
+pub fn timestamp() -> String {
+    // UTC time in ISO 8601 format (days converted to a date as in Hinnant's civil_from_days)
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+// metadata of the samples (e.g. patient ID, site, collection date), from a tab-separated
+// file whose header names the columns and whose first column is the sample name
+#[derive(Clone, Debug, Default)]
+pub struct SampleSheet {
+    pub columns: Vec<String>,
+    rows: HashMap<String, Vec<String>>,
+}
+
+impl SampleSheet {
+    pub fn from_string(content: &str) -> Result<Self, String> {
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        // header (can start with '#'), the first column being the sample name
+        let Some((_, header)) = lines.next() else {
+            return Err("the sample sheet is empty".to_string());
+        };
+        let columns: Vec<String> = header
+            .trim_start_matches('#')
+            .split('\t')
+            .skip(1)
+            .map(|column| column.trim().to_string())
+            .collect();
+
+        let mut rows: HashMap<String, Vec<String>> = HashMap::new();
+        for (line_number, line) in lines {
+            let mut fields = line.split('\t').map(|field| field.trim().to_string());
+            let sample = fields.next().unwrap_or_default();
+            let mut values: Vec<String> = fields.collect();
+            if values.len() > columns.len() {
+                return Err(format!(
+                    "line {} of the sample sheet has more fields than its header",
+                    line_number + 1
+                ));
+            }
+            values.resize(columns.len(), String::new());
+            if rows.insert(sample.to_string(), values).is_some() {
+                return Err(format!(
+                    "line {} of the sample sheet repeats the sample {}",
+                    line_number + 1,
+                    sample
+                ));
+            }
+        }
+        Ok(SampleSheet { columns, rows })
+    }
+
+    pub fn nb_samples(&self) -> usize {
+        self.rows.len()
+    }
+
+    // tab-separated metadata of a sample, starting with a tab (empty fields if not listed)
+    pub fn metadata(&self, sample: &str) -> String {
+        match self.rows.get(sample) {
+            Some(values) => values.iter().map(|value| format!("\t{}", value)).collect(),
+            None => "\t".repeat(self.columns.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_columns_of_the_samples() {
+        let sheet =
+            SampleSheet::from_string("#sample\tpatient\tsite\ns1\tP1\tLyon\n\ns2 \t P2\n").unwrap();
+        assert_eq!(sheet.columns, ["patient", "site"]);
+        assert_eq!(sheet.nb_samples(), 2);
+        assert_eq!(sheet.metadata("s1"), "\tP1\tLyon");
+        // (missing fields and samples left empty)
+        assert_eq!(sheet.metadata("s2"), "\tP2\t");
+        assert_eq!(sheet.metadata("s3"), "\t\t");
+    }
+
+    #[test]
+    fn malformed_sample_sheets_are_rejected() {
+        assert!(SampleSheet::from_string("\n").is_err());
+        let err = SampleSheet::from_string("sample\tpatient\ns1\tP1\textra\n").unwrap_err();
+        assert!(err.contains("line 2 "), "{}", err);
+        let err = SampleSheet::from_string("sample\tpatient\ns1\tP1\ns1\tP2\n").unwrap_err();
+        assert!(err.contains("line 3 ") && err.contains("s1"), "{}", err);
+    }
+}
@@ -0,0 +1,306 @@
+use serde_json::{json, Value};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::ServeArgs;
+use fastlin::analyse_sample::{
+    get_reader_from_bytes, scan_readers, scan_reads, Analysis, KmerMatching,
+};
+use fastlin::barcodes::Barcodes;
+use fastlin::hierarchy::LineageHierarchy;
+use fastlin::input_files::get_data_type;
+use fastlin::process_barcodes::{caller_by_name, LineageNames};
+use fastlin::results::{InputType, SampleResult};
+
+// barcodes loaded at startup and parameters of the typing of the samples
+struct Typing<'a> {
+    args: &'a ServeArgs,
+    scheme: &'a Barcodes,
+    matching: KmerMatching,
+    kmer_limit: Option<u64>,
+    names: &'a LineageNames,
+    hierarchy: &'a LineageHierarchy,
+    // (directory of the files typed through /files)
+    data_dir: Option<PathBuf>,
+}
+
+pub fn serve(
+    args: &ServeArgs,
+    scheme: &Barcodes,
+    names: &LineageNames,
+    hierarchy: &LineageHierarchy,
+) -> Result<(), String> {
+    // calculate maximum number of kmers to extract
+    let kmer_limit = args
+        .max_cov
+        .map(|limit| limit.saturating_mul(scheme.genome_size));
+    let matching = KmerMatching::new(
+        &scheme.index,
+        scheme.kmer_size as usize,
+        scheme.spaced_seed.clone(),
+        !args.scheme.no_prefilter,
+    );
+
+    // files typed through /files (paths relative to the data directory, or within it)
+    let data_dir = args
+        .data_dir
+        .as_ref()
+        .map(|dir| {
+            Path::new(dir)
+                .canonicalize()
+                .map_err(|err| format!("couldn't read the directory {}: {}", dir, err))
+        })
+        .transpose()?;
+    let typing = Typing {
+        args,
+        scheme,
+        matching,
+        kmer_limit,
+        names,
+        hierarchy,
+        data_dir,
+    };
+
+    let address = format!("{}:{}", args.bind, args.port);
+    let server = Server::http(&address)
+        .map_err(|err| format!("could not listen on {}: {}", address, err))?;
+    eprintln!(" . listening on {}", address);
+
+    // requests are answered one at a time using the barcodes loaded at startup
+    for mut request in server.incoming_requests() {
+        let (status, body) = match (request.method(), request.url().split('?').next()) {
+            (Method::Post, Some("/fastq")) => type_fastq(&mut request, &typing),
+            (Method::Post, Some("/files")) => type_files(&mut request, &typing),
+            _ => (404, json!({ "error": "unknown endpoint" })),
+        };
+
+        let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header);
+        if let Err(err) = request.respond(response) {
+            eprintln!("   Warning: failed to send response: {}", err);
+        }
+    }
+    Ok(())
+}
+
+impl Typing<'_> {
+    // result of a sample, as returned to the client
+    fn result(
+        &self,
+        sample: String,
+        data_type: InputType,
+        analysis: Analysis,
+        min_count: i32,
+    ) -> Value {
+        let mut result = SampleResult::with_caller(
+            sample,
+            data_type,
+            analysis,
+            min_count,
+            self.args.n_barcodes,
+            self.hierarchy,
+            caller_by_name(&self.args.caller).unwrap(),
+        );
+        result.apply_min_fraction(self.args.min_fraction);
+        result.compute_proportion_intervals();
+        result.rename_lineages(self.names);
+        json!(result)
+    }
+}
+
+fn type_fastq(request: &mut Request, typing: &Typing) -> (u16, Value) {
+    // the body is a single-end FASTQ file, either plain or gzipped
+    let sample = query_value(request.url(), "sample").unwrap_or("sample".to_string());
+
+    let data = match read_body(request, typing.args.max_body_size) {
+        Ok(data) => data,
+        Err(error) => return error,
+    };
+
+    let scheme = typing.scheme;
+    let analysis = scan_readers(
+        vec![(sample.to_string(), get_reader_from_bytes(data))],
+        &scheme.index,
+        &scheme.kmer_size,
+        typing.kmer_limit,
+        scheme.genome_size,
+        Some(&typing.matching),
+        None,
+    );
+    (
+        200,
+        typing.result(sample, InputType::Single, analysis, typing.args.min_count),
+    )
+}
+
+fn type_files(request: &mut Request, typing: &Typing) -> (u16, Value) {
+    // the body lists the paths of the files of a single sample, one per line
+    let Some(data_dir) = &typing.data_dir else {
+        return (
+            403,
+            json!({ "error": "typing files is disabled (see --data-dir)" }),
+        );
+    };
+    let sample = query_value(request.url(), "sample").unwrap_or("sample".to_string());
+
+    let body = match read_body(request, typing.args.max_body_size) {
+        Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+        Err(error) => return error,
+    };
+
+    let mut list_files = Vec::new();
+    for path in body.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        match data_file(data_dir, path) {
+            Some(file) => list_files.push(file),
+            None => {
+                let message = format!("file not found in the data directory: {}", path);
+                return (400, json!({ "error": message }));
+            }
+        }
+    }
+
+    let data_type = match get_data_type(&list_files) {
+        Ok(data_type) => data_type,
+        Err((count_fasta, count_fastq)) => {
+            let message = format!("{} fasta and {} fastq files", count_fasta, count_fastq);
+            return (400, json!({ "error": message }));
+        }
+    };
+
+    let (kmer_limit, min_count) = match &data_type {
+        InputType::Assembly => (None, 1),
+        InputType::Single | InputType::Paired => (typing.kmer_limit, typing.args.min_count),
+    };
+
+    let scheme = typing.scheme;
+    let analysis = scan_reads(
+        list_files,
+        &scheme.index,
+        &scheme.kmer_size,
+        kmer_limit,
+        scheme.genome_size,
+        Some(&typing.matching),
+        None,
+    );
+    (200, typing.result(sample, data_type, analysis, min_count))
+}
+
+// file of the data directory (None if it doesn't exist or is outside of the directory, e.g.
+// through '..' or a symbolic link)
+fn data_file(data_dir: &Path, path: &str) -> Option<PathBuf> {
+    let file = data_dir.join(path).canonicalize().ok()?;
+    (file.starts_with(data_dir) && file.is_file()).then_some(file)
+}
+
+// body of a request, refused with a 413 above the maximum size (in MB), whether announced by
+// Content-Length or not
+fn read_body(request: &mut Request, max_size: u64) -> Result<Vec<u8>, (u16, Value)> {
+    let limit = max_size.saturating_mul(1 << 20);
+    let too_large = || {
+        let message = format!(
+            "request body larger than {} MB (see --max-body-size)",
+            max_size
+        );
+        (413, json!({ "error": message }))
+    };
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > limit)
+    {
+        return Err(too_large());
+    }
+
+    let mut data = Vec::new();
+    if let Err(err) = request
+        .as_reader()
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut data)
+    {
+        return Err((
+            400,
+            json!({ "error": format!("failed to read body: {}", err) }),
+        ));
+    }
+    if data.len() as u64 > limit {
+        return Err(too_large());
+    }
+    Ok(data)
+}
+
+fn query_value(url: &str, key: &str) -> Option<String> {
+    // extract the value of a key from the query string
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| percent_decode(k) == key)
+        .map(|(_, v)| percent_decode(v))
+}
+
+fn percent_decode(value: &str) -> String {
+    // '+' for spaces and '%XX' escapes (kept as is if not followed by two hexadecimal digits)
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let byte = value
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                if let Some(byte) = byte {
+                    decoded.push(byte);
+                    i += 2;
+                } else {
+                    decoded.push(b'%');
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_values_are_percent_decoded() {
+        let url = "/fastq?path=dir%2Ffile&sample=my%20sample+1";
+        assert_eq!(query_value(url, "sample").as_deref(), Some("my sample 1"));
+        assert_eq!(query_value(url, "path").as_deref(), Some("dir/file"));
+        assert_eq!(query_value(url, "other"), None);
+        assert_eq!(query_value("/fastq", "sample"), None);
+    }
+
+    #[test]
+    fn malformed_escapes_are_kept() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("a%zzb"), "a%zzb");
+        assert_eq!(percent_decode("%C3%A9t%C3%A9"), "été");
+    }
+
+    #[test]
+    fn files_outside_of_the_data_directory_are_refused() {
+        let data_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .canonicalize()
+            .unwrap();
+        assert_eq!(
+            data_file(&data_dir, "reads/single.fastq.gz"),
+            Some(data_dir.join("reads/single.fastq.gz"))
+        );
+        assert_eq!(data_file(&data_dir, "reads/missing.fastq.gz"), None);
+        // (directories, parent directories and absolute paths elsewhere)
+        assert_eq!(data_file(&data_dir, "reads"), None);
+        assert_eq!(data_file(&data_dir, "../../Cargo.toml"), None);
+        let cargo_toml = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        assert_eq!(data_file(&data_dir, &cargo_toml.to_string_lossy()), None);
+    }
+}
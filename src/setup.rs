@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+use std::io::{BufRead, Read};
+use std::path::{Path, PathBuf};
+
+use crate::SchemeArgs;
+use fastlin::analyse_sample::get_reader;
+use fastlin::barcode_source::load_barcodes;
+use fastlin::barcodes::{Barcodes, SpacedSeed, MIN_KMER_SIZE};
+use fastlin::check_scheme::{parse_scheme_report, repeated_barcodes};
+use fastlin::hierarchy::LineageHierarchy;
+use fastlin::human_fraction::HumanPanel;
+use fastlin::process_barcodes::{is_sublineage, parse_lineage_names, LineageNames};
+use fastlin::sample_sheet::SampleSheet;
+
+pub fn check_kmer_size(kmer_size: Option<u8>) -> Result<(), String> {
+    // (the barcode file can declare the kmer size when it isn't given, and the maximum kmer
+    // size is checked against the barcode file)
+    match kmer_size.is_some_and(|kmer_size| kmer_size < MIN_KMER_SIZE) {
+        true => Err(format!(
+            "the kmer size should be at least {}.",
+            MIN_KMER_SIZE
+        )),
+        false => Ok(()),
+    }
+}
+
+pub fn load_scheme(
+    barcodes_file: &str,
+    kmer_size: Option<u8>,
+    args: &SchemeArgs,
+) -> Result<Barcodes, String> {
+    let mut scheme = load_barcodes(Path::new(barcodes_file), kmer_size, args.genome_size)?;
+    // (before the spaced seed, the reference being compared with the full kmers)
+    if let Some(file_name) = &args.mask_repeats {
+        let repeated = load_repeated_barcodes(file_name, &scheme)?;
+        let nb_barcodes = scheme.drop_barcodes(&repeated);
+        eprintln!(
+            " . drop barcodes repeated in the reference\t({} dropped, {} kept)",
+            repeated.len(),
+            nb_barcodes
+        );
+    }
+    if let Some(pattern) = &args.spaced_seed {
+        let nb_shared =
+            SpacedSeed::parse(pattern).and_then(|seed| scheme.apply_spaced_seed(seed))?;
+        eprintln!(
+            " . apply spaced seed\t({} keys shared by several lineages)",
+            nb_shared
+        );
+    }
+    if args.drop_ambiguous_kmers {
+        let nb_kmers = scheme.drop_ambiguous();
+        eprintln!(
+            " . drop kmers shared by several lineages\t({} kmers)",
+            nb_kmers
+        );
+    }
+    if !args.include_lineages.is_empty() {
+        let nb_barcodes = scheme.retain_lineages(|lineage| {
+            args.include_lineages
+                .iter()
+                .any(|parent| is_sublineage(lineage, parent))
+        });
+        eprintln!(
+            " . keep lineages {}\t({} barcodes)",
+            args.include_lineages.join(", "),
+            nb_barcodes
+        );
+    }
+    if !args.exclude_lineages.is_empty() {
+        let nb_barcodes = scheme.retain_lineages(|lineage| {
+            !args
+                .exclude_lineages
+                .iter()
+                .any(|parent| is_sublineage(lineage, parent))
+        });
+        eprintln!(
+            " . drop lineages {}\t({} barcodes)",
+            args.exclude_lineages.join(", "),
+            nb_barcodes
+        );
+    }
+    Ok(scheme)
+}
+
+// barcodes repeated in a reference genome (FASTA), or reported as repeated by check-scheme
+fn load_repeated_barcodes(file_name: &str, scheme: &Barcodes) -> Result<HashSet<String>, String> {
+    let read_error = |err| format!("couldn't read {}: {}", file_name, err);
+    let mut content = String::new();
+    let mut reader = get_reader(&PathBuf::from(file_name));
+    reader.read_line(&mut content).map_err(read_error)?;
+    if !content.starts_with("#barcode") {
+        let reference = load_reference(file_name)?;
+        return Ok(repeated_barcodes(
+            &scheme.index,
+            scheme.kmer_size as usize,
+            &reference,
+        ));
+    }
+    reader.read_to_string(&mut content).map_err(read_error)?;
+    parse_scheme_report(&content)
+}
+
+// sequences of a reference genome (chromosomes and plasmids)
+pub fn load_reference(file_name: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut reader = seq_io::fasta::Reader::new(get_reader(&PathBuf::from(file_name)));
+    let mut reference = Vec::new();
+    while let Some(record) = reader.next() {
+        let record = record.map_err(|err| format!("couldn't read {}: {}", file_name, err))?;
+        reference.push(record.full_seq().to_ascii_uppercase());
+    }
+    if reference.is_empty() {
+        return Err(format!("couldn't read a sequence from {}", file_name));
+    }
+    Ok(reference)
+}
+
+// content of a file given as option, parsed
+fn read_file<T>(path: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<T, String> {
+    std::fs::read_to_string(path)
+        .map_err(|err| format!("couldn't read {}: {}", path, err))
+        .and_then(|content| parse(&content))
+}
+
+pub fn load_lineage_names(args: &SchemeArgs) -> Result<LineageNames, String> {
+    let Some(path) = &args.rename_lineages else {
+        return Ok(LineageNames::new());
+    };
+    let names = read_file(path, parse_lineage_names)?;
+    eprintln!(" . get lineage names\t({} lineages)", names.len());
+    Ok(names)
+}
+
+pub fn load_human_panel(path: &Option<String>) -> Result<Option<HumanPanel>, String> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let panel = read_file(path, HumanPanel::from_string)?;
+    eprintln!(" . get human kmers	({} kmers)", panel.len());
+    Ok(Some(panel))
+}
+
+pub fn load_hierarchy(args: &SchemeArgs) -> Result<LineageHierarchy, String> {
+    let mut hierarchy = match &args.lineage_hierarchy {
+        Some(path) => read_file(path, LineageHierarchy::from_string)?,
+        None => LineageHierarchy::default(),
+    };
+    if let Some(level) = args.level {
+        hierarchy.set_level(level as usize);
+    }
+    Ok(hierarchy)
+}
+
+pub fn load_sample_sheet(path: &Option<String>) -> Result<SampleSheet, String> {
+    let Some(path) = path else {
+        return Ok(SampleSheet::default());
+    };
+    let sheet = read_file(path, SampleSheet::from_string)?;
+    eprintln!(" . get sample sheet\t({} samples)", sheet.nb_samples());
+    Ok(sheet)
+}
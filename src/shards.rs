@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::qc_report::parse_results;
+use crate::results::RunSummary;
+
+// shard of the samples of a run (--shard i/N): index from 1 and number of shards
+pub fn parse_shard(value: &str) -> Result<(usize, usize), String> {
+    let shard = value.split_once('/').and_then(|(index, nb_shards)| {
+        Some((
+            index.trim().parse::<usize>().ok()?,
+            nb_shards.trim().parse::<usize>().ok()?,
+        ))
+    });
+    match shard {
+        Some((index, nb_shards)) if (1..=nb_shards).contains(&index) => Ok((index, nb_shards)),
+        _ => Err(format!(
+            "{} is not a valid shard (i/N, with i between 1 and N)",
+            value
+        )),
+    }
+}
+
+// whether a sample, given by its position in the sorted samples of the run, belongs to a
+// shard (samples dealt in turn to the shards, so that they get samples of all runs)
+pub fn in_shard(position: usize, (index, nb_shards): (usize, usize)) -> bool {
+    position % nb_shards == index - 1
+}
+
+// output file merging the output files of the shards of a run: their rows sorted by run and
+// sample, and the summary lines of all their samples (with the wall time of the longest shard).
+// The shards should have the same columns and disjoint samples
+pub fn merge_shards(shards: &[(String, String)]) -> Result<String, String> {
+    let Some((_, first)) = shards.first() else {
+        return Err("no output file to merge".to_string());
+    };
+    let header = first.lines().next().unwrap_or_default();
+    let columns: Vec<&str> = header.trim_start_matches('#').split('\t').collect();
+    let sample_column = columns.iter().position(|column| *column == "sample");
+    let run_column = columns.iter().position(|column| *column == "run");
+
+    let mut rows: Vec<(String, String, &str)> = Vec::new();
+    let mut owners: HashMap<(String, String), usize> = HashMap::new();
+    let mut wall_time: f64 = 0.0;
+    for (n, (name, content)) in shards.iter().enumerate() {
+        let mut lines = content.lines();
+        if lines.next() != Some(header) {
+            return Err(format!(
+                "the columns of {} differ from the ones of the other output files",
+                name
+            ));
+        }
+        for line in lines.filter(|line| !line.is_empty()) {
+            if let Some(seconds) = line
+                .strip_prefix("# wall time: ")
+                .and_then(|time| time.trim_end_matches(" s").parse::<f64>().ok())
+            {
+                wall_time = wall_time.max(seconds);
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let field = |column: Option<usize>| {
+                column
+                    .and_then(|n| fields.get(n))
+                    .unwrap_or(&"")
+                    .to_string()
+            };
+            let (run, sample) = (field(run_column), field(sample_column));
+            // (several rows per sample when several kmer sizes are compared)
+            if let Some(owner) = owners.insert((run.clone(), sample.clone()), n) {
+                if owner != n {
+                    return Err(format!(
+                        "the sample {} is in both {} and {}",
+                        sample, shards[owner].0, name
+                    ));
+                }
+            }
+            rows.push((run, sample, line));
+        }
+    }
+    rows.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    let mut merged = format!("{}\n", header);
+    for (_, _, line) in rows {
+        merged.push_str(line);
+        merged.push('\n');
+    }
+    let mut summary = RunSummary::default();
+    for row in parse_results(&merged)? {
+        summary.nb_samples += 1;
+        summary.nb_failed += usize::from(row.failed());
+        summary.nb_mixtures += usize::from(row.mixture);
+        for lineage in row.called_lineages() {
+            *summary.lineages.entry(lineage.to_string()).or_default() += 1;
+        }
+    }
+    for line in summary.lines(Duration::from_secs_f64(wall_time)) {
+        merged.push_str(&format!("# {}\n", line));
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_are_dealt_in_turn_to_the_shards() {
+        assert_eq!(parse_shard("2/10"), Ok((2, 10)));
+        assert_eq!(parse_shard(" 1 / 1 "), Ok((1, 1)));
+        for value in ["0/10", "11/10", "1", "a/2", "1/0"] {
+            assert!(parse_shard(value).is_err(), "{}", value);
+        }
+        let shard: Vec<usize> = (0..7).filter(|n| in_shard(*n, (2, 3))).collect();
+        assert_eq!(shard, [1, 4]);
+        // (every sample in a single shard)
+        assert!((0..7).all(|n| (1..=3).filter(|i| in_shard(n, (*i, 3))).count() == 1));
+    }
+
+    #[test]
+    fn shards_with_other_columns_or_the_same_samples_are_not_merged() {
+        assert!(merge_shards(&[]).is_err());
+        let shard = |name: &str, content: &str| (name.to_string(), content.to_string());
+        let err = merge_shards(&[
+            shard("a.txt", "#sample\tlineages\ns1\t2.2 (10)\n"),
+            shard("b.txt", "#sample\tk_cov\ns2\t10\n"),
+        ])
+        .err()
+        .unwrap();
+        assert!(err.contains("columns of b.txt"), "{}", err);
+
+        let err = merge_shards(&[
+            shard("a.txt", "#sample\tlineages\ns1\t2.2 (10)\n"),
+            shard("b.txt", "#sample\tlineages\ns1\t4.1 (10)\n"),
+        ])
+        .err()
+        .unwrap();
+        assert!(
+            err.contains("the sample s1 is in both a.txt and b.txt"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+
+use crate::process_barcodes::is_sublineage;
+
+// SNP defining a lineage: lineage, 0-based position in the reference and alternate base
+pub type LineageSnp = (String, usize, u8);
+
+// simulated read: name and sequence (qualities are not simulated)
+pub type SimulatedRead = (String, Vec<u8>);
+
+// small deterministic random number generator (splitmix64), so that simulations can be
+// reproduced from their seed without extra dependency
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform number in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // uniform number in [0, n)
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+pub fn parse_lineage_snps(content: &str) -> Result<Vec<LineageSnp>, String> {
+    // tab-separated lines: lineage, 1-based position in the reference, alternate base
+    let mut snps = Vec::new();
+    for (line_number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let collection = line.split('\t').map(str::trim).collect::<Vec<&str>>();
+        let snp = match collection[..] {
+            [lineage, position, base] => match (position.parse::<usize>(), base.as_bytes()) {
+                (Ok(position), [base]) if position > 0 && b"ACGT".contains(base) => {
+                    Some((lineage.to_string(), position - 1, *base))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        match snp {
+            Some(snp) => snps.push(snp),
+            None => {
+                return Err(format!(
+                    "line {} of the lineage SNP file should contain a lineage, a position and a base (A, C, G or T)",
+                    line_number + 1
+                ));
+            }
+        }
+    }
+    Ok(snps)
+}
+
+pub fn lineage_genome(reference: &[u8], snps: &[LineageSnp], lineage: &str) -> Vec<u8> {
+    // genome of a lineage: reference with the SNPs of the lineage and of its ancestors
+    let mut genome = reference.to_ascii_uppercase();
+    for (snp_lineage, position, base) in snps {
+        if is_sublineage(lineage, snp_lineage) && *position < genome.len() {
+            genome[*position] = *base;
+        }
+    }
+    genome
+}
+
+pub fn simulate_reads(
+    genomes: &[(Vec<u8>, f64)],
+    coverage: f64,
+    read_length: usize,
+    error_rate: f64,
+    rng: &mut Rng,
+) -> Vec<SimulatedRead> {
+    // reads sampled uniformly from both strands of the genomes, given with their proportions
+    let total: f64 = genomes.iter().map(|(_, ratio)| ratio).sum();
+    let mut reads = Vec::new();
+
+    for (index, (genome, ratio)) in genomes.iter().enumerate() {
+        if genome.len() < read_length {
+            continue;
+        }
+        let nb_reads =
+            (coverage * ratio / total * genome.len() as f64 / read_length as f64).round() as usize;
+        for n in 0..nb_reads {
+            let start = rng.below(genome.len() - read_length + 1);
+            let mut seq = genome[start..start + read_length].to_vec();
+            if rng.next_f64() < 0.5 {
+                seq = seq.iter().rev().map(|base| complement(*base)).collect();
+            }
+            // sequencing errors are substitutions to another base
+            for base in seq.iter_mut() {
+                if rng.next_f64() < error_rate {
+                    let current = *base;
+                    let others = b"ACGT"
+                        .iter()
+                        .filter(|b| **b != current)
+                        .collect::<Vec<_>>();
+                    *base = *others[rng.below(others.len())];
+                }
+            }
+            reads.push((format!("sim_{}_{}", index, n), seq));
+        }
+    }
+    reads
+}
+
+pub fn write_fastq<W: Write>(writer: &mut W, reads: &[SimulatedRead]) -> io::Result<()> {
+    for (name, seq) in reads {
+        writeln!(writer, "@{}", name)?;
+        writer.write_all(seq)?;
+        writer.write_all(b"\n+\n")?;
+        writer.write_all(&vec![b'I'; seq.len()])?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        _ => b'N',
+    }
+}
@@ -0,0 +1,96 @@
+use std::io::BufReader;
+use std::num::NonZeroUsize;
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+use crate::analyse_sample::NamedReader;
+use crate::archives::{send_chunks, ChannelReader};
+
+// threads available to the run: the CPUs the process may use, as limited by its affinity and
+// by its cgroup CPU quota (e.g. containers and batch schedulers)
+pub fn available_threads() -> usize {
+    thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+}
+
+// allocation of the threads of the run (--threads) to its stages: samples typed in parallel by
+// sample workers (their rows still being written in order), each counting the reads of its
+// sample with one thread and decompressing its input files ahead of the counting with one
+// reader thread per file. The workers get the threads of the counting and of the readers of
+// the files of a sample first (no more workers than samples), the threads left giving readers
+// to the first files of the samples when there are too few for all of them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThreadBudget {
+    pub sample_workers: usize,
+    // (reader threads per worker, at most one per file)
+    pub decompression: usize,
+    pub counting: usize,
+}
+
+impl ThreadBudget {
+    pub fn new(threads: usize, max_workers: usize, files_per_sample: usize) -> Self {
+        let threads = threads.max(1);
+        let sample_workers = (threads / (1 + files_per_sample)).clamp(1, max_workers.max(1));
+        ThreadBudget {
+            sample_workers,
+            decompression: (threads / sample_workers - 1).min(files_per_sample),
+            counting: 1,
+        }
+    }
+
+    // threads used by the workers (the main thread only writing the results)
+    pub fn total(&self) -> usize {
+        self.sample_workers * (self.decompression + self.counting)
+    }
+
+    // readers of the files of a sample, the first ones decompressed by reader threads if the
+    // budget has decompression threads (the others being read by the counting thread)
+    pub fn readers(&self, readers: Vec<NamedReader>) -> Vec<NamedReader> {
+        readers
+            .into_iter()
+            .enumerate()
+            .map(|(n, (file_name, mut reader))| {
+                if n >= self.decompression {
+                    return (file_name, reader);
+                }
+                let (sender, receiver) = sync_channel(4);
+                thread::spawn(move || {
+                    if let Err(err) = send_chunks(&mut reader, &sender) {
+                        let _ = sender.send(Err(err));
+                    }
+                });
+                let reader: NamedReader = (
+                    file_name,
+                    Box::new(BufReader::new(ChannelReader::new(receiver))),
+                );
+                reader
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_are_shared_out_between_workers_and_readers() {
+        // paired samples: a counting thread and two readers per worker
+        assert_eq!(
+            ThreadBudget::new(16, 100, 2),
+            ThreadBudget {
+                sample_workers: 5,
+                decompression: 2,
+                counting: 1
+            }
+        );
+        assert_eq!(ThreadBudget::new(16, 100, 2).total(), 15);
+        // no more workers than samples, and no more readers than files
+        assert_eq!(ThreadBudget::new(16, 2, 2).total(), 6);
+        assert_eq!(ThreadBudget::new(16, 100, 1).sample_workers, 8);
+        // too few threads for the readers of all the files
+        assert_eq!(ThreadBudget::new(2, 100, 2).decompression, 1);
+        assert_eq!(ThreadBudget::new(1, 100, 2).total(), 1);
+    }
+}
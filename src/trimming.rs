@@ -0,0 +1,115 @@
+// common Illumina adapters (TruSeq, Nextera and small RNA), trimmed with --trim-adapters
+pub const DEFAULT_ADAPTERS: [&str; 3] = ["AGATCGGAAGAGC", "CTGTCTCTTATACACATCT", "TGGAATTCTCGG"];
+
+// minimum overlap between the end of a read and the start of an adapter to trim it
+pub const MIN_ADAPTER_OVERLAP: usize = 6;
+
+// size of the sliding window of the quality trimming (as fastp's cut_tail)
+pub const QUALITY_WINDOW: usize = 4;
+
+// adapters trimmed from the 3' end of reads before their kmers are extracted: a read is cut
+// at the first occurrence of an adapter (read-through of short fragments), or at the start of
+// a partial adapter at its end
+#[derive(Clone, Debug)]
+pub struct Adapters {
+    adapters: Vec<Vec<u8>>,
+}
+
+impl Adapters {
+    pub fn new(adapters: &[String]) -> Result<Self, String> {
+        let adapters: Vec<Vec<u8>> = adapters
+            .iter()
+            .map(|adapter| adapter.trim().to_ascii_uppercase().into_bytes())
+            .collect();
+        if let Some(adapter) = adapters.iter().find(|adapter| {
+            adapter.len() < MIN_ADAPTER_OVERLAP
+                || !adapter.iter().all(|base| b"ACGT".contains(base))
+        }) {
+            return Err(format!(
+                "the adapter {} should have at least {} bases (A, C, G or T only)",
+                String::from_utf8_lossy(adapter),
+                MIN_ADAPTER_OVERLAP
+            ));
+        }
+        Ok(Adapters { adapters })
+    }
+
+    // length of the read once the adapters are trimmed
+    pub fn trimmed_length(&self, seq: &[u8]) -> usize {
+        let mut length = seq.len();
+        for adapter in &self.adapters {
+            // full adapter within the part of the read still kept
+            if let Some(start) = seq[..length]
+                .windows(adapter.len())
+                .position(|window| window.eq_ignore_ascii_case(adapter))
+            {
+                length = start;
+                continue;
+            }
+            // start of the adapter at the end of the read
+            let max_overlap = (adapter.len() - 1).min(length);
+            if let Some(overlap) = (MIN_ADAPTER_OVERLAP..=max_overlap).rev().find(|overlap| {
+                seq[length - overlap..length].eq_ignore_ascii_case(&adapter[..*overlap])
+            }) {
+                length -= overlap;
+            }
+        }
+        length
+    }
+}
+
+// length of a read once its 3' end is quality trimmed: a window slides from the 3' end towards
+// the 5' end, its bases being dropped while their mean quality (phred+33) is below min_quality
+pub fn quality_trimmed_length(qual: &[u8], min_quality: u8) -> usize {
+    let min_sum = (min_quality as usize + 33) * QUALITY_WINDOW;
+    let mut length = qual.len();
+    while length >= QUALITY_WINDOW {
+        let window = &qual[length - QUALITY_WINDOW..length];
+        if window.iter().map(|q| *q as usize).sum::<usize>() >= min_sum {
+            return length;
+        }
+        length -= QUALITY_WINDOW;
+    }
+    // (shorter tail than the window)
+    match qual[..length].iter().all(|q| *q >= min_quality + 33) {
+        true => length,
+        false => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adapters_are_trimmed_from_the_3p_end() {
+        let adapters = Adapters::new(&["AGATCGGAAGAGC".to_string()]).unwrap();
+        assert_eq!(adapters.trimmed_length(b"ACGTACGTAGATCGGAAGAGCTTTT"), 8);
+        assert_eq!(adapters.trimmed_length(b"acgtacgtagatcggaagagc"), 8);
+        // (partial adapters of at least MIN_ADAPTER_OVERLAP bases at the end of the read)
+        assert_eq!(adapters.trimmed_length(b"ACGTACGTAGATCG"), 8);
+        assert_eq!(adapters.trimmed_length(b"ACGTACGTAGATC"), 13);
+        assert_eq!(adapters.trimmed_length(b"ACGT"), 4);
+
+        let adapters: Vec<String> = DEFAULT_ADAPTERS.iter().map(|a| a.to_string()).collect();
+        let adapters = Adapters::new(&adapters).unwrap();
+        assert_eq!(adapters.trimmed_length(b"ACGTACGTCTGTCTCTTATACACATCT"), 8);
+
+        let err = Adapters::new(&["AGATCN".to_string()]).err().unwrap();
+        assert!(err.contains("AGATCN"), "{}", err);
+        assert!(Adapters::new(&["AGATC".to_string()]).is_err());
+    }
+
+    #[test]
+    fn low_quality_tails_are_trimmed_by_window() {
+        // ('I': quality 40, '!': quality 0)
+        assert_eq!(quality_trimmed_length(b"IIIIIIII!!!!", 20), 8);
+        assert_eq!(quality_trimmed_length(b"IIIIIIII", 20), 8);
+        // (window of mixed qualities whose mean reaches the threshold)
+        assert_eq!(quality_trimmed_length(b"IIIII!", 20), 6);
+        // (tail shorter than the window)
+        assert_eq!(quality_trimmed_length(b"II", 20), 2);
+        assert_eq!(quality_trimmed_length(b"I!!!!!", 20), 0);
+        assert_eq!(quality_trimmed_length(b"", 20), 0);
+    }
+}
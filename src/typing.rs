@@ -0,0 +1,1489 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use crate::output::{
+    check_overwrite, header, sample_row, set_outdir, write_manifest, write_run_record,
+    write_versions, ExtraColumns, OutputFiles, SampleOutputs,
+};
+use crate::setup::{
+    check_kmer_size, load_hierarchy, load_human_panel, load_lineage_names, load_sample_sheet,
+    load_scheme,
+};
+use crate::{Args, PARTIAL_RUN_EXIT_CODE};
+use fastlin::allele_fractions::{
+    allele_counts, format_allele_fractions, mixture_pvalue, AlleleCounts, MIXTURE_SIGNIFICANCE,
+};
+use fastlin::analyse_sample::{
+    count_reads, get_readers, legacy_error, mismatched_mates, scan_readers, Analysis, KmerMatching,
+    KmerSizeScans, NamedReader, ReadVisitor,
+};
+use fastlin::barcode_loci::{format_bed, format_vcf, BarcodeLocus};
+use fastlin::barcodes::{BarcodeIndex, Barcodes};
+use fastlin::bin_reads::ReadBinner;
+use fastlin::deconvolution::{em_proportions, format_em_proportions};
+use fastlin::dedup::{dedup_readers, MAX_DUPLICATION_RATE};
+use fastlin::hierarchy::LineageHierarchy;
+use fastlin::human_fraction::{HumanPanel, HumanScan};
+use fastlin::input_files::{
+    combine_files, file_md5, get_data_type, get_input_files, get_run_dirs, is_stream, merge_files,
+    name_collisions, stream_data_type,
+};
+use fastlin::io_retry::set_io_retries;
+use fastlin::kmer_spectrum::KmerSpectrum;
+use fastlin::position_stats::PositionStats;
+use fastlin::process_barcodes::{
+    amplicon_min_count, barcode_lineage, caller_by_name, covered_targets, depth_histogram,
+    format_abundance, lineage_name, proportion_intervals, target_depth, target_genome_depth,
+    Caller, LineageNames,
+};
+use fastlin::read_table::ReadTable;
+use fastlin::results::{previous_rows, InputType, RunSummary, SampleResult};
+use fastlin::run_log::{log, open_log, sample_status, warning, warnings, RunRecord, SampleRecord};
+use fastlin::sample_sheet::SampleSheet;
+use fastlin::shards::in_shard;
+use fastlin::threads::{available_threads, ThreadBudget};
+use fastlin::trimming::{Adapters, DEFAULT_ADAPTERS};
+
+// samples of a run (run name, and sample names with their input files)
+type Run = (String, Vec<(String, Vec<PathBuf>)>);
+
+// data type of the files of a sample (merged files for ambiguous samples)
+type TypedFiles<'f> = (InputType, Cow<'f, [PathBuf]>);
+
+// barcodes and parameters of the typing, shared by the samples of the run
+struct Typing<'a> {
+    args: &'a Args,
+    barcodes: BarcodeIndex,
+    kmer_size: u8,
+    genome_size: u64,
+    scheme_version: Option<String>,
+    // (indexes of the other kmer sizes compared)
+    other_schemes: Vec<Barcodes>,
+    // (all the barcodes in major-only mode, to rescan the mixed samples)
+    all_barcodes: Option<BarcodeIndex>,
+    matching: KmerMatching,
+    all_matching: Option<KmerMatching>,
+    barcode_ids: HashSet<String>,
+    all_barcode_ids: Option<HashSet<String>>,
+    barcode_loci: Vec<Option<BarcodeLocus>>,
+    // (barcode sites with counts of their wild-type kmers)
+    has_ref: Vec<bool>,
+    lineage_names: LineageNames,
+    hierarchy: LineageHierarchy,
+    caller: &'static dyn Caller,
+    sample_sheet: SampleSheet,
+    human_panel: Option<HumanPanel>,
+    kmer_limit: Option<u64>,
+    batch_mode: bool,
+    multi_k: bool,
+    thread_budget: ThreadBudget,
+}
+
+// per-read statistics of a sample, written to the files of the run
+struct SampleScans {
+    position_stats: Option<PositionStats>,
+    kmer_spectrum: Option<KmerSpectrum>,
+}
+
+// sample to type: its files and data type, and the parameters of its scan
+struct SampleJob<'j> {
+    run_name: Option<&'j str>,
+    sample: &'j str,
+    data_type: InputType,
+    list_files: Cow<'j, [PathBuf]>,
+    kmer_limit: Option<u64>,
+    min_count: i32,
+    // (reported as SKIPPED once the maximum run time is reached)
+    skipped: bool,
+}
+
+// scan of the files of a sample: analyses of the kmer sizes, human fraction, first read names
+// of the paired files if they don't correspond, and number of duplicated reads removed
+struct SampleScan {
+    analysis: Analysis,
+    other_analyses: Vec<(usize, Analysis)>,
+    human_fraction: Option<String>,
+    mismatch: Option<(String, String)>,
+    nb_duplicates: u64,
+}
+
+// typed sample: its result (for the summary of the run), record and outputs
+struct TypedSample {
+    result: SampleResult,
+    failed: bool,
+    record: SampleRecord,
+    outputs: SampleOutputs,
+    nb_reads: u64,
+}
+
+// progress bar of the run (in bytes of the input files, for the throughput and ETA; the members
+// of archives count as empty, and each sample as one unit with --fifo)
+struct Progress {
+    bar: ProgressBar,
+    fifo: bool,
+    nb_samples: usize,
+    nb_done: usize,
+    nb_reads: u64,
+    start: Instant,
+}
+
+// typing of the samples of the run directories, or of the input files in single-sample mode
+// (the default command)
+pub fn type_samples(mut args: Args, start_time: Instant) -> Result<ExitCode, String> {
+    check_overwrite(&args)?;
+    if let Some(outdir) = args.outdir.clone() {
+        set_outdir(&mut args, Path::new(&outdir))?;
+    }
+    let parameters = serde_json::to_value(&args).expect("Failed to serialize the parameters");
+    let args = &args;
+    let barcodes_file = args.barcodes.clone().unwrap();
+
+    // log file
+    if let Some(log_file) = &args.log {
+        open_log(Path::new(log_file))
+            .map_err(|err| format!("couldn't create the file {}: {}", log_file, err))?;
+        let command: Vec<String> = std::env::args().collect();
+        log(
+            "INFO",
+            &format!(
+                "fastlin {}: {}",
+                env!("CARGO_PKG_VERSION"),
+                command.join(" ")
+            ),
+        );
+    }
+
+    check_options(args)?;
+    let mut typing = Typing::new(args, &barcodes_file)?;
+
+    // single-sample mode: the result row goes to stdout and the versions to a file
+    let single_sample = !args.input.is_empty();
+    if single_sample {
+        let scheme_version = typing.scheme_version.clone().unwrap_or_else(|| {
+            let path = Path::new(&barcodes_file);
+            path.file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy()
+                .to_string()
+        });
+        write_versions(&args.versions, &scheme_version)?;
+    }
+
+    let all_runs = list_runs(args, typing.batch_mode)?;
+    let nb_samples: usize = all_runs.iter().map(|(_, samples)| samples.len()).sum();
+    if let Some((index, nb_shards)) = args.shard {
+        eprintln!(" . shard {}/{}\t({} samples)", index, nb_shards, nb_samples);
+    }
+
+    // manifest of the run (before typing the samples)
+    if let Some(manifest_out) = &args.manifest_out {
+        let scheme = serde_json::json!({
+            "path": barcodes_file,
+            "md5": file_md5(Path::new(&barcodes_file)).ok(),
+            "version": typing.scheme_version,
+            "kmer_size": typing.kmer_size,
+            "genome_size": typing.genome_size,
+        });
+        let samples = manifest_samples(&all_runs, typing.batch_mode);
+        write_manifest(manifest_out, parameters, scheme, samples)?;
+    }
+
+    let header = header(
+        args,
+        typing.batch_mode,
+        typing.multi_k,
+        typing.human_panel.is_some(),
+        &typing.sample_sheet.columns,
+    );
+    let previous = match single_sample {
+        true => String::new(),
+        false => previous_output_rows(args, &header, &all_runs, typing.batch_mode)?,
+    };
+    let mut outputs = OutputFiles::create(args, header, &previous, single_sample)?;
+
+    // create read assignment table
+    let mut read_table = args
+        .read_table
+        .as_ref()
+        .map(|path| {
+            ReadTable::new(Path::new(path))
+                .map_err(|err| format!("couldn't create the file {}: {}", path, err))
+        })
+        .transpose()?;
+    if let Some(read_table) = read_table.as_mut() {
+        read_table.set_lineage_names(typing.lineage_names.clone());
+    }
+    set_io_retries(args.io_retries);
+
+    // threads of the run: sample workers (a single one with the read table, whose rows are
+    // written as the reads are scanned), each with a counting thread and reader threads for
+    // the files of its samples
+    let threads = args.threads.unwrap_or_else(available_threads);
+    let max_workers = match read_table {
+        Some(_) => 1,
+        None => nb_samples,
+    };
+    let files_per_sample = all_runs
+        .iter()
+        .flat_map(|(_, samples)| samples.iter().map(|(_, files)| files.len()))
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    typing.thread_budget = ThreadBudget::new(threads, max_workers, files_per_sample);
+    let thread_budget = typing.thread_budget;
+    eprintln!(
+        " . threads\t({} used of {}: {} sample workers x ({} reader + {} counting threads))",
+        thread_budget.total(),
+        threads,
+        thread_budget.sample_workers,
+        thread_budget.decompression,
+        thread_budget.counting
+    );
+    let typing = &typing;
+
+    let mut progress = Progress::new(&all_runs, args.fifo);
+
+    // process the samples, in parallel with several sample workers (their results being
+    // written in the order of the samples)
+    eprintln!(" . analyse all samples");
+    let samples: Vec<(&String, &String, &Vec<PathBuf>)> = all_runs
+        .iter()
+        .flat_map(|(run, samples)| samples.iter().map(move |(s, f)| (run, s, f)))
+        .collect();
+    let mut summary = RunSummary::default();
+    let mut sample_records = Vec::new();
+    let nb_skipped = AtomicUsize::new(0);
+
+    // typing of a sample (None if it is skipped as ambiguous)
+    let type_sample = |index: usize,
+                       scans: &mut SampleScans,
+                       read_table: Option<&mut ReadTable>|
+     -> Result<Option<TypedSample>, String> {
+        let (run_name, sample, list_files) = samples[index];
+        // samples left once the maximum run time is reached (reported as SKIPPED, not scanned)
+        let skipped = args
+            .max_runtime
+            .is_some_and(|max_runtime| start_time.elapsed() >= max_runtime);
+        if skipped && nb_skipped.fetch_add(1, Ordering::Relaxed) == 0 {
+            warning(&format!(
+                "maximum run time reached after {:.0} s: the {} remaining samples are skipped",
+                start_time.elapsed().as_secs_f64(),
+                nb_samples - index
+            ));
+        }
+
+        let run_name = typing.batch_mode.then_some(run_name.as_str());
+        match typing.job(run_name, sample, list_files, skipped)? {
+            Some(job) => typing.type_sample(&job, scans, read_table).map(Some),
+            None => Ok(None),
+        }
+    };
+
+    // results of a sample written to the output files
+    let mut write_sample = |index: usize, typed: Option<TypedSample>| -> Result<(), String> {
+        let (run_name, sample, list_files) = samples[index];
+        let Some(typed) = typed else {
+            // (samples skipped as ambiguous counted as done, for the progress and the samples left)
+            progress.sample_done(list_files, None);
+            return Ok(());
+        };
+        summary.add(&typed.result, typed.failed);
+        let run_name = typing.batch_mode.then_some(run_name.as_str());
+        outputs.write_sample(run_name, sample, &typed.outputs)?;
+        sample_records.push(typed.record);
+        progress.sample_done(list_files, Some(typed.nb_reads));
+        Ok(())
+    };
+
+    match thread_budget.sample_workers {
+        1 => {
+            let mut scans = typing.sample_scans();
+            for index in 0..nb_samples {
+                let typed = type_sample(index, &mut scans, read_table.as_mut())?;
+                write_sample(index, typed)?;
+            }
+        }
+        sample_workers => {
+            let next_index = AtomicUsize::new(0);
+            let stop = AtomicBool::new(false);
+            let (type_sample, next_index, stop) = (&type_sample, &next_index, &stop);
+            thread::scope(|scope| {
+                let (sender, receiver) = channel();
+                for _ in 0..sample_workers {
+                    let sender = sender.clone();
+                    scope.spawn(move || {
+                        let mut scans = typing.sample_scans();
+                        while !stop.load(Ordering::Relaxed) {
+                            let index = next_index.fetch_add(1, Ordering::Relaxed);
+                            if index >= nb_samples {
+                                break;
+                            }
+                            let typed = type_sample(index, &mut scans, None);
+                            let failed = typed.is_err();
+                            if sender.send((index, typed)).is_err() || failed {
+                                break;
+                            }
+                        }
+                    });
+                }
+                drop(sender);
+
+                // (results received out of order kept until the previous samples are written)
+                let mut pending = BTreeMap::new();
+                let mut index = 0;
+                for (received, typed) in receiver {
+                    pending.insert(received, typed);
+                    while let Some(typed) = pending.remove(&index) {
+                        if let Err(err) = typed.and_then(|typed| write_sample(index, typed)) {
+                            stop.store(true, Ordering::Relaxed);
+                            return Err(err);
+                        }
+                        index += 1;
+                    }
+                }
+                Ok(())
+            })?;
+        }
+    }
+    let nb_skipped = nb_skipped.into_inner();
+
+    if let Some(read_table) = read_table {
+        if let Err(err) = read_table.finish() {
+            warning(&format!("couldn't write the read table: {}", err));
+        }
+    }
+
+    eprintln!("   done.");
+
+    eprintln!(" . summary");
+    outputs.finish(&summary.lines(start_time.elapsed()), args.legacy_output)?;
+
+    // JSON record of the run
+    if let Some(run_json) = &args.run_json {
+        let record = RunRecord {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            command: std::env::args().collect(),
+            parameters: serde_json::json!({
+                "barcodes": barcodes_file,
+                "scheme_version": typing.scheme_version,
+                "kmer_size": typing.kmer_size,
+                "min_count": args.min_count,
+                "n_barcodes": args.n_barcodes,
+                "min_fraction": args.min_fraction,
+                "caller": args.caller,
+                "max_cov": args.max_cov,
+                "min_reads": args.min_reads,
+                "min_bases": args.min_bases,
+            }),
+            samples: sample_records,
+            summary,
+            wall_time: start_time.elapsed().as_secs_f64(),
+            warnings: warnings(),
+        };
+        write_run_record(run_json, &record)?;
+    }
+
+    // partial run (--max-runtime)
+    if nb_skipped > 0 {
+        eprintln!(
+            " . stopped by the maximum run time\t({} samples skipped)",
+            nb_skipped
+        );
+        return Ok(ExitCode::from(PARTIAL_RUN_EXIT_CODE));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+// options that can't be combined
+fn check_options(args: &Args) -> Result<(), String> {
+    for kmer_size in &args.kmer_size {
+        check_kmer_size(Some(*kmer_size))?;
+    }
+    if args.kmer_size.len() > 1 && args.major_only {
+        return Err("several kmer sizes can't be scanned in major-only mode".to_string());
+    }
+    if args.kmer_size.len() > 1 && args.scheme.spaced_seed.is_some() {
+        return Err("several kmer sizes can't be scanned with a spaced seed".to_string());
+    }
+    if args.legacy_output && (args.kmer_size.len() > 1 || args.dir.len() > 1) {
+        return Err("the legacy output has no column for several kmer sizes or runs".to_string());
+    }
+    if args.error_rate && args.scheme.spaced_seed.is_some() {
+        return Err("the error rate can't be estimated with a spaced seed".to_string());
+    }
+    Ok(())
+}
+
+// samples and input files of each run (a single unnamed run unless several directories are
+// given), in the shard of the run
+fn list_runs(args: &Args, batch_mode: bool) -> Result<Vec<Run>, String> {
+    let run_dirs: Vec<PathBuf> = match &args.runs {
+        Some(runs_dir) => get_run_dirs(Path::new(runs_dir))
+            .map_err(|err| format!("couldn't read the directory {}: {}", runs_dir, err))?,
+        None => args.dir.iter().map(PathBuf::from).collect(),
+    };
+
+    // custom naming convention of the input files
+    let sample_regex = match &args.sample_regex {
+        Some(pattern) => match Regex::new(pattern) {
+            Ok(regex) if regex.captures_len() > 1 => Some(regex),
+            Ok(_) => return Err("the sample regex should have a capture group".to_string()),
+            Err(err) => return Err(format!("invalid sample regex: {}", err)),
+        },
+        None => None,
+    };
+
+    let mut all_runs = Vec::new();
+    if !args.input.is_empty() {
+        let list_files: Vec<PathBuf> = args.input.iter().map(PathBuf::from).collect();
+        let sample = match &args.sample_name {
+            Some(name) => name.to_string(),
+            None => combine_files(list_files.to_vec(), sample_regex.as_ref())
+                .into_keys()
+                .next()
+                .ok_or("unrecognised input file extension(s) (the sample name of named pipes is given by --sample-name).")?,
+        };
+        all_runs.push((String::new(), vec![(sample, list_files)]));
+    }
+    for run_dir in &run_dirs {
+        let run_name = match run_dir.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => run_dir.to_string_lossy().to_string(),
+        };
+        if batch_mode {
+            eprintln!(" . run {}", run_name);
+        }
+        let mut all_samples = get_input_files(run_dir, sample_regex.as_ref());
+
+        // different file sets reducing to the same sample name (handled by --on-ambiguous-sample,
+        // the samples of a sample regex being grouped as intended)
+        let collisions = match sample_regex {
+            Some(_) => Vec::new(),
+            None => name_collisions(&all_samples),
+        };
+        for sample in collisions {
+            let mut files: Vec<String> = all_samples[&sample]
+                .iter()
+                .map(|file| file.display().to_string())
+                .collect();
+            files.sort();
+            let message = format!(
+                "the files {} reduce to the same sample name {}",
+                files.join(", "),
+                sample
+            );
+            match args.on_ambiguous_sample.as_str() {
+                "skip" => {
+                    warning(&format!("{} (skipped)", message));
+                    all_samples.remove(&sample);
+                }
+                "merge" => warning(&format!("{} (merged)", message)),
+                _ => {
+                    log("ERROR", &message);
+                    return Err(format!("{} (see --on-ambiguous-sample)", message));
+                }
+            }
+        }
+
+        // sort samples
+        let mut sorted_samples: Vec<_> = all_samples.into_iter().collect();
+        sorted_samples.sort_by(|a, b| a.0.cmp(&b.0));
+
+        all_runs.push((run_name, sorted_samples));
+    }
+    if let Some(shard) = args.shard {
+        let mut position = 0;
+        for (_, samples) in all_runs.iter_mut() {
+            samples.retain(|_| {
+                position += 1;
+                in_shard(position - 1, shard)
+            });
+        }
+    }
+
+    // named pipes can only be read once (no check of the mates, nor other passes over the files)
+    if !args.fifo {
+        let stream = all_runs
+            .iter()
+            .flat_map(|(_, samples)| samples.iter().flat_map(|(_, files)| files))
+            .find(|file| is_stream(file));
+        if let Some(stream) = stream {
+            return Err(format!(
+                "{} is a named pipe or process substitution, which can only be read once: use --fifo",
+                stream.display()
+            ));
+        }
+    }
+    Ok(all_runs)
+}
+
+// samples of the manifest of the run, with their input files
+fn manifest_samples(all_runs: &[Run], batch_mode: bool) -> Vec<serde_json::Value> {
+    all_runs
+        .iter()
+        .flat_map(|(run, samples)| {
+            samples.iter().map(move |(sample, files)| {
+                let mut files: Vec<String> = files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect();
+                files.sort();
+                serde_json::json!({
+                    "run": batch_mode.then_some(run),
+                    "sample": sample,
+                    "files": files,
+                })
+            })
+        })
+        .collect()
+}
+
+// rows of the existing output file (append mode)
+fn previous_output_rows(
+    args: &Args,
+    header: &str,
+    all_runs: &[Run],
+    batch_mode: bool,
+) -> Result<String, String> {
+    let content = match std::fs::read_to_string(&args.output) {
+        Ok(content) if args.append => content,
+        _ => return Ok(String::new()),
+    };
+    let samples: HashSet<String> = all_runs
+        .iter()
+        .flat_map(|(run, samples)| {
+            samples.iter().map(move |(sample, _)| match batch_mode {
+                true => format!("{}\t{}", run, sample),
+                false => sample.to_string(),
+            })
+        })
+        .collect();
+    previous_rows(&content, header, &samples, args.overwrite_samples)
+        .map_err(|err| format!("can't append to {}: {}", args.output, err))
+}
+
+fn no_data_error(
+    data_type: &InputType,
+    list_files: &[PathBuf],
+    min_reads: Option<u64>,
+    min_bases: Option<u64>,
+) -> Option<String> {
+    // NO_DATA status of the read samples below the minimum numbers of reads or bases
+    if *data_type == InputType::Assembly || (min_reads.is_none() && min_bases.is_none()) {
+        return None;
+    }
+    let (min_reads, min_bases) = (min_reads.unwrap_or(0), min_bases.unwrap_or(0));
+    match count_reads(list_files, min_reads, min_bases) {
+        Some((nb_reads, nb_bases)) if nb_reads < min_reads || nb_bases < min_bases => {
+            Some(format!("NO_DATA ({} reads, {} bases)", nb_reads, nb_bases))
+        }
+        _ => None,
+    }
+}
+
+fn input_checksums(list_files: &[PathBuf]) -> BTreeMap<String, String> {
+    // MD5 checksums of the files of a sample (files that can't be read are reported)
+    let mut checksums = BTreeMap::new();
+    for path in list_files {
+        match file_md5(path) {
+            Ok(checksum) => {
+                checksums.insert(path.display().to_string(), checksum);
+            }
+            Err(err) => warning(&format!(
+                "couldn't compute the checksum of {}: {}",
+                path.display(),
+                err
+            )),
+        }
+    }
+    checksums
+}
+
+impl<'a> Typing<'a> {
+    // barcodes (one index per kmer size, the first one being the main one), lineages and
+    // kmer matching of the run
+    fn new(args: &'a Args, barcodes_file: &str) -> Result<Self, String> {
+        let multi_k = args.kmer_size.len() > 1;
+        let mut schemes = Vec::new();
+        for kmer_size in &args.kmer_size {
+            if multi_k {
+                eprintln!(" . kmer size {}", kmer_size);
+            }
+            schemes.push(load_scheme(barcodes_file, Some(*kmer_size), &args.scheme)?);
+        }
+        let scheme = if schemes.is_empty() {
+            load_scheme(barcodes_file, None, &args.scheme)?
+        } else {
+            schemes.remove(0)
+        };
+        let other_schemes = schemes;
+        let (mut barcodes, kmer_size, genome_size, spaced_seed) = (
+            scheme.index,
+            scheme.kmer_size,
+            scheme.genome_size,
+            scheme.spaced_seed,
+        );
+        let barcode_loci = scheme.loci;
+        let ref_kmers = Arc::new(scheme.ref_kmers);
+        if args.allele_fractions.is_some() && ref_kmers.is_empty() {
+            warning("the barcode file has no ref column: no allele fraction will be reported");
+        }
+        if args.barcode_bed.is_some() && barcode_loci.is_empty() {
+            warning("the barcode file has no position column: no BED file will be written");
+        }
+        if args.barcode_vcf.is_some()
+            && !barcode_loci
+                .iter()
+                .flatten()
+                .any(|locus| locus.ref_allele.is_some())
+        {
+            warning("the barcode file has no position and ref columns: no SNP will be written to the VCF files");
+        }
+        let lineage_names = load_lineage_names(&args.scheme)?;
+        let hierarchy = load_hierarchy(&args.scheme)?;
+        // (names checked by clap)
+        let caller = match args.metagenomic {
+            true => caller_by_name("poisson").unwrap(),
+            false => caller_by_name(&args.caller).unwrap(),
+        };
+        let sample_sheet = load_sample_sheet(&args.sample_sheet)?;
+        let human_panel = load_human_panel(&args.human_kmers)?;
+
+        // major-only mode: smaller index (all barcodes kept if mixed samples are rescanned)
+        let all_barcodes = if args.major_only {
+            let all_barcodes = args.rescan_mixtures.then(|| barcodes.clone());
+            let nb_barcodes = barcodes.retain_lineages(|lineage| hierarchy.is_top_level(lineage));
+            eprintln!(" . keep top-level lineages\t({} barcodes)", nb_barcodes);
+            all_barcodes
+        } else {
+            None
+        };
+
+        // kmer matching of the barcodes scanned (and of all barcodes, for rescans)
+        let (prefilter, error_rate) = (!args.scheme.no_prefilter, args.error_rate);
+        let mut matching = KmerMatching::with_error_rate(
+            &barcodes,
+            kmer_size as usize,
+            spaced_seed.clone(),
+            prefilter,
+            error_rate,
+        );
+        let mut all_matching = all_barcodes.as_ref().map(|all_barcodes| {
+            KmerMatching::with_error_rate(
+                all_barcodes,
+                kmer_size as usize,
+                spaced_seed,
+                prefilter,
+                error_rate,
+            )
+        });
+
+        // adapters and low quality ends trimmed from the reads, overlapping mates counted once,
+        // and counts of the barcodes capped
+        matching.trim_quality = args.trim_qual;
+        matching.mate_overlap = args.mate_overlap;
+        matching.max_count = args.max_barcode_count;
+        if let Some(all_matching) = all_matching.as_mut() {
+            all_matching.trim_quality = args.trim_qual;
+            all_matching.mate_overlap = args.mate_overlap;
+            all_matching.max_count = args.max_barcode_count;
+        }
+        // wild-type kmers of the barcode sites, whose occurrences give the allele fractions
+        if args.allele_fractions.is_some() && !ref_kmers.is_empty() {
+            matching = matching.with_ref_kmers(ref_kmers.clone(), &barcodes, kmer_size as usize);
+            if let (Some(all_matching), Some(all_barcodes)) =
+                (all_matching.as_mut(), all_barcodes.as_ref())
+            {
+                *all_matching = all_matching.clone().with_ref_kmers(
+                    ref_kmers.clone(),
+                    all_barcodes,
+                    kmer_size as usize,
+                );
+            }
+        }
+        if args.trim_adapters {
+            let adapters = match args.adapters.is_empty() {
+                true => DEFAULT_ADAPTERS.map(String::from).to_vec(),
+                false => args.adapters.clone(),
+            };
+            let adapters = Arc::new(Adapters::new(&adapters)?);
+            matching.adapters = Some(adapters.clone());
+            if let Some(all_matching) = all_matching.as_mut() {
+                all_matching.adapters = Some(adapters);
+            }
+        }
+
+        let barcode_ids: HashSet<String> = barcodes.barcode_ids().into_iter().cloned().collect();
+        let all_barcode_ids = all_barcodes
+            .as_ref()
+            .filter(|_| args.em_proportions)
+            .map(|all_barcodes| all_barcodes.barcode_ids().into_iter().cloned().collect());
+        // (no site without counts of the wild-type kmers)
+        let mut has_ref = vec![false; barcodes.ids.len()];
+        for index in ref_kmers
+            .values()
+            .filter(|_| args.allele_fractions.is_some())
+        {
+            has_ref[*index as usize] = barcode_ids.contains(&barcodes.ids[*index as usize]);
+        }
+
+        Ok(Typing {
+            args,
+            // (calculate maximum number of kmers to extract, saturating for huge maximum
+            // coverages)
+            kmer_limit: args.max_cov.map(|limit| limit.saturating_mul(genome_size)),
+            barcodes,
+            kmer_size,
+            genome_size,
+            scheme_version: scheme.version,
+            other_schemes,
+            all_barcodes,
+            matching,
+            all_matching,
+            barcode_ids,
+            all_barcode_ids,
+            barcode_loci,
+            has_ref,
+            lineage_names,
+            hierarchy,
+            caller,
+            sample_sheet,
+            human_panel,
+            batch_mode: args.runs.is_some() || args.dir.len() > 1,
+            multi_k,
+            // (shared out once the samples are listed)
+            thread_budget: ThreadBudget::new(1, 1, 1),
+        })
+    }
+
+    fn sample_scans(&self) -> SampleScans {
+        let mut position_stats = self
+            .args
+            .position_stats
+            .as_ref()
+            .map(|_| PositionStats::new(self.kmer_size as usize));
+        if let Some(position_stats) = position_stats.as_mut() {
+            position_stats.set_lineage_names(self.lineage_names.clone());
+        }
+        SampleScans {
+            position_stats,
+            kmer_spectrum: self.args.kmer_spectrum.as_ref().map(|_| {
+                KmerSpectrum::new(
+                    &self.barcodes,
+                    self.kmer_size as usize,
+                    self.matching.spaced_seed.clone(),
+                )
+            }),
+        }
+    }
+
+    // sequencing type ('single' or 'paired' reads) of a sample, and its files (samples with
+    // unexpected file counts handled by --on-ambiguous-sample, None if skipped)
+    fn data_type<'f>(
+        &self,
+        sample: &str,
+        list_files: &'f [PathBuf],
+    ) -> Result<Option<TypedFiles<'f>>, String> {
+        // (input streams without file extension typed as reads with --fifo)
+        let data_type = match stream_data_type(list_files).filter(|_| self.args.fifo) {
+            Some(data_type) => Ok(data_type),
+            None => get_data_type(list_files),
+        };
+        let (count_fasta, count_fastq) = match data_type {
+            Ok(data_type) => return Ok(Some((data_type, Cow::Borrowed(list_files)))),
+            Err(counts) => counts,
+        };
+        let message = format!(
+            "the sample {} has {} fasta and {} fastq files",
+            sample, count_fasta, count_fastq
+        );
+        match self.args.on_ambiguous_sample.as_str() {
+            "skip" => {
+                warning(&format!("skipped {}", message));
+                Ok(None)
+            }
+            "merge" => {
+                let (data_type, files) = merge_files(list_files);
+                log(
+                    "INFO",
+                    &format!("{}: merged {} files as {}", message, files.len(), data_type),
+                );
+                Ok(Some((data_type, Cow::Owned(files))))
+            }
+            _ => {
+                log("ERROR", &message);
+                Err(format!("{} (see --on-ambiguous-sample)", message))
+            }
+        }
+    }
+
+    // binning of the reads by lineage (one sub-directory per sample)
+    fn binner(
+        &self,
+        run_name: Option<&str>,
+        sample: &str,
+        data_type: &InputType,
+    ) -> Result<Option<ReadBinner>, String> {
+        let (Some(bin_dir), InputType::Single | InputType::Paired) =
+            (&self.args.bin_reads, data_type)
+        else {
+            return Ok(None);
+        };
+        let mut sample_dir = PathBuf::from(bin_dir);
+        if let Some(run_name) = run_name {
+            sample_dir.push(run_name);
+        }
+        sample_dir.push(sample);
+        ReadBinner::new(&sample_dir).map(Some).map_err(|err| {
+            format!(
+                "couldn't create the directory {}: {}",
+                sample_dir.display(),
+                err
+            )
+        })
+    }
+
+    // lineages called from the counts of the barcodes
+    fn call(
+        &self,
+        sample: &str,
+        data_type: InputType,
+        analysis: Analysis,
+        min_count: i32,
+    ) -> SampleResult {
+        let mut result = SampleResult::with_caller(
+            sample.to_string(),
+            data_type,
+            analysis,
+            min_count,
+            self.args.n_barcodes,
+            &self.hierarchy,
+            self.caller,
+        );
+        result.apply_min_fraction(self.args.min_fraction);
+        result
+    }
+
+    // lines of the depth histogram of a sample (number of barcodes per kmer count)
+    fn histogram_lines(&self, sample: &str, analysis: &Analysis) -> String {
+        let mut lines = String::new();
+        let histogram = depth_histogram(&analysis.barcode_counts(), &self.barcode_ids);
+        for (lineage, depths) in histogram {
+            for (depth, nb_barcodes) in depths {
+                let _ = writeln!(
+                    lines,
+                    "{}\t{}\t{}\t{}\t{}",
+                    sample,
+                    analysis.coverage,
+                    lineage_name(&self.lineage_names, &lineage),
+                    depth,
+                    nb_barcodes
+                );
+            }
+        }
+        lines
+    }
+
+    // lines of the allele fraction file (counts of both alleles of the barcode sites)
+    fn allele_lines(&self, sample: &str, sites: &[AlleleCounts]) -> String {
+        let mut lines = String::new();
+        for site in sites {
+            let _ = writeln!(
+                lines,
+                "{}\t{}\t{}\t{}\t{}\t{:.4}",
+                sample,
+                site.barcode,
+                lineage_name(&self.lineage_names, barcode_lineage(&site.barcode)),
+                site.alt,
+                site.reference,
+                site.alt_fraction()
+            );
+        }
+        lines
+    }
+
+    // files of a sample in the output directories: loci of the barcodes found (from the
+    // position column of the barcode file) and their SNPs, and counts of all the barcode kmers
+    // (unless the files couldn't be read)
+    fn sample_files(
+        &self,
+        sample: &str,
+        analysis: &Analysis,
+        min_count: i32,
+        kmer_spectrum: Option<&KmerSpectrum>,
+    ) -> Vec<(String, String, String)> {
+        let args = self.args;
+        let mut files = Vec::new();
+        if let Some(bed_dir) = args
+            .barcode_bed
+            .as_ref()
+            .filter(|_| !self.barcode_loci.is_empty())
+        {
+            let bed = format_bed(
+                sample,
+                &analysis.counts,
+                &analysis.barcode_ids,
+                &self.barcode_loci,
+            );
+            files.push((bed_dir.clone(), format!("{}.bed", sample), bed));
+        }
+        if let Some(vcf_dir) = args
+            .barcode_vcf
+            .as_ref()
+            .filter(|_| !self.barcode_loci.is_empty())
+        {
+            let vcf = format_vcf(
+                sample,
+                &analysis.counts,
+                &analysis.barcode_ids,
+                &self.barcode_loci,
+                min_count.max(1) as u32,
+            );
+            files.push((vcf_dir.clone(), format!("{}.vcf", sample), vcf));
+        }
+        if let (Some(spectrum_dir), Some(kmer_spectrum)) = (&args.kmer_spectrum, kmer_spectrum) {
+            if analysis.error.is_empty() {
+                files.push((
+                    spectrum_dir.clone(),
+                    format!("{}.kmers.tsv", sample),
+                    kmer_spectrum.format(),
+                ));
+            }
+        }
+        files
+    }
+
+    // record of a typed sample for the run record, with its status logged (and the checksums
+    // of its files with --checksums)
+    fn record(
+        &self,
+        job: &SampleJob,
+        result: &SampleResult,
+        failed: bool,
+        seconds: f64,
+    ) -> SampleRecord {
+        let (run_name, sample, skipped) = (job.run_name, job.sample, job.skipped);
+        let name = match run_name {
+            Some(run_name) => format!("{}/{}", run_name, sample),
+            None => sample.to_string(),
+        };
+        let checksums = match self.args.checksums && !skipped {
+            true => input_checksums(&job.list_files),
+            false => BTreeMap::new(),
+        };
+        for (file, checksum) in &checksums {
+            log("INFO", &format!("{}: {} (MD5 {})", name, file, checksum));
+        }
+        let status = match skipped {
+            true => "skipped",
+            false => sample_status(failed, &result.log_errors),
+        };
+        match status {
+            "failed" => log("ERROR", &format!("{}: {}", name, result.log_errors)),
+            "skipped" => log("WARNING", &format!("{}: {}", name, result.log_errors)),
+            "warning" => log("WARNING", &format!("{}: {}", name, result.log_errors)),
+            _ => (),
+        }
+        // (confidence intervals of the proportions only computed for the run record)
+        let mut lineages = result.lineages.clone();
+        if self.args.run_json.is_some() {
+            proportion_intervals(&mut lineages, &result.log_barcodes);
+        }
+        SampleRecord {
+            run: run_name.map(str::to_string),
+            sample: sample.to_string(),
+            status: status.to_string(),
+            seconds,
+            k_cov: result.k_cov,
+            mixture: result.mixture,
+            lineages,
+            log_errors: result.log_errors.clone(),
+            checksums,
+        }
+    }
+
+    // sample to type, with its data type and the parameters of its scan (None if the sample is
+    // skipped as ambiguous)
+    fn job<'j>(
+        &self,
+        run_name: Option<&'j str>,
+        sample: &'j str,
+        list_files: &'j [PathBuf],
+        skipped: bool,
+    ) -> Result<Option<SampleJob<'j>>, String> {
+        let args = self.args;
+        let Some((data_type, list_files)) = self.data_type(sample, list_files)? else {
+            return Ok(None);
+        };
+
+        // (no maximum coverage for amplicons: the genome size is meaningless for targeted panels)
+        let (kmer_limit, min_count) = match &data_type {
+            InputType::Assembly => (None, 1),
+            InputType::Single | InputType::Paired if args.amplicon => (None, args.min_count),
+            // (statistical correction by the Poisson caller)
+            InputType::Single | InputType::Paired if args.metagenomic => (self.kmer_limit, 1),
+            InputType::Single | InputType::Paired => (self.kmer_limit, args.min_count),
+        };
+        Ok(Some(SampleJob {
+            run_name,
+            sample,
+            data_type,
+            list_files,
+            kmer_limit,
+            min_count,
+            skipped,
+        }))
+    }
+
+    // readers of the input files of a sample (without the duplicated reads with --dedup), and
+    // number of duplicated reads
+    fn readers(&self, job: &SampleJob) -> (Vec<NamedReader>, u64) {
+        let list_files = job.list_files.to_vec();
+        let (readers, nb_duplicates) = match (self.args.dedup, &job.data_type) {
+            (true, InputType::Single | InputType::Paired) => {
+                dedup_readers(list_files, self.args.dedup_length)
+            }
+            _ => (get_readers(list_files), 0),
+        };
+        (self.thread_budget.readers(readers), nb_duplicates)
+    }
+
+    // scan of the files of a sample with the per-read visitors (unless it is skipped, or a
+    // failed library with too few reads)
+    fn scan(
+        &self,
+        job: &SampleJob,
+        scans: &mut SampleScans,
+        read_table: Option<&mut ReadTable>,
+    ) -> Result<SampleScan, String> {
+        let args = self.args;
+        let (sample, list_files) = (job.sample, job.list_files.as_ref());
+        let mut binner = match job.skipped {
+            true => None,
+            false => self.binner(job.run_name, sample, &job.data_type)?,
+        };
+
+        // per-read visitors
+        let mut visitors: Vec<&mut dyn ReadVisitor> = Vec::new();
+        if let Some(binner) = binner.as_mut() {
+            visitors.push(binner);
+        }
+        if let Some(read_table) = read_table {
+            read_table.set_sample(sample);
+            visitors.push(read_table);
+        }
+        if let Some(position_stats) = scans.position_stats.as_mut() {
+            visitors.push(position_stats);
+        }
+        if let Some(kmer_spectrum) = scans.kmer_spectrum.as_mut() {
+            kmer_spectrum.reset();
+            visitors.push(kmer_spectrum);
+        }
+        let mut human_scan = self.human_panel.as_ref().map(HumanScan::new);
+        if let Some(human_scan) = human_scan.as_mut() {
+            visitors.push(human_scan);
+        }
+        let mut other_scans = KmerSizeScans::new(
+            self.other_schemes
+                .iter()
+                .map(|scheme| (scheme.kmer_size as usize, &scheme.index)),
+        );
+        if self.multi_k {
+            visitors.push(&mut other_scans);
+        }
+
+        // mates of paired samples (files of different samples paired by mistake)
+        let mismatch = match job.data_type {
+            InputType::Paired if !args.fifo && !job.skipped => mismatched_mates(list_files),
+            _ => None,
+        };
+        let mate_error = mismatch
+            .as_ref()
+            .filter(|_| args.strict_mates)
+            .map(|(read_1, read_2)| format!("MATE_MISMATCH (reads {} and {})", read_1, read_2));
+
+        let skip_error = job
+            .skipped
+            .then(|| "SKIPPED (maximum run time reached)".to_string());
+        let mut nb_duplicates = 0;
+        let analysis = match skip_error
+            .or(mate_error)
+            .or_else(|| no_data_error(&job.data_type, list_files, args.min_reads, args.min_bases))
+        {
+            Some(error) => Analysis {
+                error,
+                ..Default::default()
+            },
+            None => {
+                let (readers, duplicates) = self.readers(job);
+                nb_duplicates = duplicates;
+                scan_readers(
+                    readers,
+                    &self.barcodes,
+                    &self.kmer_size,
+                    job.kmer_limit,
+                    self.genome_size,
+                    Some(&self.matching),
+                    if visitors.is_empty() {
+                        None
+                    } else {
+                        Some(&mut visitors)
+                    },
+                )
+            }
+        };
+        drop(visitors);
+
+        if let Some(binner) = binner {
+            if let Err(err) = binner.finish() {
+                warning(&format!(
+                    "couldn't write the binned reads of {}: {}",
+                    sample, err
+                ));
+            }
+        }
+
+        // analyses of the other kmer sizes (discarded if the files couldn't be read)
+        let mut other_analyses = other_scans.finish(self.genome_size);
+        if !analysis.error.is_empty() {
+            for (_, other_analysis) in other_analyses.iter_mut() {
+                *other_analysis = Analysis {
+                    error: analysis.error.clone(),
+                    ..Default::default()
+                };
+            }
+        }
+        Ok(SampleScan {
+            analysis,
+            other_analyses,
+            human_fraction: human_scan.as_ref().map(HumanScan::fraction_column),
+            mismatch,
+            nb_duplicates,
+        })
+    }
+
+    // lineages called from the scan of a sample (rescanned at full resolution if mixed in
+    // major-only mode), with the warnings of the sample and the columns of the options, for
+    // each kmer size: kmer size, result and coverage of the legacy output
+    fn call_sample(
+        &self,
+        job: &SampleJob,
+        scan: SampleScan,
+        min_count: i32,
+        sites: &[AlleleCounts],
+        columns: &mut ExtraColumns,
+    ) -> Vec<(usize, SampleResult, u32)> {
+        let args = self.args;
+        let (sample, data_type) = (job.sample, job.data_type);
+        let (kmer_size, genome_size) = (self.kmer_size, self.genome_size);
+        let SampleScan {
+            analysis,
+            other_analyses,
+            mismatch,
+            mut nb_duplicates,
+            ..
+        } = scan;
+
+        // barcode counts and number of kmers for the abundance of the target genome
+        let metagenomic_counts = (args.metagenomic && data_type != InputType::Assembly)
+            .then(|| (analysis.barcode_counts(), analysis.kmer_count));
+
+        // barcode counts (and barcodes scanned) for the EM deconvolution of mixtures
+        let mut em_counts = args
+            .em_proportions
+            .then(|| (analysis.barcode_counts(), &self.barcode_ids));
+
+        // reads (or pairs of reads) kept after removing the duplicates
+        let nb_fragments = analysis.files.first().map_or(0, |file| file.nb_reads);
+
+        // process barcodes
+        let failed = !analysis.error.is_empty();
+        let legacy_k_cov = analysis.legacy_coverage(genome_size);
+        let mut result = self.call(sample, data_type, analysis, min_count);
+
+        // rescan mixed samples at full resolution (without per-read outputs)
+        if let Some(all_barcodes) = self.all_barcodes.as_ref().filter(|_| result.mixture) {
+            let (readers, duplicates) = self.readers(job);
+            nb_duplicates = duplicates;
+            let mut analysis = scan_readers(
+                readers,
+                all_barcodes,
+                &kmer_size,
+                job.kmer_limit,
+                genome_size,
+                self.all_matching.as_ref(),
+                None,
+            );
+            if args.amplicon {
+                analysis.coverage = result.k_cov;
+            }
+            if let (Some(em_counts), Some(all_barcode_ids)) =
+                (em_counts.as_mut(), self.all_barcode_ids.as_ref())
+            {
+                *em_counts = (analysis.barcode_counts(), all_barcode_ids);
+            }
+            result = self.call(sample, data_type, analysis, min_count);
+        }
+        if let Some((read_1, read_2)) = mismatch.filter(|_| !args.strict_mates) {
+            result.add_warning(&format!(
+                "the read names of the paired files don't correspond ({} and {})",
+                read_1, read_2
+            ));
+        }
+        // duplication of the library
+        if nb_duplicates > 0 && !failed {
+            let rate = nb_duplicates as f64 / (nb_duplicates + nb_fragments) as f64;
+            log(
+                "INFO",
+                &format!(
+                    "{}: {} duplicated reads removed ({:.1}%)",
+                    sample,
+                    nb_duplicates,
+                    100.0 * rate
+                ),
+            );
+            if rate >= MAX_DUPLICATION_RATE {
+                result.add_warning(&format!(
+                    "high duplication ({:.1}% of the reads removed)",
+                    100.0 * rate
+                ));
+            }
+        }
+
+        // metagenomic mode: abundance of the target genome, and no call below the detection
+        // limit (a few erroneous or background kmers can still make calls)
+        columns.abundance = "NA".to_string();
+        if let Some((counts, kmer_count)) = metagenomic_counts.filter(|_| !failed) {
+            let depth = target_genome_depth(&result.lineages, &counts, &self.barcode_ids);
+            columns.abundance = format_abundance(depth, genome_size, kmer_count);
+            if depth < args.detection_limit {
+                result.no_call(&format!(
+                    "estimated depth of {:.2}x below the detection limit of {}x",
+                    depth, args.detection_limit
+                ));
+            }
+        }
+        // (the binomial test of the allele counts replaces the number of lineages called for
+        // the mixture status)
+        columns.allele_fractions =
+            format_allele_fractions(&result.lineages, sites, &self.lineage_names);
+        columns.mixture_pvalue = mixture_pvalue(&result.lineages, sites);
+        let em_proportions = em_counts
+            .filter(|_| !failed)
+            .map(|(counts, barcode_ids)| {
+                em_proportions(
+                    &result.lineages,
+                    &counts,
+                    barcode_ids,
+                    sites,
+                    &self.hierarchy,
+                    args.em_components,
+                )
+            })
+            .unwrap_or_default();
+        columns.em_proportions = format_em_proportions(&em_proportions, &self.lineage_names);
+        if let Some(pvalue) = columns.mixture_pvalue {
+            result.mixture = pvalue < MIXTURE_SIGNIFICANCE;
+        }
+
+        let mut results = vec![(kmer_size as usize, result, legacy_k_cov)];
+        for (other_kmer_size, other_analysis) in other_analyses {
+            let legacy_k_cov = other_analysis.legacy_coverage(genome_size);
+            let other_result = self.call(sample, data_type, other_analysis, min_count);
+            results.push((other_kmer_size, other_result, legacy_k_cov));
+        }
+        results
+    }
+
+    // typing of a sample: scan of its files, lineages called and outputs
+    fn type_sample(
+        &self,
+        job: &SampleJob,
+        scans: &mut SampleScans,
+        read_table: Option<&mut ReadTable>,
+    ) -> Result<TypedSample, String> {
+        let args = self.args;
+        let sample = job.sample;
+        let sample_start = Instant::now();
+        let mut scan = self.scan(job, scans, read_table)?;
+        let analysis = &mut scan.analysis;
+        let nb_reads = analysis.files.iter().map(|file| file.nb_reads).sum::<u64>();
+
+        // amplicon mode: depth of the targets as coverage, and minimum count of the barcodes
+        // relative to it
+        let mut columns = ExtraColumns::default();
+        let min_count = match args.amplicon {
+            true => {
+                let counts = analysis.barcode_counts();
+                let depth = target_depth(&counts);
+                let min_count = match &job.data_type {
+                    InputType::Assembly => job.min_count,
+                    InputType::Single | InputType::Paired => {
+                        analysis.coverage = depth as u32;
+                        amplicon_min_count(job.min_count, depth)
+                    }
+                };
+                columns.targets =
+                    covered_targets(&counts, &self.barcode_ids, min_count, &self.lineage_names);
+                min_count
+            }
+            false => job.min_count,
+        };
+
+        // Note: coverage used to be fixed to 1 for assemblies
+
+        // positions of barcode hits within reads, histogram of barcode kmer counts, files of
+        // the sample and counts of both alleles of the barcode sites
+        let mut outputs = SampleOutputs::default();
+        if let Some(position_stats) = scans.position_stats.as_mut() {
+            position_stats
+                .write_sample(&mut outputs.positions, sample)
+                .expect("Failed to write to file");
+        }
+        if args.depth_histogram.is_some() {
+            outputs.histogram = self.histogram_lines(sample, analysis);
+        }
+        outputs.files =
+            self.sample_files(sample, analysis, min_count, scans.kmer_spectrum.as_ref());
+        let sites = allele_counts(
+            &analysis.counts,
+            &analysis.ref_counts,
+            &analysis.barcode_ids,
+            &self.has_ref,
+        );
+        if args.allele_fractions.is_some() {
+            outputs.alleles = self.allele_lines(sample, &sites);
+        }
+
+        let failed = !analysis.error.is_empty();
+        let error = legacy_error(&analysis.error);
+        columns.human_fraction = scan.human_fraction.take();
+        columns.metadata = self.sample_sheet.metadata(sample);
+        let mut results = self.call_sample(job, scan, min_count, &sites, &mut columns);
+
+        // rows of the sample (one per kmer size, the first one giving its status)
+        for (kmer_size, result, legacy_k_cov) in results.iter_mut() {
+            result.rename_lineages(&self.lineage_names);
+            outputs.rows.push(sample_row(
+                args,
+                job.run_name,
+                self.multi_k.then_some(*kmer_size),
+                result,
+                (*legacy_k_cov, &error),
+                &columns,
+            ));
+        }
+        let (_, result, _) = results.swap_remove(0);
+        let record = self.record(job, &result, failed, sample_start.elapsed().as_secs_f64());
+        Ok(TypedSample {
+            result,
+            failed,
+            record,
+            outputs,
+            nb_reads,
+        })
+    }
+}
+
+// bytes of the input files of a sample (each sample counting as one unit with --fifo)
+fn sample_bytes(files: &[PathBuf], fifo: bool) -> u64 {
+    if fifo {
+        return 1;
+    }
+    files
+        .iter()
+        .filter_map(|file| std::fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+impl Progress {
+    fn new(all_runs: &[Run], fifo: bool) -> Self {
+        let nb_samples = all_runs.iter().map(|(_, samples)| samples.len()).sum();
+        let total_bytes: u64 = all_runs
+            .iter()
+            .flat_map(|(_, samples)| samples.iter().map(|(_, files)| sample_bytes(files, fifo)))
+            .sum();
+        let bar = ProgressBar::new(total_bytes);
+        let style = ProgressStyle::with_template(match fifo {
+            true => "   {bar:60.cyan/blue} [{elapsed_precise}, ETA {eta_precise}] {msg}",
+            false => "   {bar:60.cyan/blue} {binary_bytes:>9}/{binary_total_bytes:9} {binary_bytes_per_sec:>11} [{elapsed_precise}, ETA {eta_precise}] {msg}",
+        })
+        .unwrap()
+        .progress_chars("##-");
+        bar.set_style(style);
+        bar.set_message(format!("0/{} samples", nb_samples));
+        Progress {
+            bar,
+            fifo,
+            nb_samples,
+            nb_done: 0,
+            nb_reads: 0,
+            start: Instant::now(),
+        }
+    }
+
+    // sample done (samples typed and reads per second, unless the sample was skipped)
+    fn sample_done(&mut self, files: &[PathBuf], nb_reads: Option<u64>) {
+        self.nb_done += 1;
+        self.bar.inc(sample_bytes(files, self.fifo));
+        let Some(nb_reads) = nb_reads else {
+            self.bar
+                .set_message(format!("{}/{} samples", self.nb_done, self.nb_samples));
+            return;
+        };
+        self.nb_reads += nb_reads;
+        self.bar.set_message(format!(
+            "{}/{} samples, {:.0} reads/s",
+            self.nb_done,
+            self.nb_samples,
+            self.nb_reads as f64 / self.start.elapsed().as_secs_f64()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_is_counted_in_bytes_of_the_input_files() {
+        let dir = std::env::temp_dir().join(format!("fastlin_progress_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = vec![dir.join("s1_1.fq.gz"), dir.join("s1_2.fq.gz")];
+        std::fs::write(&files[0], [0; 100]).unwrap();
+        std::fs::write(&files[1], [0; 50]).unwrap();
+        assert_eq!(sample_bytes(&files, false), 150);
+        // (samples of named pipes count as one unit, files that can't be read as empty)
+        assert_eq!(sample_bytes(&files, true), 1);
+        assert_eq!(sample_bytes(&[dir.join("missing.fq.gz")], false), 0);
+
+        let all_runs: Vec<Run> = vec![(
+            "run".to_string(),
+            vec![
+                ("s1".to_string(), files.clone()),
+                ("s2".to_string(), vec![files[1].clone()]),
+            ],
+        )];
+        let mut progress = Progress::new(&all_runs, false);
+        assert_eq!(progress.bar.length(), Some(200));
+        progress.sample_done(&files, Some(1000));
+        assert_eq!(progress.bar.position(), 150);
+        assert!(progress.bar.message().starts_with("1/2 samples, "));
+        // (skipped samples)
+        progress.sample_done(&files[1..], None);
+        assert_eq!(progress.bar.position(), 200);
+        assert_eq!(progress.bar.message(), "2/2 samples");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
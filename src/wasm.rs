@@ -0,0 +1,71 @@
+use wasm_bindgen::prelude::*;
+
+use crate::analyse_sample::{get_reader_from_bytes, scan_readers};
+use crate::barcodes::{BarcodeIndex, Barcodes};
+use crate::results::{InputType, SampleResult};
+
+// JavaScript API: `new Scheme(barcodeFileContent, 25).typeFastq(bytes, 4, 3)`
+#[wasm_bindgen]
+pub struct Scheme {
+    barcodes: BarcodeIndex,
+    genome_size: u64,
+    kmer_size: u8,
+}
+
+#[wasm_bindgen]
+impl Scheme {
+    #[wasm_bindgen(constructor)]
+    pub fn new(barcode_file: String, kmer_size: u8) -> Result<Scheme, JsValue> {
+        let barcodes = Barcodes::from_string(&barcode_file, kmer_size)
+            .map_err(|err| JsValue::from_str(&err))?;
+
+        Ok(Scheme {
+            barcodes: barcodes.index,
+            genome_size: barcodes.genome_size,
+            kmer_size,
+        })
+    }
+
+    // types a single-end FASTQ file (plain or gzipped) given as bytes and returns the
+    // serialised SampleResult
+    #[wasm_bindgen(js_name = typeFastq)]
+    pub fn type_fastq(&self, fastq: Vec<u8>, min_count: i32, n_barcodes: usize) -> String {
+        let analysis = scan_readers(
+            vec![(String::from("fastq"), get_reader_from_bytes(fastq))],
+            &self.barcodes,
+            &self.kmer_size,
+            None,
+            self.genome_size,
+            None,
+            None,
+        );
+        let mut result = SampleResult::new(
+            String::new(),
+            InputType::Single,
+            analysis,
+            min_count,
+            n_barcodes,
+        );
+        result.compute_proportion_intervals();
+        serde_json::to_string(&result).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (run natively with `cargo test --features wasm`)
+    #[test]
+    fn fastq_bytes_are_typed_into_json() {
+        let data = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data");
+        let barcode_file = std::fs::read_to_string(format!("{}/barcodes.txt", data)).unwrap();
+        let fastq = std::fs::read(format!("{}/reads/single.fastq.gz", data)).unwrap();
+        let scheme = Scheme::new(barcode_file, 25).unwrap();
+        let result: serde_json::Value =
+            serde_json::from_str(&scheme.type_fastq(fastq, 4, 3)).unwrap();
+        assert_eq!(result["data_type"], "single");
+        assert_eq!(result["mixture"], "no");
+        assert_eq!(result["lineages"], "2.2 (10)");
+    }
+}
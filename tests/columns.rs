@@ -0,0 +1,205 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// columns of the header and of the rows of the samples (by name) of a run on the test reads
+fn run(name: &str, args: &[&str]) -> (Vec<String>, Vec<Vec<String>>) {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let dir = std::env::temp_dir().join(format!("fastlin_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .arg("-d")
+        .arg(data.join("reads"))
+        .arg("-b")
+        .arg(data.join("barcodes.txt"))
+        .args(["-o", "out.txt"])
+        .args(args)
+        .current_dir(&dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("couldn't run fastlin");
+    assert!(status.success());
+    let output = fs::read_to_string(dir.join("out.txt")).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+
+    let split = |line: &str| -> Vec<String> { line.split('\t').map(String::from).collect() };
+    let mut lines = output.lines().filter(|line| !line.starts_with("# "));
+    let header = split(lines.next().unwrap());
+    let mut rows: Vec<Vec<String>> = lines.map(split).collect();
+    rows.sort();
+    (header, rows)
+}
+
+// values of a column in the rows
+fn column(header: &[String], rows: &[Vec<String>], name: &str) -> Vec<String> {
+    let index = header.iter().position(|column| column == name).unwrap();
+    rows.iter().map(|row| row[index].clone()).collect()
+}
+
+#[test]
+fn sample_sheet_columns_are_copied_to_the_rows() {
+    let sheet = std::env::temp_dir().join(format!("fastlin_sheet_{}.tsv", std::process::id()));
+    fs::write(
+        &sheet,
+        "#sample\tpatient\tsite\npure\tP1\tLyon\nsingle\tP2\n",
+    )
+    .unwrap();
+    let (header, rows) = run("sample_sheet", &["--sample-sheet", sheet.to_str().unwrap()]);
+    fs::remove_file(&sheet).unwrap();
+
+    assert_eq!(
+        header[header.len() - 3..],
+        ["median_read_length", "patient", "site"]
+    );
+    // (mixed, pure and single, missing values left empty)
+    let metadata: Vec<&[String]> = rows.iter().map(|row| &row[row.len() - 2..]).collect();
+    assert_eq!(metadata, [["", ""], ["P1", "Lyon"], ["P2", ""]]);
+}
+
+#[test]
+fn amplicon_runs_report_the_targets_covered() {
+    let (header, rows) = run("amplicon", &["--amplicon"]);
+    // (mixed, pure and single, k_cov being the depth of the targets)
+    assert_eq!(column(&header, &rows, "k_cov"), ["15", "20", "10"]);
+    assert_eq!(
+        column(&header, &rows, "targets"),
+        [
+            "12/15 (2.2: 6/6, 4.1: 6/6)",
+            "9/15 (4.1: 6/6, 4.1.2: 3/3)",
+            "6/15 (2.2: 6/6)"
+        ]
+    );
+}
+
+#[test]
+fn metagenomic_runs_report_the_abundance() {
+    let (header, rows) = run("metagenomic", &["--metagenomic"]);
+    assert_eq!(
+        column(&header, &rows, "abundance"),
+        [
+            "30.00x (100.000% of the kmers)",
+            "20.00x (100.000% of the kmers)",
+            "10.00x (100.000% of the kmers)"
+        ]
+    );
+
+    // samples below the detection limit aren't typed
+    let (header, rows) = run(
+        "detection_limit",
+        &["--metagenomic", "--detection-limit", "15"],
+    );
+    assert_eq!(
+        column(&header, &rows, "lineages"),
+        ["4.1 (24), 2.2 (6)", "4.1.2 (20)", ""]
+    );
+    let errors = column(&header, &rows, "log_errors");
+    assert!(errors[2].starts_with("NO_CALL"), "{}", errors[2]);
+}
+
+#[test]
+fn barcodes_found_in_both_mates_are_counted_once() {
+    // barcode counts of the samples, lineage by lineage
+    let counts = |args: &[&str]| -> Vec<Vec<u32>> {
+        let (header, rows) = run(&format!("mate_overlap{}", args.len()), args);
+        column(&header, &rows, "log_barcodes")
+            .iter()
+            .map(|log| {
+                log.split(['(', ')', ','])
+                    .filter_map(|count| count.trim().parse().ok())
+                    .collect()
+            })
+            .collect()
+    };
+    let (all, once) = (counts(&[]), counts(&["--mate-overlap"]));
+    // (mixed and pure have overlapping mates, the single-end reads are unaffected)
+    for (all, once) in all.iter().zip(&once) {
+        assert_eq!(all.len(), once.len());
+        assert!(all.iter().zip(once).all(|(all, once)| once <= all));
+    }
+    assert!(once[0].iter().sum::<u32>() < all[0].iter().sum());
+    assert!(once[1].iter().sum::<u32>() < all[1].iter().sum());
+    assert_eq!(once[2], all[2]);
+}
+
+#[test]
+fn barcode_counts_are_capped() {
+    let (header, rows) = run("max_barcode_count", &["--max-barcode-count", "12"]);
+    assert_eq!(
+        column(&header, &rows, "lineages"),
+        ["4.1 (12), 2.2 (6)", "4.1.2 (12)", "2.2 (10)"]
+    );
+}
+
+#[test]
+fn file_coverage_reports_each_input_file() {
+    let (header, rows) = run(
+        "file_coverage",
+        &["--file-coverage", "--genome-size", "1000"],
+    );
+    assert_eq!(
+        column(&header, &rows, "file_coverage"),
+        [
+            "mixed_1.fastq.gz (30), mixed_2.fastq.gz (30)",
+            "pure_1.fastq.gz (30), pure_2.fastq.gz (30)",
+            "single.fastq.gz (15)"
+        ]
+    );
+    assert!(column(&header, &rows, "log_errors")
+        .iter()
+        .all(|errors| !errors.contains("imbalanced")));
+}
+
+#[test]
+fn mates_of_uneven_coverage_are_flagged() {
+    use flate2::read::MultiGzDecoder;
+    use flate2::write::GzEncoder;
+    use std::io::{Read, Write};
+
+    // second mates cut to 40 bp (16 kmers per read instead of 76)
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let dir = std::env::temp_dir().join(format!("fastlin_imbalance_{}", std::process::id()));
+    fs::create_dir_all(dir.join("reads")).unwrap();
+    for mate in ["1", "2"] {
+        let mut reads = String::new();
+        MultiGzDecoder::new(
+            fs::File::open(data.join(format!("reads/pure_{}.fastq.gz", mate))).unwrap(),
+        )
+        .read_to_string(&mut reads)
+        .unwrap();
+        let file = fs::File::create(dir.join(format!("reads/s_{}.fastq.gz", mate))).unwrap();
+        let mut writer = GzEncoder::new(file, flate2::Compression::default());
+        for (i, line) in reads.lines().enumerate() {
+            match mate == "2" && i % 2 == 1 {
+                true => writeln!(writer, "{}", &line[..40]).unwrap(),
+                false => writeln!(writer, "{}", line).unwrap(),
+            }
+        }
+        writer.finish().unwrap();
+    }
+    let status = Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .arg("-d")
+        .arg(dir.join("reads"))
+        .arg("-b")
+        .arg(data.join("barcodes.txt"))
+        .args(["--file-coverage", "--genome-size", "1000", "-o"])
+        .arg(dir.join("out.txt"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("couldn't run fastlin");
+    assert!(status.success());
+    let output = fs::read_to_string(dir.join("out.txt")).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+    let row = output.lines().find(|line| line.starts_with("s\t")).unwrap();
+    assert!(
+        row.contains("s_1.fastq.gz (30), s_2.fastq.gz (6)"),
+        "{}",
+        row
+    );
+    assert!(
+        row.contains("imbalanced paired files (kmer coverage 30 and 6)"),
+        "{}",
+        row
+    );
+}
@@ -0,0 +1,57 @@
+use std::path::Path;
+use std::process::Command;
+
+// legacy_output.tsv was written by the first version of fastlin on the same reads: a lineage and
+// its sublineage (pure), a mixture (whose lineages were listed in hash order then, the reference
+// being a run listing them by decreasing depth), barcodes below the minimum count (low), a coverage
+// rounded down by the L - k kmers of the reads (single) and a truncated file (trunc).
+// The barcodes of a lineage have the same depth, as they were also listed in hash order
+#[test]
+fn legacy_output_matches_the_original_fastlin() {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let dir = std::env::temp_dir().join(format!("fastlin_legacy_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("reads")).unwrap();
+    // (test reads, along with the low and trunc samples)
+    for reads in ["reads", "legacy_reads"] {
+        for file in std::fs::read_dir(data.join(reads)).unwrap() {
+            let file = file.unwrap();
+            std::fs::copy(file.path(), dir.join("reads").join(file.file_name())).unwrap();
+        }
+    }
+    let output = dir.join("legacy_output.tsv");
+    let status = Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .arg("-d")
+        .arg(dir.join("reads"))
+        .arg("-b")
+        .arg(data.join("barcodes.txt"))
+        .arg("-o")
+        .arg(&output)
+        .args(["--legacy-output", "--force", "--threads", "1"])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .expect("couldn't run fastlin");
+    assert!(status.success());
+
+    let written = std::fs::read(&output).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    let expected = std::fs::read(data.join("legacy_output.tsv")).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&written),
+        String::from_utf8_lossy(&expected)
+    );
+}
+
+#[test]
+fn legacy_output_rejects_the_extra_columns() {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let output = Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .arg("-d")
+        .arg(data.join("reads"))
+        .arg("-b")
+        .arg(data.join("barcodes.txt"))
+        .args(["--legacy-output", "--error-rate"])
+        .output()
+        .expect("couldn't run fastlin");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+}
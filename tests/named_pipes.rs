@@ -0,0 +1,59 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::thread;
+
+// single-sample run of fastlin on an input file, in its directory (receiving versions.yml)
+fn fastlin(input: &Path, args: &[&str]) -> Output {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .arg("-i")
+        .arg(input)
+        .arg("-b")
+        .arg(data.join("barcodes.txt"))
+        .args(["--sample-name", "single"])
+        .args(args)
+        .current_dir(input.parent().unwrap())
+        .output()
+        .expect("couldn't run fastlin")
+}
+
+#[cfg(unix)]
+#[test]
+fn reads_are_typed_from_named_pipes() {
+    let dir = std::env::temp_dir().join(format!("fastlin_fifo_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let pipe = dir.join("reads");
+    assert!(Command::new("mkfifo")
+        .arg(&pipe)
+        .status()
+        .unwrap()
+        .success());
+
+    // named pipes are only read with --fifo (the pipe isn't opened)
+    let output = fastlin(&pipe, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("use --fifo"), "{}", stderr);
+
+    // (gzipped reads without file extension, written as fastlin reads them)
+    let reads = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/reads/single.fastq.gz");
+    let reads = fs::read(reads).unwrap();
+    let writer = {
+        let pipe = pipe.clone();
+        thread::spawn(move || fs::File::create(pipe).unwrap().write_all(&reads).unwrap())
+    };
+    let output = fastlin(&pipe, &["--fifo"]);
+    writer.join().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let row = stdout
+        .lines()
+        .find(|line| line.starts_with("single\t"))
+        .unwrap();
+    let row: Vec<&str> = row.split('\t').collect();
+    assert_eq!(row[..2], ["single", "single"]);
+    assert!(row.contains(&"2.2 (10)"), "{}", stdout);
+    fs::remove_dir_all(dir).unwrap();
+}
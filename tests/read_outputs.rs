@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// run of fastlin on the test reads, in a directory of its own receiving its outputs
+fn run(name: &str, args: &[&str]) -> PathBuf {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let dir = std::env::temp_dir().join(format!("fastlin_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .arg("-d")
+        .arg(data.join("reads"))
+        .arg("-b")
+        .arg(data.join("barcodes.txt"))
+        .args(["-o", "out.txt"])
+        .args(args)
+        .current_dir(&dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("couldn't run fastlin");
+    assert!(status.success());
+    dir
+}
+
+#[test]
+fn reads_are_binned_by_sample_and_lineage() {
+    let dir = run("bin_reads", &["--bin-reads", "bins"]);
+    let bins = |sample: &str| {
+        let mut names: Vec<String> = fs::read_dir(dir.join("bins").join(sample))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(bins("pure"), ["4.1.2.fastq.gz", "4.1.fastq.gz"]);
+    assert_eq!(bins("mixed"), ["2.2.fastq.gz", "4.1.fastq.gz"]);
+    assert_eq!(bins("single"), ["2.2.fastq.gz"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn the_read_table_lists_the_hits_of_every_sample() {
+    let dir = run("read_table", &["--read-table", "hits.tsv"]);
+    let table = fs::read_to_string(dir.join("hits.tsv")).unwrap();
+    let mut lines = table.lines();
+    assert_eq!(
+        lines.next(),
+        Some("#sample\tfile\tread\tlineage\tbarcode\tposition")
+    );
+    // (6 barcodes of 2.2 found 10 times in the single-end sample)
+    let single: Vec<Vec<&str>> = lines
+        .map(|line| line.split('\t').collect::<Vec<&str>>())
+        .filter(|columns| columns[0] == "single")
+        .collect();
+    assert_eq!(single.len(), 60);
+    assert!(single
+        .iter()
+        .all(|columns| columns[1].ends_with("single.fastq.gz") && columns[3] == "2.2"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn the_depth_histogram_counts_the_barcodes_of_each_lineage() {
+    let dir = run("depth_histogram", &["--depth-histogram", "histogram.tsv"]);
+    let histogram = fs::read_to_string(dir.join("histogram.tsv")).unwrap();
+    let lines: Vec<&str> = histogram.lines().collect();
+    assert_eq!(lines[0], "#sample\tk_cov\tlineage\tdepth\tnb_barcodes");
+    // (barcodes not found counted at depth 0)
+    assert_eq!(
+        lines[4..7],
+        [
+            "pure\t2\t2.2\t0\t6",
+            "pure\t2\t4.1\t20\t6",
+            "pure\t2\t4.1.2\t20\t3"
+        ]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn the_kmer_spectrum_lists_every_barcode_kmer() {
+    let dir = run("kmer_spectrum", &["--kmer-spectrum", "spectrum"]);
+    for sample in ["mixed", "pure", "single"] {
+        let spectrum = fs::read_to_string(dir.join(format!("spectrum/{}.kmers.tsv", sample)));
+        let counts: Vec<(String, u32)> = spectrum
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (kmer, count) = line.split_once('\t').unwrap();
+                (kmer.to_string(), count.parse().unwrap())
+            })
+            .collect();
+        // (both strands of the 15 barcodes, sorted, including the kmers not found)
+        assert_eq!(counts.len(), 30);
+        assert!(counts.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(counts.iter().all(|(kmer, _)| kmer.len() == 25));
+        if sample == "single" {
+            // (the 6 barcodes of 2.2 found 10 times, 5 on each strand)
+            let found: Vec<u32> = counts
+                .iter()
+                .map(|(_, count)| *count)
+                .filter(|&c| c > 0)
+                .collect();
+            assert_eq!(found, [5; 12]);
+        }
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
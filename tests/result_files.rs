@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// empty directory of a test
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fastlin_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// run of fastlin on the test reads, in the given directory
+fn fastlin(dir: &Path, args: &[&str]) -> Output {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .arg("-d")
+        .arg(data.join("reads"))
+        .arg("-b")
+        .arg(data.join("barcodes.txt"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("couldn't run fastlin")
+}
+
+#[test]
+fn the_rows_of_each_sample_are_written_to_a_file_of_its_own() {
+    let dir = test_dir("per_sample");
+    let output = fastlin(&dir, &["-o", "out.txt", "--per-sample-output", "samples"]);
+    assert!(output.status.success());
+    let combined = fs::read_to_string(dir.join("out.txt")).unwrap();
+    let lines: Vec<&str> = combined.lines().collect();
+    let pure = lines
+        .iter()
+        .find(|line| line.starts_with("pure\t"))
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("samples/pure.tsv")).unwrap(),
+        format!("{}\n{}\n", lines[0], pure)
+    );
+
+    let output = fastlin(
+        &dir,
+        &[
+            "-o",
+            "json.txt",
+            "--per-sample-output",
+            "json",
+            "--per-sample-format",
+            "json",
+            "--no-combined-output",
+        ],
+    );
+    assert!(output.status.success());
+    assert!(!dir.join("json.txt").exists());
+    let rows: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.join("json/pure.json")).unwrap()).unwrap();
+    assert_eq!(rows.as_array().unwrap().len(), 1);
+    assert_eq!(rows[0]["sample"], "pure");
+    assert_eq!(rows[0]["data_type"], "paired");
+    assert_eq!(rows[0]["lineages"], "4.1.2 (20)");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn results_are_written_to_a_structured_directory() {
+    let dir = test_dir("outdir");
+    let output = fastlin(&dir, &["--outdir", "results"]);
+    assert!(output.status.success());
+    for file in [
+        "summary.tsv",
+        "samples/mixed.json",
+        "samples/pure.json",
+        "samples/single.json",
+        "logs/fastlin.log",
+        "logs/run.json",
+        "manifest.json",
+    ] {
+        assert!(dir.join("results").join(file).exists(), "{}", file);
+    }
+    let summary = fs::read_to_string(dir.join("results/summary.tsv")).unwrap();
+    assert!(summary.lines().any(|line| line.starts_with("single\t")));
+
+    // (the output file is the summary)
+    let output = fastlin(&dir, &["--outdir", "other", "-o", "out.txt"]);
+    assert!(!output.status.success());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn existing_results_are_only_overwritten_with_force() {
+    let dir = test_dir("force");
+    fs::write(dir.join("out.txt"), "previous results\n").unwrap();
+    let output = fastlin(&dir, &["-o", "out.txt"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("out.txt already exists"), "{}", stderr);
+    assert_eq!(
+        fs::read_to_string(dir.join("out.txt")).unwrap(),
+        "previous results\n"
+    );
+
+    let output = fastlin(&dir, &["-o", "out.txt", "--force"]);
+    assert!(output.status.success());
+    let results = fs::read_to_string(dir.join("out.txt")).unwrap();
+    assert!(results.starts_with("#sample\t"));
+
+    // (likewise for the results directories which aren't empty)
+    fs::create_dir(dir.join("results")).unwrap();
+    fs::write(dir.join("results/notes.txt"), "").unwrap();
+    assert!(!fastlin(&dir, &["--outdir", "results"]).status.success());
+    assert!(fastlin(&dir, &["--outdir", "results", "--force"])
+        .status
+        .success());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn no_partial_file_is_left_once_the_run_is_complete() {
+    let dir = test_dir("partial");
+    let output = fastlin(&dir, &["-o", "out.txt", "--per-sample-output", "samples"]);
+    assert!(output.status.success());
+    let mut files: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .chain(fs::read_dir(dir.join("samples")).unwrap())
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        ["mixed.tsv", "out.txt", "pure.tsv", "samples", "single.tsv"]
+    );
+    assert!(fs::read_to_string(dir.join("out.txt"))
+        .unwrap()
+        .contains("# samples: 3"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn shards_of_a_run_are_merged_into_its_output_file() {
+    let dir = test_dir("shards");
+    assert!(fastlin(&dir, &["-o", "all.txt"]).status.success());
+    for shard in ["1/2", "2/2"] {
+        let output = format!("shard_{}.txt", &shard[..1]);
+        assert!(fastlin(&dir, &["--shard", shard, "-o", &output])
+            .status
+            .success());
+    }
+    let status = Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .args([
+            "merge-shards",
+            "shard_1.txt",
+            "shard_2.txt",
+            "-o",
+            "merged.txt",
+        ])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // (same rows and summary as the run of all the samples, apart from the wall time)
+    let rows = |name: &str| -> Vec<String> {
+        fs::read_to_string(dir.join(name))
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with("# wall time"))
+            .map(String::from)
+            .collect()
+    };
+    let nb_samples = |name: &str| {
+        rows(name)
+            .iter()
+            .filter(|row| !row.starts_with('#'))
+            .count()
+    };
+    assert_eq!(
+        (nb_samples("shard_1.txt"), nb_samples("shard_2.txt")),
+        (2, 1)
+    );
+    assert_eq!(rows("merged.txt"), rows("all.txt"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn samples_past_the_maximum_run_time_are_skipped() {
+    let dir = test_dir("max_runtime");
+    let output = fastlin(&dir, &["-o", "out.txt", "--max-runtime", "0"]);
+    assert_eq!(output.status.code(), Some(3));
+    let results = fs::read_to_string(dir.join("out.txt")).unwrap();
+    let rows: Vec<&str> = results.lines().filter(|l| !l.starts_with('#')).collect();
+    assert_eq!(rows.len(), 3);
+    assert!(rows
+        .iter()
+        .all(|row| row.contains("\tSKIPPED (maximum run time reached)")));
+    assert!(results.contains("# failures: 3"));
+
+    // (complete runs end as usual)
+    let output = fastlin(&dir, &["-o", "out.txt", "--force", "--max-runtime", "1h"]);
+    assert!(output.status.success());
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,107 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+// server started on a port of its own, killed when the test ends
+struct Server {
+    child: Child,
+    port: u16,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn start_server(port: u16) -> Server {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let child = Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .arg("serve")
+        .arg("-b")
+        .arg(data.join("barcodes.txt"))
+        .arg("--data-dir")
+        .arg(&data)
+        .arg("--port")
+        .arg(port.to_string())
+        .arg("--max-body-size")
+        .arg("1")
+        .spawn()
+        .expect("couldn't run fastlin");
+    let server = Server { child, port };
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return server;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("the server didn't start");
+}
+
+// status code and body of the response to a POST request
+fn post(server: &Server, url: &str, body: &[u8]) -> (u16, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        url,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).unwrap();
+    // (the server may answer and close the connection before reading a body it refuses)
+    let _ = stream.write_all(body);
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+#[test]
+fn samples_are_typed_from_their_reads_or_their_files() {
+    let server = start_server(20000 + (std::process::id() % 20000) as u16);
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+
+    let fastq = std::fs::read(data.join("reads/single.fastq.gz")).unwrap();
+    let (status, body) = post(&server, "/fastq?sample=single", &fastq);
+    assert_eq!(status, 200, "{}", body);
+    let result: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(result["sample"], "single");
+    assert_eq!(result["data_type"], "single");
+    assert_eq!(result["lineages"][0]["lineage"], "2.2");
+
+    // (paths relative to the data directory)
+    let files = "reads/pure_1.fastq.gz\nreads/pure_2.fastq.gz\n";
+    let (status, body) = post(&server, "/files?sample=pure", files.as_bytes());
+    assert_eq!(status, 200, "{}", body);
+    let result: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(result["sample"], "pure");
+    assert_eq!(result["data_type"], "paired");
+    assert_eq!(result["lineages"][0]["lineage"], "4.1.2");
+
+    let (status, _) = post(&server, "/files", b"missing.fastq.gz\n");
+    assert_eq!(status, 400);
+    let (status, _) = post(&server, "/files", b"../../Cargo.toml\n");
+    assert_eq!(status, 400);
+    let (status, _) = post(&server, "/unknown", b"");
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn sample_names_are_decoded_and_oversized_bodies_refused() {
+    let server = start_server(20000 + (std::process::id() % 20000) as u16 + 1);
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let fastq = std::fs::read(data.join("reads/single.fastq.gz")).unwrap();
+
+    let (status, body) = post(&server, "/fastq?sample=my%20sample", &fastq);
+    assert_eq!(status, 200, "{}", body);
+    let result: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(result["sample"], "my sample");
+    assert_eq!(result["data_type"], "single");
+
+    let (status, body) = post(&server, "/fastq", &vec![b'A'; (1 << 20) + 1]);
+    assert_eq!(status, 413, "{}", body);
+}
@@ -0,0 +1,30 @@
+use std::path::Path;
+use std::process::Command;
+
+// single-sample mode (-i): stdout only carries the header and the result row, for the
+// wrappers parsing it, the run summary going to stderr
+#[test]
+fn stdout_is_the_header_and_the_result_row() {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let versions =
+        std::env::temp_dir().join(format!("fastlin_versions_{}.yml", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_fastlin"))
+        .arg("-i")
+        .arg(data.join("reads/pure_1.fastq.gz"))
+        .arg(data.join("reads/pure_2.fastq.gz"))
+        .arg("-b")
+        .arg(data.join("barcodes.txt"))
+        .arg("--versions")
+        .arg(&versions)
+        .output()
+        .expect("couldn't run fastlin");
+    let _ = std::fs::remove_file(&versions);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    assert!(lines[0].starts_with("#sample\tdata_type\t"));
+    assert!(lines[1].starts_with("pure\tpaired\t"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("samples: 1"));
+}